@@ -0,0 +1,48 @@
+// A binary with its own world next to the built-in ones:
+//     cargo run --release --example custom_world -- --world=three_spheres > image.ppm
+use raytracer::bhv::BvhConfig;
+use raytracer::hittable::{Hittable, HittableList};
+use raytracer::materials::{Dielectric, Lambertian, Metal};
+use raytracer::raytrace::{Background, GradientBackground};
+use raytracer::shapes::Sphere;
+use raytracer::textures::SolidColor;
+use raytracer::vec::{Color, Point3};
+use raytracer::worlds::{self, World, WorldCamera};
+
+struct ThreeSpheres {}
+
+impl World for ThreeSpheres {
+    fn name(&self) -> &'static str {
+        "three_spheres"
+    }
+
+    fn description(&self) -> &'static str {
+        "Diffuse, glass and metal balls in a row."
+    }
+
+    fn background(&self) -> Box<dyn Background> {
+        Box::new(GradientBackground::default())
+    }
+
+    fn camera(&self) -> WorldCamera {
+        WorldCamera { lookfrom: Point3::new(0.0, 1.0, 4.0), lookat: Point3::new(0.0, 0.5, 0.0), field_of_view: 40.0 }
+    }
+
+    fn build(&self, _: BvhConfig, _: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+        let mut shapes = HittableList::new();
+        shapes.add(Sphere::new(
+            Point3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)),
+        ));
+        shapes.add(Sphere::new(Point3::new(-1.1, 0.5, 0.0), 0.5, Lambertian::new(SolidColor::new(0.7, 0.2, 0.2))));
+        shapes.add(Sphere::new(Point3::new(0.0, 0.5, 0.0), 0.5, Dielectric::new(1.5)));
+        shapes.add(Sphere::new(Point3::new(1.1, 0.5, 0.0), 0.5, Metal::new(Color::new(0.8, 0.8, 0.8), 0.0)));
+        Box::new(shapes)
+    }
+}
+
+fn main() {
+    worlds::register_world(Box::new(ThreeSpheres {}));
+    raytracer::cli::run();
+}
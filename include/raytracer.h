@@ -0,0 +1,36 @@
+/* C API of the raytracer library (libraytracer.so, raytracer.dll or libraytracer.dylib), see src/ffi.rs. */
+#ifndef RAYTRACER_H
+#define RAYTRACER_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct Scene RtScene;
+
+/* Called with the lines rendered so far, the total, and the user data given to rt_render. */
+typedef void (*RtProgress)(int done, int total, void *user);
+
+/* Description of the last error on this thread. */
+const char *rt_last_error(void);
+
+/* Loads a scene file and builds its world; NULL on errors. */
+RtScene *rt_scene_load(const char *path);
+void rt_scene_free(RtScene *scene);
+
+/* Settings; these return 0 on success and -1 on errors. */
+int rt_set_samples(RtScene *scene, int samples);
+int rt_set_max_depth(RtScene *scene, int max_depth);
+int rt_set_seed(RtScene *scene, uint64_t seed);
+int rt_set_light_sampling(RtScene *scene, int on);
+
+/* Renders into rgba, width * height * 4 bytes from the top left; progress may be NULL. */
+int rt_render(RtScene *scene, int width, int height, uint8_t *rgba, RtProgress progress, void *user);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -1,8 +1,11 @@
 use crate::bhv::AABB;
 use crate::hittable::Hit;
+use crate::interval::Interval;
 use crate::materials::Material;
 use crate::transforms::{index, Axis};
+use crate::validate::SceneStats;
 use crate::vec::{Point3, Ray, Vec3};
+use rand::Rng;
 
 fn other(a0: Axis, a1: Axis) -> Axis {
     match (a0, a1) {
@@ -36,15 +39,21 @@ impl AARect {
 
             a0_v0: a0_v0.min(a0_v1),
             a0_v1: a0_v1.max(a0_v0),
-            a1_v0: a1_v0.min(a1_v0),
+            a1_v0: a1_v0.min(a1_v1),
             a1_v1: a1_v1.max(a1_v0),
             aplane_v,
         }
     }
 
-    pub fn hit<'a>(&self, r: &Ray, tmin: f64, tmax: f64, material: &'a dyn Material) -> Option<Hit<'a>> {
+    // "a0,a1,b0,b1 k" as scene files take it after the plane, see `scene::SceneFile`.
+    pub fn export(&self) -> String {
+        format!("{},{},{},{} {}", self.a0_v0, self.a0_v1, self.a1_v0, self.a1_v1, self.aplane_v)
+    }
+
+    // Returns (t, a0 coordinate, a1 coordinate) of the intersection point.
+    fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<(f64, f64, f64)> {
         let t = (self.aplane_v - r.orig.e[self.aplane]) / r.dir.e[self.aplane];
-        if t < tmin || t > tmax {
+        if !ray_t.contains(t) {
             return None;
         }
 
@@ -54,13 +63,86 @@ impl AARect {
         if a0_v < self.a0_v0 || a0_v > self.a0_v1 || a1_v < self.a1_v0 || a1_v > self.a1_v1 {
             return None;
         }
+        Some((t, a0_v, a1_v))
+    }
+
+    pub fn hit_any(&self, r: &Ray, ray_t: Interval) -> bool {
+        self.intersect(r, ray_t).is_some()
+    }
+
+    pub fn hit<'a>(&self, r: &Ray, ray_t: Interval, material: &'a dyn Material) -> Option<Hit<'a>> {
+        let (t, a0_v, a1_v) = self.intersect(r, ray_t)?;
 
         let u = (a0_v - self.a0_v0) / (self.a0_v1 - self.a0_v0);
         let v = (a1_v - self.a1_v0) / (self.a1_v1 - self.a1_v0);
         let mut outward_normal = Vec3::ZERO;
         outward_normal.e[self.aplane] = 1.0;
+        let (dpdu, dpdv) = self.tangents();
 
-        Some(Hit::new_with_face_normal(&r.at(t), t, u, v, &outward_normal, r, material))
+        Some(Hit::new_with_face_normal(&r.at(t), t, u, v, &outward_normal, r, material).with_tangents(dpdu, dpdv))
+    }
+
+    // Derivatives of the point by u and v, the coordinates along the sides of the rect.
+    fn tangents(&self) -> (Vec3, Vec3) {
+        let (mut dpdu, mut dpdv) = (Vec3::ZERO, Vec3::ZERO);
+        dpdu.e[self.a0] = self.a0_v1 - self.a0_v0;
+        dpdv.e[self.a1] = self.a1_v1 - self.a1_v0;
+        (dpdu, dpdv)
+    }
+
+    // Solid angle density of `random_point` seen from `o`, along `v`.
+    pub fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let t = match self.intersect(&Ray::new(*o, *v), Interval::FORWARD) {
+            None => return 0.0,
+            Some((t, _, _)) => t,
+        };
+        let area = (self.a0_v1 - self.a0_v0) * (self.a1_v1 - self.a1_v0);
+        let distance_squared = t * t * v.length_squared();
+        let cosine = (v.e[self.aplane] / v.length()).abs();
+        distance_squared / (cosine * area)
+    }
+
+    // Uniformly distributed point on the rect.
+    pub fn random_point(&self, rng: &mut dyn rand::RngCore) -> Point3 {
+        let mut p = Point3::ZERO;
+        p.e[self.a0] = rng.gen_range(self.a0_v0..=self.a0_v1);
+        p.e[self.a1] = rng.gen_range(self.a1_v0..=self.a1_v1);
+        p.e[self.aplane] = self.aplane_v;
+        p
+    }
+
+    // Uniformly distributed point on the rect as a hit facing along the axis of the plane, and the density of
+    // picking it per unit area, see `Hittable::sample_surface`.
+    pub fn sample_surface<'a>(&self, material: &'a dyn Material, rng: &mut dyn rand::RngCore) -> (Hit<'a>, f64) {
+        let p = self.random_point(rng);
+        let u = (p.e[self.a0] - self.a0_v0) / (self.a0_v1 - self.a0_v0);
+        let v = (p.e[self.a1] - self.a1_v0) / (self.a1_v1 - self.a1_v0);
+        let mut normal = Vec3::ZERO;
+        normal.e[self.aplane] = 1.0;
+        let area = (self.a0_v1 - self.a0_v0) * (self.a1_v1 - self.a1_v0);
+        let (dpdu, dpdv) = self.tangents();
+        let h = Hit {
+            p,
+            normal,
+            geometric_normal: normal,
+            shading_offset: Vec3::ZERO,
+            dpdu,
+            dpdv,
+            t: 0.0,
+            u,
+            v,
+            front_face: true,
+            material,
+            time: 0.0,
+        };
+        (h, 1.0 / area)
+    }
+
+    pub fn inspect(&self, stats: &mut SceneStats) {
+        stats.check_finite("rect", &[self.a0_v0, self.a0_v1, self.a1_v0, self.a1_v1, self.aplane_v]);
+        if self.a0_v0 == self.a0_v1 || self.a1_v0 == self.a1_v1 {
+            stats.problem(format!("rect with zero area at {}", self.bounding_box()));
+        }
     }
 
     pub fn bounding_box(&self) -> AABB {
@@ -68,11 +150,27 @@ impl AARect {
         let mut maximum = Point3::ZERO;
         minimum.e[self.a0] = self.a0_v0;
         minimum.e[self.a1] = self.a1_v0;
-        minimum.e[self.aplane] = self.aplane_v - 0.001;
-        maximum.e[self.a0] = self.a0_v0;
-        maximum.e[self.a1] = self.a1_v0;
-        maximum.e[self.aplane] = self.aplane_v + 0.001;
+        minimum.e[self.aplane] = self.aplane_v;
+        maximum.e[self.a0] = self.a0_v1;
+        maximum.e[self.a1] = self.a1_v1;
+        maximum.e[self.aplane] = self.aplane_v;
+
+        AABB::new(minimum, maximum).pad()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        AABB::new(minimum, maximum)
+    #[test]
+    fn test_bounds() {
+        // The corners may be given in either order; the box spans the whole rect, so BVHs don't cull it.
+        let rect = AARect::new(Axis::X, 3.0, 1.0, Axis::Z, 5.0, -2.0, 4.0);
+        let bounds = rect.bounding_box();
+        assert_eq!(Point3::new(1.0, 3.999, -2.0), bounds.min());
+        assert_eq!(Point3::new(3.0, 4.001, 5.0), bounds.max());
+        let ray = Ray::new(Point3::new(2.5, 10.0, 4.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(rect.hit_any(&ray, Interval::FORWARD));
     }
 }
@@ -0,0 +1,185 @@
+use crate::camera::CameraParams;
+use crate::vec::{Point3, Vec3};
+use std::str::FromStr;
+
+// Camera position at a point in time, in seconds.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Keyframe {
+    pub time: f64,
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub field_of_view: f64,
+}
+
+// Keyframes sorted by time; the camera moves linearly between them.
+//
+// In text form one keyframe per line, `time lookfrom lookat field_of_view`, e.g. `1.5 13,2,3 0,0,0 20`.
+// Empty lines and lines starting with '#' are skipped.
+#[derive(Debug)]
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn new(mut keyframes: Vec<Keyframe>) -> CameraPath {
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        CameraPath { keyframes }
+    }
+
+    pub fn load(path: &str) -> Result<CameraPath, String> {
+        std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?.parse::<CameraPath>()
+    }
+
+    pub fn start(&self) -> f64 {
+        self.keyframes.first().map_or(0.0, |k| k.time)
+    }
+
+    pub fn end(&self) -> f64 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+
+    // The camera at `time`, clamped to the path; the rest of `camera` is kept. Focuses at lookat.
+    pub fn camera_at(&self, time: f64, camera: &CameraParams) -> CameraParams {
+        let next = self.keyframes.iter().position(|k| k.time > time).unwrap_or(self.keyframes.len());
+        let key = match next {
+            0 => self.keyframes[0],
+            n if n == self.keyframes.len() => self.keyframes[n - 1],
+            n => {
+                let (a, b) = (&self.keyframes[n - 1], &self.keyframes[n]);
+                let f = (time - a.time) / (b.time - a.time);
+                let lerp = |a: Vec3, b: Vec3| (1.0 - f) * a + f * b;
+                Keyframe {
+                    time,
+                    lookfrom: lerp(a.lookfrom, b.lookfrom),
+                    lookat: lerp(a.lookat, b.lookat),
+                    field_of_view: (1.0 - f) * a.field_of_view + f * b.field_of_view,
+                }
+            }
+        };
+        CameraParams {
+            lookfrom: key.lookfrom,
+            lookat: key.lookat,
+            field_of_view: key.field_of_view,
+            focus_dist: (key.lookat - key.lookfrom).length(),
+            ..*camera
+        }
+    }
+}
+
+impl FromStr for CameraPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CameraPath, String> {
+        let mut keyframes = Vec::new();
+        for (n, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |e: String| format!("line {}: {}", n + 1, e);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 4 {
+                return Err(error(format!("expected time lookfrom lookat field_of_view, got '{}'", line)));
+            }
+            let number = |s: &str| s.parse::<f64>().map_err(|e| error(format!("'{}': {}", s, e)));
+            let time = number(fields[0])?;
+            if !time.is_finite() {
+                return Err(error(format!("time '{}' isn't a number of seconds", fields[0])));
+            }
+            keyframes.push(Keyframe {
+                time,
+                lookfrom: fields[1].parse::<Vec3>().map_err(error)?,
+                lookat: fields[2].parse::<Vec3>().map_err(error)?,
+                field_of_view: number(fields[3])?,
+            });
+        }
+        if keyframes.is_empty() {
+            return Err("camera path without keyframes".to_string());
+        }
+        Ok(CameraPath::new(keyframes))
+    }
+}
+
+// File names of the frames: a printf-style pattern with one %d, optionally zero-padded like %04d, for the frame
+// number, e.g. `shots/frame_%04d.png`. Without one the pattern is a prefix and frames are saved as
+// <prefix>NNNN.png.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FramePattern {
+    prefix: String,
+    width: usize,
+    suffix: String,
+}
+
+impl FramePattern {
+    pub fn file(&self, frame: usize) -> String {
+        format!("{}{:0width$}{}", self.prefix, frame, self.suffix, width = self.width)
+    }
+}
+
+impl FromStr for FramePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FramePattern, String> {
+        let (prefix, rest) = match s.split_once('%') {
+            None => return Ok(FramePattern { prefix: s.to_string(), width: 4, suffix: ".png".to_string() }),
+            Some(split) => split,
+        };
+        let (width, suffix) = match rest.split_once('d') {
+            Some((width, suffix)) if width.is_empty() || (width.starts_with('0') && width.len() > 1) => {
+                (width.parse::<usize>().unwrap_or(0), suffix)
+            }
+            _ => return Err(format!("'{}' should have %d or %0Nd for the frame number", s)),
+        };
+        if suffix.contains('%') {
+            return Err(format!("'{}' has more than one %", s));
+        }
+        Ok(FramePattern { prefix: prefix.to_string(), width, suffix: suffix.to_string() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_camera_at() {
+        let path = "# flythrough\n2 0,0,10 0,0,0 40\n\n0 0,0,0 0,0,-1 20\n".parse::<CameraPath>().unwrap();
+        assert_eq!((0.0, 2.0), (path.start(), path.end()));
+
+        let camera = CameraParams {
+            lookfrom: Point3::ZERO,
+            lookat: Point3::ZERO,
+            up: Vec3::new(0.0, 1.0, 0.0),
+            field_of_view: 90.0,
+            aspect_ratio: 1.0,
+            aperture: 0.1,
+            focus_dist: 1.0,
+            shift: 0.0,
+            near: 0.0,
+            far: f64::INFINITY,
+        };
+        let middle = path.camera_at(0.5, &camera);
+        assert_eq!(Point3::new(0.0, 0.0, 2.5), middle.lookfrom);
+        assert_eq!(Point3::new(0.0, 0.0, -0.75), middle.lookat);
+        assert_eq!(25.0, middle.field_of_view);
+        assert_eq!(3.25, middle.focus_dist);
+        assert_eq!(0.1, middle.aperture);
+        assert_eq!(Point3::new(0.0, 0.0, 10.0), path.camera_at(3.0, &camera).lookfrom);
+
+        assert!("0 1,2 0,0,0 20".parse::<CameraPath>().unwrap_err().starts_with("line 1"));
+        assert!("0 0,0,0 0,0,-1 20\nNaN 0,0,0 0,0,-1 20".parse::<CameraPath>().unwrap_err().starts_with("line 2"));
+        assert!("inf 0,0,0 0,0,-1 20".parse::<CameraPath>().is_err());
+    }
+
+    #[test]
+    fn test_frame_pattern() {
+        let file = |pattern: &str, frame| pattern.parse::<FramePattern>().map(|p| p.file(frame));
+        assert_eq!(Ok("frame_0012.png".to_string()), file("frame_", 12));
+        assert_eq!(Ok("shots/frame_0012.png".to_string()), file("shots/frame_%04d.png", 12));
+        assert_eq!(Ok("f12.jpg".to_string()), file("f%d.jpg", 12));
+        assert_eq!(Ok("f12345.png".to_string()), file("f%03d.png", 12345));
+        assert!(file("f%4d.png", 1).is_err());
+        assert!(file("f%x.png", 1).is_err());
+        assert!(file("f%d_%d.png", 1).is_err());
+    }
+}
@@ -0,0 +1,360 @@
+// Files that worlds and scene files read, e.g. image textures, meshes and measured BRDFs, loaded once per process
+// and shared by handle, so the earth map of a world used by two materials, or a mesh placed by every job of the
+// HTTP server, is read and decoded only the first time. A file changed since it was loaded is read again, and one
+// that can't be loaded isn't kept, so it is retried the next time. With a budget, files no longer used are dropped,
+// the least recently used first whatever their kind, to keep the memory of the cached files within it.
+use crate::logging::{self, Level};
+use crate::measured::MerlBrdf;
+use crate::mesh::{self, MeshData};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::tiled_image::TiledImage;
+use image::RgbImage;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+// What cached files take in memory, counted against the budget.
+pub trait Asset {
+    fn bytes(&self) -> usize;
+}
+
+impl Asset for RgbImage {
+    fn bytes(&self) -> usize {
+        self.as_raw().len()
+    }
+}
+
+impl Asset for MeshData {
+    fn bytes(&self) -> usize {
+        // Everything the mesh keeps; the normals of smooth meshes are made for every placement, see `scene.rs`.
+        std::mem::size_of_val(self.vertices.as_slice()) + std::mem::size_of_val(self.faces.as_slice())
+    }
+}
+
+// What a budget needs of the caches sharing it to drop their files, the least recently used of all first.
+trait Evict: Sync {
+    // When the least recently used file that no world uses any more was last asked for.
+    fn oldest_unused(&self) -> Option<u64>;
+
+    // Drops that file, false if there no longer is one.
+    fn evict_oldest(&self) -> bool;
+}
+
+// The memory that caches sharing it may keep files in, and how much they do.
+pub struct Budget {
+    limit: AtomicUsize,
+    used: AtomicUsize,
+    // Counts the files asked for from every cache, so their uses can be compared.
+    clock: AtomicU64,
+    caches: Mutex<Vec<&'static dyn Evict>>,
+}
+
+impl Budget {
+    pub const fn new() -> Budget {
+        Budget {
+            limit: AtomicUsize::new(usize::MAX),
+            used: AtomicUsize::new(0),
+            clock: AtomicU64::new(0),
+            caches: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Files loaded beyond `bytes` make room by dropping the least recently used ones that no world uses any more;
+    // `None` keeps every file.
+    pub fn set_limit(&self, bytes: Option<usize>) {
+        self.limit.store(bytes.unwrap_or(usize::MAX), Ordering::Relaxed);
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        Some(self.limit.load(Ordering::Relaxed)).filter(|&l| l != usize::MAX)
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    // Drops the least recently used files of all the caches until the budget is kept. Files still used, e.g. by
+    // the world being rendered, stay, as dropping them frees nothing; the budget may be exceeded until they aren't.
+    // No cache is locked while another is, so caches loading files at the same time can't wait on each other.
+    fn make_room(&self) {
+        while self.used() > self.limit.load(Ordering::Relaxed) {
+            let caches = self.caches.lock().unwrap().clone();
+            let oldest = caches.iter().filter_map(|c| Some((c.oldest_unused()?, c))).min_by_key(|(used, _)| *used);
+            match oldest {
+                Some((_, cache)) => cache.evict_oldest(),
+                None => break,
+            };
+        }
+    }
+}
+
+struct Entry<T> {
+    // When the file was modified when loaded.
+    modified: Option<SystemTime>,
+    value: Arc<T>,
+    bytes: usize,
+    // The clock of the budget when the file was last asked for.
+    used: u64,
+}
+
+// What a cache did since the process started.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stats {
+    pub files: usize,
+    pub bytes: usize,
+    // Files asked for that were already loaded.
+    pub hits: usize,
+    pub loads: usize,
+    // Files dropped to stay within the budget.
+    pub evictions: usize,
+}
+
+struct State<T> {
+    entries: BTreeMap<String, Entry<T>>,
+    stats: Stats,
+}
+
+// Loaded files of one kind by path.
+pub struct Cache<T> {
+    name: &'static str,
+    budget: &'static Budget,
+    // Whether the budget knows the cache, so loading files of other kinds can drop its files.
+    registered: AtomicBool,
+    state: Mutex<State<T>>,
+}
+
+impl<T: Asset + Send + Sync> Cache<T> {
+    pub const fn new(name: &'static str, budget: &'static Budget) -> Cache<T> {
+        let stats = Stats { files: 0, bytes: 0, hits: 0, loads: 0, evictions: 0 };
+        let state = Mutex::new(State { entries: BTreeMap::new(), stats });
+        Cache { name, budget, registered: AtomicBool::new(false), state }
+    }
+
+    // The file at `path`, loaded by `load` unless it was already. The lock is held while loading, so threads
+    // asking for the same file wait for the first to load it instead of loading it again.
+    pub fn get(&'static self, path: &str, load: impl FnOnce(&str) -> Result<T, String>) -> Result<Arc<T>, String> {
+        if !self.registered.swap(true, Ordering::Relaxed) {
+            self.budget.caches.lock().unwrap().push(self);
+        }
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut state = self.state.lock().unwrap();
+        let clock = self.budget.clock.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(entry) = state.entries.get_mut(path) {
+            if entry.modified == modified {
+                entry.used = clock;
+                let value = entry.value.clone();
+                state.stats.hits += 1;
+                logging::trace(&format!("Reused {}", path));
+                return Ok(value);
+            }
+        }
+        self.remove(&mut state, path);
+        state.stats.loads += 1;
+        let value = logging::timed(Level::Verbose, &format!("load {}", path), || load(path))?;
+        let value = Arc::new(value);
+        let bytes = value.bytes();
+        self.budget.used.fetch_add(bytes, Ordering::Relaxed);
+        state.entries.insert(path.to_string(), Entry { modified, value: value.clone(), bytes, used: clock });
+        drop(state);
+        self.budget.make_room();
+        Ok(value)
+    }
+
+    fn remove(&self, state: &mut State<T>, path: &str) {
+        if let Some(entry) = state.entries.remove(path) {
+            self.budget.used.fetch_sub(entry.bytes, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let state = self.state.lock().unwrap();
+        let bytes = state.entries.values().map(|e| e.bytes).sum();
+        Stats { files: state.entries.len(), bytes, ..state.stats }
+    }
+
+    // Files loaded so far.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: Asset + Send + Sync> Evict for Cache<T> {
+    fn oldest_unused(&self) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        state.entries.values().filter(|e| Arc::strong_count(&e.value) == 1).map(|e| e.used).min()
+    }
+
+    fn evict_oldest(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let unused = state.entries.iter().filter(|(_, e)| Arc::strong_count(&e.value) == 1);
+        let path = match unused.min_by_key(|(_, e)| e.used) {
+            Some((path, _)) => path.clone(),
+            None => return false,
+        };
+        self.remove(&mut state, &path);
+        state.stats.evictions += 1;
+        logging::trace(&format!("Dropped {} from the {} cache", path, self.name));
+        true
+    }
+}
+
+static BUDGET: Budget = Budget::new();
+static IMAGES: Cache<RgbImage> = Cache::new("images", &BUDGET);
+static MESHES: Cache<MeshData> = Cache::new("meshes", &BUDGET);
+static BRDFS: Cache<MerlBrdf> = Cache::new("brdfs", &BUDGET);
+#[cfg(not(target_arch = "wasm32"))]
+static TILED_IMAGES: Cache<TiledImage> = Cache::new("tiled images", &BUDGET);
+
+// An image texture's pixels, see `image_texture::Image::open`.
+pub fn image(path: &str) -> Result<Arc<RgbImage>, String> {
+    IMAGES.get(path, |path| image::open(path).map(|image| image.to_rgb8()).map_err(|e| e.to_string()))
+}
+
+// A mesh as read from an OBJ file, before subdividing or mirroring, see `mesh::load_obj`.
+pub fn mesh(path: &str) -> Result<Arc<MeshData>, String> {
+    MESHES.get(path, mesh::load_obj)
+}
+
+pub fn brdf(path: &str) -> Result<Arc<MerlBrdf>, String> {
+    BRDFS.get(path, MerlBrdf::load)
+}
+
+// A mapped tiled image, counted by its cache of decoded tiles, see `tiled_image::TiledImage`.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn tiled_image(path: &str) -> Result<Arc<TiledImage>, String> {
+    TILED_IMAGES.get(path, TiledImage::open)
+}
+
+// Limits the memory of the image textures, tiled textures, meshes and BRDFs kept loaded together, e.g. for an HTTP
+// server rendering jobs with many textures. Only loading a file makes room, among the files of every kind.
+pub fn set_budget(bytes: Option<usize>) {
+    BUDGET.set_limit(bytes);
+}
+
+// A line per cache, e.g. "images: 3 files, 48.0 MiB, 12 hits, 5 loads, 2 evictions", then the total.
+pub fn report() -> Vec<String> {
+    let mib = |bytes: usize| format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0));
+    #[allow(unused_mut)]
+    let mut caches = vec![("images", IMAGES.stats()), ("meshes", MESHES.stats()), ("brdfs", BRDFS.stats())];
+    #[cfg(not(target_arch = "wasm32"))]
+    caches.push(("tiled images", TILED_IMAGES.stats()));
+    let mut lines: Vec<String> = caches
+        .iter()
+        .map(|(name, s)| {
+            format!(
+                "{}: {} files, {}, {} hits, {} loads, {} evictions",
+                name,
+                s.files,
+                mib(s.bytes),
+                s.hits,
+                s.loads,
+                s.evictions
+            )
+        })
+        .collect();
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let (hits, loads) = crate::tiled_image::stats();
+        lines.push(format!("tiled textures: {} tiles decoded, {} hits", loads, hits));
+    }
+    let limit = BUDGET.limit().map_or("no limit".to_string(), |l| format!("budget {}", mib(l)));
+    lines.push(format!("total: {} of {}", mib(BUDGET.used()), limit));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let path = std::env::temp_dir().join(format!("assets_{}.obj", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        static BUDGET: Budget = Budget::new();
+        static CACHE: Cache<MeshData> = Cache::new("test", &BUDGET);
+        let cache = &CACHE;
+        let loads = std::cell::Cell::new(0);
+        let load = |path: &str| {
+            loads.set(loads.get() + 1);
+            mesh::load_obj(path)
+        };
+        let first = cache.get(path, load).unwrap();
+        let second = cache.get(path, load).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!((1, 1), (loads.get(), cache.len()));
+
+        // A changed file is read again.
+        let modified = std::fs::metadata(path).unwrap().modified().unwrap();
+        std::fs::write(path, "v 0 0 0\nv 2 0 0\nv 0 2 0\nf 1 2 3\n").unwrap();
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(modified + std::time::Duration::from_secs(1)).unwrap();
+        let changed = cache.get(path, load).unwrap();
+        assert_eq!((2, 1), (loads.get(), cache.len()));
+        assert_eq!(2.0, changed.vertices[1].x());
+        std::fs::remove_file(path).unwrap();
+
+        // Files that can't be loaded aren't kept.
+        assert!(cache.get(path, load).is_err());
+        assert!(cache.get(path, load).is_err());
+        assert_eq!((4, 0), (loads.get(), cache.len()));
+        assert_eq!(0, BUDGET.used());
+    }
+
+    #[test]
+    fn test_budget() {
+        static BUDGET: Budget = Budget::new();
+        static CACHE: Cache<MeshData> = Cache::new("test", &BUDGET);
+        static IMAGES: Cache<RgbImage> = Cache::new("test images", &BUDGET);
+        let cache = &CACHE;
+        let dir = std::env::temp_dir();
+        let paths: Vec<String> = (0..3)
+            .map(|k| {
+                let path = dir.join(format!("assets_budget_{}_{}.obj", std::process::id(), k));
+                std::fs::write(&path, "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect();
+        let bytes = cache.get(&paths[0], mesh::load_obj).unwrap().bytes();
+        assert_eq!(bytes, BUDGET.used());
+
+        // Room for two meshes: loading the third drops the least recently used one that isn't held.
+        BUDGET.set_limit(Some(2 * bytes));
+        let held = cache.get(&paths[1], mesh::load_obj).unwrap();
+        cache.get(&paths[0], mesh::load_obj).unwrap();
+        cache.get(&paths[2], mesh::load_obj).unwrap();
+        let stats = cache.stats();
+        assert_eq!((2, 2 * bytes, 1, 3, 1), (stats.files, stats.bytes, stats.hits, stats.loads, stats.evictions));
+        assert_eq!(Some(2 * bytes), BUDGET.limit());
+        cache.get(&paths[1], mesh::load_obj).unwrap();
+        assert_eq!(2, cache.stats().hits);
+
+        // Files of another kind make room among the meshes too: the image drops the mesh that isn't held.
+        let image = dir.join(format!("assets_budget_{}.png", std::process::id()));
+        let image = image.to_str().unwrap();
+        RgbImage::new(4, 2).save(image).unwrap();
+        let load_image = |path: &str| Ok(image::open(path).map_err(|e| e.to_string())?.to_rgb8());
+        BUDGET.set_limit(Some(2 * bytes + 23));
+        IMAGES.get(image, load_image).unwrap();
+        let stats = cache.stats();
+        assert_eq!((1, 2), (stats.files, stats.evictions));
+        assert_eq!(bytes + 24, BUDGET.used());
+        assert!(cache.get(&paths[2], |_| Err("dropped".to_string())).is_err());
+        std::fs::remove_file(image).unwrap();
+
+        // Held meshes, and the one being loaded, stay even over the budget.
+        BUDGET.set_limit(Some(0));
+        let _third = cache.get(&paths[2], mesh::load_obj).unwrap();
+        cache.get(&paths[0], mesh::load_obj).unwrap();
+        assert_eq!(3, cache.len());
+        drop(held);
+        for path in paths.iter() {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
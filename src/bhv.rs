@@ -1,9 +1,19 @@
 use crate::hittable::{Hit, Hittable};
+use crate::interval::Interval;
+use crate::logging::{self, Level};
+use crate::scene::SceneWriter;
+use crate::scratch::Scratch;
 use crate::shapes;
-use crate::vec::{Point3, Ray};
-use rand::Rng;
+use crate::validate::SceneStats;
+use crate::vec::{Point3, Ray, Vec3};
+use rand::{Rng, SeedableRng};
 use std::cmp::Ordering;
 use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::time::{Duration, Instant};
+
+// Boxes of planar shapes are padded to at least this thickness, see `AABB::pad`.
+const MIN_THICKNESS: f64 = 0.002;
 
 // Axis-Aligned Bounding Box
 #[derive(Copy, Clone, Debug)]
@@ -13,6 +23,10 @@ pub struct AABB {
 }
 
 impl AABB {
+    // Contains nothing, not even the origin; surrounding it with another box gives that box.
+    pub const EMPTY: AABB =
+        AABB { minimum: Point3 { e: [f64::INFINITY; 3] }, maximum: Point3 { e: [f64::NEG_INFINITY; 3] } };
+
     pub fn new(a: Point3, b: Point3) -> AABB {
         let min = [a.e[0].min(b.e[0]), a.e[1].min(b.e[1]), a.e[2].min(b.e[2])];
         let max = [a.e[0].max(b.e[0]), a.e[1].max(b.e[1]), a.e[2].max(b.e[2])];
@@ -26,19 +40,68 @@ impl AABB {
         self.maximum
     }
 
-    fn hit(&self, r: &Ray, tmin: f64, tmax: f64) -> bool {
-        let mut tmin = tmin;
-        let mut tmax = tmax;
+    pub fn is_empty(&self) -> bool {
+        (0..3).any(|a| self.minimum.e[a] > self.maximum.e[a])
+    }
+
+    pub fn axis(&self, a: usize) -> Interval {
+        Interval::new(self.minimum.e[a], self.maximum.e[a])
+    }
+
+    // Grows the axes thinner than `MIN_THICKNESS` to that thickness, around their middle.
+    // A ray can't hit a box with no volume, see `hit`, so planes in the box would be missed.
+    pub fn pad(&self) -> AABB {
+        if self.is_empty() {
+            return *self;
+        }
+        let mut padded = *self;
+        for a in 0..3 {
+            let axis = self.axis(a);
+            if axis.size() < MIN_THICKNESS {
+                let grown = axis.expand(MIN_THICKNESS - axis.size());
+                padded.minimum.e[a] = grown.min;
+                padded.maximum.e[a] = grown.max;
+            }
+        }
+        padded
+    }
+
+    pub fn translate(&self, offset: Vec3) -> AABB {
+        AABB { minimum: self.minimum + offset, maximum: self.maximum + offset }
+    }
+
+    fn hit(&self, r: &Ray, ray_t: Interval) -> bool {
+        !self.clip(r, ray_t).is_empty()
+    }
+
+    // Part of `ray_t` where the ray is inside the box, empty if it misses it.
+    pub fn clip(&self, r: &Ray, ray_t: Interval) -> Interval {
+        if self.is_empty() {
+            return Interval::EMPTY;
+        }
+        let mut ray_t = ray_t;
         for a in 0..3 {
             let t0 = (self.minimum.e[a] - r.orig.e[a]) / r.dir.e[a];
             let t1 = (self.maximum.e[a] - r.orig.e[a]) / r.dir.e[a];
-            tmin = t0.min(t1).max(tmin);
-            tmax = t0.max(t1).min(tmax);
-            if tmax <= tmin {
-                return false;
+            ray_t = ray_t.intersect(&Interval::new(t0.min(t1), t0.max(t1)));
+            if ray_t.max <= ray_t.min {
+                return Interval::EMPTY;
             }
         }
-        true
+        ray_t
+    }
+
+    // Zero for an empty box. The chance of a random ray hitting a box is proportional to it, see `BvhBuilder::Sah`.
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let d = self.maximum - self.minimum;
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
+    pub fn center(&self) -> Point3 {
+        0.5 * (self.minimum + self.maximum)
     }
 
     pub fn surround(&self, other: &AABB) -> AABB {
@@ -48,7 +111,7 @@ impl AABB {
             min[a] = self.minimum.e[a].min(other.minimum.e[a]);
             max[a] = self.maximum.e[a].max(other.maximum.e[a]);
         }
-        AABB::new(Point3 { e: min }, Point3 { e: max })
+        AABB { minimum: Point3 { e: min }, maximum: Point3 { e: max } }
     }
 }
 
@@ -62,14 +125,27 @@ pub trait Bounded: Hittable {
     fn bounding_box(&self) -> AABB;
 }
 
+impl<T: Bounded + ?Sized> Bounded for Box<T> {
+    fn bounding_box(&self) -> AABB {
+        (**self).bounding_box()
+    }
+}
+
 pub struct SceneBuilder<'a> {
     contents: Vec<Option<Box<dyn Bounded + 'a>>>,
+    config: BvhConfig,
 }
 
 impl<'a> SceneBuilder<'a> {
     pub fn new() -> SceneBuilder<'a> {
-        SceneBuilder { contents: Vec::new() }
+        SceneBuilder::with_config(BvhConfig::default())
+    }
+
+    // Builds its BVH as `config` says, e.g. as given on the command line and passed on by `World::build`.
+    pub fn with_config(config: BvhConfig) -> SceneBuilder<'a> {
+        SceneBuilder { contents: Vec::new(), config }
     }
+
     pub fn add<T: Bounded + 'a>(&mut self, v: T) -> &mut Self {
         self.contents.push(Some(Box::new(v)));
         self
@@ -79,56 +155,518 @@ impl<'a> SceneBuilder<'a> {
         self.contents.push(Some(v));
         self
     }
+
+    // Adds the contents of `group` as one shape with a BVH of its own, see `Group`.
+    pub fn group(&mut self, name: &str, group: SceneBuilder<'a>, rng: &mut dyn rand::RngCore) -> &mut Self {
+        self.add(Group::new(name, group, rng))
+    }
+
+    // Adds everything in `other` as if it had been added to this builder.
+    pub fn merge(&mut self, other: SceneBuilder<'a>) -> &mut Self {
+        self.contents.extend(other.contents);
+        self
+    }
+}
+
+// Most shapes a BVH node keeps in a list instead of splitting them further, unless configured otherwise.
+// Testing a few shapes one after the other costs less than the boxes of the nodes it would take to tell them
+// apart: with 4, `--gen=spheres` renders about 20% faster than with single shapes and `random` about 10%, while
+// `final_scene`, whose shapes are mostly groups, is within noise.
+pub const DEFAULT_LEAF_SIZE: usize = 4;
+
+// How the BVHs of a scene are built, see `SceneBuilder::with_config`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BvhConfig {
+    // How the shapes are split into nodes.
+    pub builder: BvhBuilder,
+    // Shapes per leaf at most, tested one after the other.
+    pub leaf_size: usize,
+}
+
+impl Default for BvhConfig {
+    fn default() -> BvhConfig {
+        BvhConfig { builder: BvhBuilder::Median, leaf_size: DEFAULT_LEAF_SIZE }
+    }
+}
+
+// How BVHs split their shapes into nodes. The trees differ in how fast rays go through them, never in what the
+// rays hit.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BvhBuilder {
+    // Sorts the shapes along a random axis and splits them in half.
+    Median,
+    // Splits where the surface area heuristic expects the fewest box and shape tests: a ray that hits a node hits
+    // its children about as often as their surface areas are a part of its own. Slower to build, faster to trace.
+    Sah,
+    // Sorts the shapes along a Morton curve through their middles with a radix sort and splits them where their
+    // codes first differ, in time linear in their number: for millions of shapes, when building takes longer
+    // than rendering.
+    Lbvh,
+}
+
+impl std::str::FromStr for BvhBuilder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BvhBuilder, String> {
+        match s {
+            "median" => Ok(BvhBuilder::Median),
+            "sah" => Ok(BvhBuilder::Sah),
+            "lbvh" => Ok(BvhBuilder::Lbvh),
+            _ => Err(format!("unknown BVH builder '{}'", s)),
+        }
+    }
+}
+
+// Nanoseconds spent building BVHs, summed over the threads that built them, when logged verbosely.
+static BUILD_TIME: AtomicU64 = AtomicU64::new(0);
+
+// The time spent building BVHs so far, e.g. to tell it apart from the rest of building a world.
+pub fn build_time() -> Duration {
+    Duration::from_nanos(BUILD_TIME.load(AtomicOrdering::Relaxed))
 }
 
 // Bounded Volume Hierarchy
 pub struct BHV<'a> {
-    root: Node<'a>,
+    // Children before their parents, the root last.
+    nodes: Vec<Node<'a>>,
 }
 
 impl<'a> BHV<'a> {
+    // Built as the config of `scene` says. The tree draws its random numbers from a generator of its own, seeded
+    // by one number from `rng`, so the rest of the world comes out the same whatever the config.
     pub fn new<'b>(scene: &'b mut SceneBuilder<'a>, rng: &mut dyn rand::RngCore) -> BHV<'a> {
-        let root = Node::new(scene.contents.as_mut_slice(), rng);
-        scene.contents.clear();
-        BHV { root }
+        let BvhConfig { builder, leaf_size } = scene.config;
+        // Timed only when logged, like `logging::timed`.
+        let start = logging::enabled(Level::Verbose).then(Instant::now);
+        let mut shapes: Vec<Entry<'a>> = scene.contents.drain(..).enumerate().collect();
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(rng.next_u64());
+        let leaf_size = leaf_size.max(1);
+        let mut nodes = Vec::new();
+        match builder {
+            BvhBuilder::Median => Node::median(&mut nodes, shapes.as_mut_slice(), leaf_size, &mut rng),
+            BvhBuilder::Sah => {
+                let mut items = boxes(&shapes);
+                Node::sah(&mut nodes, &mut shapes, &mut items, leaf_size)
+            }
+            BvhBuilder::Lbvh => {
+                let items = boxes(&shapes);
+                Node::lbvh(&mut nodes, &mut shapes, items, leaf_size)
+            }
+        };
+        if let Some(start) = start {
+            let elapsed = start.elapsed();
+            BUILD_TIME.fetch_add(elapsed.as_nanos() as u64, AtomicOrdering::Relaxed);
+            if logging::enabled(Level::Trace) {
+                logging::trace(&format!("BVH of {} shapes built in {:.3}s", shapes.len(), elapsed.as_secs_f64()));
+            }
+        }
+        BHV { nodes }
+    }
+
+    fn root(&self) -> usize {
+        self.nodes.len() - 1
+    }
+
+    // Recomputes the bounds of the nodes from the current bounding boxes of the shapes, keeping the tree.
+    // Much cheaper than building a new BVH, but the tree gets worse as shapes move away from where they were
+    // when it was built, so it is meant for small movements, e.g. between the frames of an animation.
+    pub fn refit(&mut self) {
+        for k in 0..self.nodes.len() {
+            let children = match &self.nodes[k] {
+                Node::Inner { bounds: _, left, right } => {
+                    Some(self.nodes[*left].bounding_box().surround(&self.nodes[*right].bounding_box()))
+                }
+                _ => None,
+            };
+            match &mut self.nodes[k] {
+                Node::Leaf { shape: _, index: _ } => {}
+                Node::List { bounds, shapes } => {
+                    *bounds = shapes.iter().fold(AABB::EMPTY, |b, (_, shape)| b.surround(&shape.bounding_box()));
+                }
+                Node::Inner { bounds, left: _, right: _ } => *bounds = children.unwrap(),
+            }
+        }
+    }
+
+    // Calls `update` with each shape and its index in the order it was added to the `SceneBuilder`, e.g. to
+    // replace it with a moved copy, then refits.
+    pub fn update(&mut self, mut update: impl FnMut(usize, &mut Box<dyn Bounded + 'a>)) {
+        for node in self.nodes.iter_mut() {
+            match node {
+                Node::Leaf { shape, index: Some(index) } => update(*index, shape),
+                Node::List { bounds: _, shapes } => shapes.iter_mut().for_each(|(index, shape)| update(*index, shape)),
+                _ => {}
+            }
+        }
+        self.refit();
+    }
+
+    fn inspect_node(&self, node: usize, stats: &mut SceneStats, depth: usize) {
+        stats.bvh_nodes += 1;
+        stats.bvh_max_depth = stats.bvh_max_depth.max(depth);
+        stats.bvh_memory += std::mem::size_of::<Node>();
+        match &self.nodes[node] {
+            Node::Leaf { shape, index: _ } => {
+                stats.bvh_leaves += 1;
+                shape.inspect(stats);
+            }
+            Node::List { bounds: _, shapes } => {
+                stats.bvh_leaves += 1;
+                shapes.iter().for_each(|(_, shape)| shape.inspect(stats));
+            }
+            Node::Inner { left, right, bounds: _ } => {
+                self.inspect_node(*left, stats, depth + 1);
+                self.inspect_node(*right, stats, depth + 1);
+            }
+        }
     }
 }
 
+// The traversals go through the nodes with a stack from `scratch` instead of recursion, left child first.
 impl<'b> Hittable for BHV<'b> {
-    fn hit<'a>(&'a self, r: &Ray, tmin: f64, tmax: f64, rng: &mut dyn rand::RngCore) -> Option<Hit<'a>> {
-        self.root.hit(r, tmin, tmax, rng)
+    fn hit<'a>(&'a self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> Option<Hit<'a>> {
+        let mut result: Option<Hit> = None;
+        let mut stack = Scratch::<usize>::take();
+        stack.push(self.root());
+        while let Some(node) = stack.pop() {
+            let node_t = ray_t.with_max(result.as_ref().map_or(ray_t.max, |h| h.t));
+            match &self.nodes[node] {
+                Node::Leaf { shape, index: _ } => {
+                    if let Some(h) = shape.hit(r, node_t, rng) {
+                        result = Some(h);
+                    }
+                }
+                Node::List { bounds, shapes } => {
+                    if !bounds.hit(r, node_t) {
+                        continue;
+                    }
+                    for (_, shape) in shapes.iter() {
+                        let closest_so_far = result.as_ref().map_or(ray_t.max, |h| h.t);
+                        if let Some(h) = shape.hit(r, ray_t.with_max(closest_so_far), rng) {
+                            result = Some(h);
+                        }
+                    }
+                }
+                Node::Inner { bounds, left, right } => {
+                    if bounds.hit(r, node_t) {
+                        stack.push(*right);
+                        stack.push(*left);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> bool {
+        let mut stack = Scratch::<usize>::take();
+        stack.push(self.root());
+        while let Some(node) = stack.pop() {
+            match &self.nodes[node] {
+                Node::Leaf { shape, index: _ } => {
+                    if shape.hit_any(r, ray_t, rng) {
+                        return true;
+                    }
+                }
+                Node::List { bounds, shapes } => {
+                    if bounds.hit(r, ray_t) && shapes.iter().any(|(_, shape)| shape.hit_any(r, ray_t, rng)) {
+                        return true;
+                    }
+                }
+                Node::Inner { bounds, left, right } => {
+                    if bounds.hit(r, ray_t) {
+                        stack.push(*right);
+                        stack.push(*left);
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        self.inspect_node(self.root(), stats, 1);
+    }
+
+    // The leaves come in the same order in `nodes` as from left to right in the tree.
+    fn export(&self, scene: &mut SceneWriter) -> Result<(), String> {
+        self.nodes.iter().try_for_each(|node| match node {
+            Node::Leaf { shape, index: _ } => shape.export(scene),
+            Node::List { bounds: _, shapes } => shapes.iter().try_for_each(|(_, shape)| shape.export(scene)),
+            Node::Inner { left: _, right: _, bounds: _ } => Ok(()),
+        })
     }
 }
 
 impl<'b> Bounded for BHV<'b> {
     fn bounding_box(&self) -> AABB {
-        self.root.bounding_box()
+        self.nodes[self.root()].bounding_box()
+    }
+}
+
+// Named part of a scene, e.g. the ground of FinalScene, kept in its own BVH. The parent BVH skips the whole group
+// when a ray misses its box, and `--validate` reports its object count under its name.
+pub struct Group<'a> {
+    name: String,
+    bhv: BHV<'a>,
+}
+
+impl<'a> Group<'a> {
+    pub fn new(name: &str, mut scene: SceneBuilder<'a>, rng: &mut dyn rand::RngCore) -> Group<'a> {
+        Group { name: name.to_string(), bhv: BHV::new(&mut scene, rng) }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<'b> Hittable for Group<'b> {
+    fn hit<'a>(&'a self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> Option<Hit<'a>> {
+        self.bhv.hit(r, ray_t, rng)
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> bool {
+        self.bhv.hit_any(r, ray_t, rng)
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        let mut group = SceneStats::new();
+        self.bhv.inspect(&mut group);
+        let objects = group.objects;
+        stats.merge(group, |b| b);
+        stats.groups.push((self.name.clone(), objects));
+    }
+
+    // Scene files have no groups, only their shapes.
+    fn export(&self, scene: &mut SceneWriter) -> Result<(), String> {
+        self.bhv.export(scene)
+    }
+}
+
+impl<'b> Bounded for Group<'b> {
+    fn bounding_box(&self) -> AABB {
+        self.bhv.bounding_box()
+    }
+}
+
+// A shape with its index in the `SceneBuilder`.
+type Entry<'a> = (usize, Option<Box<dyn Bounded + 'a>>);
+
+// The bounding box of every shape with its position in `shapes`, for the builders that look at them more than once.
+fn boxes(shapes: &[Entry]) -> Vec<(usize, AABB)> {
+    shapes.iter().enumerate().map(|(k, (_, shape))| (k, shape.as_ref().unwrap().bounding_box())).collect()
+}
+
+// Bins of the surface area heuristic along each axis: more find better splits, but take longer to compare.
+const SAH_BINS: usize = 12;
+
+// Bits of a Morton code per axis.
+const MORTON_BITS: u32 = 10;
+
+// The bits of `x` spread out to every third bit.
+fn spread_bits(x: u32) -> u32 {
+    let mut x = x & 0x3ff;
+    x = (x | (x << 16)) & 0x030000ff;
+    x = (x | (x << 8)) & 0x0300f00f;
+    x = (x | (x << 4)) & 0x030c30c3;
+    (x | (x << 2)) & 0x09249249
+}
+
+// Position of `p` along a Morton curve through `bounds`: the bits of its coordinates in the box, interleaved, so
+// that points close on the curve are close in space.
+fn morton_code(p: Point3, bounds: &AABB) -> u32 {
+    let scale = ((1 << MORTON_BITS) - 1) as f64;
+    let coordinate = |a: usize| {
+        let axis = bounds.axis(a);
+        let x = if axis.size() > 0.0 { (p.e[a] - axis.min) / axis.size() } else { 0.5 };
+        (x.clamp(0.0, 1.0) * scale) as u32
+    };
+    (spread_bits(coordinate(0)) << 2) | (spread_bits(coordinate(1)) << 1) | spread_bits(coordinate(2))
+}
+
+// Least significant byte first: four passes over the items, linear in their number unlike comparison sorts.
+fn radix_sort<T: Copy>(items: &mut Vec<(u32, T)>) {
+    let mut sorted = items.clone();
+    for shift in (0..32).step_by(8) {
+        let digit = |code: u32| ((code >> shift) & 0xff) as usize;
+        let mut starts = [0usize; 257];
+        for (code, _) in items.iter() {
+            starts[digit(*code) + 1] += 1;
+        }
+        for d in 1..starts.len() {
+            starts[d] += starts[d - 1];
+        }
+        for item in items.iter() {
+            let d = digit(item.0);
+            sorted[starts[d]] = *item;
+            starts[d] += 1;
+        }
+        std::mem::swap(items, &mut sorted);
     }
 }
 
 enum Node<'a> {
-    Leaf { shape: Box<dyn Bounded + 'a> },
-    Inner { bounds: AABB, left: Box<Node<'a>>, right: Box<Node<'a>> },
+    // `index` is None for the placeholder in an empty BVH.
+    Leaf { shape: Box<dyn Bounded + 'a>, index: Option<usize> },
+    // Up to the leaf size of shapes, with their indices.
+    List { bounds: AABB, shapes: Vec<(usize, Box<dyn Bounded + 'a>)> },
+    // Positions of the children in `BHV::nodes`.
+    Inner { bounds: AABB, left: usize, right: usize },
 }
 
+// The builders add the nodes of the tree they build to `nodes` after those of their children and return the position
+// of the root.
 impl<'a> Node<'a> {
     fn bounding_box(&self) -> AABB {
         match self {
-            Node::Leaf { shape } => shape.bounding_box(),
+            Node::Leaf { shape, index: _ } => shape.bounding_box(),
+            Node::List { bounds, shapes: _ } => *bounds,
             Node::Inner { bounds, left: _, right: _ } => *bounds,
         }
     }
 
-    fn new<'b>(shapes: &'b mut [Option<Box<dyn Bounded + 'a>>], rng: &mut dyn rand::RngCore) -> Node<'a> {
-        match shapes {
-            [] => Node::Leaf { shape: Box::new(shapes::Empty::INSTANCE) },
-            [v] => Node::Leaf { shape: v.take().unwrap() },
+    fn push(nodes: &mut Vec<Node<'a>>, node: Node<'a>) -> usize {
+        nodes.push(node);
+        nodes.len() - 1
+    }
+
+    // A leaf with `items`, given by their positions in `shapes`.
+    fn leaf(nodes: &mut Vec<Node<'a>>, shapes: &mut [Entry<'a>], items: &[(usize, AABB)]) -> usize {
+        let mut taken: Vec<(usize, Box<dyn Bounded + 'a>)> =
+            items.iter().map(|&(k, _)| (shapes[k].0, shapes[k].1.take().unwrap())).collect();
+        let node = match taken.len() {
+            0 => Node::Leaf { shape: Box::new(shapes::Empty::INSTANCE), index: None },
+            1 => {
+                let (index, shape) = taken.pop().unwrap();
+                Node::Leaf { shape, index: Some(index) }
+            }
+            _ => {
+                let bounds = items.iter().fold(AABB::EMPTY, |b, (_, item)| b.surround(item));
+                Node::List { bounds, shapes: taken }
+            }
+        };
+        Node::push(nodes, node)
+    }
+
+    fn inner(nodes: &mut Vec<Node<'a>>, left: usize, right: usize) -> usize {
+        let bounds = nodes[left].bounding_box().surround(&nodes[right].bounding_box());
+        Node::push(nodes, Node::Inner { left, right, bounds })
+    }
+
+    // See `BvhBuilder::Sah`. Splits along the axis and between the bins of the middles of `items` where the
+    // sums of the surface areas of both sides times their shape counts are least.
+    fn sah(
+        nodes: &mut Vec<Node<'a>>,
+        shapes: &mut [Entry<'a>],
+        items: &mut [(usize, AABB)],
+        leaf_size: usize,
+    ) -> usize {
+        if items.len() <= leaf_size {
+            return Node::leaf(nodes, shapes, items);
+        }
+        let centers =
+            items.iter().fold(AABB::EMPTY, |b, (_, item)| b.surround(&AABB::new(item.center(), item.center())));
+        let bin = |a: usize, item: &AABB| {
+            let axis = centers.axis(a);
+            let x = (item.center().e[a] - axis.min) / axis.size();
+            ((x * SAH_BINS as f64) as usize).min(SAH_BINS - 1)
+        };
+        // The axis, the number of bins on the left and the cost.
+        let mut best: Option<(usize, usize, f64)> = None;
+        for a in (0..3).filter(|&a| centers.axis(a).size() > 0.0) {
+            let mut bins = [(AABB::EMPTY, 0usize); SAH_BINS];
+            for (_, item) in items.iter() {
+                let (bounds, count) = &mut bins[bin(a, item)];
+                *bounds = bounds.surround(item);
+                *count += 1;
+            }
+            // Areas and counts of the bins left of every split, then the cost with those right of it.
+            let mut left = Vec::with_capacity(SAH_BINS);
+            let (mut bounds, mut count) = (AABB::EMPTY, 0);
+            for (b, c) in bins.iter() {
+                bounds = bounds.surround(b);
+                count += c;
+                left.push((bounds.surface_area(), count));
+            }
+            let (mut bounds, mut count) = (AABB::EMPTY, 0);
+            for split in (1..SAH_BINS).rev() {
+                bounds = bounds.surround(&bins[split].0);
+                count += bins[split].1;
+                let cost = left[split - 1].0 * left[split - 1].1 as f64 + bounds.surface_area() * count as f64;
+                if left[split - 1].1 > 0 && count > 0 && best.is_none_or(|(_, _, c)| cost < c) {
+                    best = Some((a, split, cost));
+                }
+            }
+        }
+        let middle = match best {
+            Some((a, split, _)) => {
+                items.sort_by_key(|(_, item)| bin(a, item));
+                items.partition_point(|(_, item)| bin(a, item) < split)
+            }
+            // All the middles are at the same point.
+            None => items.len() / 2,
+        };
+        let (left, right) = items.split_at_mut(middle);
+        let left = Node::sah(nodes, shapes, left, leaf_size);
+        let right = Node::sah(nodes, shapes, right, leaf_size);
+        Node::inner(nodes, left, right)
+    }
+
+    // See `BvhBuilder::Lbvh`.
+    fn lbvh(nodes: &mut Vec<Node<'a>>, shapes: &mut [Entry<'a>], items: Vec<(usize, AABB)>, leaf_size: usize) -> usize {
+        let centers =
+            items.iter().fold(AABB::EMPTY, |b, (_, item)| b.surround(&AABB::new(item.center(), item.center())));
+        let mut coded: Vec<(u32, (usize, AABB))> =
+            items.into_iter().map(|item| (morton_code(item.1.center(), &centers), item)).collect();
+        radix_sort(&mut coded);
+        Node::lbvh_sorted(nodes, shapes, &coded, leaf_size)
+    }
+
+    // Splits shapes sorted by their Morton codes at the first bit where the codes differ, so each side covers a
+    // half of the space in the other's box, or in the middle if they are all the same.
+    fn lbvh_sorted(
+        nodes: &mut Vec<Node<'a>>,
+        shapes: &mut [Entry<'a>],
+        coded: &[(u32, (usize, AABB))],
+        leaf_size: usize,
+    ) -> usize {
+        if coded.len() <= leaf_size {
+            let items: Vec<(usize, AABB)> = coded.iter().map(|&(_, item)| item).collect();
+            return Node::leaf(nodes, shapes, &items);
+        }
+        let (first, last) = (coded[0].0, coded[coded.len() - 1].0);
+        let middle = if first == last {
+            coded.len() / 2
+        } else {
+            let common = (first ^ last).leading_zeros();
+            coded.partition_point(|&(code, _)| (code ^ first).leading_zeros() > common)
+        };
+        let (left, right) = coded.split_at(middle);
+        let left = Node::lbvh_sorted(nodes, shapes, left, leaf_size);
+        let right = Node::lbvh_sorted(nodes, shapes, right, leaf_size);
+        Node::inner(nodes, left, right)
+    }
+
+    // See `BvhBuilder::Median`.
+    fn median(
+        nodes: &mut Vec<Node<'a>>,
+        shapes: &mut [Entry<'a>],
+        leaf_size: usize,
+        rng: &mut dyn rand::RngCore,
+    ) -> usize {
+        let node = match shapes {
+            [] => Node::Leaf { shape: Box::new(shapes::Empty::INSTANCE), index: None },
+            [(index, v)] => Node::Leaf { shape: v.take().unwrap(), index: Some(*index) },
+            _ if shapes.len() <= leaf_size => {
+                let shapes: Vec<_> = shapes.iter_mut().map(|(index, v)| (*index, v.take().unwrap())).collect();
+                let bounds = shapes.iter().fold(AABB::EMPTY, |b, (_, shape)| b.surround(&shape.bounding_box()));
+                Node::List { bounds, shapes }
+            }
             _ => {
                 let axis = rng.gen_range(0..3);
-                let get_dim = |a: &Option<Box<dyn Bounded + 'a>>| a.as_ref().unwrap().bounding_box().minimum.e[axis];
-                let comparator = |a: &Option<Box<dyn Bounded>>, b: &Option<Box<dyn Bounded>>| match get_dim(a)
-                    .partial_cmp(&get_dim(b))
-                {
+                let get_dim = |a: &Entry<'a>| a.1.as_ref().unwrap().bounding_box().minimum.e[axis];
+                let comparator = |a: &Entry<'a>, b: &Entry<'a>| match get_dim(a).partial_cmp(&get_dim(b)) {
                     Some(ordering) => ordering,
                     None => Ordering::Equal,
                 };
@@ -136,32 +674,12 @@ impl<'a> Node<'a> {
                 shapes.sort_by(comparator);
                 let (left_shapes, right_shapes) = shapes.split_at_mut(shapes.len() / 2);
 
-                let left = Box::new(Node::new(left_shapes, rng));
-                let right = Box::new(Node::new(right_shapes, rng));
-                let bounds = left.bounding_box().surround(&right.bounding_box());
-                Node::Inner { left, right, bounds }
-            }
-        }
-    }
-
-    fn hit<'b>(&'b self, r: &Ray, tmin: f64, tmax: f64, rng: &mut dyn rand::RngCore) -> Option<Hit<'b>> {
-        match self {
-            Node::Leaf { shape } => shape.hit(r, tmin, tmax, rng),
-            Node::Inner { left, right, bounds } => {
-                if !bounds.hit(r, tmin, tmax) {
-                    return None;
-                }
-                let hit_left = left.hit(r, tmin, tmax, rng);
-                let tmax_for_right = match hit_left.as_ref() {
-                    Some(h) => h.t,
-                    None => tmax,
-                };
-                match right.hit(r, tmin, tmax_for_right, rng) {
-                    None => hit_left,
-                    hit_right => hit_right,
-                }
+                let left = Node::median(nodes, left_shapes, leaf_size, rng);
+                let right = Node::median(nodes, right_shapes, leaf_size, rng);
+                return Node::inner(nodes, left, right);
             }
-        }
+        };
+        Node::push(nodes, node)
     }
 }
 
@@ -175,8 +693,8 @@ mod aabb_tests {
         let aabb = AABB::new(Point3::new(1.0, 1.0, 1.0), Point3::new(2.0, 2.0, 2.0));
         let aabb_rev = AABB::new(aabb.max(), aabb.min());
         let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
-        assert_eq!(true, aabb.hit(&r, 0.0, f64::INFINITY));
-        assert_eq!(true, aabb_rev.hit(&r, 0.0, f64::INFINITY));
+        assert_eq!(true, aabb.hit(&r, Interval::FORWARD));
+        assert_eq!(true, aabb_rev.hit(&r, Interval::FORWARD));
     }
 
     #[test]
@@ -185,8 +703,8 @@ mod aabb_tests {
         let aabb_rev = AABB::new(aabb.max(), aabb.min());
         // Ray in Y-direction from projection of a minimum to XZ plane, inside the cube.
         let r = Ray::new(Point3::new(1.0001, 0.0, 1.0001), Vec3::new(0.0, 1.0, 0.0));
-        assert_eq!(true, aabb.hit(&r, 0.0, f64::INFINITY));
-        assert_eq!(true, aabb_rev.hit(&r, 0.0, f64::INFINITY));
+        assert_eq!(true, aabb.hit(&r, Interval::FORWARD));
+        assert_eq!(true, aabb_rev.hit(&r, Interval::FORWARD));
     }
 
     #[test]
@@ -195,8 +713,8 @@ mod aabb_tests {
         let aabb_rev = AABB::new(aabb.max(), aabb.min());
         // Ray in Y-direction from projection of a minimum to XZ plane, outside the cube.
         let r = Ray::new(Point3::new(0.99999, 0.0, 0.9999), Vec3::new(0.0, 1.0, 0.0));
-        assert_eq!(false, aabb.hit(&r, 0.0, f64::INFINITY));
-        assert_eq!(false, aabb_rev.hit(&r, 0.0, f64::INFINITY));
+        assert_eq!(false, aabb.hit(&r, Interval::FORWARD));
+        assert_eq!(false, aabb_rev.hit(&r, Interval::FORWARD));
     }
 
     #[test]
@@ -205,8 +723,8 @@ mod aabb_tests {
         let aabb_rev = AABB::new(aabb.max(), aabb.min());
         // Ray in Y-direction from projection of a center of an edge to XZ plane, inside the cube.
         let r = Ray::new(Point3::new(1.5, 0.0, 1.0001), Vec3::new(0.0, 3.0, 0.0));
-        assert_eq!(true, aabb.hit(&r, 0.0, f64::INFINITY));
-        assert_eq!(true, aabb_rev.hit(&r, 0.0, f64::INFINITY));
+        assert_eq!(true, aabb.hit(&r, Interval::FORWARD));
+        assert_eq!(true, aabb_rev.hit(&r, Interval::FORWARD));
     }
 
     #[test]
@@ -215,7 +733,185 @@ mod aabb_tests {
         let aabb_rev = AABB::new(aabb.max(), aabb.min());
         // Ray in Y-direction from projection of a center of an edge to XZ plane, outside the cube.
         let r = Ray::new(Point3::new(1.5, 0.0, 0.9999), Vec3::new(0.0, 3.0, 0.0));
-        assert_eq!(false, aabb.hit(&r, 0.0, f64::INFINITY));
-        assert_eq!(false, aabb_rev.hit(&r, 0.0, f64::INFINITY));
+        assert_eq!(false, aabb.hit(&r, Interval::FORWARD));
+        assert_eq!(false, aabb_rev.hit(&r, Interval::FORWARD));
+    }
+
+    #[test]
+    fn test_pad() {
+        let flat = AABB::new(Point3::new(0.0, 0.0, 1.0), Point3::new(1.0, 1.0, 1.0));
+        let r = Ray::new(Point3::new(0.5, 0.5, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(false, flat.hit(&r, Interval::FORWARD));
+        let padded = flat.pad();
+        assert_eq!(true, padded.hit(&r, Interval::FORWARD));
+        assert_eq!(flat.axis(0), padded.axis(0));
+        assert!((padded.axis(2).size() - MIN_THICKNESS).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_empty() {
+        let aabb = AABB::new(Point3::new(1.0, 1.0, 1.0), Point3::new(2.0, 2.0, 2.0));
+        let surrounding = AABB::EMPTY.surround(&aabb);
+        assert_eq!(aabb.min(), surrounding.min());
+        assert_eq!(aabb.max(), surrounding.max());
+        assert!(AABB::EMPTY.translate(Vec3::ONE).pad().is_empty());
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+        assert_eq!(false, AABB::EMPTY.hit(&r, Interval::UNIVERSE));
+    }
+}
+
+#[cfg(test)]
+mod bhv_tests {
+    use super::*;
+    use crate::materials::Lambertian;
+    use crate::shapes::{Sphere, XZRect};
+    use crate::textures::SolidColor;
+    use crate::vec::Vec3;
+
+    #[test]
+    fn test_hit_any_agrees_with_hit() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(42);
+        let material = Lambertian::new(SolidColor::new(0.5, 0.5, 0.5));
+        let mut scene = SceneBuilder::new();
+        for _ in 0..50 {
+            scene.add(Sphere::new(Point3::random(-10.0, 10.0, &mut rng), 0.5, material));
+        }
+        scene.add(XZRect::new(-10.0, 10.0, -10.0, 10.0, -10.0, material));
+        let bhv = BHV::new(&mut scene, &mut rng);
+
+        for _ in 0..1000 {
+            let r = Ray::new(Point3::random(-12.0, 12.0, &mut rng), Vec3::random(-1.0, 1.0, &mut rng));
+            let t_max = rng.gen_range(0.0..30.0);
+            let expected = bhv.hit(&r, Interval::new(0.0, t_max), &mut rng).is_some();
+            assert_eq!(expected, bhv.hit_any(&r, Interval::new(0.0, t_max), &mut rng));
+        }
+    }
+
+    #[test]
+    fn test_groups() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(42);
+        let material = Lambertian::new(SolidColor::new(0.5, 0.5, 0.5));
+        let mut spheres = SceneBuilder::new();
+        spheres.add(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, material));
+        let mut more = SceneBuilder::new();
+        more.add(Sphere::new(Point3::new(0.0, 0.0, -4.0), 1.0, material));
+        spheres.merge(more);
+        let mut scene = SceneBuilder::new();
+        scene.group("spheres", spheres, &mut rng).add(Sphere::new(Point3::new(0.0, 5.0, 0.0), 1.0, material));
+        let bhv = BHV::new(&mut scene, &mut rng);
+
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(4.0, bhv.hit(&r, Interval::FORWARD, &mut rng).unwrap().t);
+        assert_eq!(-5.0, bhv.bounding_box().min().z());
+
+        let mut stats = SceneStats::new();
+        bhv.inspect(&mut stats);
+        assert_eq!(3, stats.objects);
+        assert_eq!(vec![("spheres".to_string(), 2)], stats.groups);
+    }
+
+    #[test]
+    fn test_update_refits() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(42);
+        let material = Lambertian::new(SolidColor::new(0.5, 0.5, 0.5));
+        let mut scene = SceneBuilder::new();
+        for i in 0..8 {
+            scene.add(Sphere::new(Point3::new(3.0 * i as f64, 0.0, 0.0), 1.0, material));
+        }
+        let mut bhv = BHV::new(&mut scene, &mut rng);
+        let r = Ray::new(Point3::new(9.0, 10.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert_eq!(9.0, bhv.hit(&r, Interval::FORWARD, &mut rng).unwrap().t);
+
+        // Lift the sphere at x = 9 by 5.
+        let mut seen = Vec::new();
+        bhv.update(|index, shape| {
+            seen.push(index);
+            if index == 3 {
+                *shape = Box::new(Sphere::new(Point3::new(9.0, 5.0, 0.0), 1.0, material));
+            }
+        });
+        seen.sort_unstable();
+        assert_eq!((0..8).collect::<Vec<_>>(), seen);
+        assert_eq!(4.0, bhv.hit(&r, Interval::FORWARD, &mut rng).unwrap().t);
+        assert_eq!(6.0, bhv.bounding_box().max().y());
+    }
+
+    #[test]
+    fn test_leaf_sizes_agree() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(42);
+        let material = Lambertian::new(SolidColor::new(0.5, 0.5, 0.5));
+        let spheres: Vec<Sphere<_>> =
+            (0..100).map(|_| Sphere::new(Point3::random(-10.0, 10.0, &mut rng), 0.5, material)).collect();
+        let build = |leaf_size: usize, rng: &mut rand_pcg::Pcg64| {
+            let mut scene = SceneBuilder::with_config(BvhConfig { leaf_size, ..BvhConfig::default() });
+            for sphere in spheres.iter() {
+                scene.add(sphere.clone());
+            }
+            BHV::new(&mut scene, rng)
+        };
+        let (single, lists) = (build(1, &mut rng), build(4, &mut rng));
+        let (mut single_stats, mut lists_stats) = (SceneStats::new(), SceneStats::new());
+        single.inspect(&mut single_stats);
+        lists.inspect(&mut lists_stats);
+        assert_eq!(100, single_stats.bvh_leaves);
+        assert!(lists_stats.bvh_leaves <= 100 / 4 * 2, "{}", lists_stats.bvh_leaves);
+        assert_eq!(100, lists_stats.objects);
+
+        for _ in 0..1000 {
+            let r = Ray::new(Point3::random(-12.0, 12.0, &mut rng), Vec3::random(-1.0, 1.0, &mut rng));
+            let t = |bhv: &BHV| bhv.hit(&r, Interval::FORWARD, &mut rand_pcg::Pcg64::seed_from_u64(0)).map(|h| h.t);
+            assert_eq!(t(&single), t(&lists));
+            assert_eq!(t(&lists).is_some(), lists.hit_any(&r, Interval::FORWARD, &mut rng));
+        }
+    }
+
+    #[test]
+    fn test_builders_agree() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(7);
+        let material = Lambertian::new(SolidColor::new(0.5, 0.5, 0.5));
+        let mut spheres: Vec<Sphere<_>> =
+            (0..300).map(|_| Sphere::new(Point3::random(-10.0, 10.0, &mut rng), 0.3, material)).collect();
+        // Some in the same place, which all builders must still split.
+        spheres.extend((0..20).map(|_| Sphere::new(Point3::new(1.0, 1.0, 1.0), 0.3, material)));
+        let build = |builder: BvhBuilder, rng: &mut rand_pcg::Pcg64| {
+            let mut scene = SceneBuilder::with_config(BvhConfig { builder, leaf_size: 2 });
+            for sphere in spheres.iter() {
+                scene.add(sphere.clone());
+            }
+            BHV::new(&mut scene, rng)
+        };
+        let bhvs = [BvhBuilder::Median, BvhBuilder::Sah, BvhBuilder::Lbvh].map(|b| build(b, &mut rng));
+        for bhv in bhvs.iter() {
+            let mut stats = SceneStats::new();
+            bhv.inspect(&mut stats);
+            assert_eq!(320, stats.objects);
+            assert!(stats.bvh_leaves >= 160, "{}", stats.bvh_leaves);
+        }
+
+        for _ in 0..1000 {
+            let r = Ray::new(Point3::random(-12.0, 12.0, &mut rng), Vec3::random(-1.0, 1.0, &mut rng));
+            let t = |bhv: &BHV| bhv.hit(&r, Interval::FORWARD, &mut rand_pcg::Pcg64::seed_from_u64(0)).map(|h| h.t);
+            assert_eq!(t(&bhvs[0]), t(&bhvs[1]));
+            assert_eq!(t(&bhvs[0]), t(&bhvs[2]));
+        }
+        assert_eq!(Ok(BvhBuilder::Lbvh), "lbvh".parse());
+        assert!("kd".parse::<BvhBuilder>().is_err());
+    }
+
+    #[test]
+    fn test_morton_order() {
+        let bounds = AABB::new(Point3::ZERO, Point3::new(1.0, 1.0, 1.0));
+        assert_eq!(0, morton_code(Point3::ZERO, &bounds));
+        assert_eq!(0x3fffffff, morton_code(Point3::new(1.0, 1.0, 1.0), &bounds));
+        // x is the most significant bit of every triple.
+        assert_eq!(4, morton_code(Point3::new(1.0 / 1023.0, 0.0, 0.0), &bounds));
+        assert_eq!(1, morton_code(Point3::new(0.0, 0.0, 1.0 / 1023.0), &bounds));
+
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(7);
+        let mut codes: Vec<(u32, usize)> = (0..1000).map(|k| (rng.gen::<u32>(), k)).collect();
+        let mut expected = codes.clone();
+        expected.sort_by_key(|&(code, _)| code);
+        radix_sort(&mut codes);
+        assert_eq!(expected, codes);
     }
 }
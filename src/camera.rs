@@ -1,14 +1,88 @@
+use crate::hittable::Hittable;
+use crate::raytrace::{visible_hit, RayKind};
 use crate::vec::{Point3, Ray, Vec3};
 
+// Everything needed to construct a camera; kept around when the camera needs to be rebuilt, e.g. when it moves.
+#[derive(Copy, Clone)]
+pub struct CameraParams {
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub up: Vec3,
+    pub field_of_view: f64, // degrees, (0..180)
+    pub aspect_ratio: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+    // Horizontal offset of the image plane, in image widths, for off-axis projections.
+    pub shift: f64,
+    // Clipping distances along the viewing direction, see `Camera::clipped`.
+    pub near: f64,
+    pub far: f64,
+}
+
+impl CameraParams {
+    pub fn camera(&self) -> Camera {
+        Camera::new(
+            self.lookfrom,
+            self.lookat,
+            self.up,
+            self.field_of_view,
+            self.aspect_ratio,
+            self.aperture,
+            self.focus_dist,
+        )
+        .shifted(self.shift)
+        .clipped(self.near, self.far)
+    }
+
+    // Distance to focus at to get pixel (x, y) of an image `width` x `height` (counted from the top left) sharp:
+    // that of the first hit along the ray through its center that camera rays see, measured along the viewing
+    // direction. None if the ray hits nothing it shows.
+    pub fn autofocus(
+        &self,
+        world: &dyn Hittable,
+        (x, y): (f64, f64),
+        (width, height): (usize, usize),
+        rng: &mut dyn rand::RngCore,
+    ) -> Option<f64> {
+        let pinhole = CameraParams { aperture: 0.0, ..*self }.camera();
+        let s = (x + 0.5) / (width as f64 - 1.0);
+        let t = (height as f64 - 1.0 - y + 0.5) / (height as f64 - 1.0);
+        let r = pinhole.get_ray(s, t, rng);
+        // What the pixel shows, as the tracer finds it for camera rays.
+        let h = visible_hit(world, &r, RayKind::Camera, rng).filter(|h| !pinhole.beyond_far(h.p, r.time))?;
+        Some((h.p - self.lookfrom).dot((self.lookat - self.lookfrom).unit()))
+    }
+
+    // One eye of a stereo pair, moved `offset` to the right (negative: left) of this camera. Both eyes look in
+    // the same direction, with their image planes shifted so that their views coincide at distance `convergence`.
+    pub fn stereo_eye(&self, offset: f64, convergence: f64) -> CameraParams {
+        let w = (self.lookfrom - self.lookat).unit();
+        let right = offset * self.up.cross(w).unit();
+        let viewport_width = 2.0 * (self.field_of_view.to_radians() / 2.0).tan() * self.aspect_ratio;
+        CameraParams {
+            lookfrom: self.lookfrom + right,
+            lookat: self.lookat + right,
+            shift: self.shift - offset / (convergence * viewport_width),
+            ..*self
+        }
+    }
+}
+
 pub struct Camera {
     origin: Point3,
     lower_left_corner: Point3,
+    // From the origin to the lower left corner, for pinhole rays.
+    to_corner: Vec3,
     horizontal: Vec3,
     vertical: Vec3,
     u: Vec3,
     v: Vec3,
-    //w: Vec3,
+    w: Vec3,
     lens_radius: f64,
+    near: f64,
+    far: f64,
+    // Where the camera is at the end of the motion, and the times it moves between; for motion blur.
+    end: Option<(Box<Camera>, f64, f64)>,
 }
 
 impl Camera {
@@ -34,16 +108,271 @@ impl Camera {
         let horizontal = focus_dist * viewport_width * u;
         let vertical = focus_dist * viewport_height * v;
         let lower_left_corner = origin - horizontal / 2.0 - vertical / 2.0 - focus_dist * w;
-        return Camera { origin, lower_left_corner, horizontal, vertical, u, v, lens_radius: aperture / 2.0 };
+        return Camera {
+            origin,
+            lower_left_corner,
+            to_corner: lower_left_corner - origin,
+            horizontal,
+            vertical,
+            u,
+            v,
+            w,
+            lens_radius: aperture / 2.0,
+            near: 0.0,
+            far: f64::INFINITY,
+            end: None,
+        };
+    }
+
+    // Moves the image plane sideways by `shift` image widths.
+    pub fn shifted(mut self, shift: f64) -> Camera {
+        self.lower_left_corner = self.lower_left_corner + shift * self.horizontal;
+        self.to_corner = self.lower_left_corner - self.origin;
+        self
+    }
+
+    // Camera rays only see what lies between `near` and `far` along the viewing direction, from the lens: they
+    // start at the near plane, cutting away everything in front of it, and see the background beyond the far
+    // one, see `beyond_far`. Bounces still reach everything.
+    pub fn clipped(mut self, near: f64, far: f64) -> Camera {
+        self.near = near;
+        self.far = far;
+        self
+    }
+
+    // Whether `p` lies beyond the far clipping plane for a ray cast at `time`.
+    pub fn beyond_far(&self, p: Point3, time: f64) -> bool {
+        if let Some((end, time0, time1)) = &self.end {
+            return self.lerp(end, Camera::fraction(time, *time0, *time1)).beyond_far(p, time);
+        }
+        (p - self.origin).dot(-self.w) > self.far
+    }
+
+    pub fn clips_far(&self) -> bool {
+        self.far < f64::INFINITY
+    }
+
+    // Moves the camera from here at `time0` to `end` at `time1`, blurring the motion while the shutter is open.
+    pub fn moving_to(mut self, end: Camera, time0: f64, time1: f64) -> Camera {
+        self.end = Some((Box::new(end), time0, time1));
+        self
+    }
+
+    // The camera a fraction `time` of the way to `end`.
+    fn lerp(&self, end: &Camera, time: f64) -> Camera {
+        let lerp = |a: Vec3, b: Vec3| (1.0 - time) * a + time * b;
+        let (origin, lower_left_corner) =
+            (lerp(self.origin, end.origin), lerp(self.lower_left_corner, end.lower_left_corner));
+        Camera {
+            origin,
+            lower_left_corner,
+            to_corner: lower_left_corner - origin,
+            horizontal: lerp(self.horizontal, end.horizontal),
+            vertical: lerp(self.vertical, end.vertical),
+            u: lerp(self.u, end.u).unit(),
+            v: lerp(self.v, end.v).unit(),
+            w: lerp(self.w, end.w).unit(),
+            lens_radius: (1.0 - time) * self.lens_radius + time * end.lens_radius,
+            near: self.near,
+            far: self.far,
+            end: None,
+        }
+    }
+
+    // How far `time` is from `time0` to `time1`, from 0 to 1.
+    fn fraction(time: f64, time0: f64, time1: f64) -> f64 {
+        if time1 > time0 {
+            ((time - time0) / (time1 - time0)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
     }
 
     pub fn get_ray(&self, s: f64, t: f64, rng: &mut dyn rand::RngCore) -> Ray {
-        let rd = self.lens_radius * Vec3::random_in_unit_disk(rng);
-        let offset = self.u * rd.x() + self.v * rd.y();
+        self.get_ray_at(s, t, 0.0, rng)
+    }
 
-        Ray {
-            orig: self.origin + offset,
-            dir: self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
+    // Ray cast at `time`, see `RenderingParams::shutter_open`.
+    pub fn get_ray_at(&self, s: f64, t: f64, time: f64, rng: &mut dyn rand::RngCore) -> Ray {
+        if let Some((end, time0, time1)) = &self.end {
+            return self.lerp(end, Camera::fraction(time, *time0, *time1)).get_ray_at(s, t, time, rng);
         }
+        // Without an aperture every ray starts at the origin; skip sampling the lens.
+        let ray = if self.lens_radius == 0.0 {
+            Ray { orig: self.origin, dir: self.to_corner + s * self.horizontal + t * self.vertical, time }
+        } else {
+            let rd = self.lens_radius * Vec3::random_in_unit_disk(rng);
+            let offset = self.u * rd.x() + self.v * rd.y();
+            Ray {
+                orig: self.origin + offset,
+                dir: self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
+                time,
+            }
+        };
+        if self.near > 0.0 {
+            // The ray starts on the lens, so it reaches the near plane after near / cos of the angle to the axis.
+            return Ray { orig: ray.at(self.near / ray.dir.dot(-self.w)), ..ray };
+        }
+        ray
+    }
+
+    // Light tracing: where `p` shows up through a random point of the lens at `time`, as the (s, t) that
+    // `get_ray_at` takes, with that point of the lens and the camera's importance there: how much a unit of
+    // radiance leaving a unit of area at `p` head-on towards the lens adds to the image summed over (s, t).
+    // None if `p` is behind the camera or clipped away.
+    pub fn project(&self, p: Point3, time: f64, rng: &mut dyn rand::RngCore) -> Option<(f64, f64, Point3, f64)> {
+        if let Some((end, time0, time1)) = &self.end {
+            return self.lerp(end, Camera::fraction(time, *time0, *time1)).project(p, time, rng);
+        }
+        let lens = if self.lens_radius == 0.0 {
+            self.origin
+        } else {
+            let rd = self.lens_radius * Vec3::random_in_unit_disk(rng);
+            self.origin + self.u * rd.x() + self.v * rd.y()
+        };
+        let to_p = p - lens;
+        let depth = to_p.dot(-self.w);
+        if depth <= 0.0 || depth < self.near || self.beyond_far(p, time) {
+            return None;
+        }
+        // Through the lens point to the plane in focus, which the image spans.
+        let focus_dist = (self.lower_left_corner - lens).dot(-self.w);
+        let on_plane = lens + (focus_dist / depth) * to_p - self.lower_left_corner;
+        let s = on_plane.dot(self.horizontal) / self.horizontal.length_squared();
+        let t = on_plane.dot(self.vertical) / self.vertical.length_squared();
+        // The image is (s, t) in [0, 1]² on a plane at distance 1 from the lens; a unit of (s, t) covers this much
+        // of it, and a unit of that plane a solid angle of cos³ at the angle to the axis.
+        let area = self.horizontal.length() * self.vertical.length() / (focus_dist * focus_dist);
+        let cosine = depth / to_p.length();
+        Some((s, t, lens, 1.0 / (area * cosine.powi(3) * to_p.length_squared())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::HittableList;
+    use crate::materials::{Lambertian, Visibility, Visible};
+    use crate::shapes::Sphere;
+    use crate::textures::SolidColor;
+
+    fn params() -> CameraParams {
+        CameraParams {
+            lookfrom: Point3::new(0.0, 1.0, 5.0),
+            lookat: Point3::new(0.0, 1.0, 0.0),
+            up: Vec3::new(0.0, 1.0, 0.0),
+            field_of_view: 40.0,
+            aspect_ratio: 1.5,
+            aperture: 0.0,
+            focus_dist: 5.0,
+            shift: 0.0,
+            near: 0.0,
+            far: f64::INFINITY,
+        }
+    }
+
+    #[test]
+    fn test_autofocus() {
+        let sphere = Sphere::new(Point3::new(0.0, 1.0, -1.0), 1.0, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)));
+        let mut rng = rand::thread_rng();
+        let mut focus = |x, y| params().autofocus(&sphere, (x, y), (31, 21), &mut rng);
+        assert!((focus(14.5, 10.5).unwrap() - 5.0).abs() < 1e-9);
+        assert_eq!(None, focus(0.0, 0.0));
+
+        // Surfaces hidden from the camera, and those beyond the far plane, aren't focused on.
+        let hidden = Visibility { camera: false, ..Visibility::ALL };
+        let mut world = HittableList::new();
+        world.add(Sphere::new(
+            Point3::new(0.0, 1.0, 2.0),
+            1.0,
+            Visible(Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)), hidden),
+        ));
+        world.add(sphere);
+        let mut focus = |far| CameraParams { far, ..params() }.autofocus(&world, (14.5, 10.5), (31, 21), &mut rng);
+        assert!((focus(f64::INFINITY).unwrap() - 5.0).abs() < 1e-9);
+        assert_eq!(None, focus(3.0));
+    }
+
+    #[test]
+    fn test_pinhole_draws_no_random_numbers() {
+        use rand::{Rng, SeedableRng};
+        let camera = params().camera();
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        let r = camera.get_ray(0.25, 0.75, &mut rng);
+        assert_eq!(Point3::new(0.0, 1.0, 5.0), r.orig);
+        assert_eq!(rand_pcg::Pcg64::seed_from_u64(1).gen::<u64>(), rng.gen::<u64>());
+    }
+
+    #[test]
+    fn test_stereo_eyes_converge() {
+        let params = params();
+        let left = params.stereo_eye(-0.03, 2.0).camera();
+        let right = params.stereo_eye(0.03, 2.0).camera();
+        let mut rng = rand::thread_rng();
+        // The same pixel of either eye sees the same point at the convergence distance.
+        for &(s, t) in &[(0.5, 0.5), (0.1, 0.9), (0.8, 0.3)] {
+            let (l, r) = (left.get_ray(s, t, &mut rng), right.get_ray(s, t, &mut rng));
+            let (pl, pr) = (l.at(2.0 / -l.dir.z()), r.at(2.0 / -r.dir.z()));
+            assert!((pl - pr).length() < 1e-9, "{} {}", pl, pr);
+            assert!(((l.orig - r.orig).length() - 0.06).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_motion_blur() {
+        let mut rng = rand::thread_rng();
+        let end = CameraParams { lookfrom: Point3::new(2.0, 1.0, 5.0), lookat: Point3::new(2.0, 1.0, 0.0), ..params() };
+        let camera = params().camera().moving_to(end.camera(), 0.25, 0.75);
+        // Before the shutter opens the camera is at the start, after it closes at the end, and between them it
+        // moves linearly, keeping the direction of every pixel.
+        for &(time, x) in &[(0.0, 0.0), (0.25, 0.0), (0.5, 1.0), (0.625, 1.5), (0.75, 2.0), (1.0, 2.0)] {
+            let r = camera.get_ray_at(0.3, 0.6, time, &mut rng);
+            assert!((r.orig - Point3::new(x, 1.0, 5.0)).length() < 1e-9, "{}: {}", time, r.orig);
+            assert_eq!(time, r.time);
+            let still = params().camera().get_ray(0.3, 0.6, &mut rng);
+            assert!((r.dir - still.dir).length() < 1e-9, "{}: {}", time, r.dir);
+        }
+        assert_eq!(Point3::new(0.0, 1.0, 5.0), camera.get_ray(0.3, 0.6, &mut rng).orig);
+        // Points in view at the end project from where the camera is then.
+        let (s, t, lens, _) = camera.project(Point3::new(2.0, 1.0, 0.0), 1.0, &mut rng).unwrap();
+        assert!((s - 0.5).abs() < 1e-9 && (t - 0.5).abs() < 1e-9, "{} {}", s, t);
+        assert_eq!(Point3::new(2.0, 1.0, 5.0), lens);
+    }
+
+    #[test]
+    fn test_clipping() {
+        let camera = CameraParams { near: 2.0, far: 4.0, aperture: 0.5, ..params() }.camera();
+        let mut rng = rand::thread_rng();
+        for &(s, t) in &[(0.5, 0.5), (0.1, 0.9), (0.8, 0.3)] {
+            // Rays start on the near plane, at z = 5 - 2.
+            let r = camera.get_ray(s, t, &mut rng);
+            assert!((r.orig.z() - 3.0).abs() < 1e-9, "{}", r.orig);
+        }
+        assert!(!camera.beyond_far(Point3::new(0.5, 1.0, 1.5), 0.0));
+        assert!(camera.beyond_far(Point3::new(0.5, 1.0, 0.5), 0.0));
+        assert!(camera.clips_far() && !params().camera().clips_far());
+    }
+
+    #[test]
+    fn test_project() {
+        let mut rng = rand::thread_rng();
+        // Points seen by a pixel project back onto it; through a wide lens only those in focus do.
+        let pinhole = params().camera();
+        let lens = CameraParams { aperture: 0.5, ..params() }.camera();
+        for &(s, t) in &[(0.5, 0.5), (0.1, 0.9), (0.8, 0.3)] {
+            let r = pinhole.get_ray(s, t, &mut rng);
+            let (ps, pt, origin, _) = pinhole.project(r.at(3.0), 0.0, &mut rng).unwrap();
+            assert!((ps - s).abs() < 1e-9 && (pt - t).abs() < 1e-9, "{} {}", ps, pt);
+            assert_eq!(r.orig, origin);
+            let in_focus = lens.get_ray(s, t, &mut rng).at(1.0);
+            let (ps, pt, _, _) = lens.project(in_focus, 0.0, &mut rng).unwrap();
+            assert!((ps - s).abs() < 1e-9 && (pt - t).abs() < 1e-9, "{} {}", ps, pt);
+        }
+        assert!(pinhole.project(Point3::new(0.0, 1.0, 6.0), 0.0, &mut rng).is_none());
+
+        // Straight ahead, a unit of (s, t) covers the whole image, at 2 tan(20°) high and 1.5 times as wide.
+        let (_, _, _, importance) = pinhole.project(Point3::new(0.0, 1.0, 3.0), 0.0, &mut rng).unwrap();
+        let height = 2.0 * 20f64.to_radians().tan();
+        assert!((importance - 1.0 / (1.5 * height * height * 4.0)).abs() < 1e-9, "{}", importance);
     }
 }
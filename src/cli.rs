@@ -0,0 +1,1715 @@
+// Command line interface of the raytracer binary.
+use crate::animation::{CameraPath, FramePattern};
+use crate::camera::{Camera, CameraParams};
+use crate::filter::{Filter, FilterKind};
+use crate::lights::LightTree;
+use crate::logging::{self, Event, Level};
+use crate::paths::PathFilter;
+use crate::postprocess::{self, Bloom, DepthOfField, Lens, PostProcess};
+use crate::raytrace::{Accumulator, LightSamplingRayTracer, RayTracer, RecursiveRayTracer, Renderer, AOV_SAMPLES};
+use crate::rngator::Rngator;
+use crate::sampler::Sampler;
+use crate::scene::{self, SceneFile};
+use crate::tiles::{self, TileOrder};
+use crate::vec::{Color, Vec3};
+use crate::{
+    assets, bhv, compare, hittable, http, image_texture, output, par, raytrace, rngator, server, validate, volumes,
+    worlds,
+};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::io::{BufRead, Read};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Clone)]
+struct Parameters {
+    pub world: Arc<dyn worlds::World>,
+    pub seed: Option<u64>,
+    pub randomized_rendering: bool,
+
+    pub render: raytrace::RenderingParams,
+    pub max_depth: i32,
+    // By kind of bounce, on top of `max_depth`.
+    pub max_bounces: raytrace::Bounces,
+    pub regularization: Option<raytrace::Regularization>,
+
+    pub camera: CameraParams,
+    // Instead of the world's own background.
+    pub background: Option<BackgroundOverride>,
+    // Fog around the world, in addition to any of its own.
+    pub fog: Option<volumes::FogParams>,
+    // Trace the fog as single scattering, see `LightSamplingRayTracer::fog`.
+    pub single_scattering: bool,
+    pub server: bool,
+    // Address to answer render jobs over HTTP on, see `http::serve`.
+    pub http: Option<String>,
+    // Directory the scene files posted over HTTP may read meshes and BRDFs from; none if not given.
+    pub http_files: Option<String>,
+    pub exr: Option<String>,
+    pub light_groups: usize,
+    // Named light path expressions, each collecting the light of its paths into an EXR buffer, see `paths`.
+    pub paths: Vec<(String, PathFilter)>,
+    pub light_sampling: bool,
+    // Trace paths from the lights to the camera instead, see `Renderer::accumulate_light_paths`.
+    pub light_tracing: bool,
+    // Pick the lights to sample from a `lights::LightTree` instead of uniformly.
+    pub light_tree: bool,
+    pub validate: bool,
+    // Print the worlds instead of rendering.
+    pub list_worlds: bool,
+    // Write the world as a scene file to this path instead of rendering, see `scene::export`.
+    pub export_scene: Option<String>,
+    // Report the memory used by the world after building it.
+    pub memory: bool,
+    // Report what the asset caches hold and did when done, see `assets::report`.
+    pub asset_report: bool,
+    // How to build the BVHs of the world, e.g. with fewer shapes per leaf than `bhv::DEFAULT_LEAF_SIZE`.
+    pub bvh: bhv::BvhConfig,
+    // Stop at the first NaN or infinite value along a path, see `raytrace::RecursiveRayTracer`.
+    pub check_nan: bool,
+    // Save the texture of the --noise world to this path instead of rendering.
+    pub bake: Option<(String, worlds::DebugPerlin)>,
+    // Compare two images instead of rendering.
+    pub diff: Option<Diff>,
+    // Convert an image into a tiled texture file instead of rendering: input, output and tile size.
+    pub tile_texture: Option<(String, String, usize)>,
+    // Seconds; `render.samples_per_pixel` is then the maximum.
+    pub time_limit: Option<f64>,
+    pub heatmap: Option<String>,
+    // Pause, snapshot and finish commands read from stdin during the render, once `do_it` starts listening.
+    pub controls: Option<Arc<Controls>>,
+    // The image so far saved every so often during the render.
+    pub checkpoint: Option<Checkpoint>,
+    // More images saved from the same render, each with its own exposure and gamma.
+    pub outputs: Vec<Output>,
+    // Samples per pixel added on the edges found after the first pass, and the edge threshold, see
+    // `Renderer::accumulate_edges`.
+    pub edge_samples: Option<(usize, f64)>,
+    pub variance: bool,
+    // Save coverage as alpha in the EXR and the --output images; holdouts are left out of it.
+    pub alpha: bool,
+    // Reconstruction filter of the samples, see `Renderer::with_filter`.
+    pub filter: Filter,
+    // Apply the filter by importance sampling the camera rays, see `Renderer::with_importance_filter`.
+    pub importance_filter: bool,
+    // Render by tiles in this order rather than by lines, see `Renderer::with_tile_order`.
+    pub tile_order: Option<TileOrder>,
+    // Print the slowest tiles after rendering, and save the times of all of them as CSV, see `tiles::report`.
+    pub tile_report: bool,
+    pub tile_csv: Option<String>,
+    // Seconds per pass after which a tile is split, see `Renderer::with_tile_splitting`.
+    pub split_tiles: Option<f64>,
+    // Render on this many threads instead of one per core, see `par::with_threads`.
+    pub threads: Option<usize>,
+    // See `Renderer::with_nice`.
+    pub nice: bool,
+    // Where in the pixels the camera rays go, see `Renderer::with_sampler`.
+    pub sampler: Sampler,
+    pub stereo: Option<Stereo>,
+    // Render through a pinhole and blur by depth afterwards, see `DepthOfField`.
+    pub dof_preview: bool,
+    // Pixel to focus on, see `CameraParams::autofocus`.
+    pub autofocus: Option<(f64, f64)>,
+    pub camera_path: Option<String>,
+    pub fps: f64,
+    // Where frames are saved, None when they only go to the video.
+    pub frames: Option<FramePattern>,
+    // Path of a video the frames are encoded into with ffmpeg.
+    pub video: Option<String>,
+}
+
+#[derive(Clone)]
+struct Diff {
+    a: String,
+    b: String,
+    // Path for an image of the difference of every pixel.
+    heatmap: Option<String>,
+}
+
+// Replaces the world's background, see `--background`.
+#[derive(Clone)]
+enum BackgroundOverride {
+    Gradient,
+    Black,
+    Color(Color),
+    // Path of an equirectangular Radiance HDR image.
+    Hdr(String),
+}
+
+impl BackgroundOverride {
+    fn background(&self) -> Box<dyn raytrace::Background> {
+        match self {
+            BackgroundOverride::Gradient => Box::new(raytrace::GradientBackground::default()),
+            BackgroundOverride::Black => Box::new(raytrace::BlackBackground::new()),
+            BackgroundOverride::Color(color) => Box::new(raytrace::SolidBackground::new(*color)),
+            BackgroundOverride::Hdr(path) => Box::new(
+                image_texture::EnvironmentMap::open(path).unwrap_or_else(|e| panic!("can't read {}: {}", path, e)),
+            ),
+        }
+    }
+}
+
+// Image saved from the render besides the one on stdout, see `--output`; None keeps the main image's setting.
+#[derive(Clone)]
+struct Output {
+    path: String,
+    exposure: Option<Color>,
+    gamma: Option<f64>,
+    // Bits per channel, 8 or 16, see `output::save_image16`.
+    bits: u32,
+}
+
+impl Output {
+    fn post(&self, post: &PostProcess) -> PostProcess {
+        PostProcess {
+            exposure: self.exposure.unwrap_or(post.exposure),
+            gamma: self.gamma.unwrap_or(post.gamma),
+            ..*post
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Stereo {
+    // Distance between the eyes, in world units.
+    interocular: f64,
+    // Distance at which the eyes' views coincide; defaults to the distance to lookat.
+    convergence: Option<f64>,
+    // Path for the right eye's image; if None, it is rendered next to the left one.
+    right: Option<String>,
+}
+
+// The image so far saved to `path` every `every` during a progressive render, to watch a long render or keep what it
+// got to if it is stopped.
+#[derive(Clone)]
+struct Checkpoint {
+    path: String,
+    every: Duration,
+}
+
+// The image so far is developed like the final one: pixels are the averages of the samples they have, so snapshots
+// are as bright after a few passes as after all of them, only noisier.
+fn save_snapshot(path: &str, accumulator: &Accumulator, post: &PostProcess) {
+    match output::save_image(path, &accumulator.to_rgb(post)) {
+        Ok(()) => logging::progress(&format!("Saved {} at {} samples", path, accumulator.samples_per_pixel())),
+        Err(e) => logging::warning(&format!("Can't save {}: {}", path, e)),
+    }
+}
+
+// Control of a progressive render by single letter commands on stdin, each followed by Enter: p pauses and
+// resumes, s saves the image so far to `snapshot`, q finishes with the samples taken so far. They take effect
+// between passes.
+struct Controls {
+    snapshot: String,
+    paused: AtomicBool,
+    save: AtomicBool,
+    finish: AtomicBool,
+}
+
+impl Controls {
+    fn new(snapshot: String) -> Controls {
+        Controls {
+            snapshot,
+            paused: AtomicBool::new(false),
+            save: AtomicBool::new(false),
+            finish: AtomicBool::new(false),
+        }
+    }
+
+    // Starts reading the commands from `input` on a thread of their own.
+    fn listen(self: &Arc<Controls>, input: impl Read + Send + 'static) {
+        let listener = self.clone();
+        std::thread::spawn(move || {
+            for line in std::io::BufReader::new(input).lines() {
+                match line {
+                    Ok(line) => listener.command(&line),
+                    Err(_) => return,
+                }
+            }
+        });
+    }
+
+    fn command(&self, line: &str) {
+        const R: Ordering = Ordering::Relaxed;
+        match line.trim() {
+            "p" => {
+                let paused = !self.paused.fetch_xor(true, R);
+                logging::progress(if paused { "Paused, p resumes" } else { "Resumed" });
+            }
+            "s" => self.save.store(true, R),
+            "q" => {
+                self.finish.store(true, R);
+                self.paused.store(false, R);
+            }
+            "" => {}
+            command => logging::warning(&format!("Unknown command '{}', use p, s or q", command)),
+        }
+    }
+
+    // Handles the commands given since the last pass: returns whether to go on, and how long the render was paused
+    // if it was; a pause blocks until it is resumed.
+    fn after_pass(&self, accumulator: &Accumulator, post: &PostProcess) -> (bool, Option<Duration>) {
+        const R: Ordering = Ordering::Relaxed;
+        if self.save.swap(false, R) {
+            save_snapshot(&self.snapshot, accumulator, post);
+        }
+        let mut paused = None;
+        let paused_at = Instant::now();
+        while self.paused.load(R) {
+            std::thread::sleep(Duration::from_millis(50));
+            paused = Some(paused_at.elapsed());
+        }
+        (!self.finish.load(R), paused)
+    }
+}
+
+fn arg<'a>(name: &'a str, default_value: &'a str) -> Arg<'a, 'a> {
+    Arg::with_name(name).long(name).takes_value(true).default_value(default_value)
+}
+
+fn undef_arg<'a>(name: &'a str, help: &'a str) -> Arg<'a, 'a> {
+    Arg::with_name(name).long(name).help(help).takes_value(true)
+}
+
+fn parse_aspect_ratio(s: &str) -> f64 {
+    let v: Vec<&str> = s.split(':').collect();
+    return v[0].parse::<i32>().unwrap() as f64 / v[1].parse::<i32>().unwrap() as f64;
+}
+
+fn parse_vector(s: &str) -> Vec3 {
+    let input: Vec<&str> = s.split(',').collect();
+    let mut e = [0.0, 0.0, 0.0];
+    for i in 0..3 {
+        e[i] = input[i].parse::<f64>().unwrap();
+    }
+
+    Vec3 { e }
+}
+
+// A pixel as x,y, e.g. for `--autofocus`.
+fn parse_pixel(s: &str) -> Result<(f64, f64), String> {
+    let v =
+        s.split(',').map(|v| v.parse::<f64>().map_err(|e| format!("'{}': {}", s, e))).collect::<Result<Vec<_>, _>>()?;
+    match v[..] {
+        [x, y] => Ok((x, y)),
+        _ => Err(format!("expected x,y, got '{}'", s)),
+    }
+}
+
+// Either one value in stops for all channels, or r,g,b.
+fn parse_exposure(s: &str) -> Color {
+    let stops = match s.parse::<f64>() {
+        Ok(v) => Color::ONE * v,
+        Err(_) => parse_vector(s),
+    };
+    postprocess::exposure_from_stops(stops)
+}
+
+fn parse_background(s: &str) -> BackgroundOverride {
+    match s.split_once(':') {
+        None if s == "gradient" => BackgroundOverride::Gradient,
+        None if s == "black" => BackgroundOverride::Black,
+        Some(("color", color)) => BackgroundOverride::Color(parse_vector(color)),
+        Some(("hdr", path)) => BackgroundOverride::Hdr(path.to_string()),
+        _ => panic!("unknown background '{}'", s),
+    }
+}
+
+// path[,exposure=stops or r:g:b stops][,gamma=][,bits=8|16]
+fn parse_output(s: &str) -> Output {
+    let mut fields = s.split(',');
+    let mut output = Output { path: fields.next().unwrap().to_string(), exposure: None, gamma: None, bits: 8 };
+    for field in fields {
+        match field.split_once('=') {
+            Some(("exposure", v)) => output.exposure = Some(parse_exposure(&v.replace(':', ","))),
+            Some(("gamma", v)) => output.gamma = Some(v.parse::<f64>().unwrap()),
+            Some(("bits", "8")) => output.bits = 8,
+            Some(("bits", "16")) if [".png", ".tif", ".tiff"].iter().any(|e| output.path.ends_with(e)) => {
+                output.bits = 16
+            }
+            Some(("bits", "16")) => panic!("16 bits per channel need a PNG or TIFF file, not '{}'", output.path),
+            _ => panic!("unknown output setting '{}' in '{}'", field, s),
+        }
+    }
+    output
+}
+
+// path[,every=seconds], every minute by default.
+fn parse_checkpoint(s: &str) -> Checkpoint {
+    let mut fields = s.split(',');
+    let mut checkpoint = Checkpoint { path: fields.next().unwrap().to_string(), every: Duration::from_secs(60) };
+    for field in fields {
+        match field.split_once('=') {
+            Some(("every", v)) => checkpoint.every = Duration::from_secs_f64(v.parse::<f64>().unwrap()),
+            _ => panic!("unknown checkpoint setting '{}' in '{}'", field, s),
+        }
+    }
+    checkpoint
+}
+
+// [name=]expression, named path_<k> after its position `k` among the paths if not named.
+fn parse_path((k, s): (usize, &str)) -> (String, PathFilter) {
+    let (name, expression) = match s.split_once('=') {
+        Some((name, expression)) => (name.to_string(), expression),
+        None => (format!("path_{}", k), s),
+    };
+    (name, expression.parse::<PathFilter>().unwrap_or_else(|e| panic!("{}", e)))
+}
+
+// Light groups and paths are split per camera ray, so a filter other than the box has to be applied by importance
+// sampling the rays; clap can't make the conflict depend on --importance_filter.
+fn check_filter(matches: &ArgMatches) -> Result<(), clap::Error> {
+    let split = matches.is_present("light_groups") || matches.is_present("path");
+    let filter = matches.value_of("filter").map_or(Filter::BOX, |v| v.parse::<Filter>().unwrap());
+    if split && filter.kind != FilterKind::Box && !matches.is_present("importance_filter") {
+        let message = "--filter needs --importance_filter with --light_groups or --path";
+        return Err(clap::Error::with_description(message, clap::ErrorKind::ArgumentConflict));
+    }
+    Ok(())
+}
+
+// Radius in pixels and strength are optional.
+fn parse_bloom(s: &str) -> Bloom {
+    let v: Vec<f64> = s.split(',').map(|v| v.parse::<f64>().unwrap()).collect();
+    Bloom::new(v[0], v.get(1).copied().unwrap_or(5.0), v.get(2).copied().unwrap_or(0.5))
+}
+
+fn args() -> Parameters {
+    let matches = app().get_matches();
+    check_filter(&matches).unwrap_or_else(|e| e.exit());
+    // Before loading the scene, whose assets are timed when verbose.
+    logging::set_level(match matches.value_of("log_level") {
+        _ if matches.is_present("quiet") => logging::Level::Quiet,
+        Some(level) => level.parse().unwrap_or_else(|e| panic!("{}", e)),
+        None => logging::Level::Normal,
+    });
+    if let Some(mib) = matches.value_of("asset_budget") {
+        let mib = mib.parse::<f64>().unwrap();
+        assets::set_budget(Some((mib * 1024.0 * 1024.0) as usize));
+    }
+    parameters(&matches)
+}
+
+fn app() -> App<'static, 'static> {
+    let world_names: Vec<&'static str> = worlds::worlds().iter().map(|w| w.name()).collect();
+    App::new("mulambda raytracer")
+        .version("0.1")
+        .arg(arg("aspect_ratio", "16:9"))
+        .arg(arg("image_width", "400"))
+        .arg(arg("samples_per_pixel", "200"))
+        .arg(arg("max_depth", "50"))
+        .arg(undef_arg("max_diffuse_depth", "[int] diffuse bounces of a path at most, within max_depth"))
+        .arg(undef_arg("max_specular_depth", "[int] reflections and refractions by smooth surfaces at most"))
+        .arg(undef_arg("max_volume_depth", "[int] scattering events in volumes at most"))
+        .arg(undef_arg(
+            "regularize",
+            "[roughness[,after]] blur specular bounces after this many diffuse ones (default 1), against caustic noise",
+        ))
+        .arg(undef_arg("lookfrom", "[point] camera position"))
+        .arg(undef_arg("lookat", "[point] point that camera looks at"))
+        .arg(arg("up", "0,1.0,0"))
+        .arg(undef_arg("field_of_view", "[float] field of view, in degrees"))
+        .arg(arg("aperture", "0.0"))
+        .arg(Arg::with_name("focus_dist").long("focus_dist").takes_value(true))
+        .arg(
+            undef_arg("autofocus", "[x,y] focus on what pixel x,y (from the top left) sees, instead of lookat")
+                .conflicts_with("focus_dist"),
+        )
+        .arg(undef_arg(
+            "clip",
+            "[near[,far]] the camera only sees what is between these distances along the viewing direction",
+        ))
+        .arg(
+            Arg::with_name("world")
+                .long("world")
+                .takes_value(true)
+                .possible_values(&world_names)
+                .default_value("simple"),
+        )
+        .arg(
+            Arg::with_name("gen")
+                .long("gen")
+                .takes_value(true)
+                .possible_values(&["spheres"])
+                .help("generate a procedural world instead of --world"),
+        )
+        .arg(undef_arg("count", "[int] number of generated objects").requires("gen"))
+        .arg(undef_arg("area", "[float] generated objects cover [-area, area] in x and z").requires("gen"))
+        .arg(undef_arg("material_mix", "[diffuse,metal,glass] relative weights of generated materials").requires("gen"))
+        .arg(
+            undef_arg("cornell", "[blocks|smoke|spheres][,glass][,mirror] Cornell box variant instead of --world")
+                .conflicts_with("gen"),
+        )
+        .arg(
+            undef_arg(
+                "noise",
+                "[noise|turbulence|marble][,scale=][,depth=][,contrast=][,table=] debug_perlin world with this texture",
+            )
+            .conflicts_with_all(&["gen", "cornell"]),
+        )
+        .arg(
+            undef_arg(
+                "material",
+                "[lambertian|metal|glass|light][,color=r:g:b][,fuzz=][,ior=] material_preview world with this material",
+            )
+            .conflicts_with_all(&["gen", "cornell", "noise"]),
+        )
+        .arg(
+            undef_arg("furnace", "[material options] white furnace world with a ball of this material, see --material")
+                .conflicts_with_all(&["gen", "cornell", "noise", "material"]),
+        )
+        .arg(
+            undef_arg("scene", "[path] render the world and camera of a scene file instead of --world")
+                .conflicts_with_all(&["gen", "cornell", "noise", "material", "furnace"]),
+        )
+        .arg(undef_arg("export_scene", "[path] write the world and camera as a scene file instead of rendering"))
+        .arg(undef_arg("bake", "[path] save the --noise texture as an image instead of rendering").requires("noise"))
+        .arg(undef_arg("time_limit", "[seconds] render passes until the time is up, samples_per_pixel at most"))
+        .arg(
+            Arg::with_name("variance")
+                .long("variance")
+                .requires("exr")
+                .help("write the per-pixel variance of the image to the EXR"),
+        )
+        .arg(Arg::with_name("alpha").long("alpha").help(
+            "save coverage as alpha, in the EXR and --output images that store it, leaving out the background and \
+             holdouts",
+        ))
+        .arg(undef_arg("heatmap", "[path] also save an image of the number of samples taken per pixel"))
+        .arg(
+            undef_arg(
+                "controls",
+                "[path] render progressively, p on stdin pauses, s saves a snapshot here, q finishes",
+            )
+            .conflicts_with("server"),
+        )
+        .arg(
+            undef_arg(
+                "checkpoint",
+                "[path[,every=seconds]] render progressively and save the image so far here every minute, or as often \
+                 as given",
+            )
+            .conflicts_with("server"),
+        )
+        .arg(
+            undef_arg(
+                "edge_samples",
+                "[samples[,threshold]] then add this many samples per pixel on high-contrast edges",
+            )
+            .conflicts_with_all(&["time_limit", "light_groups", "path"]),
+        )
+        .arg(
+            undef_arg("stereo", "[interocular[,convergence]] render left and right eyes side by side")
+                .conflicts_with("server"),
+        )
+        .arg(
+            undef_arg("stereo_right", "[path] save the right eye here instead, stdout gets the left one")
+                .requires("stereo"),
+        )
+        .arg(
+            Arg::with_name("dof_preview")
+                .long("dof_preview")
+                .help("quick preview of aperture and focus_dist, blurring a pinhole image by depth"),
+        )
+        .arg(
+            undef_arg("camera_path", "[path] render the frames of a flythrough along the keyframes in this file")
+                .conflicts_with_all(&["server", "stereo"]),
+        )
+        .arg(arg("fps", "24").help("frames per second of the camera path"))
+        .arg(undef_arg(
+            "shutter",
+            "[[open,]close] seconds the shutter is open, after the frame's time, for motion blur",
+        ))
+        .arg(arg("frames", "frame_").help(
+            "camera path frames are saved as <frames>NNNN.png, or with a pattern like frame_%04d.png to this name",
+        ))
+        .arg(
+            undef_arg("video", "[path] encode the camera path frames into this video with ffmpeg, e.g. flight.mp4")
+                .requires("camera_path"),
+        )
+        .arg(undef_arg(
+            "background",
+            "[gradient|black|color:r,g,b|hdr:path] background instead of the world's, hdr is an equirectangular map",
+        ))
+        .arg(undef_arg(
+            "fog",
+            "[density][,color=r:g:b][,falloff=][,height=][,radius=] fill the space around the world with fog",
+        ))
+        .arg(
+            Arg::with_name("single_scattering")
+                .long("single_scattering")
+                .requires_all(&["fog", "light_sampling"])
+                .help("trace --fog as single scattering of the sampled lights, for light shafts with less noise"),
+        )
+        .arg(Arg::with_name("seed").long("seed").takes_value(true))
+        .arg(Arg::with_name("randomized_rendering").long("randomized_rendering").short("rr"))
+        .arg(arg("gamma", "2.2").help("display gamma the 8-bit output is encoded for"))
+        .arg(undef_arg("exposure", "[stops or r,g,b stops] exposure adjustment of the 8-bit output"))
+        .arg(
+            undef_arg(
+                "output",
+                "[path][,exposure=][,gamma=][,bits=16] also save the image here, e.g. at another exposure",
+            )
+            .multiple(true)
+            .number_of_values(1)
+            .conflicts_with("camera_path"),
+        )
+        .arg(undef_arg("filter", "[box|tent|gaussian|mitchell][,radius] pixel filter the samples are weighted with"))
+        .arg(
+            Arg::with_name("importance_filter")
+                .long("importance_filter")
+                .requires("filter")
+                .help("apply the filter by sampling the offsets of the camera rays from it instead of splatting"),
+        )
+        .arg(undef_arg("sampler", "[random|halton] where in the pixels the camera rays go"))
+        .arg(undef_arg("tile_order", "[scanline|hilbert|spiral] render by tiles in this order instead of by lines"))
+        .arg(Arg::with_name("tile_report").long("tile_report").help("render by tiles and print the slowest ones"))
+        .arg(undef_arg("tile_csv", "[path] render by tiles and save the time every tile took as CSV"))
+        .arg(undef_arg(
+            "split_tiles",
+            "[seconds] render by tiles and split those that took longer per pass among threads in the next passes",
+        ))
+        .arg(undef_arg("bloom", "[threshold[,radius[,strength]]] glow around pixels brighter than threshold"))
+        .arg(undef_arg(
+            "vignetting",
+            "[float] darken the image towards the edges, 1 is the natural falloff of the lens",
+        ))
+        .arg(undef_arg("chromatic_aberration", "[float] magnify red by 1 + value and blue by 1 - value"))
+        .arg(Arg::with_name("no_dither").long("no_dither").help("disable ordered dithering of the 8-bit output"))
+        .arg(undef_arg("exr", "[path] also write a multichannel EXR with the HDR image and AOVs"))
+        .arg(undef_arg("light_groups", "[int] number of per-light-group buffers written to the EXR").requires("exr"))
+        .arg(
+            undef_arg("path", "[[name=]expression] also write the light of the paths matching e.g. CDL to the EXR")
+                .multiple(true)
+                .number_of_values(1)
+                .requires("exr")
+                .conflicts_with("light_groups"),
+        )
+        .arg(
+            Arg::with_name("light_sampling")
+                .long("light_sampling")
+                .help("sample the world's lights directly at each bounce"),
+        )
+        .arg(
+            Arg::with_name("light_tracing")
+                .long("light_tracing")
+                .conflicts_with_all(&[
+                    "time_limit",
+                    "controls",
+                    "checkpoint",
+                    "light_groups",
+                    "path",
+                    "edge_samples",
+                    "server",
+                ])
+                .help("trace paths from the world's lights to the camera instead, to preview caustics"),
+        )
+        .arg(
+            Arg::with_name("light_tree")
+                .long("light_tree")
+                .help("pick the lights to sample by their power and distance, for worlds with many lights"),
+        )
+        .arg(
+            Arg::with_name("validate")
+                .long("validate")
+                .alias("dry_run")
+                .help("build the world and report its stats and problems without rendering"),
+        )
+        .arg(
+            Arg::with_name("list_worlds")
+                .long("list_worlds")
+                .help("print the worlds with their descriptions, cameras, recommended settings and assets"),
+        )
+        .arg(
+            Arg::with_name("check_nan")
+                .long("check_nan")
+                .help("stop with the pixel, bounce and object at the first NaN or infinite value along a path"),
+        )
+        .arg(undef_arg("threads", "[int] render on this many threads instead of one per core").conflicts_with("http"))
+        .arg(Arg::with_name("nice").long("nice").help("yield to other programs often, to render in the background"))
+        .arg(Arg::with_name("quiet").long("quiet").help("print only warnings and errors, no progress"))
+        .arg(
+            undef_arg(
+                "log_level",
+                "[quiet|normal|verbose|trace] how much to print, verbose adds the time of every phase, trace details",
+            )
+            .conflicts_with("quiet"),
+        )
+        .arg(Arg::with_name("memory").long("memory").help("report the memory used by the world after building it"))
+        .arg(undef_arg(
+            "asset_budget",
+            "[MiB] memory to keep loaded textures, meshes and BRDFs in, dropping unused ones",
+        ))
+        .arg(
+            Arg::with_name("asset_report")
+                .long("asset_report")
+                .help("report the files, memory, hits and evictions of the asset caches when done"),
+        )
+        .arg(undef_arg("bvh_leaf_size", "[int] shapes per BVH leaf at most, tested one after another"))
+        .arg(undef_arg("bvh_builder", "[sah|median|lbvh] how to split the shapes into BVH nodes (default median)"))
+        .arg(Arg::with_name("server").long("server").help("keep the scene in memory and render on commands from stdin"))
+        .arg(
+            undef_arg("http", "[address:port] render scene files posted over HTTP, e.g. 127.0.0.1:8080")
+                .conflicts_with("server"),
+        )
+        .arg(
+            undef_arg("http_files", "[dir] directory the posted scene files may read meshes and BRDFs from")
+                .requires("http"),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("compare two images and print their RMSE, PSNR and largest difference")
+                .arg(Arg::with_name("a").required(true).index(1))
+                .arg(Arg::with_name("b").required(true).index(2))
+                .arg(undef_arg("heatmap", "[path] save the difference of every pixel as an image")),
+        )
+        .subcommand(
+            SubCommand::with_name("tile_texture")
+                .about("convert an image into a tiled file that large textures are streamed from")
+                .arg(Arg::with_name("input").required(true).index(1))
+                .arg(Arg::with_name("output").required(true).index(2))
+                .arg(undef_arg("tile_size", "[int] side of the tiles in pixels (default 128)")),
+        )
+}
+
+// The parameters of a command line, without setting up the process for it.
+fn parameters(matches: &ArgMatches) -> Parameters {
+    let mut worlds = worlds::worlds();
+
+    fn val<'a, T>(m: &ArgMatches<'a>, name: &str) -> T
+    where
+        T: std::str::FromStr,
+        <T as std::str::FromStr>::Err: std::fmt::Debug,
+    {
+        m.value_of(name).unwrap().parse::<T>().unwrap()
+    }
+
+    let scene = matches.value_of("scene").map(|path| {
+        logging::timed(Level::Verbose, "load scene", || SceneFile::load(path)).unwrap_or_else(|e| {
+            eprintln!("Can't load the scene: {}", e);
+            std::process::exit(1);
+        })
+    });
+    let world: Arc<dyn worlds::World> = match (matches.value_of("gen"), matches.value_of("cornell")) {
+        (None, Some(options)) => Arc::new(options.parse::<worlds::Cornell>().unwrap()),
+        (None, None) if matches.is_present("noise") => {
+            Arc::new(matches.value_of("noise").unwrap().parse::<worlds::DebugPerlin>().unwrap())
+        }
+        (None, None) if scene.is_some() => scene.as_ref().unwrap().world(),
+        (None, None) if matches.is_present("material") => {
+            Arc::new(matches.value_of("material").unwrap().parse::<worlds::MaterialPreview>().unwrap())
+        }
+        (None, None) if matches.is_present("furnace") => {
+            Arc::new(matches.value_of("furnace").unwrap().parse::<worlds::Furnace>().unwrap())
+        }
+        (Some(_), _) => {
+            let mut spheres = worlds::RandomSpheres::new("spheres");
+            if let Some(count) = matches.value_of("count") {
+                spheres.count = count.parse::<usize>().unwrap();
+            }
+            if let Some(area) = matches.value_of("area") {
+                spheres.area = area.parse::<f64>().unwrap();
+            }
+            if let Some(mix) = matches.value_of("material_mix") {
+                spheres = spheres.with_material_mix(parse_vector(mix).e).unwrap_or_else(|e| panic!("{}", e));
+            }
+            Arc::new(spheres)
+        }
+        (None, None) => {
+            let world_name = matches.value_of("world").unwrap();
+            worlds.remove(worlds.iter().position(|w| w.name() == world_name).unwrap())
+        }
+    };
+
+    let aspect_ratio = parse_aspect_ratio(matches.value_of("aspect_ratio").unwrap());
+    let image_width = val::<usize>(matches, "image_width");
+
+    let view = scene.map_or_else(|| world.camera(), |scene| scene.view());
+    let lookfrom = matches.value_of("lookfrom").map_or(view.lookfrom, parse_vector);
+    let lookat = matches.value_of("lookat").map_or(view.lookat, parse_vector);
+    let field_of_view = matches.value_of("field_of_view").map_or(view.field_of_view, |v| v.parse::<f64>().unwrap());
+
+    let focus_dist = match matches.value_of("focus_dist") {
+        None => (lookat - lookfrom).length(),
+        Some(v) => v.parse::<f64>().unwrap(),
+    };
+
+    let (near, far) = match matches.value_of("clip") {
+        None => (0.0, f64::INFINITY),
+        Some(v) => match v.split_once(',') {
+            None => (v.parse::<f64>().unwrap(), f64::INFINITY),
+            Some((near, far)) => (near.parse::<f64>().unwrap(), far.parse::<f64>().unwrap()),
+        },
+    };
+
+    let (shutter_open, shutter_close) = match matches.value_of("shutter") {
+        None => (0.0, 0.0),
+        Some(v) => match v.split_once(',') {
+            None => (0.0, v.parse::<f64>().unwrap()),
+            Some((open, close)) => (open.parse::<f64>().unwrap(), close.parse::<f64>().unwrap()),
+        },
+    };
+
+    let time_limit = matches.value_of("time_limit").map(|v| v.parse::<f64>().unwrap());
+    let samples_per_pixel = match time_limit {
+        Some(_) if matches.occurrences_of("samples_per_pixel") == 0 => usize::MAX,
+        _ => val::<usize>(matches, "samples_per_pixel"),
+    };
+
+    let lens = Lens {
+        vignetting: matches.value_of("vignetting").map_or(world.lens().vignetting, |v| {
+            v.parse::<f64>().unwrap() * postprocess::natural_vignetting(field_of_view, aspect_ratio)
+        }),
+        chromatic_aberration: matches
+            .value_of("chromatic_aberration")
+            .map_or(world.lens().chromatic_aberration, |v| v.parse::<f64>().unwrap()),
+    };
+
+    Parameters {
+        world,
+        seed: matches.value_of("seed").map(|v| v.parse::<u64>().unwrap()),
+        randomized_rendering: matches.is_present("randomized_rendering"),
+        render: raytrace::RenderingParams {
+            image_width,
+            image_height: (image_width as f64 / aspect_ratio) as usize,
+            samples_per_pixel,
+            post: PostProcess {
+                exposure: matches.value_of("exposure").map_or(Color::ONE, parse_exposure),
+                gamma: val::<f64>(matches, "gamma"),
+                dither: !matches.is_present("no_dither"),
+                bloom: matches.value_of("bloom").map(parse_bloom),
+                lens,
+            },
+            shutter_open,
+            shutter_close,
+        },
+        max_depth: val::<i32>(matches, "max_depth"),
+        max_bounces: raytrace::Bounces {
+            diffuse: matches.value_of("max_diffuse_depth").map_or(i32::MAX, |v| v.parse::<i32>().unwrap()),
+            specular: matches.value_of("max_specular_depth").map_or(i32::MAX, |v| v.parse::<i32>().unwrap()),
+            volume: matches.value_of("max_volume_depth").map_or(i32::MAX, |v| v.parse::<i32>().unwrap()),
+        },
+        regularization: matches.value_of("regularize").map(|v| match v.split_once(',') {
+            None => raytrace::Regularization { roughness: v.parse::<f64>().unwrap(), after: 1 },
+            Some((roughness, after)) => raytrace::Regularization {
+                roughness: roughness.parse::<f64>().unwrap(),
+                after: after.parse::<i32>().unwrap(),
+            },
+        }),
+        camera: CameraParams {
+            lookfrom,
+            lookat,
+            up: parse_vector(matches.value_of("up").unwrap()),
+            field_of_view,
+            aspect_ratio,
+            aperture: val::<f64>(matches, "aperture"),
+            focus_dist,
+            shift: 0.0,
+            near,
+            far,
+        },
+        background: matches.value_of("background").map(parse_background),
+        fog: matches.value_of("fog").map(|v| v.parse::<volumes::FogParams>().unwrap()),
+        single_scattering: matches.is_present("single_scattering"),
+        server: matches.is_present("server"),
+        http: matches.value_of("http").map(|v| v.to_string()),
+        http_files: matches.value_of("http_files").map(|v| v.to_string()),
+        exr: matches.value_of("exr").map(|v| v.to_string()),
+        light_groups: matches.value_of("light_groups").map_or(0, |v| v.parse::<usize>().unwrap()),
+        paths: matches.values_of("path").map_or(Vec::new(), |v| v.enumerate().map(parse_path).collect()),
+        light_sampling: matches.is_present("light_sampling"),
+        light_tracing: matches.is_present("light_tracing"),
+        light_tree: matches.is_present("light_tree"),
+        validate: matches.is_present("validate"),
+        list_worlds: matches.is_present("list_worlds"),
+        export_scene: matches.value_of("export_scene").map(|v| v.to_string()),
+        memory: matches.is_present("memory"),
+        asset_report: matches.is_present("asset_report"),
+        bvh: bhv::BvhConfig {
+            builder: matches.value_of("bvh_builder").map_or(bhv::BvhBuilder::Median, |v| v.parse().unwrap()),
+            leaf_size: matches
+                .value_of("bvh_leaf_size")
+                .map_or(bhv::DEFAULT_LEAF_SIZE, |v| v.parse::<usize>().unwrap()),
+        },
+        check_nan: matches.is_present("check_nan"),
+        diff: matches.subcommand_matches("diff").map(|m| Diff {
+            a: m.value_of("a").unwrap().to_string(),
+            b: m.value_of("b").unwrap().to_string(),
+            heatmap: m.value_of("heatmap").map(|v| v.to_string()),
+        }),
+        tile_texture: matches.subcommand_matches("tile_texture").map(|m| {
+            let tile_size = m.value_of("tile_size").map_or(128, |v| v.parse::<usize>().unwrap().max(1));
+            (m.value_of("input").unwrap().to_string(), m.value_of("output").unwrap().to_string(), tile_size)
+        }),
+        bake: matches
+            .value_of("bake")
+            .map(|path| (path.to_string(), matches.value_of("noise").unwrap().parse::<worlds::DebugPerlin>().unwrap())),
+        time_limit,
+        heatmap: matches.value_of("heatmap").map(|v| v.to_string()),
+        controls: matches.value_of("controls").map(|v| Arc::new(Controls::new(v.to_string()))),
+        checkpoint: matches.value_of("checkpoint").map(parse_checkpoint),
+        outputs: matches.values_of("output").map_or(Vec::new(), |v| v.map(parse_output).collect()),
+        edge_samples: matches.value_of("edge_samples").map(|v| match v.split_once(',') {
+            None => (v.parse::<usize>().unwrap(), DEFAULT_EDGE_THRESHOLD),
+            Some((samples, threshold)) => (samples.parse::<usize>().unwrap(), threshold.parse::<f64>().unwrap()),
+        }),
+        variance: matches.is_present("variance"),
+        alpha: matches.is_present("alpha"),
+        filter: matches.value_of("filter").map_or(Filter::BOX, |v| v.parse::<Filter>().unwrap()),
+        importance_filter: matches.is_present("importance_filter"),
+        tile_order: match matches.value_of("tile_order") {
+            Some(order) => Some(order.parse::<TileOrder>().unwrap()),
+            None if ["tile_report", "tile_csv", "split_tiles"].iter().any(|a| matches.is_present(a)) => {
+                Some(TileOrder::Scanline)
+            }
+            None => None,
+        },
+        tile_report: matches.is_present("tile_report"),
+        tile_csv: matches.value_of("tile_csv").map(|v| v.to_string()),
+        split_tiles: matches.value_of("split_tiles").map(|v| v.parse::<f64>().unwrap()),
+        threads: matches.value_of("threads").map(|v| v.parse::<usize>().unwrap().max(1)),
+        nice: matches.is_present("nice"),
+        sampler: matches.value_of("sampler").map_or(Sampler::Random, |v| v.parse::<Sampler>().unwrap()),
+        dof_preview: matches.is_present("dof_preview"),
+        camera_path: matches.value_of("camera_path").map(|v| v.to_string()),
+        fps: val::<f64>(matches, "fps"),
+        // With a video, frames are only saved when asked for.
+        frames: match matches.is_present("video") && matches.occurrences_of("frames") == 0 {
+            true => None,
+            false => Some(matches.value_of("frames").unwrap().parse::<FramePattern>().unwrap()),
+        },
+        video: matches.value_of("video").map(|v| v.to_string()),
+        autofocus: matches.value_of("autofocus").map(|v| parse_pixel(v).unwrap_or_else(|e| panic!("{}", e))),
+        stereo: matches.value_of("stereo").map(|v| {
+            let v: Vec<f64> = v.split(',').map(|v| v.parse::<f64>().unwrap()).collect();
+            Stereo {
+                interocular: v[0],
+                convergence: v.get(1).copied(),
+                right: matches.value_of("stereo_right").map(|v| v.to_string()),
+            }
+        }),
+    }
+}
+
+// Sobel gradient above which a pixel is on an edge, a tenth of a black to white step.
+const DEFAULT_EDGE_THRESHOLD: f64 = 0.1;
+
+// Tiles listed by --tile_report.
+const TILE_REPORT_LENGTH: usize = 5;
+
+// Everything rendered from one camera.
+struct View {
+    accumulator: Accumulator,
+    // Per light group or path, see `Parameters::paths`.
+    buffers: Vec<Accumulator>,
+    // Empty unless writing an EXR or alpha.
+    aovs: Vec<Vec<raytrace::Aov>>,
+}
+
+impl View {
+    fn side_by_side(&self, right: &View) -> View {
+        View {
+            accumulator: self.accumulator.side_by_side(&right.accumulator),
+            buffers: self.buffers.iter().zip(&right.buffers).map(|(l, r)| l.side_by_side(r)).collect(),
+            aovs: raytrace::side_by_side(&self.aovs, &right.aovs),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_view<T>(
+    params: &Parameters,
+    camera: &Camera,
+    world: &dyn hittable::Hittable,
+    lights: &dyn hittable::Hittable,
+    background: &dyn raytrace::Background,
+    tracer: &dyn RayTracer,
+    rngator: T,
+    deadline: Option<Instant>,
+) -> View
+where
+    T: Rngator,
+{
+    let start_time = Instant::now();
+    let remaining_count = AtomicUsize::new(usize::MAX);
+    // Passes of a single sample, so that the time limit is met closely and the controls respond quickly.
+    let progressive = deadline.is_some() || params.controls.is_some() || params.checkpoint.is_some();
+    let render = match progressive {
+        true => raytrace::RenderingParams { samples_per_pixel: 1, ..params.render },
+        false => params.render,
+    };
+    let rt = Renderer::new_with_rng(
+        camera, world, background, render, tracer,
+        // raytrace::SingleLightSourceRayTracer { light_source: Point3::new(14.0, 3.0, 3.0), intensity: 1.0 },
+        rngator,
+    );
+    let rt =
+        if params.importance_filter { rt.with_importance_filter(params.filter) } else { rt.with_filter(params.filter) };
+    let rt = match params.tile_order {
+        Some(order) => rt.with_tile_order(order),
+        None => rt,
+    };
+    let rt = match params.split_tiles {
+        Some(threshold) => rt.with_tile_splitting(threshold),
+        None => rt,
+    };
+    let rt = if params.nice { rt.with_nice() } else { rt };
+    let rt = rt.with_sampler(params.sampler);
+    let rt = rt.with_path_filters(params.paths.iter().map(|(_, filter)| filter.clone()).collect());
+    let last_logged = AtomicUsize::new(0);
+    let (width, height) = (params.render.image_width, params.render.image_height);
+    let mut accumulator = Accumulator::new(width, height);
+    let mut buffers: Vec<Accumulator> =
+        (0..params.light_groups.max(params.paths.len())).map(|_| Accumulator::new(width, height)).collect();
+    let logger = |_, total| {
+        const R: Ordering = Ordering::Relaxed;
+        let _ = remaining_count.compare_exchange(usize::MAX, total, R, R);
+        let remaining = remaining_count.fetch_sub(1, R) - 1;
+        if remaining == 0 {
+            logging::progress("Done!");
+            return;
+        }
+        let elapsed = start_time.elapsed().as_millis() as usize;
+        let ll = last_logged.load(R);
+        if ll < elapsed && elapsed - ll > 300 {
+            match last_logged.compare_exchange_weak(ll, elapsed, R, R) {
+                Err(_) => return, // Someone got to print first, exiting.
+                Ok(_) => logging::progress(&format!("Remaining: {:3}%", remaining * 100 / total)),
+            }
+        }
+    };
+    if params.light_tracing {
+        rt.accumulate_light_paths(&mut accumulator, lights, params.max_depth, logger);
+    } else if progressive {
+        accumulate_progressively(&rt, &mut accumulator, &mut buffers, params, deadline);
+    } else if buffers.is_empty() {
+        rt.accumulate(&mut accumulator, logger);
+    } else {
+        rt.accumulate_light_groups(&mut accumulator, &mut buffers, logger);
+    }
+    if let Some((samples, threshold)) = params.edge_samples {
+        let sampled = rt.accumulate_edges(&mut accumulator, samples, threshold, |_, _| {});
+        logging::progress(&format!("Edges: {} of {} pixels", sampled, width * height));
+    }
+    logging::log(Level::Verbose, Event::Timing("render", start_time.elapsed()));
+    let aovs = match params.exr.is_some() || params.dof_preview || params.alpha {
+        true => logging::timed(Level::Verbose, "render AOVs", || {
+            rt.render_aovs(accumulator.samples_per_pixel().min(AOV_SAMPLES))
+        }),
+        false => Vec::new(),
+    };
+    View { accumulator, buffers, aovs }
+}
+
+// Passes until the samples, the time limit or the controls finish the render, saving checkpoints in between.
+fn accumulate_progressively<RT: RayTracer, T: Rngator>(
+    rt: &Renderer<RT, T>,
+    accumulator: &mut Accumulator,
+    buffers: &mut [Accumulator],
+    params: &Parameters,
+    deadline: Option<Instant>,
+) {
+    let max_samples = params.render.samples_per_pixel;
+    // Without a time limit, the controls finish the render.
+    let mut deadline = deadline.unwrap_or_else(|| Instant::now() + Duration::from_secs(365 * 24 * 3600));
+    let mut last_checkpoint = Instant::now();
+    loop {
+        // Pauses don't count towards the time limit, so the passes start over with a later deadline.
+        let mut resumed = false;
+        rt.accumulate_until(accumulator, buffers, deadline, max_samples, |a| {
+            logging::progress(&format!("Samples: {:6}", a.samples_per_pixel()));
+            if let Some(checkpoint) = params.checkpoint.as_ref().filter(|c| last_checkpoint.elapsed() >= c.every) {
+                save_snapshot(&checkpoint.path, a, &params.render.post);
+                last_checkpoint = Instant::now();
+            }
+            let (go_on, paused) = match &params.controls {
+                Some(controls) => controls.after_pass(a, &params.render.post),
+                None => (true, None),
+            };
+            if let (true, Some(paused)) = (go_on, paused) {
+                deadline += paused;
+                resumed = true;
+            }
+            go_on && !resumed
+        });
+        if !resumed {
+            break;
+        }
+    }
+}
+
+fn do_tracing<T>(
+    params: Parameters,
+    cameras: &[Camera],
+    world: &dyn hittable::Hittable,
+    lights: &dyn hittable::Hittable,
+    background: &dyn raytrace::Background,
+    tracer: &dyn RayTracer,
+    rngator: T,
+) where
+    T: Rngator,
+{
+    let start_time = Instant::now();
+    // With a time limit, every camera gets an equal share of it.
+    let deadline = |k: usize| {
+        params.time_limit.map(|t| start_time + Duration::from_secs_f64(t * (k + 1) as f64 / cameras.len() as f64))
+    };
+    let views: Vec<View> = cameras
+        .iter()
+        .enumerate()
+        .map(|(k, camera)| render_view(&params, camera, world, lights, background, tracer, &rngator, deadline(k)))
+        .collect();
+    logging::info(&format!("Rendered in {:.3}s", start_time.elapsed().as_secs_f32()));
+    let save_time = Instant::now();
+    if params.tile_report || params.tile_csv.is_some() {
+        // Of the left eye with --stereo.
+        report_tiles(&params, views[0].accumulator.tile_times());
+    }
+
+    let to_linear = |view: &View| {
+        let linear = view.accumulator.to_linear();
+        if !params.dof_preview {
+            return linear;
+        }
+        let depth: Vec<Vec<f64>> = view.aovs.iter().map(|line| line.iter().map(|aov| aov.depth).collect()).collect();
+        DepthOfField::new(&params.camera).apply(&linear, &depth)
+    };
+    let to_rgb = |view: &View| params.render.post.apply(&to_linear(view));
+
+    // Stereo pairs go side by side into every output, unless the right eye is written separately. Every output is
+    // developed from the same linear image, blurred by depth eye by eye in a depth of field preview.
+    let (view, linear) = match (views.as_slice(), &params.stereo) {
+        ([left, right], Some(stereo)) => match &stereo.right {
+            Some(path) => {
+                output::save_image(path, &to_rgb(right)).unwrap();
+                let left = views.into_iter().next().unwrap();
+                let linear = to_linear(&left);
+                (left, linear)
+            }
+            None => (left.side_by_side(right), raytrace::side_by_side(&to_linear(left), &to_linear(right))),
+        },
+        _ => {
+            let view = views.into_iter().next().unwrap();
+            let linear = to_linear(&view);
+            (view, linear)
+        }
+    };
+    if let Some(path) = &params.exr {
+        let names = match params.paths.is_empty() {
+            true => (0..params.light_groups).map(|g| format!("light_{}", g)).collect(),
+            false => params.paths.iter().map(|(name, _)| name.clone()).collect::<Vec<_>>(),
+        };
+        let buffers: Vec<_> = names.into_iter().zip(&view.buffers).map(|(n, b)| (n, b.to_linear())).collect();
+        let variance = if params.variance { view.accumulator.variance() } else { Vec::new() };
+        let linear = view.accumulator.to_linear();
+        output::save_exr(path, &linear, &view.aovs, &buffers, &variance, params.alpha).unwrap();
+    }
+    if let Some(path) = &params.heatmap {
+        output::save_heatmap(path, view.accumulator.counts()).unwrap();
+    }
+    let alpha: Vec<Vec<f64>> = view.aovs.iter().map(|line| line.iter().map(|aov| aov.alpha).collect()).collect();
+    for out in params.outputs.iter() {
+        let post = out.post(&params.render.post);
+        match out.bits {
+            16 => {
+                let alpha = if params.alpha { Some(alpha.as_slice()) } else { None };
+                output::save_image16(&out.path, &post.apply16(&linear), alpha).unwrap()
+            }
+            _ if params.alpha => output::save_image_with_alpha(&out.path, &post.apply(&linear), &alpha).unwrap(),
+            _ => output::save_image(&out.path, &post.apply(&linear)).unwrap(),
+        }
+    }
+    let image = params.render.post.apply(&linear);
+    println!("P3\n{} {}\n255", image[0].len(), image.len());
+    for line in image.iter().rev() {
+        for (r, g, b) in line.iter() {
+            println!("{} {} {}", r, g, b);
+        }
+    }
+    logging::log(Level::Verbose, Event::Timing("save", save_time.elapsed()));
+}
+
+fn report_tiles(params: &Parameters, times: &[f64]) {
+    if times.is_empty() {
+        logging::warning("No tile times: light groups, paths and splatting filters render by lines");
+        return;
+    }
+    let (width, height) = (params.render.image_width, params.render.image_height);
+    if params.tile_report {
+        for line in tiles::report(width, height, times, TILE_REPORT_LENGTH) {
+            logging::info(&line);
+        }
+    }
+    if let Some(path) = &params.tile_csv {
+        if let Err(e) = std::fs::write(path, tiles::csv(width, height, times)) {
+            logging::warning(&format!("Can't save {}: {}", path, e));
+        }
+    }
+}
+
+// Renders the frames along a camera path and saves them as images, or encodes them into a video.
+fn do_animation<T>(
+    params: &Parameters,
+    path: &CameraPath,
+    world: &dyn hittable::Hittable,
+    lights: &dyn hittable::Hittable,
+    background: &dyn raytrace::Background,
+    tracer: &dyn RayTracer,
+    rngator: T,
+) where
+    T: Rngator,
+{
+    let frame_time = 1.0 / params.fps;
+    let frames = ((path.end() - path.start()) * params.fps + 1e-9).floor() as usize + 1;
+    let mut video = params.video.as_ref().map(|file| {
+        let (width, height) = (params.render.image_width, params.render.image_height);
+        output::Video::ffmpeg(file, width, height, params.fps)
+            .unwrap_or_else(|e| panic!("can't start ffmpeg for {}: {}", file, e))
+    });
+    for frame in 0..frames {
+        let time = path.start() + frame as f64 * frame_time;
+        let (open, close) = (time + params.render.shutter_open, time + params.render.shutter_close);
+        let mut camera = path.camera_at(open, &params.camera).camera();
+        if close > open {
+            camera = camera.moving_to(path.camera_at(close, &params.camera).camera(), open, close);
+        }
+        let render = raytrace::RenderingParams { shutter_open: open, shutter_close: close, ..params.render };
+        let frame_params = Parameters { render, ..params.clone() };
+        logging::info(&format!("Frame {}/{}", frame + 1, frames));
+        let deadline = params.time_limit.map(|t| Instant::now() + Duration::from_secs_f64(t));
+        let view = render_view(&frame_params, &camera, world, lights, background, tracer, &rngator, deadline);
+        let image = view.accumulator.to_rgb(&params.render.post);
+        if let Some(frames) = &params.frames {
+            output::save_image(&frames.file(frame), &image).unwrap();
+        }
+        if let Some(video) = &mut video {
+            video.add_frame(&image).unwrap();
+        }
+    }
+    if let Some(video) = video {
+        video.finish().unwrap();
+    }
+}
+
+// Prints the stats of the world and returns false if there are problems.
+fn do_validate(parameters: &Parameters, world: &dyn hittable::Hittable, lights: &hittable::HittableList) -> bool {
+    let mut stats = validate::SceneStats::new();
+    world.inspect(&mut stats);
+    let camera = &parameters.camera;
+    stats.check_point("camera position", &camera.lookfrom);
+    stats.check_point("camera target", &camera.lookat);
+    if camera.lookfrom == camera.lookat {
+        stats.problem(format!("camera looks at its own position {}", camera.lookfrom));
+    }
+    if lights.is_empty() && parameters.light_sampling {
+        stats.problem(format!("world {} has no lights to sample", parameters.world.name()));
+    }
+
+    println!("world:     {}", parameters.world.name());
+    println!("lights:    {}", lights.len());
+    print!("{}", stats);
+    stats.problems.is_empty()
+}
+
+// Prints how much two images differ, see `compare::Difference`.
+fn do_diff(diff: &Diff) {
+    let open = |path: &str| image::open(path).unwrap_or_else(|e| panic!("can't read {}: {}", path, e)).to_rgb8();
+    let (a, b) = (open(&diff.a), open(&diff.b));
+    print!("{}", compare::compare(&a, &b).unwrap());
+    if let Some(path) = &diff.heatmap {
+        compare::heatmap(&a, &b).unwrap().save(path).unwrap();
+    }
+}
+
+// Converts an image into the tiled file of `tiled_image::TiledImage`.
+fn do_tile_texture(input: &str, output: &str, tile_size: usize) {
+    #[cfg(not(target_arch = "wasm32"))]
+    let converted = crate::tiled_image::write(input, output, tile_size);
+    // Tiled textures are mapped into memory, which the browser can't.
+    #[cfg(target_arch = "wasm32")]
+    let converted = Err::<(), _>(format!("{} to {} in tiles of {}: not in the browser", input, output, tile_size));
+    if let Err(e) = converted {
+        eprintln!("Can't convert the texture: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn do_it<T>(mut parameters: Parameters, rngator: T)
+where
+    T: Rngator,
+{
+    let mut rng = rngator.rng(0);
+
+    if let Some(diff) = &parameters.diff {
+        do_diff(diff);
+        return;
+    }
+
+    if let Some((input, output, tile_size)) = &parameters.tile_texture {
+        do_tile_texture(input, output, *tile_size);
+        return;
+    }
+
+    if parameters.list_worlds {
+        for world in worlds::worlds() {
+            print!("{}", worlds::summary(world.as_ref()));
+        }
+        return;
+    }
+
+    if let Some(path) = &parameters.export_scene {
+        let written = scene::export(parameters.world.as_ref(), &parameters.camera, &mut rng)
+            .and_then(|text| std::fs::write(path, text).map_err(|e| format!("can't write {}: {}", path, e)));
+        if let Err(e) = written {
+            eprintln!("Can't export the scene: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(address) = &parameters.http {
+        let listener = std::net::TcpListener::bind(address).unwrap_or_else(|e| {
+            eprintln!("Can't listen on {}: {}", address, e);
+            std::process::exit(1);
+        });
+        logging::info(&format!("Listening on http://{}", address));
+        let files = match &parameters.http_files {
+            Some(dir) => scene::Files::Under(dir.into()),
+            None => scene::Files::Nowhere,
+        };
+        http::serve(listener, files).unwrap();
+        return;
+    }
+
+    if let Some((path, world)) = &parameters.bake {
+        let render = &parameters.render;
+        let texels = world.bake(render.image_width, render.image_height, &mut rng);
+        let rgb: Vec<Vec<_>> = texels
+            .iter()
+            .enumerate()
+            .map(|(j, line)| line.iter().enumerate().map(|(i, c)| render.post.to_rgb(c, i, j)).collect())
+            .collect();
+        output::save_image(path, &rgb).unwrap();
+        return;
+    }
+
+    // World
+    let world = logging::timed(Level::Verbose, "build world", || parameters.world.build(parameters.bvh, &mut rng));
+    logging::log(Level::Verbose, Event::Timing("build world BVHs", bhv::build_time()));
+    let background = match &parameters.background {
+        None => parameters.world.background(),
+        Some(background) => background.background(),
+    };
+    let lights = logging::timed(Level::Verbose, "build lights", || parameters.world.lights(parameters.bvh, &mut rng));
+    // Single scattering is done by the light sampling tracer, otherwise the fog goes around the world.
+    let light_sampling = parameters.light_sampling && !lights.is_empty();
+    let tracer_fog = if parameters.single_scattering && light_sampling { parameters.fog } else { None };
+    let world: Box<dyn hittable::Hittable> = match parameters.fog {
+        Some(fog) if tracer_fog.is_none() => Box::new(volumes::Fog::new(world, fog)),
+        _ => world,
+    };
+
+    if parameters.memory {
+        let mut stats = validate::SceneStats::new();
+        world.inspect(&mut stats);
+        logging::info(&format!("World memory: {}", stats.memory_summary()));
+    }
+
+    if parameters.light_tracing && lights.is_empty() {
+        eprintln!("World {} has no lights to trace paths from", parameters.world.name());
+        std::process::exit(1);
+    }
+
+    if parameters.validate {
+        if !do_validate(&parameters, world.as_ref(), &lights) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let missing = image_texture::take_missing();
+    if !missing.is_empty() {
+        eprintln!("Can't render without the textures {}", missing.join(", "));
+        std::process::exit(1);
+    }
+
+    if let Some(pixel) = parameters.autofocus {
+        let size = (parameters.render.image_width, parameters.render.image_height);
+        match parameters.camera.autofocus(world.as_ref(), pixel, size, &mut rng) {
+            Some(focus_dist) => {
+                logging::info(&format!("Autofocus: focus_dist={:.4}", focus_dist));
+                parameters.camera.focus_dist = focus_dist;
+            }
+            None => logging::warning(&format!(
+                "Autofocus: nothing at {},{}, keeping focus_dist={:.4}",
+                pixel.0, pixel.1, parameters.camera.focus_dist
+            )),
+        }
+    }
+
+    let lights: Box<dyn hittable::Hittable> = match parameters.light_tree {
+        true => Box::new(logging::timed(Level::Verbose, "build light tree", || LightTree::new(lights, &mut rng))),
+        false => Box::new(lights),
+    };
+    let (max_depth, max_bounces, check_nan) = (parameters.max_depth, parameters.max_bounces, parameters.check_nan);
+    let regularization = parameters.regularization;
+    let recursive = RecursiveRayTracer { max_depth, max_bounces, regularization, check_nan };
+    let light_sampling_tracer = LightSamplingRayTracer {
+        max_depth,
+        max_bounces,
+        regularization,
+        lights: lights.as_ref(),
+        check_nan,
+        fog: tracer_fog,
+    };
+    let tracer: &dyn RayTracer = if light_sampling { &light_sampling_tracer } else { &recursive };
+
+    if parameters.server {
+        let (world, background) = (world.as_ref(), background.as_ref());
+        let (render, camera) = (parameters.render, parameters.camera);
+        let stdin = std::io::stdin();
+        let dof_preview = parameters.dof_preview;
+        let result = if parameters.randomized_rendering {
+            server::Server::new(world, background, render, camera, tracer, rngator::ThreadRngator {})
+                .with_dof_preview(dof_preview)
+                .run(stdin.lock(), std::io::stdout())
+        } else {
+            server::Server::new(world, background, render, camera, tracer, rngator)
+                .with_dof_preview(dof_preview)
+                .run(stdin.lock(), std::io::stdout())
+        };
+        result.unwrap();
+        return;
+    }
+
+    if let Some(controls) = &parameters.controls {
+        controls.listen(std::io::stdin());
+    }
+
+    if let Some(path) = &parameters.camera_path {
+        let path = CameraPath::load(path).unwrap_or_else(|e| {
+            eprintln!("Can't read the camera path: {}", e);
+            std::process::exit(1);
+        });
+        if parameters.randomized_rendering {
+            do_animation(
+                &parameters,
+                &path,
+                world.as_ref(),
+                lights.as_ref(),
+                background.as_ref(),
+                tracer,
+                rngator::ThreadRngator {},
+            );
+        } else {
+            do_animation(&parameters, &path, world.as_ref(), lights.as_ref(), background.as_ref(), tracer, rngator);
+        }
+        return;
+    }
+
+    // Camera; both eyes of a stereo pair share the world.
+    let cameras: Vec<Camera> = render_cameras(&parameters).iter().map(|camera| camera.camera()).collect();
+
+    if parameters.randomized_rendering {
+        do_tracing(
+            parameters,
+            &cameras,
+            world.as_ref(),
+            lights.as_ref(),
+            background.as_ref(),
+            tracer,
+            rngator::ThreadRngator {},
+        );
+    } else {
+        do_tracing(parameters, &cameras, world.as_ref(), lights.as_ref(), background.as_ref(), tracer, rngator);
+    }
+}
+
+// The cameras rays are shot from, one per eye of a stereo pair. A depth of field preview renders through pinholes
+// and blurs by depth afterwards.
+fn render_cameras(parameters: &Parameters) -> Vec<CameraParams> {
+    let camera = match parameters.dof_preview {
+        true => CameraParams { aperture: 0.0, ..parameters.camera },
+        false => parameters.camera,
+    };
+    match &parameters.stereo {
+        None => vec![camera],
+        Some(stereo) => {
+            let convergence = stereo.convergence.unwrap_or((camera.lookat - camera.lookfrom).length());
+            let eye = |offset: f64| camera.stereo_eye(offset, convergence);
+            vec![eye(-stereo.interocular / 2.0), eye(stereo.interocular / 2.0)]
+        }
+    }
+}
+
+// Does what the command line asks for on the threads it asks for.
+fn run_with(parameters: Parameters) {
+    par::with_threads(parameters.threads, || match parameters.seed {
+        None => do_it(parameters, rngator::ThreadRngator {}),
+        Some(seed) => do_it(parameters, rngator::SeedableRngator::new(seed)),
+    })
+}
+
+pub fn run() {
+    // Image
+    let parameters = args();
+    let asset_report = parameters.asset_report;
+    run_with(parameters);
+    if asset_report {
+        for line in assets::report() {
+            logging::info(&line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Parameters {
+        parameters(&app().get_matches_from(std::iter::once("raytracer").chain(args.iter().copied())))
+    }
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("cli_{}_{}", std::process::id(), name));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_parse_pixel() {
+        assert_eq!(Ok((200.0, 160.5)), parse_pixel("200,160.5"));
+        assert!(parse_pixel("10").is_err());
+        assert!(parse_pixel("1,2,3").is_err());
+        assert!(parse_pixel("1,y").is_err());
+        assert_eq!(Some((3.0, 4.0)), parse(&["--autofocus=3,4"]).autofocus);
+    }
+
+    #[test]
+    fn test_controls() {
+        let snapshot = temp_path("snapshot.png");
+        let parameters = parse(&["--controls", &snapshot]);
+        let controls = parameters.controls.unwrap();
+        let (accumulator, post) = (Accumulator::new(4, 3), PostProcess::new());
+        assert_eq!((true, None), controls.after_pass(&accumulator, &post));
+
+        // s saves the image so far once, q finishes.
+        controls.command("s");
+        controls.command("x");
+        assert_eq!((true, None), controls.after_pass(&accumulator, &post));
+        assert_eq!((4, 3), image::open(&snapshot).unwrap().to_rgb8().dimensions());
+        std::fs::remove_file(&snapshot).unwrap();
+        assert_eq!((true, None), controls.after_pass(&accumulator, &post));
+        assert!(std::fs::metadata(&snapshot).is_err());
+
+        // p blocks after the pass until it is given again, or q is.
+        for resume in ["p", "q"] {
+            controls.command("p");
+            let resumer = controls.clone();
+            let thread = std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(200));
+                resumer.command(resume);
+            });
+            let (go_on, paused) = controls.after_pass(&accumulator, &post);
+            thread.join().unwrap();
+            assert_eq!(resume == "p", go_on);
+            assert!(paused.unwrap() >= Duration::from_millis(150), "{:?}", paused);
+        }
+        assert_eq!((false, None), controls.after_pass(&accumulator, &post));
+
+        // Commands are read a line at a time.
+        let controls = Arc::new(Controls::new(snapshot));
+        controls.listen(std::io::Cursor::new("\n s \nq\n"));
+        let start = Instant::now();
+        while !controls.finish.load(Ordering::Relaxed) && start.elapsed() < Duration::from_secs(10) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(controls.save.load(Ordering::Relaxed) && controls.finish.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_pause_extends_time_limit() {
+        use crate::materials::Lambertian;
+        use crate::raytrace::{Bounces, RenderingParams, SolidBackground};
+        use crate::shapes::Sphere;
+        use crate::textures::SolidColor;
+        use crate::vec::Point3;
+        let parameters =
+            parse(&["--samples_per_pixel=1000000", "--time_limit=0.5", "--controls", &temp_path("paused.png")]);
+        let sphere = Sphere::new(Point3::ZERO, 1.0, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)));
+        let camera =
+            Camera::new(Point3::new(0.0, 0.0, 5.0), Point3::ZERO, Vec3::new(0.0, 1.0, 0.0), 60.0, 1.0, 0.0, 5.0);
+        let render = RenderingParams { samples_per_pixel: 1, image_width: 4, image_height: 4, ..parameters.render };
+        let tracer = RecursiveRayTracer {
+            max_depth: 3,
+            max_bounces: Bounces::UNLIMITED,
+            regularization: None,
+            check_nan: false,
+        };
+        let background = SolidBackground::new(Color::new(0.2, 0.4, 0.6));
+        let rngator = rngator::SeedableRngator::new(1);
+        let rt = Renderer::new_with_rng(&camera, &sphere, &background, render, tracer, rngator);
+
+        // Paused after the first pass for longer than the time limit, which then still has time for more passes.
+        let controls = parameters.controls.clone().unwrap();
+        controls.command("p");
+        let resumer = controls.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(800));
+            resumer.command("p");
+        });
+        let mut accumulator = Accumulator::new(4, 4);
+        let start = Instant::now();
+        accumulate_progressively(
+            &rt,
+            &mut accumulator,
+            &mut [],
+            &parameters,
+            Some(start + Duration::from_secs_f64(0.5)),
+        );
+        assert!(start.elapsed() >= Duration::from_millis(1200), "{:?}", start.elapsed());
+        assert!(accumulator.samples_per_pixel() > 1);
+    }
+
+    #[test]
+    fn test_check_filter() {
+        let check = |args: &[&str]| {
+            check_filter(&app().get_matches_from(["raytracer", "--exr=a.exr"].iter().chain(args.iter()))).is_ok()
+        };
+        assert!(check(&["--filter=gaussian"]));
+        assert!(check(&["--filter=box", "--light_groups=2"]));
+        assert!(check(&["--filter=gaussian", "--importance_filter", "--path=CDL"]));
+        assert!(!check(&["--filter=gaussian", "--light_groups=2"]));
+        assert!(!check(&["--filter=tent", "--path=CDL"]));
+    }
+
+    #[test]
+    fn test_bvh_config() {
+        assert_eq!(bhv::BvhConfig::default(), parse(&[]).bvh);
+        let config = parse(&["--bvh_leaf_size=8", "--bvh_builder=sah"]).bvh;
+        assert_eq!(bhv::BvhConfig { builder: bhv::BvhBuilder::Sah, leaf_size: 8 }, config);
+    }
+
+    #[test]
+    fn test_parse_output() {
+        let output = parse_output("hdr.png,exposure=1:0:-1,gamma=1.8,bits=16");
+        assert_eq!("hdr.png", output.path);
+        assert_eq!(Some(Color::new(2.0, 1.0, 0.5)), output.exposure);
+        assert_eq!((Some(1.8), 16), (output.gamma, output.bits));
+        let plain = parse_output("plain.jpg");
+        assert_eq!((None, None, 8), (plain.exposure, plain.gamma, plain.bits));
+        assert_eq!(Some(Color::new(0.5, 0.5, 0.5)), parse_output("dark.jpg,exposure=-1").exposure);
+        for bad in ["deep.jpg,bits=16", "a.png,bits=12", "a.png,contrast=2"] {
+            assert!(std::panic::catch_unwind(|| parse_output(bad)).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_parse_checkpoint() {
+        let checkpoint = parse_checkpoint("render.png");
+        assert_eq!(("render.png", Duration::from_secs(60)), (checkpoint.path.as_str(), checkpoint.every));
+        assert_eq!(Duration::from_millis(2500), parse_checkpoint("render.png,every=2.5").every);
+        for bad in ["render.png,often=1", "render.png,every=soon"] {
+            assert!(std::panic::catch_unwind(|| parse_checkpoint(bad)).is_err(), "{}", bad);
+        }
+        assert!(parse(&["--checkpoint=render.png"]).checkpoint.is_some());
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        use crate::materials::Lambertian;
+        use crate::raytrace::{Bounces, RenderingParams, SolidBackground};
+        use crate::shapes::Sphere;
+        use crate::textures::SolidColor;
+        use crate::vec::Point3;
+        let path = temp_path("checkpoint.png");
+        let checkpoint = format!("--checkpoint={},every=0", path);
+        let parameters = parse(&["--samples_per_pixel=5", "--exposure=1", &checkpoint]);
+        let sphere = Sphere::new(Point3::ZERO, 1.0, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)));
+        let camera =
+            Camera::new(Point3::new(0.0, 0.0, 5.0), Point3::ZERO, Vec3::new(0.0, 1.0, 0.0), 60.0, 1.0, 0.0, 5.0);
+        let render = RenderingParams { samples_per_pixel: 1, image_width: 6, image_height: 4, ..parameters.render };
+        let tracer = RecursiveRayTracer {
+            max_depth: 3,
+            max_bounces: Bounces::UNLIMITED,
+            regularization: None,
+            check_nan: false,
+        };
+        let background = SolidBackground::new(Color::new(0.2, 0.4, 0.6));
+        let rt =
+            Renderer::new_with_rng(&camera, &sphere, &background, render, tracer, rngator::SeedableRngator::new(1));
+
+        // Saved after every pass, the last checkpoint is the finished image, developed like it.
+        let mut accumulator = Accumulator::new(6, 4);
+        accumulate_progressively(&rt, &mut accumulator, &mut [], &parameters, None);
+        assert_eq!(5, accumulator.samples_per_pixel());
+        let saved = image::open(&path).unwrap().to_rgb8();
+        let developed = accumulator.to_rgb(&parameters.render.post);
+        for (j, line) in developed.iter().enumerate() {
+            for (i, &(r, g, b)) in line.iter().enumerate() {
+                assert_eq!([r as u8, g as u8, b as u8], saved.get_pixel(i as u32, 3 - j as u32).0);
+            }
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_dof_preview() {
+        let args = [
+            "--world=simple",
+            "--image_width=24",
+            "--aspect_ratio=3:2",
+            "--samples_per_pixel=2",
+            "--max_depth=3",
+            "--no_dither",
+            "--aperture=0.5",
+            "--dof_preview",
+            "--stereo=0.3",
+        ];
+        let cameras = render_cameras(&parse(&args));
+        assert_eq!(2, cameras.len());
+        assert!(cameras.iter().all(|camera| camera.aperture == 0.0));
+
+        // Each eye is blurred on its own, as when the right one is saved separately.
+        let (joined, left, right) = (temp_path("joined.png"), temp_path("left.png"), temp_path("right.png"));
+        let output = format!("--output={}", joined);
+        do_it(parse(&[&args[..], &[output.as_str()]].concat()), rngator::SeedableRngator::new(7));
+        let (output, stereo_right) = (format!("--output={}", left), format!("--stereo_right={}", right));
+        do_it(
+            parse(&[&args[..], &[output.as_str(), stereo_right.as_str()]].concat()),
+            rngator::SeedableRngator::new(7),
+        );
+        let open = |path: &str| image::open(path).unwrap().to_rgb8();
+        let (joined, left, right) = (open(&joined), open(&left), open(&right));
+        assert_eq!((48, 16), joined.dimensions());
+        for (x, y, pixel) in joined.enumerate_pixels() {
+            let eye = if x < 24 { left.get_pixel(x, y) } else { right.get_pixel(x - 24, y) };
+            assert_eq!(eye, pixel, "at {},{}", x, y);
+        }
+        for path in [&temp_path("joined.png"), &temp_path("left.png"), &temp_path("right.png")].iter() {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_threads_and_nice() {
+        let parameters = parse(&["--threads=2", "--nice"]);
+        assert_eq!((Some(2), true), (parameters.threads, parameters.nice));
+        let parameters = parse(&["--threads=0"]);
+        assert_eq!((Some(1), false), (parameters.threads, parameters.nice));
+        assert_eq!(None, parse(&[]).threads);
+        #[cfg(feature = "parallel")]
+        assert_eq!(2, par::with_threads(parse(&["--threads=2"]).threads, par::current_num_threads));
+
+        // Yielding and fewer threads don't change the image.
+        let args = ["--world=simple", "--image_width=16", "--samples_per_pixel=2", "--max_depth=3", "--seed=3"];
+        let (nice, plain) = (temp_path("nice.png"), temp_path("plain.png"));
+        let output = format!("--output={}", nice);
+        run_with(parse(&[&args[..], &["--threads=2", "--nice", output.as_str()]].concat()));
+        let output = format!("--output={}", plain);
+        run_with(parse(&[&args[..], &[output.as_str()]].concat()));
+        assert_eq!(image::open(&plain).unwrap().to_rgb8(), image::open(&nice).unwrap().to_rgb8());
+        std::fs::remove_file(nice).unwrap();
+        std::fs::remove_file(plain).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+// Comparing renders, e.g. to check that an optimization changes the output by no more than noise.
+use crate::output;
+use image::RgbImage;
+use std::fmt;
+
+// How much two 8-bit images differ, over all channels of all pixels.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Difference {
+    // Root mean square difference, in 8-bit levels.
+    pub rmse: f64,
+    // Peak signal to noise ratio in dB; infinite for identical images.
+    pub psnr: f64,
+    // Largest difference of a single channel.
+    pub max: u8,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "rmse: {:.4}", self.rmse)?;
+        writeln!(f, "psnr: {:.2} dB", self.psnr)?;
+        writeln!(f, "max:  {}", self.max)
+    }
+}
+
+fn check_dimensions(a: &RgbImage, b: &RgbImage) -> Result<(), String> {
+    if a.dimensions() != b.dimensions() {
+        return Err(format!("image sizes differ: {:?} and {:?}", a.dimensions(), b.dimensions()));
+    }
+    Ok(())
+}
+
+pub fn compare(a: &RgbImage, b: &RgbImage) -> Result<Difference, String> {
+    check_dimensions(a, b)?;
+    let mut squares = 0.0;
+    let mut max = 0;
+    for (&x, &y) in a.as_raw().iter().zip(b.as_raw()) {
+        let d = (x as i32 - y as i32).unsigned_abs() as u8;
+        squares += (d as f64).powi(2);
+        max = max.max(d);
+    }
+    let rmse = (squares / a.as_raw().len().max(1) as f64).sqrt();
+    Ok(Difference { rmse, psnr: 20.0 * (255.0 / rmse).log10(), max })
+}
+
+// Largest channel difference of each pixel, from black (none) through red and yellow to white (the largest
+// in the image).
+pub fn heatmap(a: &RgbImage, b: &RgbImage) -> Result<RgbImage, String> {
+    check_dimensions(a, b)?;
+    let difference = |x: u32, y: u32| {
+        let (p, q) = (a.get_pixel(x, y), b.get_pixel(x, y));
+        (0..3).map(|c| (p[c] as i32 - q[c] as i32).abs()).max().unwrap()
+    };
+    let max = compare(a, b)?.max.max(1) as f64;
+    Ok(RgbImage::from_fn(a.width(), a.height(), |x, y| {
+        let (r, g, b) = output::heat(difference(x, y) as f64 / max);
+        image::Rgb([r as u8, g as u8, b as u8])
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        let a = RgbImage::from_pixel(4, 4, image::Rgb([100, 100, 100]));
+        let mut b = a.clone();
+        assert_eq!(f64::INFINITY, compare(&a, &b).unwrap().psnr);
+
+        b.put_pixel(1, 2, image::Rgb([100, 116, 100]));
+        let difference = compare(&a, &b).unwrap();
+        assert_eq!(16, difference.max);
+        assert_eq!((16.0 * 16.0 / 48.0f64).sqrt(), difference.rmse);
+        assert!((difference.psnr - 40.86).abs() < 0.01, "{}", difference.psnr);
+
+        let heat = heatmap(&a, &b).unwrap();
+        assert_eq!(image::Rgb([255, 255, 255]), *heat.get_pixel(1, 2));
+        assert_eq!(image::Rgb([0, 0, 0]), *heat.get_pixel(0, 0));
+        assert!(compare(&a, &RgbImage::new(4, 3)).is_err());
+    }
+}
@@ -0,0 +1,358 @@
+// Curves for hair, fur, grass and fibers: cubic Bezier segments with a width that changes linearly along them.
+// They are intersected by recursive subdivision in a frame where the ray runs along z, as in pbrt: once a piece
+// is flat enough it is treated as a line segment, and hit if the ray passes within half the width of it.
+use crate::bhv::{Bounded, BvhConfig, SceneBuilder, AABB, BHV};
+use crate::hittable::{Hit, Hittable};
+use crate::interval::Interval;
+use crate::materials::Material;
+use crate::math::Onb;
+use crate::validate::SceneStats;
+use crate::vec::{Point3, Ray, Vec3};
+use rand::Rng;
+use std::sync::Arc;
+
+// How the width of a curve is shaded: flat ribbons always face the ray, cylinders bend the normal across the
+// width so that they look round, e.g. for hair.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CurveShape {
+    Flat,
+    Cylinder,
+}
+
+// One cubic Bezier segment, `width` wide at its start and end.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CurveSegment {
+    pub control: [Point3; 4],
+    pub width: (f64, f64),
+}
+
+// Subdivisions are limited to this depth, i.e. 1024 pieces per segment.
+const MAX_DEPTH: i32 = 10;
+
+impl CurveSegment {
+    pub fn new(control: [Point3; 4], width: (f64, f64)) -> CurveSegment {
+        CurveSegment { control, width }
+    }
+
+    pub fn point(&self, u: f64) -> Point3 {
+        bezier(&self.control, u)
+    }
+
+    pub fn tangent(&self, u: f64) -> Vec3 {
+        let c = &self.control;
+        let a = lerp(c[1] - c[0], c[2] - c[1], u);
+        let b = lerp(c[2] - c[1], c[3] - c[2], u);
+        3.0 * lerp(a, b, u)
+    }
+
+    fn width_at(&self, u: f64) -> f64 {
+        (1.0 - u) * self.width.0 + u * self.width.1
+    }
+
+    fn max_width(&self) -> f64 {
+        self.width.0.max(self.width.1)
+    }
+
+    // Ray distance (t times the length of the ray's direction) and curve parameter u of the closest hit.
+    fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<(f64, f64)> {
+        let length = r.dir.length();
+        let frame = Onb::new(r.dir);
+        let cp = self.control.map(|p| frame.to_local(p - r.orig));
+        // Subdivide until the pieces deviate from straight lines by less than a twentieth of the width.
+        let mut l0: f64 = 0.0;
+        for i in 0..2 {
+            let d = cp[i] - 2.0 * cp[i + 1] + cp[i + 2];
+            l0 = l0.max(d.x().abs()).max(d.y().abs()).max(d.z().abs());
+        }
+        let epsilon = 0.05 * self.max_width();
+        let pieces = std::f64::consts::SQRT_2 * 6.0 * l0 / (8.0 * epsilon);
+        let depth = if pieces > 1.0 { ((pieces.log2() / 2.0) as i32).min(MAX_DEPTH) } else { 0 };
+        let z_range = Interval::new(ray_t.min * length, ray_t.max * length);
+        self.intersect_piece(&cp, (0.0, 1.0), depth, z_range)
+    }
+
+    // Closest hit of the piece `cp` (in the ray's frame) between curve parameters `u`, and its distance along the
+    // ray in `z_range`.
+    fn intersect_piece(&self, cp: &[Vec3; 4], u: (f64, f64), depth: i32, z_range: Interval) -> Option<(f64, f64)> {
+        let half_width = 0.5 * self.width_at(u.0).max(self.width_at(u.1));
+        let bounds = cp.iter().fold(AABB::EMPTY, |b, p| b.surround(&AABB::new(*p, *p)));
+        let reaches = |a: usize, range: Interval| {
+            bounds.minimum.e[a] - half_width <= range.max && range.min <= bounds.maximum.e[a] + half_width
+        };
+        let origin = Interval::new(0.0, 0.0);
+        if !(reaches(0, origin) && reaches(1, origin) && reaches(2, z_range)) {
+            return None;
+        }
+        if depth > 0 {
+            let (first, second) = split(cp);
+            let middle = 0.5 * (u.0 + u.1);
+            let near = self.intersect_piece(&first, (u.0, middle), depth - 1, z_range);
+            let z_range = near.map_or(z_range, |(z, _)| z_range.with_max(z));
+            return self.intersect_piece(&second, (middle, u.1), depth - 1, z_range).or(near);
+        }
+        // Beyond the ends of the piece, where the tangents are perpendicular to the line to the ray, the
+        // neighbouring pieces take over.
+        if (cp[1].y() - cp[0].y()) * -cp[0].y() + cp[0].x() * (cp[0].x() - cp[1].x()) < 0.0
+            || (cp[2].y() - cp[3].y()) * -cp[3].y() + cp[3].x() * (cp[3].x() - cp[2].x()) < 0.0
+        {
+            return None;
+        }
+        let (dx, dy) = (cp[3].x() - cp[0].x(), cp[3].y() - cp[0].y());
+        let length_squared = dx * dx + dy * dy;
+        if length_squared == 0.0 {
+            return None;
+        }
+        let w = ((-cp[0].x() * dx - cp[0].y() * dy) / length_squared).clamp(0.0, 1.0);
+        let hit_u = u.0 + w * (u.1 - u.0);
+        let p = bezier(cp, w);
+        let hit_width = self.width_at(hit_u);
+        if p.x() * p.x() + p.y() * p.y() > 0.25 * hit_width * hit_width || !z_range.surrounds(p.z()) {
+            return None;
+        }
+        Some((p.z(), hit_u))
+    }
+
+    fn bounding_box(&self) -> AABB {
+        let half_width = 0.5 * self.max_width();
+        let grow = Vec3::new(half_width, half_width, half_width);
+        let box_of = |p: Point3| AABB::new(p - grow, p + grow);
+        self.control.iter().fold(AABB::EMPTY, |b, p| b.surround(&box_of(*p))).pad()
+    }
+}
+
+fn lerp(a: Vec3, b: Vec3, u: f64) -> Vec3 {
+    (1.0 - u) * a + u * b
+}
+
+fn bezier(cp: &[Vec3; 4], u: f64) -> Vec3 {
+    let (a, b, c) = (lerp(cp[0], cp[1], u), lerp(cp[1], cp[2], u), lerp(cp[2], cp[3], u));
+    lerp(lerp(a, b, u), lerp(b, c, u), u)
+}
+
+// The two halves of a Bezier curve, by de Casteljau's algorithm.
+fn split(cp: &[Vec3; 4]) -> ([Vec3; 4], [Vec3; 4]) {
+    let (a, b, c) = (lerp(cp[0], cp[1], 0.5), lerp(cp[1], cp[2], 0.5), lerp(cp[2], cp[3], 0.5));
+    let (d, e) = (lerp(a, b, 0.5), lerp(b, c, 0.5));
+    let middle = lerp(d, e, 0.5);
+    ([cp[0], a, d, middle], [middle, e, c, cp[3]])
+}
+
+// Segments of a set of curves, shared by the curves and their segments in the BVH.
+struct CurveGeometry {
+    segments: Vec<CurveSegment>,
+    shape: CurveShape,
+}
+
+// Segment of a set of curves, by index.
+struct CurvePiece<T: Material> {
+    geometry: Arc<CurveGeometry>,
+    material: Arc<T>,
+    segment: u32,
+}
+
+impl<T: Material> CurvePiece<T> {
+    fn segment(&self) -> &CurveSegment {
+        &self.geometry.segments[self.segment as usize]
+    }
+}
+
+impl<T: Material> Hittable for CurvePiece<T> {
+    fn hit(&self, r: &Ray, ray_t: Interval, _: &mut dyn rand::RngCore) -> Option<Hit<'_>> {
+        let segment = self.segment();
+        let (distance, u) = segment.intersect(r, ray_t)?;
+        let t = distance / r.dir.length();
+        let p = r.at(t);
+        let tangent = segment.tangent(u);
+        let side = tangent.cross(r.dir);
+        if side.near_zero() {
+            // Looking along the curve.
+            return Some(Hit::new_with_face_normal(&p, t, u, 0.5, &-r.dir.unit(), r, &*self.material));
+        }
+        let side = side.unit();
+        let mut normal = side.cross(tangent).unit();
+        if normal.dot(r.dir) > 0.0 {
+            normal = -normal;
+        }
+        // Where across the width the ray hit, from -1 to 1.
+        let width = segment.width_at(u);
+        let across = ((p - segment.point(u)).dot(side) / (0.5 * width)).clamp(-1.0, 1.0);
+        let h = Hit::new_with_face_normal(&p, t, u, 0.5 * (across + 1.0), &normal, r, &*self.material)
+            .with_tangents(tangent, width * side);
+        // The ribbon facing the ray shades as the cylinder around the curve.
+        Some(match self.geometry.shape {
+            CurveShape::Cylinder => h.with_shading_normal(&((1.0 - across * across).sqrt() * normal + across * side)),
+            _ => h,
+        })
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, _: &mut dyn rand::RngCore) -> bool {
+        self.segment().intersect(r, ray_t).is_some()
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        let segment = self.segment();
+        for p in segment.control.iter() {
+            stats.check_point("curve", p);
+        }
+        if !(segment.width.0 >= 0.0 && segment.width.1 >= 0.0) {
+            stats.problem(format!("curve at {} with width {:?}", segment.control[0], segment.width));
+        }
+        stats.object(std::mem::size_of_val(self), Some(segment.bounding_box()));
+        self.material.inspect(stats);
+    }
+}
+
+impl<T: Material> Bounded for CurvePiece<T> {
+    fn bounding_box(&self) -> AABB {
+        self.segment().bounding_box()
+    }
+}
+
+// Curves sharing one material and shape, in a BVH of their own.
+pub struct Curves<'a> {
+    segments: BHV<'a>,
+    geometry: Arc<CurveGeometry>,
+}
+
+impl<'a> Curves<'a> {
+    pub fn new<T: Material + 'a>(
+        segments: &[CurveSegment],
+        shape: CurveShape,
+        material: T,
+        bvh: BvhConfig,
+        rng: &mut dyn rand::RngCore,
+    ) -> Curves<'a> {
+        let geometry = Arc::new(CurveGeometry { segments: segments.to_vec(), shape });
+        let material = Arc::new(material);
+        let mut scene = SceneBuilder::with_config(bvh);
+        for segment in 0..segments.len() as u32 {
+            scene.add(CurvePiece { geometry: geometry.clone(), material: material.clone(), segment });
+        }
+        Curves { segments: BHV::new(&mut scene, rng), geometry }
+    }
+}
+
+impl<'b> Hittable for Curves<'b> {
+    fn hit<'a>(&'a self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> Option<Hit<'a>> {
+        self.segments.hit(r, ray_t, rng)
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> bool {
+        self.segments.hit_any(r, ray_t, rng)
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.geometry_memory += std::mem::size_of_val(self.geometry.segments.as_slice());
+        self.segments.inspect(stats);
+    }
+}
+
+impl<'b> Bounded for Curves<'b> {
+    fn bounding_box(&self) -> AABB {
+        self.segments.bounding_box()
+    }
+}
+
+// Reads curve segments, one per line: four control points and the width, or the widths at the start and end,
+// e.g. `0,0,0 0,1,0 0.2,2,0 0.5,3,0 0.1 0`. Empty lines and lines starting with '#' are skipped.
+pub fn parse_curves(s: &str) -> Result<Vec<CurveSegment>, String> {
+    let mut segments = Vec::new();
+    for (n, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |e: String| format!("line {}: {}", n + 1, e);
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 5 && fields.len() != 6 {
+            return Err(error(format!("expected 4 control points and 1 or 2 widths, got '{}'", line)));
+        }
+        let point = |i: usize| fields[i].parse::<Vec3>().map_err(error);
+        let number = |s: &str| s.parse::<f64>().map_err(|e| error(format!("'{}': {}", s, e)));
+        let start = number(fields[4])?;
+        let end = fields.get(5).map_or(Ok(start), |s| number(s))?;
+        segments.push(CurveSegment::new([point(0)?, point(1)?, point(2)?, point(3)?], (start, end)));
+    }
+    Ok(segments)
+}
+
+pub fn load_curves(path: &str) -> Result<Vec<CurveSegment>, String> {
+    parse_curves(&std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?)
+}
+
+// Blades of grass on the y = 0 plane within `area` of the origin in x and z, about `height` tall and `width`
+// wide at the base, tapering to a point and bending away from the vertical.
+pub fn grass(count: usize, area: f64, height: f64, width: f64, rng: &mut dyn rand::RngCore) -> Vec<CurveSegment> {
+    (0..count)
+        .map(|_| {
+            let base = Point3::new(rng.gen_range(-area..area), 0.0, rng.gen_range(-area..area));
+            let height = height * rng.gen_range(0.6..1.2);
+            let angle = rng.gen_range(0.0..std::f64::consts::TAU);
+            let lean = height * rng.gen_range(0.1..0.6) * Vec3::new(angle.cos(), 0.0, angle.sin());
+            let up = Vec3::new(0.0, height, 0.0);
+            let control = [base, base + up / 3.0 + 0.1 * lean, base + 2.0 * up / 3.0 + 0.4 * lean, base + up + lean];
+            CurveSegment::new(control, (width, 0.0))
+        })
+        .collect()
+}
+
+// Strands of fur growing out of a sphere, `length` long and `width` wide, drooping down a bit.
+pub fn fur(
+    center: Point3,
+    radius: f64,
+    count: usize,
+    length: f64,
+    width: f64,
+    rng: &mut dyn rand::RngCore,
+) -> Vec<CurveSegment> {
+    (0..count)
+        .map(|_| {
+            let normal = Vec3::random_unit_vector(rng);
+            let root = center + radius * normal;
+            let droop = Vec3::new(0.0, -0.5 * length, 0.0);
+            let tip = root + length * normal + droop;
+            let control = [root, root + length / 3.0 * normal, root + 2.0 * length / 3.0 * normal + droop / 2.0, tip];
+            CurveSegment::new(control, (width, 0.2 * width))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Lambertian;
+    use crate::textures::SolidColor;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_curve_hit() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        // An arc bending from the y axis towards +x, 0.2 wide at the bottom and nothing at the top.
+        let arc = CurveSegment::new(
+            [Point3::ZERO, Point3::new(0.0, 1.0, 0.0), Point3::new(0.5, 2.0, 0.0), Point3::new(1.5, 2.5, 0.0)],
+            (0.2, 0.0),
+        );
+        let material = Lambertian::new(SolidColor::new(0.5, 0.5, 0.5));
+        let curves = Curves::new(&[arc], CurveShape::Cylinder, material, BvhConfig::default(), &mut rng);
+        let shoot = |x: f64, y: f64, rng: &mut dyn rand::RngCore| {
+            curves.hit(&Ray::new(Point3::new(x, y, 5.0), Vec3::new(0.0, 0.0, -2.0)), Interval::FORWARD, rng)
+        };
+
+        // Near the bottom the curve runs along the y axis.
+        let h = shoot(0.05, 0.1, &mut rng).unwrap();
+        assert!((h.t - 2.5).abs() < 1e-6, "{}", h.t);
+        assert!(h.u < 0.1);
+        // The cylinder's normal leans towards the side that was hit.
+        assert!(h.normal.x() > 0.0 && h.normal.z() > 0.0);
+        assert!(shoot(0.15, 0.1, &mut rng).is_none());
+        // Up the curve it bends to the right and gets thinner.
+        let p = arc.point(0.7);
+        assert!(shoot(p.x(), p.y(), &mut rng).is_some());
+        assert!(shoot(p.x() + 0.08, p.y(), &mut rng).is_none());
+        assert!(curves.bounding_box().max().x() >= 1.5);
+
+        let parsed = parse_curves("# arc\n0,0,0 0,1,0 0.5,2,0 1.5,2.5,0 0.2 0\n").unwrap();
+        assert_eq!(vec![arc], parsed);
+        assert!(parse_curves("0,0,0 0,1,0 0.2").unwrap_err().starts_with("line 1"));
+    }
+}
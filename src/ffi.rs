@@ -0,0 +1,206 @@
+// C API for embedding the renderer in other programs through the cdylib, declared in include/raytracer.h; the
+// scenes are loaded from scene files, see `scene::SceneFile`. Functions returning int return 0 on success and -1
+// on errors, which `rt_last_error` then describes.
+use crate::output;
+use crate::scene::{self, SceneFile, SceneSettings};
+use std::cell::RefCell;
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::sync::Mutex;
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+// Called with the number of lines rendered so far, the total, and the user data given to `rt_render`. Calls don't
+// overlap, but come from the render threads.
+pub type ProgressCallback = Option<extern "C" fn(done: c_int, total: c_int, user: *mut c_void)>;
+
+// A built scene with the settings it is rendered with.
+pub struct Scene {
+    scene: scene::Scene,
+    settings: SceneSettings,
+}
+
+fn set_error(message: String) {
+    LAST_ERROR.with(|e| *e.borrow_mut() = CString::new(message.replace('\0', " ")).unwrap());
+}
+
+// Runs `f`, turning its errors and panics into -1 and the last error; panics must not unwind into C.
+fn status(f: impl FnOnce() -> Result<(), String>) -> c_int {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)) {
+        Ok(Ok(())) => 0,
+        Ok(Err(e)) => {
+            set_error(e);
+            -1
+        }
+        Err(panic) => {
+            let message = match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+                (Some(s), _) => s.to_string(),
+                (_, Some(s)) => s.clone(),
+                _ => "panic".to_string(),
+            };
+            set_error(format!("renderer panicked: {}", message));
+            -1
+        }
+    }
+}
+
+unsafe fn scene<'a>(scene: *mut Scene) -> Result<&'a mut Scene, String> {
+    scene.as_mut().ok_or_else(|| "scene is null".to_string())
+}
+
+// Description of the last error on this thread; valid until the next call that fails.
+#[no_mangle]
+pub extern "C" fn rt_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ptr())
+}
+
+// Loads the scene file at `path`, a NUL-terminated string, and builds its world; null on errors. Free it with
+// `rt_scene_free`.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_load(path: *const c_char) -> *mut Scene {
+    let mut loaded = None;
+    status(|| {
+        if path.is_null() {
+            return Err("path is null".to_string());
+        }
+        let path = CStr::from_ptr(path).to_str().map_err(|e| format!("path is not UTF-8: {}", e))?;
+        let scene = SceneFile::load(path)?.build();
+        loaded = Some(Box::new(Scene { scene, settings: SceneSettings::new() }));
+        Ok(())
+    });
+    loaded.map_or(std::ptr::null_mut(), Box::into_raw)
+}
+
+// Frees a scene from `rt_scene_load`, which must not be used afterwards; null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_free(scene: *mut Scene) {
+    if !scene.is_null() {
+        drop(Box::from_raw(scene));
+    }
+}
+
+// Samples per pixel, 100 by default.
+#[no_mangle]
+pub unsafe extern "C" fn rt_set_samples(s: *mut Scene, samples: c_int) -> c_int {
+    status(|| {
+        if samples <= 0 {
+            return Err(format!("samples {} is not positive", samples));
+        }
+        scene(s)?.settings.samples_per_pixel = samples as usize;
+        Ok(())
+    })
+}
+
+// Bounces of a ray at most, 50 by default.
+#[no_mangle]
+pub unsafe extern "C" fn rt_set_max_depth(s: *mut Scene, max_depth: c_int) -> c_int {
+    status(|| {
+        if max_depth <= 0 {
+            return Err(format!("max depth {} is not positive", max_depth));
+        }
+        scene(s)?.settings.max_depth = max_depth;
+        Ok(())
+    })
+}
+
+// Seed of the random numbers, 0 by default; the same seed renders the same image.
+#[no_mangle]
+pub unsafe extern "C" fn rt_set_seed(s: *mut Scene, seed: u64) -> c_int {
+    status(|| {
+        scene(s)?.settings.seed = seed;
+        Ok(())
+    })
+}
+
+// Samples the world's lights directly when not 0, like --light_sampling.
+#[no_mangle]
+pub unsafe extern "C" fn rt_set_light_sampling(s: *mut Scene, on: c_int) -> c_int {
+    status(|| {
+        scene(s)?.settings.light_sampling = on != 0;
+        Ok(())
+    })
+}
+
+// Renders the scene into `rgba`, which must hold width * height * 4 bytes, RGBA from the top left row by row;
+// reports the lines done to `progress` if it isn't null.
+#[no_mangle]
+pub unsafe extern "C" fn rt_render(
+    s: *mut Scene,
+    width: c_int,
+    height: c_int,
+    rgba: *mut u8,
+    progress: ProgressCallback,
+    user: *mut c_void,
+) -> c_int {
+    // The user data is only handed back to the callback, one call at a time.
+    struct User(*mut c_void);
+    unsafe impl Sync for User {}
+    let user = User(user);
+    status(|| {
+        let scene = scene(s)?;
+        if width <= 0 || height <= 0 {
+            return Err(format!("image size {}x{} is empty", width, height));
+        }
+        if rgba.is_null() {
+            return Err("buffer is null".to_string());
+        }
+        // Counted under the lock, so that the calls see the count go up.
+        let done = Mutex::new(0);
+        let report = |_, total: usize| {
+            let mut done = done.lock().unwrap();
+            *done += 1;
+            if let Some(progress) = progress {
+                progress(*done as c_int, total as c_int, user.0);
+            }
+        };
+        let image = scene.scene.render(&scene.settings, width as usize, height as usize, report);
+        let pixels = output::to_rgba(&image);
+        std::slice::from_raw_parts_mut(rgba, pixels.len()).copy_from_slice(&pixels);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn count_lines(done: c_int, total: c_int, user: *mut c_void) {
+        let last = unsafe { &mut *(user as *mut (c_int, c_int)) };
+        assert!(done > last.0 && done <= total);
+        *last = (done, total);
+    }
+
+    #[test]
+    fn test_c_api() {
+        let path = std::env::temp_dir().join("raytracer_test_c_api.txt");
+        std::fs::write(&path, "# Looking from the side\nworld simple\n\nlookfrom 0,0,3\nfov 40\n").unwrap();
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        unsafe {
+            let scene = rt_scene_load(path.as_ptr());
+            assert!(!scene.is_null());
+            assert_eq!(0, rt_set_samples(scene, 2));
+            assert_eq!(0, rt_set_seed(scene, 7));
+            assert_eq!(-1, rt_set_max_depth(scene, 0));
+            assert!(CStr::from_ptr(rt_last_error()).to_str().unwrap().contains("not positive"));
+
+            let mut rgba = vec![0u8; 16 * 9 * 4];
+            let mut progress = (0, 0);
+            let user = &mut progress as *mut (c_int, c_int) as *mut c_void;
+            assert_eq!(0, rt_render(scene, 16, 9, rgba.as_mut_ptr(), Some(count_lines), user));
+            assert_eq!((9, 9), progress);
+            assert!(rgba.chunks(4).all(|pixel| pixel[3] == 255));
+            // The same seed renders the same image.
+            let mut again = vec![0u8; 16 * 9 * 4];
+            assert_eq!(0, rt_render(scene, 16, 9, again.as_mut_ptr(), None, std::ptr::null_mut()));
+            assert_eq!(rgba, again);
+            assert_eq!(-1, rt_render(scene, 0, 9, again.as_mut_ptr(), None, std::ptr::null_mut()));
+            rt_scene_free(scene);
+        }
+
+        let missing = CString::new("/nonexistent/scene.txt").unwrap();
+        assert!(unsafe { rt_scene_load(missing.as_ptr()) }.is_null());
+        assert!(unsafe { CStr::from_ptr(rt_last_error()) }.to_str().unwrap().contains("can't read"));
+    }
+}
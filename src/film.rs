@@ -0,0 +1,201 @@
+// The images that the renderer adds its samples up in. A `Film` sums the samples of every pixel with their
+// filter weights, to be developed into images at any time. A `SplatFilm` takes samples from any thread, for those
+// that land in other pixels than the one they were taken for: light paths (see `Renderer::accumulate_light_paths`)
+// and filters wider than a pixel. Its sums are kept in fixed point, as integer additions come out the same in
+// whatever order the threads make them, so the same seed still renders the same image.
+use crate::postprocess::PostProcess;
+use crate::raytrace::{self, RGB, RGB16};
+use crate::vec::Color;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+// Weighted sums of the samples of every pixel and the sums of their weights, lines bottom to top like the output
+// of `Renderer::render`. With the box filter the weights are the sample counts.
+#[derive(Clone)]
+pub struct Film {
+    lines: Vec<FilmLine>,
+}
+
+#[derive(Clone)]
+pub struct FilmLine {
+    sums: Vec<Color>,
+    weights: Vec<f64>,
+}
+
+impl FilmLine {
+    // Adds `sum`, the sum of samples with filter weights summing up to `weight`, to pixel i.
+    pub fn add(&mut self, i: usize, sum: Color, weight: f64) {
+        self.sums[i] = self.sums[i] + sum;
+        self.weights[i] += weight;
+    }
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Film {
+        Film { lines: vec![FilmLine { sums: vec![Color::ZERO; width], weights: vec![0.0; width] }; height] }
+    }
+
+    pub fn width(&self) -> usize {
+        self.lines.first().map_or(0, |line| line.sums.len())
+    }
+
+    pub fn height(&self) -> usize {
+        self.lines.len()
+    }
+
+    // Adds `sum` with the weight `weight` to pixel (i, j), see `FilmLine::add`.
+    pub fn add(&mut self, i: usize, j: usize, sum: Color, weight: f64) {
+        self.lines[j].add(i, sum, weight);
+    }
+
+    // The lines, for adding to them in parallel.
+    pub fn lines_mut(&mut self) -> &mut [FilmLine] {
+        &mut self.lines
+    }
+
+    // Adds the samples splatted into `film`, which must be as large.
+    pub fn add_splats(&mut self, film: &SplatFilm) {
+        for (line, splats) in self.lines.iter_mut().zip(film.to_lines()) {
+            for (i, (sum, weight)) in splats.into_iter().enumerate() {
+                line.add(i, sum, weight);
+            }
+        }
+    }
+
+    // Adds the samples of `other`, which must be as large, e.g. rendered separately with other random numbers.
+    pub fn merge(&mut self, other: &Film) {
+        for (line, other) in self.lines.iter_mut().zip(&other.lines) {
+            for i in 0..line.sums.len() {
+                line.add(i, other.sums[i], other.weights[i]);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for line in self.lines.iter_mut() {
+            line.sums.iter_mut().for_each(|c| *c = Color::ZERO);
+            line.weights.iter_mut().for_each(|w| *w = 0.0);
+        }
+    }
+
+    // Average radiance of every pixel, weighted by the filter; black where there are no samples yet.
+    pub fn to_linear(&self) -> Vec<Vec<Color>> {
+        let average = |c: &Color, w: f64| if w > 0.0 { c / w } else { Color::ZERO };
+        self.lines
+            .iter()
+            .map(|line| line.sums.iter().zip(&line.weights).map(|(c, &w)| average(c, w)).collect())
+            .collect()
+    }
+
+    // The image developed by `post` for 8-bit outputs.
+    pub fn to_rgb(&self, post: &PostProcess) -> Vec<Vec<RGB>> {
+        post.apply(&self.to_linear())
+    }
+
+    // The image developed by `post` for 16-bit outputs.
+    pub fn to_rgb16(&self, post: &PostProcess) -> Vec<Vec<RGB16>> {
+        post.apply16(&self.to_linear())
+    }
+
+    // This image with `right` next to it, e.g. for side-by-side stereo.
+    pub fn side_by_side(&self, right: &Film) -> Film {
+        let sums = |film: &Film| film.lines.iter().map(|line| line.sums.clone()).collect::<Vec<_>>();
+        let weights = |film: &Film| film.lines.iter().map(|line| line.weights.clone()).collect::<Vec<_>>();
+        let lines = raytrace::side_by_side(&sums(self), &sums(right))
+            .into_iter()
+            .zip(raytrace::side_by_side(&weights(self), &weights(right)))
+            .map(|(sums, weights)| FilmLine { sums, weights })
+            .collect();
+        Film { lines }
+    }
+}
+
+// Fixed point units per unit of radiance. Splats are rounded to 2^-32 and every sum must stay within ±2^31.
+const UNIT: f64 = (1u64 << 32) as f64;
+
+pub struct SplatFilm {
+    width: usize,
+    height: usize,
+    // Red, green, blue and filter weight of every pixel, lines bottom to top, in `UNIT`s.
+    sums: Vec<[AtomicI64; 4]>,
+}
+
+impl SplatFilm {
+    pub fn new(width: usize, height: usize) -> SplatFilm {
+        let sums = (0..width * height).map(|_| Default::default()).collect();
+        SplatFilm { width, height, sums }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Adds `color` with the filter weight `weight` to pixel (i, j).
+    pub fn splat(&self, i: usize, j: usize, color: Color, weight: f64) {
+        let pixel = &self.sums[j * self.width + i];
+        for (sum, value) in pixel.iter().zip([color.r(), color.g(), color.b(), weight]) {
+            if value != 0.0 {
+                sum.fetch_add((value * UNIT).round() as i64, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Weighted sums of the colors and sums of the weights of every pixel, lines bottom to top.
+    pub fn to_lines(&self) -> Vec<Vec<(Color, f64)>> {
+        let value = |sum: &AtomicI64| sum.load(Ordering::Relaxed) as f64 / UNIT;
+        self.sums
+            .chunks(self.width.max(1))
+            .take(self.height)
+            .map(|line| line.iter().map(|[r, g, b, w]| (Color::new(value(r), value(g), value(b)), value(w))).collect())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::par::*;
+
+    #[test]
+    fn test_film() {
+        let mut film = Film::new(3, 2);
+        film.add(0, 0, Color::new(2.0, 4.0, 6.0), 2.0);
+        film.lines_mut()[1].add(2, Color::ONE, 0.5);
+        let mut other = Film::new(3, 2);
+        other.add(0, 0, Color::new(1.0, 0.0, 0.0), 1.0);
+        film.merge(&other);
+        let linear = film.to_linear();
+        assert_eq!(Color::new(1.0, 4.0 / 3.0, 2.0), linear[0][0]);
+        assert_eq!(Color::new(2.0, 2.0, 2.0), linear[1][2]);
+        assert_eq!(Color::ZERO, linear[1][0]);
+        assert_eq!((255, 255, 255), film.to_rgb(&PostProcess::new())[1][2]);
+
+        let wide = film.side_by_side(&other);
+        assert_eq!((6, 2), (wide.width(), wide.height()));
+        assert_eq!(Color::new(1.0, 0.0, 0.0), wide.to_linear()[0][3]);
+        film.clear();
+        assert!(film.to_linear().iter().flatten().all(|&c| c == Color::ZERO));
+    }
+
+    #[test]
+    fn test_splats_add_up_in_any_order() {
+        let splats: Vec<(usize, Color, f64)> =
+            (0..1000).map(|k| (k % 3, Color::new(0.1 * k as f64, 1.0 / (k + 1) as f64, -0.3), 0.7)).collect();
+        let forward = SplatFilm::new(3, 2);
+        splats.par_iter().for_each(|&(i, c, w)| forward.splat(i, 1, c, w));
+        let backward = SplatFilm::new(3, 2);
+        splats.iter().rev().for_each(|&(i, c, w)| backward.splat(i, 1, c, w));
+        let lines = forward.to_lines();
+        assert!(lines == backward.to_lines());
+
+        assert_eq!(2, lines.len());
+        assert_eq!((Color::ZERO, 0.0), lines[0][0]);
+        let (color, weight) = lines[1][0];
+        assert!((weight - 0.7 * 334.0).abs() < 1e-6);
+        assert!((color.r() - 0.1 * (0..1000).step_by(3).sum::<usize>() as f64).abs() < 1e-6);
+        assert!((color.b() + 0.3 * 334.0).abs() < 1e-6);
+    }
+}
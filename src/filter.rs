@@ -0,0 +1,208 @@
+// Pixel reconstruction filters: how much a sample counts towards the pixels around it, by its distance from their
+// centers in pixels. The box filter counts a sample only in the pixel it was taken in; the others reach into the
+// neighbouring pixels, which smooths jagged edges at the cost of a little sharpness.
+use rand::Rng;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FilterKind {
+    Box,
+    // Falls off linearly.
+    Tent,
+    Gaussian,
+    // Mitchell-Netravali with B = C = 1/3; its negative lobes keep edges sharper than the Gaussian.
+    Mitchell,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Filter {
+    pub kind: FilterKind,
+    // Distance from the pixel center, in pixels, at which the weight drops to zero in x and y.
+    pub radius: f64,
+}
+
+impl Filter {
+    pub const BOX: Filter = Filter { kind: FilterKind::Box, radius: 0.5 };
+
+    // The filter with its usual radius.
+    pub fn new(kind: FilterKind) -> Filter {
+        let radius = match kind {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+        };
+        Filter { kind, radius }
+    }
+
+    // Weight of a sample at offset (x, y) from a pixel's center; the filters are separable.
+    pub fn weight(&self, x: f64, y: f64) -> f64 {
+        self.weight_1d(x) * self.weight_1d(y)
+    }
+
+    fn weight_1d(&self, x: f64) -> f64 {
+        // In units of the radius, from 0 at the center to 1 at the edge.
+        let x = (x / self.radius).abs();
+        if x >= 1.0 {
+            return 0.0;
+        }
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => 1.0 - x,
+            // Shifted down to reach zero at the radius, with the falloff of pbrt's default.
+            FilterKind::Gaussian => {
+                let alpha = 2.0 * 1.5 * 1.5;
+                (-alpha * x * x).exp() - (-alpha).exp()
+            }
+            FilterKind::Mitchell => {
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                let x = 2.0 * x;
+                let w = if x < 1.0 {
+                    (12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)
+                } else {
+                    (-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c)
+                };
+                w / 6.0
+            }
+        }
+    }
+
+    // Pixels away from the sample's own that the filter reaches, in each direction.
+    pub fn reach(&self) -> usize {
+        (self.radius - 0.5).max(0.0).ceil() as usize
+    }
+}
+
+// Entries in the tables of `FilterSampler`.
+const TABLE_SIZE: usize = 256;
+
+// Draws offsets from the center of a pixel with a density proportional to the absolute value of a filter, so that
+// samples stay in their own pixel yet are weighted as if splatted; see `Renderer::with_importance_filter`. The
+// density is tabulated in one dimension, the filters being separable.
+pub struct FilterSampler {
+    filter: Filter,
+    // Cumulative distribution of the absolute value of the filter over TABLE_SIZE equal steps across its width.
+    cdf: Vec<f64>,
+    // Integral of the filter over its width, and of its absolute value.
+    integral: f64,
+    absolute_integral: f64,
+}
+
+impl FilterSampler {
+    pub fn new(filter: Filter) -> FilterSampler {
+        let step = 2.0 * filter.radius / TABLE_SIZE as f64;
+        let values: Vec<f64> =
+            (0..TABLE_SIZE).map(|k| filter.weight_1d(-filter.radius + (k as f64 + 0.5) * step)).collect();
+        let mut cdf = vec![0.0; TABLE_SIZE + 1];
+        for k in 0..TABLE_SIZE {
+            cdf[k + 1] = cdf[k] + values[k].abs() * step;
+        }
+        let absolute_integral = cdf[TABLE_SIZE];
+        cdf.iter_mut().for_each(|c| *c /= absolute_integral);
+        FilterSampler { filter, cdf, integral: values.iter().sum::<f64>() * step, absolute_integral }
+    }
+
+    // Offsets in x and y from the center of the pixel, in pixels, and the weight of the radiance sampled there: the
+    // sign of the filter, times the ratio of the integrals of its absolute value and itself, i.e. 1 unless the
+    // filter has negative lobes like Mitchell's.
+    pub fn sample(&self, rng: &mut dyn rand::RngCore) -> (f64, f64, f64) {
+        let (x, wx) = self.sample_1d(rng.gen_range(0.0..1.0));
+        let (y, wy) = self.sample_1d(rng.gen_range(0.0..1.0));
+        (x, y, wx * wy)
+    }
+
+    fn sample_1d(&self, u: f64) -> (f64, f64) {
+        let k = self.cdf.partition_point(|&c| c <= u).clamp(1, TABLE_SIZE) - 1;
+        let within = ((u - self.cdf[k]) / (self.cdf[k + 1] - self.cdf[k])).clamp(0.0, 1.0);
+        let step = 2.0 * self.filter.radius / TABLE_SIZE as f64;
+        let x = -self.filter.radius + (k as f64 + within) * step;
+        let sign = if self.filter.weight_1d(-self.filter.radius + (k as f64 + 0.5) * step) < 0.0 { -1.0 } else { 1.0 };
+        (x, sign * self.absolute_integral / self.integral)
+    }
+}
+
+impl std::str::FromStr for Filter {
+    type Err = String;
+
+    // The kind, optionally followed by the radius in pixels, e.g. gaussian,2.
+    fn from_str(s: &str) -> Result<Filter, String> {
+        let (kind, radius) = match s.split_once(',') {
+            None => (s, None),
+            Some((kind, radius)) => (kind, Some(radius)),
+        };
+        let kind = match kind.trim() {
+            "box" => FilterKind::Box,
+            "tent" => FilterKind::Tent,
+            "gaussian" => FilterKind::Gaussian,
+            "mitchell" => FilterKind::Mitchell,
+            _ => return Err(format!("unknown filter '{}'", kind)),
+        };
+        let mut filter = Filter::new(kind);
+        if let Some(radius) = radius {
+            filter.radius = radius.trim().parse::<f64>().map_err(|e| format!("bad filter radius: {}", e))?;
+            if filter.radius.is_nan() || filter.radius <= 0.0 {
+                return Err(format!("filter radius {} is not positive", filter.radius));
+            }
+        }
+        Ok(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_filters() {
+        for kind in [FilterKind::Box, FilterKind::Tent, FilterKind::Gaussian, FilterKind::Mitchell] {
+            let filter = Filter::new(kind);
+            assert!(filter.weight(0.0, 0.0) > 0.0, "{:?}", kind);
+            assert_eq!(0.0, filter.weight(filter.radius, 0.0), "{:?}", kind);
+            assert_eq!(filter.weight(0.3, -0.2), filter.weight(-0.3, 0.2), "{:?}", kind);
+            // Continuous at the edge, except for the box.
+            if kind != FilterKind::Box {
+                assert!(filter.weight(0.9999 * filter.radius, 0.0).abs() < 1e-3, "{:?}", kind);
+            }
+        }
+        // Mitchell's negative lobes.
+        assert!(Filter::new(FilterKind::Mitchell).weight(1.5, 0.0) < 0.0);
+        assert_eq!(0, Filter::BOX.reach());
+        assert_eq!(1, Filter::new(FilterKind::Gaussian).reach());
+        assert_eq!(2, Filter::new(FilterKind::Mitchell).reach());
+
+        // Importance sampled offsets stay within the radius, and their weights average out to 1.
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        for kind in [FilterKind::Tent, FilterKind::Gaussian, FilterKind::Mitchell] {
+            let filter = Filter::new(kind);
+            let sampler = FilterSampler::new(filter);
+            let n = 100000;
+            let (mut weights, mut spread) = (0.0, 0.0);
+            for _ in 0..n {
+                let (x, y, w) = sampler.sample(&mut rng);
+                assert!(x.abs() <= filter.radius && y.abs() <= filter.radius, "{:?}: {} {}", kind, x, y);
+                weights += w;
+                spread += w * x * x;
+            }
+            assert!((weights / n as f64 - 1.0).abs() < 0.02, "{:?}: {}", kind, weights / n as f64);
+            // The weighted second moment matches the filter's.
+            let steps = 10000;
+            let h = 2.0 * filter.radius / steps as f64;
+            let (mut integral, mut moment) = (0.0, 0.0);
+            for k in 0..steps {
+                let x = -filter.radius + (k as f64 + 0.5) * h;
+                integral += filter.weight(x, 0.0) * h;
+                moment += x * x * filter.weight(x, 0.0) * h;
+            }
+            let expected = moment / integral;
+            assert!((spread / n as f64 - expected).abs() < 0.03 * expected.max(0.1), "{:?}", kind);
+        }
+
+        assert_eq!(Filter { kind: FilterKind::Tent, radius: 2.0 }, "tent,2".parse().unwrap());
+        assert_eq!(Filter::new(FilterKind::Mitchell), "mitchell".parse().unwrap());
+        assert!("lanczos".parse::<Filter>().is_err());
+        assert!("box,0".parse::<Filter>().is_err());
+    }
+}
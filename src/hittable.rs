@@ -1,17 +1,36 @@
+use crate::interval::Interval;
 use crate::materials::Material;
+use crate::math::Onb;
+use crate::scene::SceneWriter;
+use crate::validate::SceneStats;
 use crate::vec::{Point3, Ray, Vec3};
+use rand::Rng;
 use std::option::Option;
 use std::vec::Vec;
 
 #[derive(Clone)]
 pub struct Hit<'a> {
     pub p: Point3,
+    // Shading normal, on the side the ray came from: the one materials scatter around. It may differ from
+    // `geometric_normal`, e.g. when bent to make a flat ribbon look round, see `with_shading_normal`.
     pub normal: Vec3,
+    // Normal of the surface itself, on the same side as `normal`; secondary rays are pushed off along it.
+    pub geometric_normal: Vec3,
+    // Where rays leaving on the side of `geometric_normal` start, relative to `p`: zero but for faces of meshes with
+    // vertex normals, which shade as a curved surface and are lit as one, see `mesh::terminator_offset`.
+    pub shading_offset: Vec3,
+    // Derivatives of `p` by `u` and `v`, along the surface, e.g. for normal maps and anisotropic materials.
+    // Surfaces whose texture coordinates don't follow them smoothly have some tangents around the normal instead,
+    // see `tangents`.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub t: f64,
     pub u: f64,
     pub v: f64,
     pub front_face: bool,
     pub material: &'a dyn Material,
+    // Time of the ray that hit, passed on to the rays leaving the hit point.
+    pub time: f64,
 }
 
 impl<'a> Hit<'a> {
@@ -26,12 +45,128 @@ impl<'a> Hit<'a> {
     ) -> Hit<'a> {
         let front_face = outward_normal.dot(r.dir) < 0.0;
         let normal = if front_face { *outward_normal } else { -outward_normal };
-        return Hit { p: *p, normal, t, u, v, front_face, material };
+        let (dpdu, dpdv) = tangents(outward_normal);
+        return Hit {
+            p: *p,
+            normal,
+            geometric_normal: normal,
+            shading_offset: Vec3::ZERO,
+            dpdu,
+            dpdv,
+            t,
+            u,
+            v,
+            front_face,
+            material,
+            time: r.time,
+        };
     }
+
+    pub fn with_tangents(self, dpdu: Vec3, dpdv: Vec3) -> Hit<'a> {
+        Hit { dpdu, dpdv, ..self }
+    }
+
+    // Shades the hit with `normal` instead of the geometric normal, turned to the same side as the latter.
+    pub fn with_shading_normal(self, normal: &Vec3) -> Hit<'a> {
+        let normal = if normal.dot(self.geometric_normal) < 0.0 { -normal } else { *normal };
+        Hit { normal, ..self }
+    }
+
+    // Ray leaving the hit point in the given direction.
+    // Instead of relying on a t_min cutoff, the origin is pushed off the surface along the geometric normal, to
+    // the side the ray leaves through, so that the ray can't re-hit the surface it starts on. The rounding error of
+    // `p` grows with its coordinates, and so does the offset: this works both for tiny scenes and for large ones.
+    pub fn spawn_ray(&self, dir: Vec3) -> Ray {
+        let magnitude = self.p.e.iter().fold(1.0f64, |m, c| m.max(c.abs()));
+        let offset = ORIGIN_OFFSET * magnitude * self.geometric_normal;
+        let orig = match dir.dot(self.geometric_normal) > 0.0 {
+            true => self.p + self.shading_offset + offset,
+            false => self.p - offset,
+        };
+        Ray::at_time(orig, dir, self.time)
+    }
+}
+
+// Some tangents perpendicular to `normal`, for hits on surfaces without smooth texture coordinates, e.g. signed
+// distance fields, and for scattering in volumes.
+pub fn tangents(normal: &Vec3) -> (Vec3, Vec3) {
+    let onb = Onb::new(*normal);
+    (onb.u, onb.v)
 }
 
+// Relative offset of secondary ray origins from the surface, see `Hit::spawn_ray`.
+const ORIGIN_OFFSET: f64 = 1e-7;
+
 pub trait Hittable: Sync {
-    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, rng: &mut dyn rand::RngCore) -> Option<Hit<'a>>;
+    fn hit<'a>(&'a self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> Option<Hit<'a>>;
+
+    // Occlusion query: true if the ray hits anything in `ray_t`.
+    // Unlike `hit`, it does not need the closest intersection, so implementations may stop at the first one
+    // and skip computing normals and UVs.
+    fn hit_any(&self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> bool {
+        self.hit(r, ray_t, rng).is_some()
+    }
+
+    // Light sampling: probability density, with respect to solid angle at `o`, of `random_point` returning
+    // a point in direction `v`. Shapes that can't be sampled return 0 and must not be used as sampled lights.
+    fn pdf_value(&self, _o: &Point3, _v: &Vec3, _rng: &mut dyn rand::RngCore) -> f64 {
+        0.0
+    }
+
+    // Light sampling: a random point on the shape, as seen from `o`.
+    fn random_point(&self, o: &Point3, _rng: &mut dyn rand::RngCore) -> Point3 {
+        o + &Vec3::new(1.0, 0.0, 0.0)
+    }
+
+    // Light tracing: a random point on the shape, uniformly distributed by area, as a hit with the outward normal,
+    // and the density of picking it per unit area. None for shapes that can't start light paths.
+    fn sample_surface<'a>(&'a self, _rng: &mut dyn rand::RngCore) -> Option<(Hit<'a>, f64)> {
+        None
+    }
+
+    // For `--validate`: adds the shape, its materials and anything it contains to `stats`, and reports
+    // problems such as non-finite coordinates.
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.object(std::mem::size_of_val(self), None);
+    }
+
+    // For `--export_scene`: writes the shape and anything it contains to `scene`, failing for shapes that scene
+    // files have no lines for.
+    fn export(&self, _scene: &mut SceneWriter) -> Result<(), String> {
+        let name = std::any::type_name::<Self>().split('<').next().unwrap();
+        Err(format!("can't write {} to a scene file", name.rsplit("::").next().unwrap()))
+    }
+}
+
+// E.g. for shapes of different types wrapped in the same transform.
+impl<T: Hittable + ?Sized> Hittable for Box<T> {
+    fn hit<'a>(&'a self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> Option<Hit<'a>> {
+        (**self).hit(r, ray_t, rng)
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> bool {
+        (**self).hit_any(r, ray_t, rng)
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, rng: &mut dyn rand::RngCore) -> f64 {
+        (**self).pdf_value(o, v, rng)
+    }
+
+    fn random_point(&self, o: &Point3, rng: &mut dyn rand::RngCore) -> Point3 {
+        (**self).random_point(o, rng)
+    }
+
+    fn sample_surface<'a>(&'a self, rng: &mut dyn rand::RngCore) -> Option<(Hit<'a>, f64)> {
+        (**self).sample_surface(rng)
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        (**self).inspect(stats)
+    }
+
+    fn export(&self, scene: &mut SceneWriter) -> Result<(), String> {
+        (**self).export(scene)
+    }
 }
 
 pub struct HittableList<'a> {
@@ -48,15 +183,24 @@ impl<'a> HittableList<'a> {
     pub fn push<T: Hittable + 'a>(&mut self, v: Box<T>) {
         self.contents.push(v);
     }
+    pub fn len(&self) -> usize {
+        self.contents.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.contents.is_empty()
+    }
+    pub fn into_vec(self) -> Vec<Box<dyn Hittable + 'a>> {
+        self.contents
+    }
 }
 
 impl<'a> Hittable for HittableList<'a> {
-    fn hit<'b>(&'b self, r: &Ray, t_min: f64, t_max: f64, rng: &mut dyn rand::RngCore) -> Option<Hit<'b>> {
+    fn hit<'b>(&'b self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> Option<Hit<'b>> {
         let mut result: Option<Hit> = None;
-        let mut closest_so_far = t_max;
+        let mut closest_so_far = ray_t.max;
 
         for o in self.contents.iter() {
-            match o.hit(r, t_min, closest_so_far, rng) {
+            match o.hit(r, ray_t.with_max(closest_so_far), rng) {
                 Some(h) => {
                     closest_so_far = h.t;
                     result = Some(h);
@@ -66,4 +210,43 @@ impl<'a> Hittable for HittableList<'a> {
         }
         return result;
     }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> bool {
+        self.contents.iter().any(|o| o.hit_any(r, ray_t, rng))
+    }
+
+    // Picks one of the contents uniformly, so the density is the average.
+    fn pdf_value(&self, o: &Point3, v: &Vec3, rng: &mut dyn rand::RngCore) -> f64 {
+        if self.contents.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self.contents.iter().map(|c| c.pdf_value(o, v, rng)).sum();
+        sum / self.contents.len() as f64
+    }
+
+    fn random_point(&self, o: &Point3, rng: &mut dyn rand::RngCore) -> Point3 {
+        let i = rng.gen_range(0..self.contents.len());
+        self.contents[i].random_point(o, rng)
+    }
+
+    // Picks one of the contents uniformly, like `random_point`.
+    fn sample_surface<'b>(&'b self, rng: &mut dyn rand::RngCore) -> Option<(Hit<'b>, f64)> {
+        if self.contents.is_empty() {
+            return None;
+        }
+        let i = rng.gen_range(0..self.contents.len());
+        let (h, pdf) = self.contents[i].sample_surface(rng)?;
+        Some((h, pdf / self.contents.len() as f64))
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.geometry_memory += std::mem::size_of_val(self.contents.as_slice());
+        for o in self.contents.iter() {
+            o.inspect(stats);
+        }
+    }
+
+    fn export(&self, scene: &mut SceneWriter) -> Result<(), String> {
+        self.contents.iter().try_for_each(|o| o.export(scene))
+    }
 }
@@ -0,0 +1,472 @@
+// Render server with an HTTP API, for render farms and web frontends: scene files (see `scene::SceneFile`) are
+// posted as jobs, which render one at a time in the order they came in, on all cores.
+//
+//   POST /jobs?width=w&height=h&samples=n&max_depth=d&seed=s&light_sampling=1
+//                              queue the scene file in the body; all parameters are optional, the image is
+//                              640x360 by default and the rest defaults to `SceneSettings::new`. Answers
+//                              201 with {"id": id}, 400 for more than `MAX_PIXELS`, `MAX_SAMPLES` or
+//                              `MAX_PATHS`, or 503 when `MAX_JOBS` jobs are queued or rendering already
+//   GET /jobs/id               the state of a job as JSON: {"state": "queued|rendering|done|failed", "done": lines,
+//                              "total": lines}, with "error" for failed jobs
+//   GET /jobs/id/progress      streams a line "done/total" as the lines get rendered, then "done" or "failed: error"
+//   GET /jobs/id/image         the finished image as PNG; 409 while it is rendering
+//
+// Only the last `KEEP_FINISHED` finished jobs are kept.
+//
+// Every connection takes one request and is closed after the response. `HANDLERS` threads answer them; while they
+// are all busy, up to as many connections wait for them and more get 503. Scene files read only the files that the
+// server allows, see `scene::Files`.
+use crate::image_texture;
+use crate::output;
+use crate::scene::{Files, SceneFile, SceneSettings};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Largest scene file accepted, in bytes.
+const MAX_BODY: usize = 1 << 20;
+// Longest request line and headers together, in bytes.
+const MAX_HEAD: usize = 16 << 10;
+// Largest jobs accepted, so that one request can't take the memory or the time of the server: a 4K image, which
+// takes about 800 MB to accumulate, samples per pixel, and paths traced in all, e.g. 4K at 500 samples.
+const MAX_PIXELS: usize = 3840 * 2160;
+const MAX_SAMPLES: usize = 1 << 14;
+const MAX_PATHS: u64 = 1 << 32;
+// Jobs queued or rendering at most; more are turned away until some finish.
+const MAX_JOBS: usize = 8;
+// Threads answering requests, and connections waiting for one of them at most. A progress stream keeps its thread
+// until the job finishes.
+const HANDLERS: usize = 8;
+// How long a connection may take to send its request or to take a response before it is dropped.
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+// Finished jobs kept with their images; older ones are forgotten, and their ids answer 404.
+const KEEP_FINISHED: usize = 32;
+// How often the progress stream looks for rendered lines.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+enum State {
+    Queued,
+    // Lines rendered and in total.
+    Rendering(usize, usize),
+    // The PNG file.
+    Done(Vec<u8>),
+    Failed(String),
+}
+
+struct Job {
+    scene: SceneFile,
+    settings: SceneSettings,
+    width: usize,
+    height: usize,
+    state: Mutex<State>,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+struct Server {
+    // By id, in the order they were posted.
+    jobs: Mutex<BTreeMap<usize, Arc<Job>>>,
+    next_id: AtomicUsize,
+    files: Files,
+    queue: Mutex<Sender<Arc<Job>>>,
+}
+
+// Answers the requests on `listener` until it fails; the posted scene files may read `files`.
+pub fn serve(listener: TcpListener, files: Files) -> io::Result<()> {
+    let (queue, queued) = mpsc::channel::<Arc<Job>>();
+    thread::spawn(move || {
+        for job in queued {
+            render(&job);
+        }
+    });
+    let server = Arc::new(Server::new(queue, files));
+    let (connections, waiting) = mpsc::sync_channel::<TcpStream>(HANDLERS);
+    let waiting = Arc::new(Mutex::new(waiting));
+    for _ in 0..HANDLERS {
+        let (server, waiting) = (server.clone(), waiting.clone());
+        thread::spawn(move || loop {
+            // Not holding the lock while handling, only while waiting for a connection.
+            let stream = match waiting.lock().unwrap().recv() {
+                Ok(stream) => stream,
+                Err(_) => return,
+            };
+            // The client going away is its business.
+            let _ = server.handle(stream);
+        });
+    }
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let timeouts =
+            stream.set_read_timeout(Some(IO_TIMEOUT)).and_then(|()| stream.set_write_timeout(Some(IO_TIMEOUT)));
+        if timeouts.is_err() {
+            continue;
+        }
+        if let Err(TrySendError::Full(mut stream)) = connections.try_send(stream) {
+            let _ = respond(&mut stream, "503 Service Unavailable", "text/plain", b"too many connections");
+        }
+    }
+    Ok(())
+}
+
+fn render(job: &Job) {
+    *job.state.lock().unwrap() = State::Rendering(0, job.height);
+    let rendered = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let scene = job.scene.build();
+        let missing = image_texture::take_missing();
+        if !missing.is_empty() {
+            return Err(format!("can't load the textures {}", missing.join(", ")));
+        }
+        let image = scene.render(&job.settings, job.width, job.height, |_, total| {
+            if let State::Rendering(done, _) = &mut *job.state.lock().unwrap() {
+                *done = (*done + 1).min(total);
+            }
+        });
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(output::to_image(&image))
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .map(|()| png)
+            .map_err(|e| e.to_string())
+    }));
+    *job.state.lock().unwrap() = match rendered {
+        Ok(Ok(png)) => State::Done(png),
+        Ok(Err(e)) => State::Failed(e),
+        Err(_) => State::Failed("the renderer panicked".to_string()),
+    };
+}
+
+impl Job {
+    fn finished(&self) -> bool {
+        matches!(*self.state.lock().unwrap(), State::Done(_) | State::Failed(_))
+    }
+}
+
+impl Server {
+    fn new(queue: Sender<Arc<Job>>, files: Files) -> Server {
+        Server { jobs: Mutex::new(BTreeMap::new()), next_id: AtomicUsize::new(0), files, queue: Mutex::new(queue) }
+    }
+
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut stream = stream;
+        let request = match read_request(&mut reader) {
+            Ok(request) => request,
+            Err((status, e)) => return respond(&mut stream, status, "text/plain", e.as_bytes()),
+        };
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("POST", ["jobs"]) => match self.post(&request) {
+                Ok(id) => {
+                    respond(&mut stream, "201 Created", "application/json", format!("{{\"id\": {}}}", id).as_bytes())
+                }
+                Err((status, e)) => respond(&mut stream, status, "text/plain", e.as_bytes()),
+            },
+            ("GET", ["jobs", id, rest @ ..]) => {
+                let job = match id.parse::<usize>().ok().and_then(|id| self.jobs.lock().unwrap().get(&id).cloned()) {
+                    Some(job) => job,
+                    None => return respond(&mut stream, "404 Not Found", "text/plain", b"no such job"),
+                };
+                match rest {
+                    [] => respond(&mut stream, "200 OK", "application/json", status_json(&job).as_bytes()),
+                    ["progress"] => stream_progress(&mut stream, &job),
+                    ["image"] => {
+                        let image = match &*job.state.lock().unwrap() {
+                            State::Done(png) => Ok(png.clone()),
+                            State::Failed(e) => Err(("500 Internal Server Error", e.clone())),
+                            _ => Err(("409 Conflict", "not rendered yet".to_string())),
+                        };
+                        match image {
+                            Ok(png) => respond(&mut stream, "200 OK", "image/png", &png),
+                            Err((status, e)) => respond(&mut stream, status, "text/plain", e.as_bytes()),
+                        }
+                    }
+                    _ => respond(&mut stream, "404 Not Found", "text/plain", b"not found"),
+                }
+            }
+            _ => respond(&mut stream, "404 Not Found", "text/plain", b"not found"),
+        }
+    }
+
+    // Queues the job of a POST request; returns its id. Errors come with the status to answer, like `read_request`.
+    fn post(&self, request: &Request) -> Result<usize, (&'static str, String)> {
+        let bad = |e: String| ("400 Bad Request", e);
+        let text = std::str::from_utf8(&request.body).map_err(|e| bad(format!("scene is not UTF-8: {}", e)))?;
+        let scene = SceneFile::parse_reading(text, &self.files).map_err(bad)?;
+        let mut settings = SceneSettings::new();
+        let (mut width, mut height): (usize, usize) = (640, 360);
+        for (key, value) in &request.query {
+            fn positive<T: std::str::FromStr + PartialOrd + Default>(key: &str, value: &str) -> Result<T, String> {
+                match value.parse::<T>() {
+                    Ok(v) if v > T::default() => Ok(v),
+                    _ => Err(format!("{} needs a positive number, not '{}'", key, value)),
+                }
+            }
+            match key.as_str() {
+                "width" => width = positive(key, value).map_err(bad)?,
+                "height" => height = positive(key, value).map_err(bad)?,
+                "samples" => settings.samples_per_pixel = positive(key, value).map_err(bad)?,
+                "max_depth" => settings.max_depth = positive(key, value).map_err(bad)?,
+                "seed" => settings.seed = value.parse().map_err(|e| bad(format!("seed '{}': {}", value, e)))?,
+                "light_sampling" => settings.light_sampling = value != "0",
+                _ => return Err(bad(format!("unknown parameter '{}'", key))),
+            }
+        }
+        let pixels = match width.checked_mul(height) {
+            Some(pixels) if pixels <= MAX_PIXELS => pixels,
+            _ => return Err(bad(format!("{}x{} is more than {} pixels", width, height, MAX_PIXELS))),
+        };
+        if settings.samples_per_pixel > MAX_SAMPLES {
+            return Err(bad(format!("samples are at most {}", MAX_SAMPLES)));
+        }
+        if pixels as u64 * settings.samples_per_pixel as u64 > MAX_PATHS {
+            return Err(bad(format!("pixels times samples are at most {}", MAX_PATHS)));
+        }
+        let job = Arc::new(Job { scene, settings, width, height, state: Mutex::new(State::Queued) });
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.values().filter(|job| !job.finished()).count() >= MAX_JOBS {
+            let busy = format!("{} jobs are queued or rendering already", MAX_JOBS);
+            return Err(("503 Service Unavailable", busy));
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        jobs.insert(id, job.clone());
+        forget_finished(&mut jobs, KEEP_FINISHED);
+        self.queue.lock().unwrap().send(job).map_err(|e| ("500 Internal Server Error", e.to_string()))?;
+        Ok(id)
+    }
+}
+
+// Forgets the finished jobs but the last `keep`, with their images.
+fn forget_finished(jobs: &mut BTreeMap<usize, Arc<Job>>, keep: usize) {
+    let finished: Vec<usize> = jobs.iter().filter(|(_, job)| job.finished()).map(|(id, _)| *id).collect();
+    for id in &finished[..finished.len().saturating_sub(keep)] {
+        jobs.remove(id);
+    }
+}
+
+fn status_json(job: &Job) -> String {
+    match &*job.state.lock().unwrap() {
+        State::Queued => format!("{{\"state\": \"queued\", \"done\": 0, \"total\": {}}}", job.height),
+        State::Rendering(done, total) => {
+            format!("{{\"state\": \"rendering\", \"done\": {}, \"total\": {}}}", done, total)
+        }
+        State::Done(_) => format!("{{\"state\": \"done\", \"done\": {0}, \"total\": {0}}}", job.height),
+        State::Failed(e) => format!(
+            "{{\"state\": \"failed\", \"done\": 0, \"total\": {}, \"error\": \"{}\"}}",
+            job.height,
+            json_string(e)
+        ),
+    }
+}
+
+// The contents of a JSON string for `s`: quotes, backslashes and control characters escaped.
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn stream_progress(stream: &mut TcpStream, job: &Job) -> io::Result<()> {
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\n")?;
+    let mut reported = None;
+    loop {
+        // Not writing under the lock, which the render threads need.
+        let (line, last) = match &*job.state.lock().unwrap() {
+            State::Rendering(done, total) if reported != Some(*done) => {
+                reported = Some(*done);
+                (Some(format!("{}/{}", done, total)), false)
+            }
+            State::Queued | State::Rendering(_, _) => (None, false),
+            State::Done(_) => (Some("done".to_string()), true),
+            State::Failed(e) => (Some(format!("failed: {}", e)), true),
+        };
+        if let Some(line) = line {
+            writeln!(stream, "{}", line)?;
+            stream.flush()?;
+        }
+        if last {
+            return Ok(());
+        }
+        thread::sleep(PROGRESS_INTERVAL);
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+// Reads the request line, the headers, which only matter for the length of the body, and the body. Errors come with
+// the status to answer.
+fn read_request(reader: &mut impl BufRead) -> Result<Request, (&'static str, String)> {
+    let bad = |e: String| ("400 Bad Request", e);
+    // The lines are read from what is left of `MAX_HEAD`.
+    let mut head = Read::take(&mut *reader, MAX_HEAD as u64);
+    let mut read_line = || {
+        let mut line = String::new();
+        head.read_line(&mut line).map_err(|e| bad(e.to_string()))?;
+        match head.limit() == 0 && !line.ends_with('\n') {
+            true => Err(("431 Request Header Fields Too Large", format!("headers are at most {} bytes", MAX_HEAD))),
+            false => Ok(line),
+        }
+    };
+    let line = read_line()?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Err(bad(format!("bad request line '{}'", line.trim()))),
+    };
+    let mut length = 0;
+    loop {
+        let header = read_line()?;
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().map_err(|e| bad(format!("bad content length: {}", e)))?;
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(("413 Payload Too Large", format!("scene files are at most {} bytes", MAX_BODY)));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|e| bad(e.to_string()))?;
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), query),
+        None => (target, ""),
+    };
+    let query = query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| match p.split_once('=') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => (p.to_string(), String::new()),
+        })
+        .collect();
+    Ok(Request { method, path, query, body })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    // Sends a request and returns the status line and the body of the response.
+    fn request(address: &std::net::SocketAddr, method: &str, target: &str, body: &str) -> (String, Vec<u8>) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "{} {} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", method, target, body.len(), body).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(response[..end].to_vec()).unwrap();
+        (head.lines().next().unwrap().to_string(), response[end + 4..].to_vec())
+    }
+
+    #[test]
+    fn test_http_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener, Files::Nowhere));
+
+        let (status, body) = request(&address, "POST", "/jobs?width=16&height=9&samples=2", "world simple\nfov 30\n");
+        assert_eq!("HTTP/1.1 201 Created", status);
+        assert_eq!(b"{\"id\": 0}", body.as_slice());
+        let (_, progress) = request(&address, "GET", "/jobs/0/progress", "");
+        let progress = String::from_utf8(progress).unwrap();
+        assert!(progress.ends_with("done\n"), "{}", progress);
+        let (status, body) = request(&address, "GET", "/jobs/0", "");
+        assert_eq!("HTTP/1.1 200 OK", status);
+        assert_eq!(b"{\"state\": \"done\", \"done\": 9, \"total\": 9}", body.as_slice());
+        let (status, png) = request(&address, "GET", "/jobs/0/image", "");
+        assert_eq!("HTTP/1.1 200 OK", status);
+        let image = image::load_from_memory(&png).unwrap().to_rgb8();
+        assert_eq!((16, 9), image.dimensions());
+
+        assert_eq!("HTTP/1.1 400 Bad Request", request(&address, "POST", "/jobs", "world atlantis").0);
+        assert_eq!("HTTP/1.1 400 Bad Request", request(&address, "POST", "/jobs?width=-3", "world simple").0);
+        let mesh = "material m lambertian\nmesh /etc/passwd m\n";
+        let (status, body) = request(&address, "POST", "/jobs", mesh);
+        assert_eq!("HTTP/1.1 400 Bad Request", status);
+        assert!(String::from_utf8(body).unwrap().contains("can't read '/etc/passwd'"));
+        let long = format!("GET /jobs/0 HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "x".repeat(MAX_HEAD));
+        let status = read_request(&mut io::Cursor::new(long)).err().map(|(status, _)| status);
+        assert_eq!(Some("431 Request Header Fields Too Large"), status);
+        let huge = "/jobs?width=100000&height=100000";
+        assert_eq!("HTTP/1.1 400 Bad Request", request(&address, "POST", huge, "world simple").0);
+        assert_eq!("HTTP/1.1 400 Bad Request", request(&address, "POST", "/jobs?samples=100000", "world simple").0);
+        let long = "/jobs?width=3840&height=2160&samples=1000";
+        assert_eq!("HTTP/1.1 400 Bad Request", request(&address, "POST", long, "world simple").0);
+        assert_eq!("HTTP/1.1 404 Not Found", request(&address, "GET", "/jobs/7", "").0);
+    }
+
+    #[test]
+    fn test_forget_finished() {
+        let job = |state| {
+            let scene = SceneFile::parse("world simple").unwrap();
+            let settings = SceneSettings::new();
+            Arc::new(Job { scene, settings, width: 1, height: 1, state: Mutex::new(state) })
+        };
+        let mut jobs = BTreeMap::new();
+        jobs.insert(0, job(State::Done(Vec::new())));
+        jobs.insert(1, job(State::Rendering(0, 1)));
+        jobs.insert(2, job(State::Failed("no".to_string())));
+        jobs.insert(3, job(State::Done(Vec::new())));
+        jobs.insert(4, job(State::Queued));
+        forget_finished(&mut jobs, 1);
+        assert_eq!(vec![1, 3, 4], jobs.keys().copied().collect::<Vec<_>>());
+        forget_finished(&mut jobs, 0);
+        assert_eq!(vec![1, 4], jobs.keys().copied().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_max_jobs() {
+        // Nothing renders the jobs, so they all stay queued.
+        let (queue, _queued) = mpsc::channel();
+        let server = Server::new(queue, Files::Nowhere);
+        let post = Request {
+            method: "POST".to_string(),
+            path: "/jobs".to_string(),
+            query: Vec::new(),
+            body: b"world simple".to_vec(),
+        };
+        for id in 0..MAX_JOBS {
+            assert_eq!(Ok(id), server.post(&post));
+        }
+        assert_eq!(Some("503 Service Unavailable"), server.post(&post).err().map(|(status, _)| status));
+        *server.jobs.lock().unwrap()[&0].state.lock().unwrap() = State::Failed("no".to_string());
+        assert_eq!(Ok(MAX_JOBS), server.post(&post));
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!("plain", json_string("plain"));
+        let error = "line 2: \"x\" in C:\\scene\r\n\tat\u{1b}[0m é";
+        assert_eq!("line 2: \\\"x\\\" in C:\\\\scene\\u000d\\n\\u0009at\\u001b[0m é", json_string(error));
+    }
+}
@@ -1,29 +1,212 @@
+use crate::assets;
+use crate::logging;
+use crate::raytrace::Background;
+use crate::shapes;
 use crate::textures::Texture;
-use crate::vec::{Color, Point3};
-use image::RgbImage;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::tiled_image::{self, TiledImage};
+use crate::validate::SceneStats;
+use crate::vec::{Color, Point3, Ray};
+use image::{Rgb, RgbImage};
+use std::sync::{Arc, Mutex};
+
+// How the values of the pixels of an image are read as colors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    // As they are, scaled to [0, 1], as albedo textures have always been read.
+    Linear,
+    // Decoded from the sRGB curve photos are stored with, so they are amounts of light again, e.g. for the
+    // emission of a light panel showing a photo of a window.
+    Srgb,
+}
+
+// Paths of the textures that couldn't be loaded, until taken by `take_missing`.
+static MISSING: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// The textures that couldn't be loaded since the last call, e.g. while building a world. Rendering stops on them
+// rather than showing their placeholders; `--validate` lists them among the problems of the world.
+pub fn take_missing() -> Vec<String> {
+    std::mem::take(&mut *MISSING.lock().unwrap())
+}
+
+#[derive(Clone)]
+enum Pixels {
+    Decoded(Arc<RgbImage>),
+    // Streamed from a file too large to decode, see `tiled_image`.
+    #[cfg(not(target_arch = "wasm32"))]
+    Tiled(Arc<TiledImage>),
+}
 
 #[derive(Clone)]
 pub struct Image {
-    image: std::sync::Arc<RgbImage>,
+    pixels: Pixels,
+    // Path of the file that couldn't be loaded, if the image is a placeholder.
+    missing: Option<String>,
+    encoding: Encoding,
 }
 
 impl Image {
     pub fn new(image: RgbImage) -> Image {
-        Image { image: std::sync::Arc::new(image) }
+        Image { pixels: Pixels::Decoded(Arc::new(image)), missing: None, encoding: Encoding::Linear }
+    }
+
+    pub fn with_encoding(self, encoding: Encoding) -> Image {
+        Image { encoding, ..self }
+    }
+
+    // Shared with every other image opened from the same file, see `assets`. Tiled files, see
+    // `tiled_image::write`, are streamed rather than decoded. A file that can't be loaded is replaced by a magenta
+    // placeholder, and reported by `take_missing`.
+    pub fn open(path: &str) -> Image {
+        #[cfg(not(target_arch = "wasm32"))]
+        let pixels = match tiled_image::is_tiled(path) {
+            true => assets::tiled_image(path).map(Pixels::Tiled),
+            false => assets::image(path).map(Pixels::Decoded),
+        };
+        #[cfg(target_arch = "wasm32")]
+        let pixels = assets::image(path).map(Pixels::Decoded);
+        match pixels {
+            Ok(pixels) => Image { pixels, missing: None, encoding: Encoding::Linear },
+            Err(e) => {
+                logging::warning(&format!("Can't load texture {}: {}", path, e));
+                MISSING.lock().unwrap().push(path.to_string());
+                let placeholder = RgbImage::from_pixel(1, 1, Rgb([255, 0, 255]));
+                Image { missing: Some(path.to_string()), ..Image::new(placeholder) }
+            }
+        }
     }
 }
 
 impl Texture for Image {
-    fn value(&self, u: f64, v: f64, _: Point3) -> Color {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        let image = match &self.pixels {
+            Pixels::Decoded(image) => image,
+            // Decoded as it is; the encoding is applied here like for other images.
+            #[cfg(not(target_arch = "wasm32"))]
+            Pixels::Tiled(tiled) => {
+                let c = tiled.value(u, v, p);
+                return match self.encoding {
+                    Encoding::Linear => c,
+                    Encoding::Srgb => Color::new(srgb_to_linear(c.r()), srgb_to_linear(c.g()), srgb_to_linear(c.b())),
+                };
+            }
+        };
         let u = u.clamp(0.0, 1.0);
         let v = (1.0 - v).clamp(0.0, 1.0);
 
-        let (width, height) = self.image.dimensions();
+        let (width, height) = image.dimensions();
         let i = (u * (width as f64)) as u32;
         let j = (v * (height as f64)) as u32;
         let i = i.clamp(0, width - 1);
         let j = j.clamp(0, height - 1);
-        let pixel = self.image.get_pixel(i, j);
-        Color::new((pixel[0] as f64) / 255.0, (pixel[1] as f64) / 255.0, (pixel[2] as f64) / 255.0)
+        let pixel = image.get_pixel(i, j);
+        let channel = |c: u8| match self.encoding {
+            Encoding::Linear => c as f64 / 255.0,
+            Encoding::Srgb => srgb_to_linear(c as f64 / 255.0),
+        };
+        Color::new(channel(pixel[0]), channel(pixel[1]), channel(pixel[2]))
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        match &self.pixels {
+            Pixels::Decoded(image) => {
+                stats.textures += 1;
+                stats.texture_data(image.as_ref(), image.as_raw().len());
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            Pixels::Tiled(tiled) => tiled.inspect(stats),
+        }
+        if let Some(path) = &self.missing {
+            stats.problem(format!("missing texture {}", path));
+        }
+    }
+}
+
+// The inverse of the sRGB transfer function, from an encoded value in [0, 1] to linear light.
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Radiance from every direction, read from an equirectangular (latitude-longitude) Radiance HDR image with +y up.
+// Directions map to the image like points on a sphere to its texture, see `Sphere`.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    // Lines top to bottom, as in the file.
+    pixels: Vec<Color>,
+}
+
+impl EnvironmentMap {
+    pub fn open(path: &str) -> image::ImageResult<EnvironmentMap> {
+        let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        let decoder = image::codecs::hdr::HdrDecoder::new(reader)?;
+        let metadata = decoder.metadata();
+        let pixels = decoder.read_image_hdr()?;
+        Ok(EnvironmentMap {
+            width: metadata.width as usize,
+            height: metadata.height as usize,
+            pixels: pixels.iter().map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64)).collect(),
+        })
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn color(&self, ray: &Ray) -> Color {
+        let (u, v) = shapes::sphere_uv(&ray.dir.unit());
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = (((1.0 - v) * self.height as f64) as usize).min(self.height - 1);
+        self.pixels[j * self.width + i]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::Vec3;
+
+    #[test]
+    fn test_encoding() {
+        let image = Image::new(RgbImage::from_pixel(1, 1, Rgb([255, 128, 0])));
+        assert_eq!(Color::new(1.0, 128.0 / 255.0, 0.0), image.value(0.5, 0.5, Point3::ZERO));
+        let decoded = image.with_encoding(Encoding::Srgb).value(0.5, 0.5, Point3::ZERO);
+        // Mid gray in sRGB is about a fifth of white in light.
+        assert_eq!((1.0, 0.0), (decoded.r(), decoded.b()));
+        assert!((decoded.g() - 0.2158).abs() < 1e-4, "{}", decoded.g());
+        assert!((srgb_to_linear(0.02) - 0.02 / 12.92).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_missing() {
+        let path = "/nonexistent/texture.png";
+        let image = Image::open(path);
+        assert_eq!(Color::new(1.0, 0.0, 1.0), image.value(0.5, 0.5, Point3::ZERO));
+        // Reported to the renderer once, and to --validate.
+        assert!(take_missing().iter().any(|p| p == path));
+        assert!(!take_missing().iter().any(|p| p == path));
+        let mut stats = SceneStats::new();
+        image.inspect(&mut stats);
+        assert_eq!(vec![format!("missing texture {}", path)], stats.problems);
+    }
+
+    #[test]
+    fn test_environment_map() {
+        // Top half red, bottom half blue, and the left quarter of the bottom green.
+        let (red, green, blue) = (Rgb([1.0f32, 0.0, 0.0]), Rgb([0.0f32, 2.0, 0.0]), Rgb([0.0f32, 0.0, 0.5]));
+        let pixels = [red, red, red, red, green, blue, blue, blue];
+        let path = std::env::temp_dir().join(format!("environment_map_{}.hdr", std::process::id()));
+        let file = std::fs::File::create(&path).unwrap();
+        image::codecs::hdr::HdrEncoder::new(file).encode(&pixels, 4, 2).unwrap();
+        let map = EnvironmentMap::open(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let look = |x, y, z| map.color(&Ray::new(Point3::ZERO, Vec3::new(x, y, z)));
+        assert_eq!(Color::new(1.0, 0.0, 0.0), look(0.0, 1.0, 0.0));
+        assert_eq!(Color::new(0.0, 0.0, 0.5), look(0.0, -1.0, 0.0));
+        // -x is at u = 0, the left edge.
+        assert_eq!(Color::new(0.0, 2.0, 0.0), look(-1.0, -0.1, 0.01));
     }
 }
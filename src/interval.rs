@@ -0,0 +1,85 @@
+// Closed range of reals, mostly of ray parameters t.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Interval {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Interval {
+    pub const EMPTY: Interval = Interval { min: f64::INFINITY, max: f64::NEG_INFINITY };
+    pub const UNIVERSE: Interval = Interval { min: f64::NEG_INFINITY, max: f64::INFINITY };
+    // Everything in front of the ray origin.
+    pub const FORWARD: Interval = Interval { min: 0.0, max: f64::INFINITY };
+
+    pub fn new(min: f64, max: f64) -> Interval {
+        Interval { min, max }
+    }
+
+    pub fn size(&self) -> f64 {
+        self.max - self.min
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min > self.max
+    }
+
+    // Including the ends.
+    pub fn contains(&self, x: f64) -> bool {
+        self.min <= x && x <= self.max
+    }
+
+    // Excluding the ends.
+    pub fn surrounds(&self, x: f64) -> bool {
+        self.min < x && x < self.max
+    }
+
+    pub fn clamp(&self, x: f64) -> f64 {
+        x.max(self.min).min(self.max)
+    }
+
+    // Grown by `delta` in total, half on each side.
+    pub fn expand(&self, delta: f64) -> Interval {
+        let padding = delta / 2.0;
+        Interval::new(self.min - padding, self.max + padding)
+    }
+
+    pub fn with_min(&self, min: f64) -> Interval {
+        Interval::new(min, self.max)
+    }
+
+    pub fn with_max(&self, max: f64) -> Interval {
+        Interval::new(self.min, max)
+    }
+
+    // Overlap of the two, empty if they don't overlap.
+    pub fn intersect(&self, other: &Interval) -> Interval {
+        Interval::new(self.min.max(other.min), self.max.min(other.max))
+    }
+
+    // Smallest interval containing both.
+    pub fn surround(&self, other: &Interval) -> Interval {
+        Interval::new(self.min.min(other.min), self.max.max(other.max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interval() {
+        let i = Interval::new(1.0, 3.0);
+        assert_eq!(2.0, i.size());
+        assert!(i.contains(1.0) && i.contains(3.0));
+        assert!(!i.surrounds(1.0) && i.surrounds(2.0));
+        assert_eq!(1.0, i.clamp(-5.0));
+        assert_eq!(3.0, i.clamp(5.0));
+        assert_eq!(Interval::new(0.5, 3.5), i.expand(1.0));
+        assert_eq!(Interval::new(2.0, 3.0), i.intersect(&Interval::new(2.0, 4.0)));
+        assert!(i.intersect(&Interval::new(4.0, 5.0)).is_empty());
+        assert_eq!(Interval::new(1.0, 5.0), i.surround(&Interval::new(4.0, 5.0)));
+        assert!(Interval::EMPTY.is_empty() && !Interval::EMPTY.contains(0.0));
+        assert!(Interval::UNIVERSE.contains(-1e300));
+        assert!(!Interval::FORWARD.contains(-1.0));
+    }
+}
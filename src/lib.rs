@@ -0,0 +1,62 @@
+// The code base follows the structure of the books closely; these lints fight that style.
+#![allow(
+    clippy::bool_assert_comparison,
+    clippy::clone_on_copy,
+    clippy::legacy_numeric_constants,
+    clippy::manual_map,
+    clippy::manual_swap,
+    clippy::missing_safety_doc,
+    clippy::needless_range_loop,
+    clippy::needless_return,
+    clippy::new_without_default,
+    clippy::op_ref,
+    clippy::question_mark,
+    clippy::should_implement_trait,
+    clippy::single_match,
+    clippy::unused_unit
+)]
+
+mod aarects;
+pub mod animation;
+pub mod assets;
+pub mod bhv;
+pub mod camera;
+pub mod cli;
+pub mod compare;
+pub mod curves;
+pub mod ffi;
+pub mod film;
+pub mod filter;
+pub mod hittable;
+pub mod http;
+pub mod image_texture;
+pub mod interval;
+pub mod lights;
+pub mod logging;
+pub mod materials;
+pub mod math;
+pub mod measured;
+pub mod mesh;
+pub mod output;
+mod par;
+pub mod paths;
+pub mod postprocess;
+pub mod raytrace;
+pub mod rngator;
+pub mod sampler;
+pub mod scatter;
+pub mod scene;
+pub mod scratch;
+pub mod sdf;
+pub mod server;
+pub mod shapes;
+pub mod textures;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod tiled_image;
+pub mod tiles;
+pub mod transforms;
+pub mod validate;
+pub mod vec;
+pub mod volumes;
+pub mod web;
+pub mod worlds;
@@ -0,0 +1,270 @@
+// A hierarchy over the lights for worlds with many of them, see `--light_tree`. `HittableList` picks the light to
+// sample uniformly, so with hundreds of windows most shadow rays go to lights far away that add next to nothing.
+// The tree groups the lights by where they are, like the BVH, and every node knows how much its lights emit;
+// from the shading point, each step down picks a child in proportion to its power over its squared distance, so
+// nearby and bright lights are sampled most. The densities follow the same choices, so the image stays unbiased,
+// however rough the estimates are.
+use crate::bhv::AABB;
+use crate::hittable::{Hit, Hittable, HittableList};
+use crate::interval::Interval;
+use crate::validate::SceneStats;
+use crate::vec::{Color, Point3, Ray, Vec3};
+use rand::Rng;
+use std::cmp::Ordering;
+
+// Points sampled on every light to estimate its bounds and power.
+const ESTIMATE_SAMPLES: usize = 64;
+
+pub struct LightTree<'a> {
+    lights: Vec<Box<dyn Hittable + 'a>>,
+    // Children before their parents, the root last.
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    bounds: AABB,
+    power: f64,
+    children: Children,
+}
+
+enum Children {
+    // Index of the light.
+    Leaf(usize),
+    // Indices of the nodes.
+    Inner(usize, usize),
+}
+
+impl<'a> LightTree<'a> {
+    pub fn new(lights: HittableList<'a>, rng: &mut dyn rand::RngCore) -> LightTree<'a> {
+        let lights = lights.into_vec();
+        let estimates: Vec<(AABB, Option<f64>)> = lights.iter().map(|light| estimate(light.as_ref(), rng)).collect();
+        // Lights that can't be sampled by area get the average power, and every light keeps some chance of
+        // being picked, or those wrongly estimated as black would never be sampled.
+        let known: Vec<f64> = estimates.iter().filter_map(|&(_, power)| power).collect();
+        let average = if known.is_empty() { 1.0 } else { known.iter().sum::<f64>() / known.len() as f64 };
+        let least = if average > 0.0 { 1e-3 * average } else { 1.0 };
+        let mut leaves: Vec<(usize, AABB, f64)> = estimates
+            .into_iter()
+            .enumerate()
+            .map(|(i, (bounds, power))| (i, bounds, power.unwrap_or(average).max(least)))
+            .collect();
+        let mut nodes = Vec::new();
+        if !leaves.is_empty() {
+            build(&mut nodes, &mut leaves);
+        }
+        LightTree { lights, nodes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    // How much `node` is worth sampling from `o`: its power over the squared distance to its middle, but no
+    // closer than its half diagonal, as its lights may be anywhere in it.
+    fn importance(&self, node: usize, o: &Point3) -> f64 {
+        let Node { bounds, power, .. } = &self.nodes[node];
+        let center = 0.5 * (bounds.min() + bounds.max());
+        let radius_squared = 0.25 * (bounds.max() - bounds.min()).length_squared();
+        power / (center - *o).length_squared().max(radius_squared).max(1e-9)
+    }
+
+    // Probability of stepping down to `left` rather than `right`, given how much each is worth.
+    fn left_probability(&self, left: usize, right: usize, worth: &impl Fn(usize) -> f64) -> f64 {
+        let (l, r) = (worth(left), worth(right));
+        if l + r > 0.0 {
+            l / (l + r)
+        } else {
+            0.5
+        }
+    }
+
+    // A light picked by stepping down from the root, and the probability of picking it.
+    fn pick(&self, worth: impl Fn(usize) -> f64, rng: &mut dyn rand::RngCore) -> (usize, f64) {
+        let (mut node, mut probability) = (self.nodes.len() - 1, 1.0);
+        loop {
+            match self.nodes[node].children {
+                Children::Leaf(i) => return (i, probability),
+                Children::Inner(left, right) => {
+                    let p = self.left_probability(left, right, &worth);
+                    if rng.gen_range(0.0..1.0) < p {
+                        (node, probability) = (left, probability * p);
+                    } else {
+                        (node, probability) = (right, probability * (1.0 - p));
+                    }
+                }
+            }
+        }
+    }
+
+    // Density of the lights under `node` in direction `v`, each weighted by the probability of picking it.
+    fn pdf_under(&self, node: usize, probability: f64, o: &Point3, v: &Vec3, rng: &mut dyn rand::RngCore) -> f64 {
+        if probability <= 0.0 {
+            return 0.0;
+        }
+        match self.nodes[node].children {
+            Children::Leaf(i) => probability * self.lights[i].pdf_value(o, v, rng),
+            Children::Inner(left, right) => {
+                let p = self.left_probability(left, right, &|n| self.importance(n, o));
+                self.pdf_under(left, probability * p, o, v, rng)
+                    + self.pdf_under(right, probability * (1.0 - p), o, v, rng)
+            }
+        }
+    }
+}
+
+// Bounds and power of `light` from points sampled on it. Lights that can't be sampled by area are bounded by the
+// points of `random_point`, and their power is unknown.
+fn estimate(light: &dyn Hittable, rng: &mut dyn rand::RngCore) -> (AABB, Option<f64>) {
+    let mut bounds = AABB::EMPTY;
+    let (mut power, mut sampled) = (0.0, true);
+    for _ in 0..ESTIMATE_SAMPLES {
+        match light.sample_surface(rng) {
+            Some((h, pdf)) => {
+                bounds = bounds.surround(&AABB::new(h.p, h.p));
+                if pdf > 0.0 {
+                    power += luminance(h.material.emit(h.u, h.v, h.p)) / pdf;
+                }
+            }
+            None => {
+                sampled = false;
+                let p = light.random_point(&Point3::ZERO, rng);
+                bounds = bounds.surround(&AABB::new(p, p));
+            }
+        }
+    }
+    (bounds, if sampled { Some(power / ESTIMATE_SAMPLES as f64) } else { None })
+}
+
+fn luminance(c: Color) -> f64 {
+    0.2126 * c.r() + 0.7152 * c.g() + 0.0722 * c.b()
+}
+
+// Adds the nodes over `leaves`, split in half along the axis their middles spread most on, and returns the index
+// of the top one.
+fn build(nodes: &mut Vec<Node>, leaves: &mut [(usize, AABB, f64)]) -> usize {
+    let center = |b: &AABB| 0.5 * (b.min() + b.max());
+    let children = if let [(i, _, _)] = leaves {
+        Children::Leaf(*i)
+    } else {
+        let centers = leaves.iter().fold(AABB::EMPTY, |b, (_, l, _)| b.surround(&AABB::new(center(l), center(l))));
+        let axis = (0..3).max_by(|&a, &b| centers.axis(a).size().total_cmp(&centers.axis(b).size())).unwrap();
+        leaves.sort_by(|(_, a, _), (_, b, _)| {
+            center(a).e[axis].partial_cmp(&center(b).e[axis]).unwrap_or(Ordering::Equal)
+        });
+        let (left, right) = leaves.split_at_mut(leaves.len() / 2);
+        Children::Inner(build(nodes, left), build(nodes, right))
+    };
+    let bounds = leaves.iter().fold(AABB::EMPTY, |b, (_, l, _)| b.surround(l));
+    let power = leaves.iter().map(|(_, _, p)| p).sum();
+    nodes.push(Node { bounds, power, children });
+    nodes.len() - 1
+}
+
+impl<'a> Hittable for LightTree<'a> {
+    fn hit<'b>(&'b self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> Option<Hit<'b>> {
+        let mut result: Option<Hit> = None;
+        for light in self.lights.iter() {
+            let closest_so_far = result.as_ref().map_or(ray_t.max, |h| h.t);
+            if let Some(h) = light.hit(r, ray_t.with_max(closest_so_far), rng) {
+                result = Some(h);
+            }
+        }
+        result
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> bool {
+        self.lights.iter().any(|light| light.hit_any(r, ray_t, rng))
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, rng: &mut dyn rand::RngCore) -> f64 {
+        if self.nodes.is_empty() {
+            return 0.0;
+        }
+        self.pdf_under(self.nodes.len() - 1, 1.0, o, v, rng)
+    }
+
+    fn random_point(&self, o: &Point3, rng: &mut dyn rand::RngCore) -> Point3 {
+        let (i, _) = self.pick(|n| self.importance(n, o), rng);
+        self.lights[i].random_point(o, rng)
+    }
+
+    // Light paths start from anywhere, so the lights are picked by their power alone.
+    fn sample_surface<'b>(&'b self, rng: &mut dyn rand::RngCore) -> Option<(Hit<'b>, f64)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let (i, probability) = self.pick(|n| self.nodes[n].power, rng);
+        let (h, pdf) = self.lights[i].sample_surface(rng)?;
+        Some((h, probability * pdf))
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.geometry_memory += std::mem::size_of_val(self.nodes.as_slice());
+        self.lights.iter().for_each(|light| light.inspect(stats));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::DiffuseLight;
+    use crate::shapes::XZRect;
+    use crate::textures::SolidColor;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_light_tree() {
+        // A row of small lamps overhead, seen from under the first one.
+        let lamps = || {
+            let mut lights = HittableList::new();
+            for i in 0..64 {
+                let x = 2.0 * i as f64;
+                lights.add(XZRect::new(
+                    x - 0.1,
+                    x + 0.1,
+                    -0.1,
+                    0.1,
+                    1.0,
+                    DiffuseLight::new(SolidColor::new(1.0, 1.0, 1.0)),
+                ));
+            }
+            lights
+        };
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        let list = lamps();
+        let tree = LightTree::new(lamps(), &mut rng);
+        assert_eq!(64, tree.len());
+
+        // Both estimate the solid angle of the lamps without bias, the tree with far less variance as it mostly
+        // samples the nearest lamps, which the list only picks once in 64 times.
+        let o = Point3::new(0.0, 0.0, 0.0);
+        let solid_angle = |lights: &dyn Hittable, rng: &mut rand_pcg::Pcg64| {
+            let samples: Vec<(f64, Point3)> = (0..20000)
+                .map(|_| {
+                    let p = lights.random_point(&o, rng);
+                    (1.0 / lights.pdf_value(&o, &(p - o), rng), p)
+                })
+                .collect();
+            let mean = samples.iter().map(|(w, _)| w).sum::<f64>() / samples.len() as f64;
+            let variance = samples.iter().map(|(w, _)| (w - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+            let nearest = samples.iter().filter(|(_, p)| p.x() < 1.0).count() as f64 / samples.len() as f64;
+            (mean, variance, nearest)
+        };
+        let (_, list_variance, list_nearest) = solid_angle(&list, &mut rng);
+        let (tree_mean, tree_variance, tree_nearest) = solid_angle(&tree, &mut rng);
+        // Each lamp covers about its area times the cosine over the squared distance.
+        let expected: f64 = (0..64).map(|i| 0.04 / (1.0 + 4.0 * (i * i) as f64).powf(1.5)).sum();
+        assert!((tree_mean / expected - 1.0).abs() < 0.03, "{} {}", tree_mean, expected);
+        assert!(tree_variance < 0.1 * list_variance, "{} {}", tree_variance, list_variance);
+        assert!(tree_nearest > 10.0 * list_nearest, "{} {}", tree_nearest, list_nearest);
+
+        // Light paths start from the lamps by power, here evenly.
+        let (_, pdf) = tree.sample_surface(&mut rng).unwrap();
+        assert!((pdf - 1.0 / (64.0 * 0.04)).abs() < 1e-6);
+        assert!(LightTree::new(HittableList::new(), &mut rng).sample_surface(&mut rng).is_none());
+    }
+}
@@ -0,0 +1,197 @@
+// What the renderer has to say while it works, e.g. the progress of a render, where the time went and files it
+// couldn't load, by how much of it the user wants to hear. Everything goes to a `Logger`, stderr unless a program
+// using the library sets its own with `set_logger`, e.g. to show progress in its window or collect the timings.
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+// How much is logged; each level logs what the levels before it do, and more.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    // Only warnings, e.g. missing textures.
+    Quiet,
+    // Progress and results, as the command line always printed them.
+    Normal,
+    // The time of every phase, e.g. building the world and its BVHs, loading assets and rendering.
+    Verbose,
+    // Details of the phases, e.g. every BVH built.
+    Trace,
+}
+
+impl std::str::FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Level, String> {
+        match s {
+            "quiet" => Ok(Level::Quiet),
+            "normal" => Ok(Level::Normal),
+            "verbose" => Ok(Level::Verbose),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!("unknown log level '{}'", s)),
+        }
+    }
+}
+
+// Something logged, as data for loggers that do more than print it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event<'a> {
+    // The state of a phase, e.g. the lines remaining, replacing the one before.
+    Progress(&'a str),
+    Message(&'a str),
+    // How long a phase took, e.g. ("build world", 1.2 s).
+    Timing(&'a str, Duration),
+}
+
+pub trait Logger: Send + Sync {
+    // Called with the events at the current level or below, from whichever thread logs them.
+    fn log(&self, level: Level, event: &Event);
+}
+
+// Writes progress over itself on one line, and messages and timings on lines of their own.
+pub struct Stderr;
+
+// Whether the last line written to stderr is progress that the next message goes below.
+static PROGRESS: AtomicBool = AtomicBool::new(false);
+
+impl Logger for Stderr {
+    fn log(&self, _: Level, event: &Event) {
+        let end_progress = || {
+            if PROGRESS.swap(false, Ordering::Relaxed) {
+                eprintln!();
+            }
+        };
+        match event {
+            Event::Progress(text) => {
+                eprint!("\r{:50}", text);
+                PROGRESS.store(true, Ordering::Relaxed);
+            }
+            Event::Message(text) => {
+                end_progress();
+                eprintln!("{}", text);
+            }
+            Event::Timing(phase, duration) => {
+                end_progress();
+                eprintln!("{}: {:.3}s", phase, duration.as_secs_f64());
+            }
+        }
+    }
+}
+
+static LEVEL: AtomicUsize = AtomicUsize::new(Level::Normal as usize);
+static LOGGER: RwLock<Option<Box<dyn Logger>>> = RwLock::new(None);
+
+// Sets the level of everything logged from now on, e.g. from the command line before building the world.
+pub fn set_level(level: Level) {
+    LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+pub fn level() -> Level {
+    match LEVEL.load(Ordering::Relaxed) {
+        l if l == Level::Quiet as usize => Level::Quiet,
+        l if l == Level::Verbose as usize => Level::Verbose,
+        l if l == Level::Trace as usize => Level::Trace,
+        _ => Level::Normal,
+    }
+}
+
+// Whether events at `level` are logged, to skip the work of making them if not.
+pub fn enabled(level: Level) -> bool {
+    level <= self::level()
+}
+
+// Sends everything logged from now on to `logger` instead of stderr.
+pub fn set_logger(logger: Box<dyn Logger>) {
+    *LOGGER.write().unwrap() = Some(logger);
+}
+
+pub fn log(level: Level, event: Event) {
+    if !enabled(level) {
+        return;
+    }
+    match LOGGER.read().unwrap().as_ref() {
+        Some(logger) => logger.log(level, &event),
+        None => Stderr.log(level, &event),
+    }
+}
+
+pub fn progress(text: &str) {
+    log(Level::Normal, Event::Progress(text));
+}
+
+pub fn info(text: &str) {
+    log(Level::Normal, Event::Message(text));
+}
+
+// Logged even when quiet.
+pub fn warning(text: &str) {
+    log(Level::Quiet, Event::Message(text));
+}
+
+pub fn verbose(text: &str) {
+    log(Level::Verbose, Event::Message(text));
+}
+
+pub fn trace(text: &str) {
+    log(Level::Trace, Event::Message(text));
+}
+
+// Runs `phase` and logs how long it took at `level`. Phases aren't timed unless logged, as there is no clock in
+// the browser.
+pub fn timed<R>(level: Level, name: &str, phase: impl FnOnce() -> R) -> R {
+    if !enabled(level) {
+        return phase();
+    }
+    let start = Instant::now();
+    let result = phase();
+    log(level, Event::Timing(name, start.elapsed()));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    impl Logger for Capture {
+        fn log(&self, level: Level, event: &Event) {
+            // Other tests log too; only this test's events are kept.
+            let text = match event {
+                Event::Progress(text) | Event::Message(text) => text.to_string(),
+                Event::Timing(phase, _) => phase.to_string(),
+            };
+            if text.starts_with("logging test") {
+                self.0.lock().unwrap().push(format!("{:?} {}", level, text));
+            }
+        }
+    }
+
+    #[test]
+    fn test_logging() {
+        assert_eq!(Ok(Level::Verbose), "verbose".parse());
+        assert!("loud".parse::<Level>().is_err());
+        assert!(Level::Quiet < Level::Normal && Level::Verbose < Level::Trace);
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        set_logger(Box::new(Capture(events.clone())));
+        set_level(Level::Verbose);
+        assert!(enabled(Level::Verbose) && !enabled(Level::Trace));
+        progress("logging test progress");
+        trace("logging test trace");
+        assert_eq!(7, timed(Level::Verbose, "logging test phase", || 7));
+        set_level(Level::Quiet);
+        info("logging test info");
+        warning("logging test warning");
+        set_level(Level::Normal);
+        set_logger(Box::new(Stderr));
+        assert_eq!(
+            vec![
+                "Normal logging test progress".to_string(),
+                "Verbose logging test phase".to_string(),
+                "Quiet logging test warning".to_string()
+            ],
+            *events.lock().unwrap()
+        );
+    }
+}
@@ -1,14 +1,236 @@
 use crate::hittable;
+use crate::math::Onb;
+use crate::raytrace::RayKind;
 use crate::textures::Texture;
+use crate::validate::SceneStats;
 use crate::vec::{Color, Point3, Ray, Vec3};
+use crate::worlds::MaterialSpec;
 use rand::Rng;
+use std::sync::Arc;
 
-pub trait Material: Sync {
+pub trait Material: Send + Sync {
+    // A direction the light arriving at the hit along `ray` continues in, and the attenuation it is weighted by:
+    // the BSDF times the cosine to the normal over the density the direction is drawn with. None if it is
+    // absorbed.
     fn scatter(&self, ray: &Ray, h: &hittable::Hit, rng: &mut dyn rand::RngCore) -> Option<(Color, Ray)>;
 
     fn emit(&self, _u: f64, _v: f64, _p: Point3) -> Color {
         Color::ZERO
     }
+
+    // For light sampling: BSDF times cosine for light leaving the hit point towards the camera along `ray`
+    // when it arrives from (unit) `direction`. None for materials that can't be light-sampled, e.g. specular ones.
+    fn eval(&self, _ray: &Ray, _h: &hittable::Hit, _direction: &Vec3) -> Option<Color> {
+        None
+    }
+
+    // For shadow rays towards sampled lights: the fraction of light that passes straight through the surface
+    // along `ray`, including what is absorbed on the way from the ray's origin when it leaves the material.
+    // None for opaque materials, which block the light.
+    fn transmission(&self, _ray: &Ray, _h: &hittable::Hit) -> Option<Color> {
+        None
+    }
+
+    // Light group the emitted light is accounted to when rendering per-light buffers.
+    // Group 0 also collects the background.
+    fn light_group(&self) -> usize {
+        0
+    }
+
+    // The kind of the rays `scatter` casts: diffuse, or specular for mirror-like reflection and refraction.
+    fn bounce_kind(&self) -> RayKind {
+        RayKind::Diffuse
+    }
+
+    // Whether `scatter` scatters in a volume rather than off a surface; such bounces have their own limit, see
+    // `raytrace::Bounces`.
+    fn in_volume(&self) -> bool {
+        false
+    }
+
+    // Whether camera rays see the background instead of the surface, see `Holdout`.
+    fn holdout(&self) -> bool {
+        false
+    }
+
+    // Which rays see the surface, see `Visible`.
+    fn visibility(&self) -> Visibility {
+        Visibility::ALL
+    }
+
+    // For `--validate`, see `Hittable::inspect`.
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.materials += 1;
+    }
+
+    // For `--export_scene`: the material as the options of a scene file, None if they can't describe it.
+    fn export(&self) -> Option<MaterialSpec> {
+        None
+    }
+}
+
+// Material that can be shared by many shapes without copying it, e.g. one with a large image texture.
+pub type SharedMaterial = Arc<dyn Material>;
+
+impl<M: Material + ?Sized> Material for Arc<M> {
+    fn scatter(&self, ray: &Ray, h: &hittable::Hit, rng: &mut dyn rand::RngCore) -> Option<(Color, Ray)> {
+        (**self).scatter(ray, h, rng)
+    }
+
+    fn emit(&self, u: f64, v: f64, p: Point3) -> Color {
+        (**self).emit(u, v, p)
+    }
+
+    fn eval(&self, ray: &Ray, h: &hittable::Hit, direction: &Vec3) -> Option<Color> {
+        (**self).eval(ray, h, direction)
+    }
+
+    fn transmission(&self, ray: &Ray, h: &hittable::Hit) -> Option<Color> {
+        (**self).transmission(ray, h)
+    }
+
+    fn light_group(&self) -> usize {
+        (**self).light_group()
+    }
+
+    fn bounce_kind(&self) -> RayKind {
+        (**self).bounce_kind()
+    }
+
+    fn in_volume(&self) -> bool {
+        (**self).in_volume()
+    }
+
+    fn holdout(&self) -> bool {
+        (**self).holdout()
+    }
+
+    fn visibility(&self) -> Visibility {
+        (**self).visibility()
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        (**self).inspect(stats)
+    }
+
+    fn export(&self) -> Option<MaterialSpec> {
+        (**self).export()
+    }
+}
+
+// A matte for compositing: camera rays see the background where the surface is, and it is left out of the
+// alpha, while it still blocks light, casts shadows and shows in reflections like the material it wraps. The
+// rest of the render can then be laid over a photo or another render of the object.
+#[derive(Copy, Clone)]
+pub struct Holdout<M: Material>(pub M);
+
+impl<M: Material> Material for Holdout<M> {
+    fn scatter(&self, ray: &Ray, h: &hittable::Hit, rng: &mut dyn rand::RngCore) -> Option<(Color, Ray)> {
+        self.0.scatter(ray, h, rng)
+    }
+
+    fn emit(&self, u: f64, v: f64, p: Point3) -> Color {
+        self.0.emit(u, v, p)
+    }
+
+    fn eval(&self, ray: &Ray, h: &hittable::Hit, direction: &Vec3) -> Option<Color> {
+        self.0.eval(ray, h, direction)
+    }
+
+    fn transmission(&self, ray: &Ray, h: &hittable::Hit) -> Option<Color> {
+        self.0.transmission(ray, h)
+    }
+
+    fn light_group(&self) -> usize {
+        self.0.light_group()
+    }
+
+    fn bounce_kind(&self) -> RayKind {
+        self.0.bounce_kind()
+    }
+
+    fn in_volume(&self) -> bool {
+        self.0.in_volume()
+    }
+
+    fn holdout(&self) -> bool {
+        true
+    }
+
+    fn visibility(&self) -> Visibility {
+        self.0.visibility()
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        self.0.inspect(stats)
+    }
+}
+
+// The kinds of rays that see a surface: camera rays, shadow rays towards sampled lights, and the rays of bounces.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Visibility {
+    pub camera: bool,
+    pub shadow: bool,
+    pub indirect: bool,
+}
+
+impl Visibility {
+    pub const ALL: Visibility = Visibility { camera: true, shadow: true, indirect: true };
+
+    pub fn sees(&self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera,
+            RayKind::Shadow => self.shadow,
+            RayKind::Diffuse | RayKind::Specular => self.indirect,
+        }
+    }
+}
+
+// Hides the surface of the material it wraps from some kinds of rays, which pass through it as if it weren't
+// there, e.g. a wall the camera looks through into a closed room that is still lit by the light it reflects.
+#[derive(Copy, Clone)]
+pub struct Visible<M: Material>(pub M, pub Visibility);
+
+impl<M: Material> Material for Visible<M> {
+    fn scatter(&self, ray: &Ray, h: &hittable::Hit, rng: &mut dyn rand::RngCore) -> Option<(Color, Ray)> {
+        self.0.scatter(ray, h, rng)
+    }
+
+    fn emit(&self, u: f64, v: f64, p: Point3) -> Color {
+        self.0.emit(u, v, p)
+    }
+
+    fn eval(&self, ray: &Ray, h: &hittable::Hit, direction: &Vec3) -> Option<Color> {
+        self.0.eval(ray, h, direction)
+    }
+
+    fn transmission(&self, ray: &Ray, h: &hittable::Hit) -> Option<Color> {
+        self.0.transmission(ray, h)
+    }
+
+    fn light_group(&self) -> usize {
+        self.0.light_group()
+    }
+
+    fn bounce_kind(&self) -> RayKind {
+        self.0.bounce_kind()
+    }
+
+    fn in_volume(&self) -> bool {
+        self.0.in_volume()
+    }
+
+    fn holdout(&self) -> bool {
+        self.0.holdout()
+    }
+
+    fn visibility(&self) -> Visibility {
+        self.1
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        self.0.inspect(stats)
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -24,12 +246,129 @@ impl<T: Texture> Lambertian<T> {
 
 impl<T: Texture> Material for Lambertian<T> {
     fn scatter(&self, _ray: &Ray, h: &hittable::Hit, rng: &mut dyn rand::RngCore) -> Option<(Color, Ray)> {
-        let mut scatter_direction = h.normal + Vec3::random_in_hemisphere(&h.normal, rng);
-        if scatter_direction.near_zero() {
-            scatter_direction = h.normal;
+        let direction = Onb::new(h.normal).to_world(Vec3::random_cosine_direction(rng));
+        let cosine = direction.dot(h.normal);
+        if cosine <= 0.0 {
+            return None;
         }
-        let attenuation = self.albedo.value(h.u, h.v, h.p);
-        return Some((attenuation, Ray::new(h.p, scatter_direction)));
+        // The directions are drawn like the cosine, so the weight comes out as the albedo.
+        let pdf = cosine / std::f64::consts::PI;
+        let attenuation = self.albedo.value(h.u, h.v, h.p) * cosine / std::f64::consts::PI / pdf;
+        return Some((attenuation, h.spawn_ray(direction)));
+    }
+
+    fn eval(&self, _ray: &Ray, h: &hittable::Hit, direction: &Vec3) -> Option<Color> {
+        let cosine = h.normal.dot(*direction).max(0.0);
+        Some(self.albedo.value(h.u, h.v, h.p) * cosine / std::f64::consts::PI)
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.materials += 1;
+        self.albedo.inspect(stats);
+    }
+
+    fn export(&self) -> Option<MaterialSpec> {
+        Some(MaterialSpec::Lambertian { color: self.albedo.constant()? })
+    }
+}
+
+// Cloth such as velvet: a diffuse base under a sheen of fibers that reflect the most at grazing angles, brightening
+// the silhouette. The sheen is the "Charlie" lobe of Estevez and Kulla's "Production Friendly Microfacet Sheen BRDF"
+// with Neubelt and Pettineo's visibility term; the base reflects what the sheen leaves, so that it never adds up to
+// more than was let in.
+#[derive(Clone)]
+pub struct Velvet<T: Texture> {
+    pub albedo: T,
+    pub sheen: Color,
+    // Of the fibers, from 0 (short and straight, a narrow rim) to 1 (a broad haze).
+    alpha: f64,
+    // Fraction of light the white sheen reflects towards directions with cosines 0 to 1 to the normal.
+    sheen_albedo: Vec<f64>,
+}
+
+// Cosines `Velvet::sheen_albedo` is tabulated at.
+const SHEEN_ALBEDO_STEPS: usize = 32;
+
+impl<T: Texture> Velvet<T> {
+    pub fn new(albedo: T, sheen: Color, roughness: f64) -> Velvet<T> {
+        let alpha = roughness.clamp(0.05, 1.0).powi(2);
+        // Integrated over incoming directions drawn like the cosine, on a grid.
+        let n = 64;
+        let sheen_albedo = (0..SHEEN_ALBEDO_STEPS)
+            .map(|k| {
+                let cos_o = (k as f64 / (SHEEN_ALBEDO_STEPS - 1) as f64).max(1e-3);
+                let wo = Vec3::new((1.0 - cos_o * cos_o).sqrt(), 0.0, cos_o);
+                let sum: f64 = (0..n * n)
+                    .map(|s| {
+                        let (u, v) = (((s / n) as f64 + 0.5) / n as f64, ((s % n) as f64 + 0.5) / n as f64);
+                        let phi = 2.0 * std::f64::consts::PI * v;
+                        let wi = Vec3::new(phi.cos() * u.sqrt(), phi.sin() * u.sqrt(), (1.0 - u).sqrt());
+                        Velvet::<T>::charlie(alpha, wi, wo)
+                    })
+                    .sum();
+                (sum / (n * n) as f64 * std::f64::consts::PI).min(1.0)
+            })
+            .collect();
+        Velvet { albedo, sheen, alpha, sheen_albedo }
+    }
+
+    // The white sheen's BRDF, for unit vectors in the tangent frame with the normal along z.
+    fn charlie(alpha: f64, wi: Vec3, wo: Vec3) -> f64 {
+        let (cos_i, cos_o) = (wi.z(), wo.z());
+        if cos_i <= 0.0 || cos_o <= 0.0 {
+            return 0.0;
+        }
+        let cos_h = (wi + wo).unit().z();
+        let sin_h = (1.0 - cos_h * cos_h).max(0.0).sqrt();
+        let distribution = (2.0 + 1.0 / alpha) * sin_h.powf(1.0 / alpha) / (2.0 * std::f64::consts::PI);
+        let visibility = 1.0 / (4.0 * (cos_i + cos_o - cos_i * cos_o));
+        distribution * visibility
+    }
+
+    fn sheen_albedo(&self, cos_o: f64) -> f64 {
+        let x = cos_o.clamp(0.0, 1.0) * (SHEEN_ALBEDO_STEPS - 1) as f64;
+        let k = (x as usize).min(SHEEN_ALBEDO_STEPS - 2);
+        let t = x - k as f64;
+        self.sheen_albedo[k] * (1.0 - t) + self.sheen_albedo[k + 1] * t
+    }
+
+    // BSDF for light arriving from `wi` and leaving towards `wo`, in the tangent frame.
+    fn brdf(&self, h: &hittable::Hit, wi: Vec3, wo: Vec3) -> Color {
+        if wi.z() <= 0.0 || wo.z() <= 0.0 {
+            return Color::ZERO;
+        }
+        let base = self.albedo.value(h.u, h.v, h.p) * (Color::ONE - self.sheen_albedo(wo.z()) * self.sheen);
+        base / std::f64::consts::PI + Velvet::<T>::charlie(self.alpha, wi, wo) * self.sheen
+    }
+}
+
+impl<T: Texture> Material for Velvet<T> {
+    fn scatter(&self, ray: &Ray, h: &hittable::Hit, rng: &mut dyn rand::RngCore) -> Option<(Color, Ray)> {
+        // The sheen is broad enough that drawing directions like the cosine serves both lobes.
+        let frame = Onb::new(h.normal);
+        let (wi, wo) = (Vec3::random_cosine_direction(rng), frame.to_local(-ray.dir.unit()));
+        if wi.z() <= 0.0 {
+            return None;
+        }
+        let pdf = wi.z() / std::f64::consts::PI;
+        let attenuation = self.brdf(h, wi, wo) * wi.z() / pdf;
+        Some((attenuation, h.spawn_ray(frame.to_world(wi))))
+    }
+
+    fn eval(&self, ray: &Ray, h: &hittable::Hit, direction: &Vec3) -> Option<Color> {
+        let frame = Onb::new(h.normal);
+        let (wi, wo) = (frame.to_local(*direction), frame.to_local(-ray.dir.unit()));
+        Some(self.brdf(h, wi, wo) * wi.z().max(0.0))
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.materials += 1;
+        self.albedo.inspect(stats);
+    }
+
+    fn export(&self) -> Option<MaterialSpec> {
+        let color = self.albedo.constant()?;
+        Some(MaterialSpec::Velvet { color, sheen: self.sheen, roughness: self.alpha.sqrt() })
     }
 }
 
@@ -37,10 +376,80 @@ impl<T: Texture> Material for Lambertian<T> {
 pub struct Metal {
     pub albedo: Color,
     pub fuzz: f64,
+    // Reflects by the Fresnel equations of this metal, tinted by `albedo`, instead of `albedo` alone.
+    pub conductor: Option<Conductor>,
 }
 impl Metal {
     pub fn new(albedo: Color, fuzz: f64) -> Metal {
-        Metal { albedo, fuzz }
+        Metal { albedo, fuzz, conductor: None }
+    }
+
+    pub fn conductor(conductor: Conductor, fuzz: f64) -> Metal {
+        Metal { albedo: Color::ONE, fuzz, conductor: Some(conductor) }
+    }
+}
+
+// Complex index of refraction of a metal, eta + ik, for red, green and blue. The light it reflects depends on the
+// angle: its own color head on, turning white towards grazing angles.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Conductor {
+    pub eta: Color,
+    pub k: Color,
+}
+
+impl Conductor {
+    // Measured values at 650, 550 and 450 nm.
+    pub const GOLD: Conductor =
+        Conductor { eta: Color::new(0.143119, 0.374957, 1.44248), k: Color::new(3.98316, 2.38572, 1.60322) };
+    pub const SILVER: Conductor =
+        Conductor { eta: Color::new(0.155265, 0.116723, 0.138342), k: Color::new(4.82835, 3.12225, 2.14696) };
+    pub const COPPER: Conductor =
+        Conductor { eta: Color::new(0.200438, 0.924033, 1.10221), k: Color::new(3.91295, 2.45285, 2.14219) };
+    pub const ALUMINUM: Conductor =
+        Conductor { eta: Color::new(1.65746, 0.880369, 0.521229), k: Color::new(9.22387, 6.26952, 4.837) };
+
+    // The preset of gold, silver, copper or aluminum (or aluminium).
+    pub fn named(name: &str) -> Option<Conductor> {
+        match name {
+            "gold" => Some(Conductor::GOLD),
+            "silver" => Some(Conductor::SILVER),
+            "copper" => Some(Conductor::COPPER),
+            "aluminum" | "aluminium" => Some(Conductor::ALUMINUM),
+            _ => None,
+        }
+    }
+
+    // The name of the preset, if it is one.
+    pub fn name(&self) -> Option<&'static str> {
+        let presets = [
+            (Conductor::GOLD, "gold"),
+            (Conductor::SILVER, "silver"),
+            (Conductor::COPPER, "copper"),
+            (Conductor::ALUMINUM, "aluminum"),
+        ];
+        presets.iter().find(|(preset, _)| preset == self).map(|(_, name)| *name)
+    }
+
+    // Fraction of unpolarized light coming in at `cos_theta` to the normal that is reflected, from air.
+    pub fn reflectance(&self, cos_theta: f64) -> Color {
+        let cos2 = cos_theta.clamp(0.0, 1.0).powi(2);
+        let sin2 = 1.0 - cos2;
+        let channel = |eta: f64, k: f64| {
+            let t0 = eta * eta - k * k - sin2;
+            let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+            let t1 = a2_plus_b2 + cos2;
+            let t2 = 2.0 * cos2.sqrt() * (0.5 * (a2_plus_b2 + t0)).sqrt();
+            let rs = (t1 - t2) / (t1 + t2);
+            let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+            let t4 = t2 * sin2;
+            let rp = rs * (t3 - t4) / (t3 + t4);
+            0.5 * (rs + rp)
+        };
+        Color::new(
+            channel(self.eta.r(), self.k.r()),
+            channel(self.eta.g(), self.k.g()),
+            channel(self.eta.b(), self.k.b()),
+        )
     }
 }
 
@@ -51,13 +460,29 @@ fn reflect(v: Vec3, n: Vec3) -> Vec3 {
 impl Material for Metal {
     fn scatter(&self, ray: &Ray, h: &hittable::Hit, rng: &mut dyn rand::RngCore) -> Option<(Color, Ray)> {
         let reflected = reflect(ray.dir.unit(), h.normal);
-        let scattered = Ray::new(h.p, reflected + self.fuzz * Vec3::random_in_unit_sphere(rng));
+        let scattered = h.spawn_ray(reflected + self.fuzz * Vec3::random_in_unit_sphere(rng));
         if scattered.dir.dot(h.normal) > 0.0 {
-            Some((self.albedo, scattered))
+            let attenuation = match &self.conductor {
+                Some(conductor) => self.albedo * conductor.reflectance(-ray.dir.unit().dot(h.normal)),
+                None => self.albedo,
+            };
+            Some((attenuation, scattered))
         } else {
             None
         }
     }
+
+    fn bounce_kind(&self) -> RayKind {
+        RayKind::Specular
+    }
+
+    // Conductors other than the presets have no options.
+    fn export(&self) -> Option<MaterialSpec> {
+        if self.conductor.is_some_and(|c| c.name().is_none()) {
+            return None;
+        }
+        Some(MaterialSpec::Metal { color: self.albedo, fuzz: self.fuzz, conductor: self.conductor })
+    }
 }
 
 fn refract(uv: Vec3, n: Vec3, etai_over_etat: f64) -> Vec3 {
@@ -70,11 +495,28 @@ fn refract(uv: Vec3, n: Vec3, etai_over_etat: f64) -> Vec3 {
 #[derive(Copy, Clone)]
 pub struct Dielectric {
     pub index_of_refraction: f64,
+    // Fraction of light absorbed per unit of distance inside, per channel; by Beer-Lambert's law thick glass
+    // comes out more deeply tinted than thin glass.
+    pub absorption: Color,
 }
 
 impl Dielectric {
     pub fn new(index_of_refraction: f64) -> Dielectric {
-        Dielectric { index_of_refraction }
+        Dielectric { index_of_refraction, absorption: Color::ZERO }
+    }
+
+    pub fn tinted(index_of_refraction: f64, absorption: Color) -> Dielectric {
+        Dielectric { index_of_refraction, absorption }
+    }
+
+    // Light left after travelling from the ray's origin to the hit inside the glass.
+    fn absorbed(&self, ray: &Ray, h: &hittable::Hit) -> Color {
+        if h.front_face {
+            return Color::ONE;
+        }
+        let distance = h.t * ray.dir.length();
+        let channel = |a: f64| if a > 0.0 { (-a * distance).exp() } else { 1.0 };
+        Color::new(channel(self.absorption.r()), channel(self.absorption.g()), channel(self.absorption.b()))
     }
 }
 
@@ -87,7 +529,7 @@ fn reflectance(cos_theta: f64, refraction_ratio: f64) -> f64 {
 
 impl Material for Dielectric {
     fn scatter(&self, ray: &Ray, h: &hittable::Hit, rng: &mut dyn rand::RngCore) -> Option<(Color, Ray)> {
-        let attenuation = Color::new(1.0, 1.0, 1.0);
+        let attenuation = self.absorbed(ray, h);
         let refraction_ratio = if !h.front_face { self.index_of_refraction } else { 1.0 / self.index_of_refraction };
 
         let unit_direction = ray.dir.unit();
@@ -101,18 +543,50 @@ impl Material for Dielectric {
             refract(unit_direction, h.normal, refraction_ratio)
         };
 
-        return Some((attenuation, Ray::new(h.p, direction)));
+        return Some((attenuation, h.spawn_ray(direction)));
+    }
+
+    fn bounce_kind(&self) -> RayKind {
+        RayKind::Specular
+    }
+
+    // Shadow rays go straight through, so the shadow of glass is lit by the light that isn't reflected at the
+    // surfaces, but not focused as it would be by refraction.
+    fn transmission(&self, ray: &Ray, h: &hittable::Hit) -> Option<Color> {
+        let refraction_ratio = if !h.front_face { self.index_of_refraction } else { 1.0 / self.index_of_refraction };
+        let cos_theta = h.normal.dot(-ray.dir.unit()).min(1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        if refraction_ratio * sin_theta > 1.0 {
+            return Some(Color::ZERO);
+        }
+        Some((1.0 - reflectance(cos_theta, refraction_ratio)) * self.absorbed(ray, h))
+    }
+
+    fn export(&self) -> Option<MaterialSpec> {
+        let (index_of_refraction, absorption) = (self.index_of_refraction, self.absorption);
+        Some(MaterialSpec::Dielectric { index_of_refraction, absorption })
     }
 }
 
 #[derive(Clone)]
 pub struct DiffuseLight<T: Texture> {
     texture: T,
+    group: usize,
+    // Multiplies the texture, so textures of colors in [0, 1], e.g. images, can be brighter than white.
+    intensity: f64,
 }
 
 impl<T: Texture> DiffuseLight<T> {
     pub fn new(texture: T) -> DiffuseLight<T> {
-        DiffuseLight { texture }
+        DiffuseLight { texture, group: 0, intensity: 1.0 }
+    }
+    pub fn with_group(texture: T, group: usize) -> DiffuseLight<T> {
+        DiffuseLight { texture, group, intensity: 1.0 }
+    }
+
+    // For an image, decode it from sRGB (see `image_texture::Encoding`) so the intensity scales light.
+    pub fn with_intensity(self, intensity: f64) -> DiffuseLight<T> {
+        DiffuseLight { intensity, ..self }
     }
 }
 
@@ -122,6 +596,141 @@ impl<T: Texture> Material for DiffuseLight<T> {
     }
 
     fn emit(&self, u: f64, v: f64, p: Point3) -> Color {
-        self.texture.value(u, v, p)
+        self.intensity * self.texture.value(u, v, p)
+    }
+
+    fn light_group(&self) -> usize {
+        self.group
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.materials += 1;
+        self.texture.inspect(stats);
+    }
+
+    // Scene files have no light groups, so the light goes to the first.
+    fn export(&self) -> Option<MaterialSpec> {
+        Some(MaterialSpec::Light { color: self.intensity * self.texture.constant()?, temperature: None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conductor_reflectance() {
+        // Gold is yellow head on and silver nearly white; both turn white at grazing angles.
+        let gold = Conductor::GOLD.reflectance(1.0);
+        assert!(gold.r() > 0.9 && gold.g() > 0.7 && gold.b() < 0.45, "{:?}", gold);
+        let silver = Conductor::SILVER.reflectance(1.0);
+        assert!(silver.r() > 0.95 && silver.b() > 0.85, "{:?}", silver);
+        assert!((Conductor::GOLD.reflectance(0.0) - Color::ONE).length() < 1e-9);
+        // Reflectance dips slightly before grazing, but never drops below a fraction of that head on.
+        for cos_theta in [0.9, 0.5, 0.2, 0.05] {
+            let r = Conductor::COPPER.reflectance(cos_theta);
+            assert!(r.r() > 0.8 && r.r() <= 1.0, "{} {:?}", cos_theta, r);
+        }
+        assert_eq!(Some(Conductor::ALUMINUM), Conductor::named("aluminium"));
+        assert_eq!(None, Conductor::named("brass"));
+    }
+
+    #[test]
+    fn test_lambertian_sampling() {
+        use crate::textures::SolidColor;
+        use rand::SeedableRng;
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        let material = Lambertian::new(SolidColor::new(0.5, 0.5, 0.5));
+        let normal = Vec3::new(1.0, 2.0, -0.5).unit();
+        let (dpdu, dpdv) = hittable::tangents(&normal);
+        let h = hittable::Hit {
+            p: Point3::ZERO,
+            normal,
+            geometric_normal: normal,
+            shading_offset: Vec3::ZERO,
+            dpdu,
+            dpdv,
+            t: 1.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: true,
+            material: &material,
+            time: 0.0,
+        };
+        let ray = Ray::new(normal, -normal);
+        // Cosine-weighted directions average cos(theta) 2/3 and cos^2(theta) 1/2, where uniform ones average 1/2
+        // and 1/3; every sample of the furnace, a uniform white environment, then sees the albedo.
+        let n = 100000;
+        let (mut cos, mut cos2) = (0.0, 0.0);
+        for _ in 0..n {
+            let (attenuation, scattered) = material.scatter(&ray, &h, &mut rng).unwrap();
+            assert!((attenuation - Color::new(0.5, 0.5, 0.5)).length() < 1e-12);
+            let c = scattered.dir.unit().dot(normal);
+            cos += c / n as f64;
+            cos2 += c * c / n as f64;
+        }
+        assert!((cos - 2.0 / 3.0).abs() < 0.005, "{}", cos);
+        assert!((cos2 - 0.5).abs() < 0.005, "{}", cos2);
+        // What `eval` gives light sampling integrates to the albedo over the hemisphere as well.
+        let furnace = (0..n).fold(0.0, |sum, _| {
+            let d = Vec3::random_in_hemisphere(&normal, &mut rng).unit();
+            sum + material.eval(&ray, &h, &d).unwrap().r() * 2.0 * std::f64::consts::PI / n as f64
+        });
+        assert!((furnace - 0.5).abs() < 0.01, "{}", furnace);
+    }
+
+    #[test]
+    fn test_velvet() {
+        use crate::textures::SolidColor;
+        use rand::SeedableRng;
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        let white = Velvet::new(SolidColor::new(1.0, 1.0, 1.0), Color::ONE, 0.5);
+        let sheen = Velvet::new(SolidColor::new(0.0, 0.0, 0.0), Color::ONE, 0.5);
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let (dpdu, dpdv) = hittable::tangents(&normal);
+        // The mean weight of the reflections of light leaving at `cos_o` to the normal.
+        let mut albedo = |material: &Velvet<SolidColor>, cos_o: f64| {
+            let h = hittable::Hit {
+                p: Point3::ZERO,
+                normal,
+                geometric_normal: normal,
+                shading_offset: Vec3::ZERO,
+                dpdu,
+                dpdv,
+                t: 1.0,
+                u: 0.0,
+                v: 0.0,
+                front_face: true,
+                material,
+                time: 0.0,
+            };
+            let ray = Ray::new(Point3::ZERO, -Vec3::new((1.0 - cos_o * cos_o).sqrt(), 0.0, cos_o));
+            let n = 100000;
+            let sum = (0..n).fold(Color::ZERO, |sum, _| match material.scatter(&ray, &h, &mut rng) {
+                Some((attenuation, _)) => sum + attenuation,
+                None => sum,
+            });
+            (sum / n as f64).r()
+        };
+        // White cloth reflects all the light, whatever the sheen takes of it.
+        for cos_o in [1.0, 0.5, 0.1] {
+            let reflected = albedo(&white, cos_o);
+            assert!((reflected - 1.0).abs() < 0.02, "{} {}", cos_o, reflected);
+        }
+        // The sheen alone is faint head on and strongest towards grazing.
+        let (head_on, grazing) = (albedo(&sheen, 1.0), albedo(&sheen, 0.1));
+        assert!(head_on > 0.0 && head_on < grazing && grazing < 1.0, "{} {}", head_on, grazing);
+    }
+
+    #[test]
+    fn test_image_light() {
+        use crate::image_texture::{Encoding, Image};
+        // A window photo whose sky is mid gray in sRGB shines with a fifth of the intensity.
+        let photo = Image::new(image::RgbImage::from_pixel(2, 2, image::Rgb([128, 128, 128])));
+        let window = DiffuseLight::new(photo.with_encoding(Encoding::Srgb)).with_intensity(10.0);
+        let light = window.emit(0.5, 0.5, Point3::ZERO);
+        assert!((light.g() - 2.158).abs() < 1e-3, "{:?}", light);
+        let white = DiffuseLight::new(crate::textures::SolidColor::new(1.0, 1.0, 1.0)).with_intensity(4.0);
+        assert_eq!(Some(MaterialSpec::Light { color: Color::new(4.0, 4.0, 4.0), temperature: None }), white.export());
     }
 }
@@ -0,0 +1,383 @@
+// Matrices and quaternions for general transforms and camera animation.
+use crate::vec::{Point3, Vec3};
+use std::ops;
+
+// 3x3 matrix, row-major; linear maps of vectors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat3 {
+    pub m: [[f64; 3]; 3],
+}
+
+impl Mat3 {
+    pub const IDENTITY: Mat3 = Mat3 { m: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]] };
+
+    pub fn new(m: [[f64; 3]; 3]) -> Mat3 {
+        Mat3 { m }
+    }
+
+    pub fn scale(s: Vec3) -> Mat3 {
+        Mat3 { m: [[s.x(), 0.0, 0.0], [0.0, s.y(), 0.0], [0.0, 0.0, s.z()]] }
+    }
+
+    // Counter-clockwise rotation by `angle` degrees, looking down a (unit) `axis`.
+    // Rotations about the coordinate axes come out exact: no rounding in the entries that are 0 or 1.
+    pub fn rotation(axis: Vec3, angle: f64) -> Mat3 {
+        let theta = angle * std::f64::consts::PI / 180.0;
+        let (s, c) = (theta.sin(), theta.cos());
+        let (x, y, z) = (axis.x(), axis.y(), axis.z());
+        let t = 1.0 - c;
+        Mat3 {
+            m: [
+                [x * x + (1.0 - x * x) * c, x * y * t - z * s, x * z * t + y * s],
+                [x * y * t + z * s, y * y + (1.0 - y * y) * c, y * z * t - x * s],
+                [x * z * t - y * s, y * z * t + x * s, z * z + (1.0 - z * z) * c],
+            ],
+        }
+    }
+
+    pub fn transpose(&self) -> Mat3 {
+        let m = &self.m;
+        Mat3 { m: [[m[0][0], m[1][0], m[2][0]], [m[0][1], m[1][1], m[2][1]], [m[0][2], m[1][2], m[2][2]]] }
+    }
+
+    pub fn determinant(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1]) - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    // None if the matrix is singular.
+    pub fn inverse(&self) -> Option<Mat3> {
+        let det = self.determinant();
+        if det == 0.0 || !det.is_finite() {
+            return None;
+        }
+        let m = &self.m;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+        // Adjugate: transposed cofactors.
+        let adjugate = [
+            [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+            [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+            [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)],
+        ];
+        let mut result = Mat3 { m: adjugate };
+        result.m.iter_mut().flatten().for_each(|v| *v /= det);
+        Some(result)
+    }
+
+    // Matrix that transforms normals the way `self` transforms vectors, keeping them perpendicular to surfaces.
+    pub fn normal_matrix(&self) -> Option<Mat3> {
+        self.inverse().map(|inverse| inverse.transpose())
+    }
+}
+
+impl ops::Mul<Vec3> for Mat3 {
+    type Output = Vec3;
+    fn mul(self, v: Vec3) -> Vec3 {
+        let row = |r: &[f64; 3]| r[0] * v.e[0] + r[1] * v.e[1] + r[2] * v.e[2];
+        Vec3::new(row(&self.m[0]), row(&self.m[1]), row(&self.m[2]))
+    }
+}
+
+impl ops::Mul<Mat3> for Mat3 {
+    type Output = Mat3;
+    fn mul(self, other: Mat3) -> Mat3 {
+        let mut m = [[0.0; 3]; 3];
+        for r in 0..3 {
+            for c in 0..3 {
+                m[r][c] = (0..3).map(|k| self.m[r][k] * other.m[k][c]).sum();
+            }
+        }
+        Mat3 { m }
+    }
+}
+
+// 4x4 matrix, row-major, acting on column vectors; affine transforms when the last row is (0, 0, 0, 1).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub const IDENTITY: Mat4 =
+        Mat4 { m: [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]] };
+
+    pub fn new(m: [[f64; 4]; 4]) -> Mat4 {
+        Mat4 { m }
+    }
+
+    // Applies `linear` first, then moves by `translation`.
+    pub fn affine(linear: Mat3, translation: Vec3) -> Mat4 {
+        let mut m = Mat4::IDENTITY.m;
+        for r in 0..3 {
+            m[r][..3].copy_from_slice(&linear.m[r]);
+            m[r][3] = translation.e[r];
+        }
+        Mat4 { m }
+    }
+
+    pub fn translation(offset: Vec3) -> Mat4 {
+        Mat4::affine(Mat3::IDENTITY, offset)
+    }
+
+    // Upper left 3x3 block: how vectors are transformed.
+    pub fn linear(&self) -> Mat3 {
+        let mut m = [[0.0; 3]; 3];
+        for r in 0..3 {
+            m[r].copy_from_slice(&self.m[r][..3]);
+        }
+        Mat3 { m }
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for r in 0..4 {
+            for c in 0..4 {
+                m[r][c] = self.m[c][r];
+            }
+        }
+        Mat4 { m }
+    }
+
+    // Gauss-Jordan elimination with partial pivoting; None if the matrix is singular.
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inverse = Mat4::IDENTITY.m;
+        for c in 0..4 {
+            let pivot = (c..4).max_by(|&i, &j| a[i][c].abs().partial_cmp(&a[j][c].abs()).unwrap())?;
+            if a[pivot][c] == 0.0 || !a[pivot][c].is_finite() {
+                return None;
+            }
+            a.swap(c, pivot);
+            inverse.swap(c, pivot);
+            let scale = 1.0 / a[c][c];
+            for k in 0..4 {
+                a[c][k] *= scale;
+                inverse[c][k] *= scale;
+            }
+            for r in 0..4 {
+                if r != c {
+                    let f = a[r][c];
+                    for k in 0..4 {
+                        a[r][k] -= f * a[c][k];
+                        inverse[r][k] -= f * inverse[c][k];
+                    }
+                }
+            }
+        }
+        Some(Mat4 { m: inverse })
+    }
+
+    pub fn transform_point(&self, p: Point3) -> Point3 {
+        let row = |r: &[f64; 4]| r[0] * p.e[0] + r[1] * p.e[1] + r[2] * p.e[2] + r[3];
+        let w = row(&self.m[3]);
+        let p = Point3::new(row(&self.m[0]), row(&self.m[1]), row(&self.m[2]));
+        if w == 1.0 {
+            p
+        } else {
+            p / w
+        }
+    }
+
+    // Directions ignore the translation.
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        self.linear() * v
+    }
+
+    // Not normalized; None if the matrix is singular.
+    pub fn transform_normal(&self, n: Vec3) -> Option<Vec3> {
+        self.linear().normal_matrix().map(|m| m * n)
+    }
+}
+
+impl ops::Mul<Mat4> for Mat4 {
+    type Output = Mat4;
+    fn mul(self, other: Mat4) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for r in 0..4 {
+            for c in 0..4 {
+                m[r][c] = (0..4).map(|k| self.m[r][k] * other.m[k][c]).sum();
+            }
+        }
+        Mat4 { m }
+    }
+}
+
+// Rotation quaternion w + xi + yj + zk; should be normalized.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quat {
+    pub w: f64,
+    pub v: Vec3,
+}
+
+impl Quat {
+    pub const IDENTITY: Quat = Quat { w: 1.0, v: Vec3::ZERO };
+
+    // Same rotation as `Mat3::rotation`.
+    pub fn from_axis_angle(axis: Vec3, angle: f64) -> Quat {
+        let half = angle * std::f64::consts::PI / 360.0;
+        Quat { w: half.cos(), v: half.sin() * axis.unit() }
+    }
+
+    pub fn dot(&self, other: &Quat) -> f64 {
+        self.w * other.w + self.v.dot(other.v)
+    }
+
+    pub fn normalize(&self) -> Quat {
+        let length = self.dot(self).sqrt();
+        Quat { w: self.w / length, v: self.v / length }
+    }
+
+    // The inverse rotation, for normalized quaternions.
+    pub fn conjugate(&self) -> Quat {
+        Quat { w: self.w, v: -self.v }
+    }
+
+    pub fn rotate(&self, v: Vec3) -> Vec3 {
+        // v + 2w(q x v) + 2 q x (q x v), with q the vector part.
+        let t = 2.0 * self.v.cross(v);
+        v + self.w * t + self.v.cross(t)
+    }
+
+    pub fn to_mat3(&self) -> Mat3 {
+        let (w, x, y, z) = (self.w, self.v.x(), self.v.y(), self.v.z());
+        Mat3 {
+            m: [
+                [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - w * z), 2.0 * (x * z + w * y)],
+                [2.0 * (x * y + w * z), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - w * x)],
+                [2.0 * (x * z - w * y), 2.0 * (y * z + w * x), 1.0 - 2.0 * (x * x + y * y)],
+            ],
+        }
+    }
+
+    // Spherical interpolation from `self` (t = 0) to `other` (t = 1) along the shorter arc.
+    pub fn slerp(&self, other: &Quat, t: f64) -> Quat {
+        let mut cos_theta = self.dot(other);
+        let mut other = *other;
+        if cos_theta < 0.0 {
+            other = Quat { w: -other.w, v: -other.v };
+            cos_theta = -cos_theta;
+        }
+        // Nearly the same rotation: linear interpolation is accurate and avoids dividing by sin(theta) ~ 0.
+        let (a, b) = if cos_theta > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            (((1.0 - t) * theta).sin() / theta.sin(), (t * theta).sin() / theta.sin())
+        };
+        Quat { w: a * self.w + b * other.w, v: a * self.v + b * other.v }.normalize()
+    }
+}
+
+// Composition: `a * b` rotates by `b` first, then by `a`.
+impl ops::Mul<Quat> for Quat {
+    type Output = Quat;
+    fn mul(self, other: Quat) -> Quat {
+        Quat {
+            w: self.w * other.w - self.v.dot(other.v),
+            v: self.w * other.v + other.w * self.v + self.v.cross(other.v),
+        }
+    }
+}
+
+// Orthonormal basis (tangent frame) with `w` along a given direction, e.g. a surface normal; for sampling
+// directions around it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn new(w: Vec3) -> Onb {
+        let w = w.unit();
+        let a = if w.x().abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let v = w.cross(a).unit();
+        let u = w.cross(v);
+        Onb { u, v, w }
+    }
+
+    // World direction of the local one (x, y, z).
+    pub fn local(&self, x: f64, y: f64, z: f64) -> Vec3 {
+        x * self.u + y * self.v + z * self.w
+    }
+
+    pub fn to_world(&self, local: Vec3) -> Vec3 {
+        self.local(local.x(), local.y(), local.z())
+    }
+
+    pub fn to_local(&self, world: Vec3) -> Vec3 {
+        Vec3::new(world.dot(self.u), world.dot(self.v), world.dot(self.w))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_rotations_agree() {
+        let axis = Vec3::new(1.0, 2.0, -0.5).unit();
+        let (m, q) = (Mat3::rotation(axis, 70.0), Quat::from_axis_angle(axis, 70.0));
+        let v = Vec3::new(0.3, -1.0, 2.0);
+        assert_near(m * v, q.rotate(v));
+        assert_near(q.to_mat3() * v, q.rotate(v));
+        assert_near(
+            Vec3::new(0.0, 1.0, 0.0),
+            Mat3::rotation(Vec3::new(0.0, 0.0, 1.0), 90.0) * Vec3::new(1.0, 0.0, 0.0),
+        );
+
+        let r = Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), -30.0);
+        assert_near((r * q).rotate(v), r.rotate(q.rotate(v)));
+        assert_near((m * r.to_mat3()) * v, m * (r.to_mat3() * v));
+        assert_near(q.conjugate().rotate(q.rotate(v)), v);
+    }
+
+    #[test]
+    fn test_inverse() {
+        let linear = Mat3::rotation(Vec3::new(0.0, 1.0, 0.0), 30.0) * Mat3::scale(Vec3::new(2.0, 1.0, 0.5));
+        let m = Mat4::affine(linear, Vec3::new(1.0, -2.0, 3.0));
+        let p = Point3::new(0.5, 4.0, -1.0);
+        assert_near(p, m.inverse().unwrap().transform_point(m.transform_point(p)));
+        assert_near(p, linear.inverse().unwrap() * (linear * p));
+        assert_near(p, Mat4::translation(Vec3::new(1.0, 0.0, 0.0)).transform_vector(p));
+        assert_eq!(None, Mat3::scale(Vec3::new(1.0, 0.0, 1.0)).inverse());
+        assert_eq!(None, Mat4::affine(Mat3::scale(Vec3::ZERO), Vec3::ZERO).inverse());
+    }
+
+    #[test]
+    fn test_normals_stay_perpendicular() {
+        let m = Mat4::affine(Mat3::scale(Vec3::new(4.0, 1.0, 1.0)), Vec3::new(0.0, 3.0, 0.0));
+        // A plane x + y = 0, with a tangent and a normal.
+        let (tangent, normal) = (Vec3::new(1.0, -1.0, 0.0), Vec3::new(1.0, 1.0, 0.0));
+        let (tangent, normal) = (m.transform_vector(tangent), m.transform_normal(normal).unwrap());
+        assert!(tangent.dot(normal).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_slerp() {
+        let a = Quat::IDENTITY;
+        let b = Quat::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), 90.0);
+        let half = a.slerp(&b, 0.5);
+        assert_near(Vec3::new(1.0, 1.0, 0.0).unit(), half.rotate(Vec3::new(1.0, 0.0, 0.0)));
+        assert_near(b.rotate(Vec3::new(1.0, 0.0, 0.0)), a.slerp(&b, 1.0).rotate(Vec3::new(1.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_onb() {
+        for w in [Vec3::new(0.0, 0.0, 1.0), Vec3::new(-3.0, 0.1, 0.2), Vec3::new(0.3, -0.5, 0.8)] {
+            let onb = Onb::new(w);
+            assert_near(w.unit(), onb.w);
+            assert!(onb.u.dot(onb.w).abs() < 1e-12 && onb.v.dot(onb.w).abs() < 1e-12);
+            assert!((onb.u.length() - 1.0).abs() < 1e-12 && onb.u.dot(onb.v).abs() < 1e-12);
+            let d = Vec3::new(0.2, -1.5, 0.7);
+            assert_near(d, onb.to_world(onb.to_local(d)));
+            assert!((onb.to_local(w).z() - w.length()).abs() < 1e-12);
+        }
+    }
+}
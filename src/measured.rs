@@ -0,0 +1,293 @@
+// Measured BRDFs in the binary format of the MERL database (Matusik et al., "A Data-Driven Reflectance Model"):
+// reflectance of isotropic materials tabulated over the half and difference angles of Rusinkiewicz's
+// parameterization, for comparing analytic models against measurements.
+use crate::hittable;
+use crate::materials::Material;
+use crate::math::Onb;
+use crate::raytrace::RayKind;
+use crate::validate::SceneStats;
+use crate::vec::{Color, Ray, Vec3};
+use crate::worlds::MaterialSpec;
+use rand::Rng;
+use std::convert::TryInto;
+use std::f64::consts::{FRAC_PI_2, PI};
+use std::sync::Arc;
+
+const THETA_H: usize = 90;
+const THETA_D: usize = 90;
+// Only half of the difference azimuths are stored, the rest follow by reciprocity.
+const PHI_D: usize = 180;
+const SAMPLES: usize = THETA_H * THETA_D * PHI_D;
+// The values of the red, green and blue tables are scaled by these.
+const SCALE: [f64; 3] = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
+
+// A tabulated BRDF, loaded from a MERL .binary file.
+#[derive(Clone, PartialEq)]
+pub struct MerlBrdf {
+    name: String,
+    // Red, green and blue, each THETA_H x THETA_D x PHI_D with the difference azimuth changing fastest.
+    values: Vec<f32>,
+    // Cumulative distribution of the half angle bins that `Measured` samples reflections from, see `sample_half`.
+    half_cdf: Vec<f64>,
+}
+
+impl std::fmt::Debug for MerlBrdf {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "MerlBrdf({})", self.name)
+    }
+}
+
+impl crate::assets::Asset for MerlBrdf {
+    fn bytes(&self) -> usize {
+        self.values.len() * std::mem::size_of::<f32>() + self.half_cdf.len() * std::mem::size_of::<f64>()
+    }
+}
+
+impl MerlBrdf {
+    pub fn load(path: &str) -> Result<MerlBrdf, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+        MerlBrdf::from_bytes(path, &bytes).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    // The path it was loaded from, or the name it was given.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // The file's contents: the three dimensions of the tables as 32-bit integers, then the red, green and blue
+    // tables as doubles, all little-endian. Negative values mark missing measurements and count as no reflection.
+    pub fn from_bytes(name: &str, bytes: &[u8]) -> Result<MerlBrdf, String> {
+        let int = |k: usize| i32::from_le_bytes(bytes[4 * k..4 * k + 4].try_into().unwrap());
+        if bytes.len() < 12 {
+            return Err("too short for a MERL BRDF".to_string());
+        }
+        let dims = (int(0), int(1), int(2));
+        if dims != (THETA_H as i32, THETA_D as i32, PHI_D as i32) {
+            return Err(format!(
+                "tables of {}x{}x{}, expected {}x{}x{}",
+                dims.0, dims.1, dims.2, THETA_H, THETA_D, PHI_D
+            ));
+        }
+        if bytes.len() != 12 + 3 * SAMPLES * 8 {
+            return Err(format!("{} bytes of tables, expected {}", bytes.len() - 12, 3 * SAMPLES * 8));
+        }
+        let values = bytes[12..]
+            .chunks_exact(8)
+            .enumerate()
+            .map(|(k, b)| (f64::from_le_bytes(b.try_into().unwrap()).max(0.0) * SCALE[k / SAMPLES]) as f32)
+            .collect();
+        Ok(MerlBrdf::new(name, values))
+    }
+
+    fn new(name: &str, values: Vec<f32>) -> MerlBrdf {
+        let mut brdf = MerlBrdf { name: name.to_string(), values, half_cdf: Vec::new() };
+        // Half angle bins weighted by how much they reflect at difference angles up to 60 degrees, where most of
+        // the light is, and by their solid angle.
+        let mut total = 0.0;
+        let half_cdf = (0..THETA_H)
+            .map(|i| {
+                let reflected: f64 = (0..THETA_D * 2 / 3)
+                    .flat_map(|j| (0..PHI_D).map(move |k| (j, k)))
+                    .map(|(j, k)| luminance(brdf.lookup(i, j, k)))
+                    .sum();
+                let (lo, hi) = (half_angle(i as f64), half_angle(i as f64 + 1.0));
+                total += reflected * (lo.cos() - hi.cos());
+                total
+            })
+            .collect::<Vec<f64>>();
+        if total > 0.0 {
+            brdf.half_cdf = half_cdf.iter().map(|c| c / total).collect();
+        } else {
+            // Reflects nothing: any distribution will do.
+            brdf.half_cdf = (1..=THETA_H).map(|i| i as f64 / THETA_H as f64).collect();
+        }
+        brdf
+    }
+
+    fn lookup(&self, i: usize, j: usize, k: usize) -> Color {
+        let index = (i * THETA_D + j) * PHI_D + k;
+        let channel = |c: usize| self.values[c * SAMPLES + index] as f64;
+        Color::new(channel(0), channel(1), channel(2))
+    }
+
+    // The BRDF for light arriving from `wi` and leaving towards `wo`, both unit vectors in the tangent frame with the
+    // normal along z.
+    pub fn value(&self, wi: Vec3, wo: Vec3) -> Color {
+        if wi.z() <= 0.0 || wo.z() <= 0.0 {
+            return Color::ZERO;
+        }
+        let half = (wi + wo).unit();
+        let theta_h = half.z().clamp(-1.0, 1.0).acos();
+        let phi_h = half.y().atan2(half.x());
+        // `wi` with the half vector rotated onto the normal.
+        let (sin, cos) = (-phi_h).sin_cos();
+        let d = Vec3::new(wi.x() * cos - wi.y() * sin, wi.x() * sin + wi.y() * cos, wi.z());
+        let (sin, cos) = (-theta_h).sin_cos();
+        let d = Vec3::new(d.x() * cos + d.z() * sin, d.y(), d.z() * cos - d.x() * sin);
+        let theta_d = d.z().clamp(-1.0, 1.0).acos();
+        let phi_d = d.y().atan2(d.x());
+        let phi_d = if phi_d < 0.0 { phi_d + PI } else { phi_d };
+
+        // The half angle bins get finer towards the normal, where the highlights are.
+        let i = half_angle_bin(theta_h).min(THETA_H - 1);
+        let j = ((theta_d / FRAC_PI_2 * THETA_D as f64) as usize).min(THETA_D - 1);
+        let k = ((phi_d / PI * PHI_D as f64) as usize).min(PHI_D - 1);
+        self.lookup(i, j, k)
+    }
+
+    // A half vector in the tangent frame, drawn from the half angle bins by `half_cdf` and uniformly within them.
+    fn sample_half(&self, rng: &mut dyn rand::RngCore) -> Vec3 {
+        let x = rng.gen_range(0.0..1.0);
+        let i = self.half_cdf.partition_point(|&c| c <= x).min(THETA_H - 1);
+        let theta_h = half_angle(i as f64 + rng.gen_range(0.0..1.0));
+        let phi_h = rng.gen_range(0.0..2.0 * PI);
+        Vec3::new(theta_h.sin() * phi_h.cos(), theta_h.sin() * phi_h.sin(), theta_h.cos())
+    }
+
+    // Density of `sample_half` per solid angle.
+    fn half_pdf(&self, half: Vec3) -> f64 {
+        let theta_h = half.z().clamp(-1.0, 1.0).acos();
+        let i = half_angle_bin(theta_h).min(THETA_H - 1);
+        let p = self.half_cdf[i] - if i > 0 { self.half_cdf[i - 1] } else { 0.0 };
+        // Uniform in the fractional bin index rather than in the angle, and over the azimuths.
+        let u = (theta_h / FRAC_PI_2).sqrt() * THETA_H as f64;
+        p / (half_angle_derivative(u) * 2.0 * PI * theta_h.sin())
+    }
+}
+
+// Half angle at the start of fractional bin `u`; the bins are quadratic in the angle.
+fn half_angle(u: f64) -> f64 {
+    (u / THETA_H as f64).powi(2) * FRAC_PI_2
+}
+
+// Derivative of `half_angle` by `u`.
+fn half_angle_derivative(u: f64) -> f64 {
+    2.0 * u / (THETA_H * THETA_H) as f64 * FRAC_PI_2
+}
+
+fn half_angle_bin(theta_h: f64) -> usize {
+    ((theta_h.max(0.0) / FRAC_PI_2).sqrt() * THETA_H as f64) as usize
+}
+
+fn luminance(c: Color) -> f64 {
+    0.2126 * c.r() + 0.7152 * c.g() + 0.0722 * c.b()
+}
+
+// Material reflecting by a measured BRDF. Reflections are drawn half the time like a diffuse surface and half the
+// time around the mirror direction, from the half angles the BRDF reflects most at, which approximates its lobes
+// well enough for glossy measurements; the weights of the samples make up for the rest.
+#[derive(Clone)]
+pub struct Measured {
+    brdf: Arc<MerlBrdf>,
+}
+
+impl Measured {
+    pub fn new(brdf: Arc<MerlBrdf>) -> Measured {
+        Measured { brdf }
+    }
+
+    // Density of the directions `scatter` draws, in the tangent frame.
+    fn pdf(&self, wi: Vec3, wo: Vec3) -> f64 {
+        if wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let half = (wi + wo).unit();
+        0.5 * wi.z() / PI + 0.5 * self.brdf.half_pdf(half) / (4.0 * wo.dot(half).abs().max(1e-9))
+    }
+}
+
+impl Material for Measured {
+    fn scatter(&self, ray: &Ray, h: &hittable::Hit, rng: &mut dyn rand::RngCore) -> Option<(Color, Ray)> {
+        let frame = Onb::new(h.normal);
+        let wo = frame.to_local(-ray.dir.unit());
+        let wi = match rng.gen_bool(0.5) {
+            true => Vec3::random_cosine_direction(rng),
+            false => {
+                let half = self.brdf.sample_half(rng);
+                2.0 * wo.dot(half) * half - wo
+            }
+        };
+        let pdf = self.pdf(wi, wo);
+        if wi.z() <= 0.0 || !(pdf > 0.0 && pdf.is_finite()) {
+            return None;
+        }
+        let attenuation = self.brdf.value(wi, wo) * wi.z() / pdf;
+        Some((attenuation, h.spawn_ray(frame.to_world(wi))))
+    }
+
+    fn eval(&self, ray: &Ray, h: &hittable::Hit, direction: &Vec3) -> Option<Color> {
+        let frame = Onb::new(h.normal);
+        let (wi, wo) = (frame.to_local(*direction), frame.to_local(-ray.dir.unit()));
+        Some(self.brdf.value(wi, wo) * wi.z().max(0.0))
+    }
+
+    fn bounce_kind(&self) -> RayKind {
+        RayKind::Diffuse
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.materials += 1;
+        stats.texture_data(self.brdf.as_ref(), std::mem::size_of_val(self.brdf.values.as_slice()));
+    }
+
+    fn export(&self) -> Option<MaterialSpec> {
+        Some(MaterialSpec::Measured { brdf: Some(self.brdf.clone()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    // A MERL file of the same value everywhere in every table.
+    fn constant_file(value: f64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for dim in [THETA_H, THETA_D, PHI_D] {
+            bytes.extend((dim as i32).to_le_bytes());
+        }
+        for _ in 0..3 * SAMPLES {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_merl_brdf() {
+        // A constant BRDF is a diffuse surface reflecting pi times as much.
+        let brdf = Arc::new(MerlBrdf::from_bytes("constant", &constant_file(0.1)).unwrap());
+        let expected = Color::new(0.1 * SCALE[0], 0.1 * SCALE[1], 0.1 * SCALE[2]);
+        let (wi, wo) = (Vec3::new(0.3, -0.2, 0.9).unit(), Vec3::new(-0.5, 0.1, 0.4).unit());
+        assert!((brdf.value(wi, wo) - expected).length() < 1e-9);
+        assert_eq!(Color::ZERO, brdf.value(-wi, wo));
+
+        // The samples' weights average to the albedo, however they are drawn.
+        let material = Measured::new(brdf);
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        let ray = Ray::new(Vec3::new(0.2, 0.3, 1.0), Vec3::new(-0.2, -0.3, -1.0));
+        let h = hittable::Hit {
+            p: Vec3::ZERO,
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            geometric_normal: Vec3::new(0.0, 0.0, 1.0),
+            shading_offset: Vec3::ZERO,
+            dpdu: Vec3::new(1.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 1.0, 0.0),
+            t: 1.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: true,
+            material: &material,
+            time: 0.0,
+        };
+        let n = 100000;
+        let sum = (0..n).fold(Color::ZERO, |sum, _| match material.scatter(&ray, &h, &mut rng) {
+            Some((attenuation, _)) => sum + attenuation,
+            None => sum,
+        });
+        let albedo = PI * expected;
+        assert!((sum / n as f64 - albedo).length() < 0.02 * albedo.length(), "{:?} {:?}", sum / n as f64, albedo);
+
+        assert!(MerlBrdf::from_bytes("short", &constant_file(0.1)[..1000]).is_err());
+        assert!(MerlBrdf::load("/nonexistent/brdf.binary").is_err());
+    }
+}
@@ -0,0 +1,514 @@
+use crate::bhv::{Bounded, BvhConfig, SceneBuilder, AABB, BHV};
+use crate::hittable::{Hit, Hittable};
+use crate::interval::Interval;
+use crate::materials::Material;
+use crate::shapes::TriangleGeometry;
+use crate::transforms::{self, Axis};
+use crate::validate::SceneStats;
+use crate::vec::{Point3, Ray, Vec3};
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+// Vertices and faces of a mesh, shared by the mesh and its faces in the BVH.
+struct MeshGeometry {
+    vertices: Vec<Point3>,
+    // Unit normals of the vertices, interpolated over the faces for smooth shading; empty for flat faces.
+    normals: Vec<Vec3>,
+    faces: Vec<[u32; 3]>,
+}
+
+impl MeshGeometry {
+    fn corners(&self, face: u32) -> [Point3; 3] {
+        let f = &self.faces[face as usize];
+        [self.vertices[f[0] as usize], self.vertices[f[1] as usize], self.vertices[f[2] as usize]]
+    }
+
+    fn triangle(&self, face: u32) -> TriangleGeometry {
+        let [p0, p1, p2] = self.corners(face);
+        TriangleGeometry::new(p0, p1, p2)
+    }
+
+    // The hit on a face shaded with the normals of its corners, if the mesh has them.
+    fn shade<'a>(&self, face: u32, h: Hit<'a>) -> Hit<'a> {
+        if self.normals.is_empty() {
+            return h;
+        }
+        let f = &self.faces[face as usize];
+        // Turned to the side the ray came from, like the geometric normal.
+        let side = if h.front_face { 1.0 } else { -1.0 };
+        let normals = f.map(|v| side * self.normals[v as usize]);
+        let weights = [1.0 - h.u - h.v, h.u, h.v];
+        let normal = (0..3).fold(Vec3::ZERO, |n, k| n + weights[k] * normals[k]);
+        let shading_offset = terminator_offset(&h.p, &self.corners(face), &normals, &weights);
+        Hit { shading_offset, ..h }.with_shading_normal(&normal.unit())
+    }
+}
+
+// Smooth normals make a coarse mesh look round, but the light still stops at its flat faces, so the shadow
+// terminator of a sphere of few faces shows them as jagged steps. The fix of Hanika ("Hacking the shadow
+// terminator", Ray Tracing Gems II) starts the rays that leave the front of a face from a point on a curved
+// surface through its corners instead: `p` is moved out of the tangent planes at the corners it is below of,
+// and these moves are weighted like the normals. Only convex parts bulge, the flat and concave ones stay put.
+fn terminator_offset(p: &Point3, corners: &[Point3; 3], normals: &[Vec3; 3], weights: &[f64; 3]) -> Vec3 {
+    (0..3).fold(Vec3::ZERO, |offset, k| {
+        let below = (*p - corners[k]).dot(normals[k]).min(0.0);
+        offset - weights[k] * below * normals[k]
+    })
+}
+
+// Face of a mesh, by index, so a face takes much less memory than a `Triangle`.
+struct MeshFace<T: Material> {
+    geometry: Arc<MeshGeometry>,
+    material: Arc<T>,
+    face: u32,
+}
+
+impl<T: Material> Hittable for MeshFace<T> {
+    fn hit(&self, r: &Ray, ray_t: Interval, _: &mut dyn rand::RngCore) -> Option<Hit<'_>> {
+        let h = self.geometry.triangle(self.face).hit(r, ray_t, &*self.material)?;
+        Some(self.geometry.shade(self.face, h))
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, _: &mut dyn rand::RngCore) -> bool {
+        self.geometry.triangle(self.face).intersect(r, ray_t).is_some()
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        let triangle = self.geometry.triangle(self.face);
+        triangle.inspect(stats);
+        stats.object(std::mem::size_of_val(self), Some(triangle.bounding_box()));
+        self.material.inspect(stats);
+    }
+}
+
+impl<T: Material> Bounded for MeshFace<T> {
+    fn bounding_box(&self) -> AABB {
+        self.geometry.triangle(self.face).bounding_box()
+    }
+}
+
+// Triangle mesh sharing one material. Besides being hit, the mesh can be sampled uniformly by area,
+// so emissive meshes can be added to the world's lights.
+pub struct Mesh<'a> {
+    triangles: BHV<'a>,
+    geometry: Arc<MeshGeometry>,
+    // Cumulative area of the faces, for picking a face proportionally to its area.
+    cdf: Vec<f64>,
+}
+
+fn area(face: &[Point3; 3]) -> f64 {
+    0.5 * (face[1] - face[0]).cross(face[2] - face[0]).length()
+}
+
+impl<'a> Mesh<'a> {
+    pub fn new<T: Material + 'a>(
+        vertices: &[Point3],
+        indices: &[[usize; 3]],
+        material: T,
+        bvh: BvhConfig,
+        rng: &mut dyn rand::RngCore,
+    ) -> Mesh<'a> {
+        Mesh::with_normals(vertices, &[], indices, material, bvh, rng)
+    }
+
+    // Shaded smoothly with the normals of the vertices, which must point out of the front of the faces, where
+    // their corners go around counter-clockwise. Empty `normals` give flat faces, as `new` does.
+    pub fn with_normals<T: Material + 'a>(
+        vertices: &[Point3],
+        normals: &[Vec3],
+        indices: &[[usize; 3]],
+        material: T,
+        bvh: BvhConfig,
+        rng: &mut dyn rand::RngCore,
+    ) -> Mesh<'a> {
+        assert!(normals.is_empty() || normals.len() == vertices.len(), "one normal per vertex");
+        let faces = indices.iter().map(|f| [f[0] as u32, f[1] as u32, f[2] as u32]).collect();
+        let normals = normals.iter().map(|n| n.unit()).collect();
+        let geometry = Arc::new(MeshGeometry { vertices: vertices.to_vec(), normals, faces });
+        let material = Arc::new(material);
+        let mut scene = SceneBuilder::with_config(bvh);
+        for face in 0..indices.len() as u32 {
+            scene.add(MeshFace { geometry: geometry.clone(), material: material.clone(), face });
+        }
+        let mut total = 0.0;
+        let cdf = (0..indices.len() as u32)
+            .map(|face| {
+                total += area(&geometry.corners(face));
+                total
+            })
+            .collect();
+        Mesh { triangles: BHV::new(&mut scene, rng), geometry, cdf }
+    }
+
+    pub fn area(&self) -> f64 {
+        self.cdf.last().copied().unwrap_or(0.0)
+    }
+
+    // Corners of a face picked proportionally to its area, and a uniformly distributed point on it. None for a mesh
+    // without area, e.g. one of degenerate faces, which can't be sampled like the shapes of the `Hittable` defaults.
+    fn random_face_point(&self, rng: &mut dyn rand::RngCore) -> Option<([Point3; 3], Point3)> {
+        if self.area() <= 0.0 {
+            return None;
+        }
+        let x = rng.gen_range(0.0..self.area());
+        let face = self.cdf.partition_point(|&c| c <= x).min(self.cdf.len() - 1);
+        let face = self.geometry.corners(face as u32);
+        let s = rng.gen_range(0.0..1.0f64).sqrt();
+        let r = rng.gen_range(0.0..1.0);
+        Some((face, face[0] + s * (1.0 - r) * (face[1] - face[0]) + s * r * (face[2] - face[0])))
+    }
+}
+
+impl<'b> Hittable for Mesh<'b> {
+    fn hit<'a>(&'a self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> Option<Hit<'a>> {
+        self.triangles.hit(r, ray_t, rng)
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> bool {
+        self.triangles.hit_any(r, ray_t, rng)
+    }
+
+    // A direction can reach several faces; its density is the sum over all of them.
+    fn pdf_value(&self, o: &Point3, v: &Vec3, rng: &mut dyn rand::RngCore) -> f64 {
+        if self.area() <= 0.0 {
+            return 0.0;
+        }
+        let r = Ray::new(*o, *v);
+        let mut pdf = 0.0;
+        let mut ray_t = Interval::FORWARD;
+        while let Some(h) = self.triangles.hit(&r, ray_t, rng) {
+            let distance_squared = h.t * h.t * v.length_squared();
+            let cosine = (v.dot(h.normal) / v.length()).abs();
+            pdf += distance_squared / (cosine * self.area());
+            ray_t = ray_t.with_min(h.t * (1.0 + 1e-9) + f64::MIN_POSITIVE);
+        }
+        pdf
+    }
+
+    fn random_point(&self, o: &Point3, rng: &mut dyn rand::RngCore) -> Point3 {
+        self.random_face_point(rng).map_or(o + &Vec3::new(1.0, 0.0, 0.0), |(_, p)| p)
+    }
+
+    // The hit comes from a ray cast back at the point along the normal of its face, for the material and the
+    // texture coordinates.
+    fn sample_surface<'a>(&'a self, rng: &mut dyn rand::RngCore) -> Option<(Hit<'a>, f64)> {
+        let (face, p) = self.random_face_point(rng)?;
+        let normal = (face[1] - face[0]).cross(face[2] - face[0]).unit();
+        let h = self.triangles.hit(&Ray::new(p + normal, -normal), Interval::new(1.0 - 1e-6, 1.0 + 1e-6), rng)?;
+        let h = Hit {
+            p,
+            normal,
+            geometric_normal: normal,
+            shading_offset: Vec3::ZERO,
+            t: 0.0,
+            front_face: true,
+            time: 0.0,
+            ..h
+        };
+        Some((h, 1.0 / self.area()))
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        // The faces in the BVH only count themselves, the vertices and faces they share are counted here.
+        stats.geometry_memory += std::mem::size_of_val(self.geometry.vertices.as_slice())
+            + std::mem::size_of_val(self.geometry.normals.as_slice())
+            + std::mem::size_of_val(self.geometry.faces.as_slice())
+            + std::mem::size_of_val(self.cdf.as_slice());
+        self.triangles.inspect(stats);
+    }
+}
+
+impl<'b> Bounded for Mesh<'b> {
+    fn bounding_box(&self) -> AABB {
+        self.triangles.bounding_box()
+    }
+}
+
+// Vertices and triangles of a mesh before it is built, e.g. read from an OBJ file by `parse_obj`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshData {
+    pub vertices: Vec<Point3>,
+    pub faces: Vec<[usize; 3]>,
+}
+
+impl MeshData {
+    // One step of Loop subdivision: every triangle is split into four at the middles of its edges, and the
+    // vertices are moved to weighted averages of their neighbours, so a coarse cage converges to a smooth
+    // surface. Edges with a face on one side only are boundaries, which stay curves through their own vertices.
+    pub fn subdivide(&self) -> MeshData {
+        // The faces on the sides of every edge, by their third vertex, in order so the new vertices are too.
+        let mut edges: BTreeMap<(usize, usize), Vec<usize>> = BTreeMap::new();
+        for f in self.faces.iter() {
+            for k in 0..3 {
+                let (a, b) = (f[k], f[(k + 1) % 3]);
+                edges.entry((a.min(b), a.max(b))).or_default().push(f[(k + 2) % 3]);
+            }
+        }
+        let v = &self.vertices;
+        let mut neighbours = vec![Vec::new(); v.len()];
+        let mut boundary_neighbours = vec![Vec::new(); v.len()];
+        for (&(a, b), opposite) in edges.iter() {
+            neighbours[a].push(b);
+            neighbours[b].push(a);
+            if opposite.len() == 1 {
+                boundary_neighbours[a].push(b);
+                boundary_neighbours[b].push(a);
+            }
+        }
+        let mut vertices: Vec<Point3> = (0..v.len())
+            .map(|i| match (boundary_neighbours[i].as_slice(), neighbours[i].len()) {
+                ([b0, b1], _) => 0.75 * v[i] + 0.125 * (v[*b0] + v[*b1]),
+                // Unused vertices and corners of odd boundaries stay put.
+                (_, 0) | ([_], _) | ([_, _, _, ..], _) => v[i],
+                ([], n) => {
+                    // Warren's weights.
+                    let beta = if n == 3 { 3.0 / 16.0 } else { 3.0 / (8.0 * n as f64) };
+                    let sum = neighbours[i].iter().fold(Vec3::ZERO, |sum, &j| sum + v[j]);
+                    (1.0 - n as f64 * beta) * v[i] + beta * sum
+                }
+            })
+            .collect();
+        let mut middles = BTreeMap::new();
+        for (&(a, b), opposite) in edges.iter() {
+            let middle = match opposite.as_slice() {
+                [c, d] => 0.375 * (v[a] + v[b]) + 0.125 * (v[*c] + v[*d]),
+                _ => 0.5 * (v[a] + v[b]),
+            };
+            middles.insert((a, b), vertices.len());
+            vertices.push(middle);
+        }
+        let middle = |a: usize, b: usize| middles[&(a.min(b), a.max(b))];
+        let faces = self
+            .faces
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let (ab, bc, ca) = (middle(a, b), middle(b, c), middle(c, a));
+                [[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]
+            })
+            .collect();
+        MeshData { vertices, faces }
+    }
+
+    // Normals of the vertices for smooth shading: the sums of the normals of the faces around them, weighted by
+    // the faces' areas.
+    pub fn vertex_normals(&self) -> Vec<Vec3> {
+        let mut normals = vec![Vec3::ZERO; self.vertices.len()];
+        for f in self.faces.iter() {
+            let [p0, p1, p2] = f.map(|i| self.vertices[i]);
+            let normal = (p1 - p0).cross(p2 - p0);
+            for &i in f.iter() {
+                normals[i] = normals[i] + normal;
+            }
+        }
+        normals.iter().map(|n| if n.near_zero() { Vec3::new(0.0, 1.0, 0.0) } else { n.unit() }).collect()
+    }
+
+    // The mesh mirrored along `axis`, e.g. to turn the left-handed coordinates of a file into right-handed ones,
+    // with the corners of the faces reversed so they still go around counter-clockwise seen from the front.
+    pub fn mirror(&self, axis: Axis) -> MeshData {
+        let a = transforms::index(axis);
+        let vertices = self
+            .vertices
+            .iter()
+            .map(|p| {
+                let mut p = *p;
+                p.e[a] = -p.e[a];
+                p
+            })
+            .collect();
+        let faces = self.faces.iter().map(|&[a, b, c]| [a, c, b]).collect();
+        MeshData { vertices, faces }
+    }
+
+    // The middle of the bounds of the vertices.
+    pub fn center(&self) -> Point3 {
+        let bounds = self.vertices.iter().fold(AABB::EMPTY, |b, p| b.surround(&AABB::new(*p, *p)));
+        if bounds.is_empty() {
+            Point3::ZERO
+        } else {
+            0.5 * (bounds.min() + bounds.max())
+        }
+    }
+
+    // The mesh after `levels` steps of `subdivide`.
+    pub fn subdivided(&self, levels: usize) -> MeshData {
+        (0..levels).fold(self.clone(), |data, _| data.subdivide())
+    }
+
+    // The mesh to render, shaded with `vertex_normals` if `smooth`, with flat faces otherwise.
+    pub fn build<'a, T: Material + 'a>(
+        &self,
+        smooth: bool,
+        material: T,
+        bvh: BvhConfig,
+        rng: &mut dyn rand::RngCore,
+    ) -> Mesh<'a> {
+        let normals = if smooth { self.vertex_normals() } else { Vec::new() };
+        Mesh::with_normals(&self.vertices, &normals, &self.faces, material, bvh, rng)
+    }
+}
+
+// Reads the vertices (`v x y z`) and faces (`f a b c ...`) of a Wavefront OBJ file; faces of more than three
+// corners are split into triangles around the first. Corners are vertex indices from 1, or from -1 counting back
+// from the last vertex, and may carry texture coordinate and normal indices after slashes, which are ignored like
+// all other lines.
+pub fn parse_obj(s: &str) -> Result<MeshData, String> {
+    let mut data = MeshData { vertices: Vec::new(), faces: Vec::new() };
+    for (n, line) in s.lines().enumerate() {
+        let error = |e: String| format!("line {}: {}", n + 1, e);
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => {
+                let coordinates = words
+                    .take(3)
+                    .map(|w| w.parse::<f64>().map_err(|e| error(format!("'{}': {}", w, e))))
+                    .collect::<Result<Vec<f64>, String>>()?;
+                match coordinates[..] {
+                    [x, y, z] => data.vertices.push(Point3::new(x, y, z)),
+                    _ => return Err(error("a vertex needs x, y and z".to_string())),
+                }
+            }
+            Some("f") => {
+                let count = data.vertices.len() as i64;
+                let corners = words
+                    .map(|w| match w.split('/').next().unwrap().parse::<i64>() {
+                        Ok(i) if i > 0 && i <= count => Ok(i as usize - 1),
+                        Ok(i) if i < 0 && -i <= count => Ok((count + i) as usize),
+                        _ => Err(error(format!("'{}' isn't one of the {} vertices so far", w, count))),
+                    })
+                    .collect::<Result<Vec<usize>, String>>()?;
+                if corners.len() < 3 {
+                    return Err(error("a face needs three corners".to_string()));
+                }
+                data.faces.extend((1..corners.len() - 1).map(|k| [corners[0], corners[k], corners[k + 1]]));
+            }
+            _ => {}
+        }
+    }
+    Ok(data)
+}
+
+pub fn load_obj(path: &str) -> Result<MeshData, String> {
+    parse_obj(&std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?)
+        .map_err(|e| format!("{}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::DiffuseLight;
+    use crate::textures::SolidColor;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_mesh_light_sampling() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(3);
+        let vertices = [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(2.0, 0.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+            Point3::new(0.0, 0.0, 3.0),
+        ];
+        let tetrahedron = [[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]];
+        let light = DiffuseLight::new(SolidColor::new(1.0, 1.0, 1.0));
+        let mesh = Mesh::new(&vertices, &tetrahedron, light, BvhConfig::default(), &mut rng);
+        let o = Point3::new(1.0, 1.5, 1.0);
+        for _ in 0..100 {
+            let p = mesh.random_point(&o, &mut rng);
+            assert!(p.x() >= 0.0 && p.y() >= 0.0 && p.z() >= 0.0);
+            assert!(mesh.pdf_value(&o, &(p - o), &mut rng) > 0.0);
+        }
+        // The density integrates to one over all directions.
+        let n = 200000;
+        let mut integral = 0.0;
+        for _ in 0..n {
+            integral += mesh.pdf_value(&o, &Vec3::random_unit_vector(&mut rng), &mut rng);
+        }
+        integral *= 4.0 * std::f64::consts::PI / n as f64;
+        assert!((integral - 1.0).abs() < 0.03, "{}", integral);
+    }
+
+    #[test]
+    fn test_mesh_without_area() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(3);
+        let light = DiffuseLight::new(SolidColor::new(1.0, 1.0, 1.0));
+        // Collinear corners, and no faces at all, can't be sampled.
+        let line = [Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(2.0, 0.0, 0.0)];
+        for faces in [&[[0, 1, 2]][..], &[]] {
+            let mesh = Mesh::new(&line, faces, light.clone(), BvhConfig::default(), &mut rng);
+            let o = Point3::new(1.0, 1.0, 0.0);
+            assert_eq!(0.0, mesh.area());
+            assert_eq!(o + Vec3::new(1.0, 0.0, 0.0), mesh.random_point(&o, &mut rng));
+            assert_eq!(0.0, mesh.pdf_value(&o, &Vec3::new(0.0, -1.0, 0.0), &mut rng));
+            assert!(mesh.sample_surface(&mut rng).is_none());
+        }
+    }
+
+    #[test]
+    fn test_smooth_shading() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(3);
+        // An octahedron with the normals of the sphere through its corners.
+        let vertices = [
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+            Point3::new(0.0, 0.0, 1.0),
+            Point3::new(-1.0, 0.0, 0.0),
+            Point3::new(0.0, -1.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+        ];
+        let faces = [[0, 1, 2], [1, 3, 2], [3, 4, 2], [4, 0, 2], [1, 0, 5], [3, 1, 5], [4, 3, 5], [0, 4, 5]];
+        let material = crate::materials::Lambertian::new(SolidColor::new(0.5, 0.5, 0.5));
+        let smooth = Mesh::with_normals(&vertices, &vertices, &faces, material, BvhConfig::default(), &mut rng);
+        let flat = Mesh::new(&vertices, &faces, material, BvhConfig::default(), &mut rng);
+
+        let r = Ray::new(Point3::new(2.0, 2.0, 2.0), Vec3::new(-1.0, -1.0, -1.0));
+        let h = smooth.hit(&r, Interval::FORWARD, &mut rng).unwrap();
+        let diagonal = Vec3::new(1.0, 1.0, 1.0).unit();
+        assert!((h.geometric_normal - diagonal).length() < 1e-9);
+        assert!((h.normal - diagonal).length() < 1e-9);
+        // The middle of a face moves out towards the sphere, but stays inside it.
+        let lifted = h.spawn_ray(diagonal).orig;
+        assert!(h.shading_offset.dot(diagonal) > 0.1, "{}", h.shading_offset);
+        assert!(lifted.length() > h.p.length() && lifted.length() < 1.0);
+        // Rays into the face start at the face.
+        assert!((h.spawn_ray(-diagonal).orig - h.p).length() < 1e-6);
+
+        // Off the middle, the normal leans towards the nearest corner.
+        let r = Ray::new(Point3::new(2.0, 0.1, 0.1), Vec3::new(-1.0, 0.0, 0.0));
+        let h = smooth.hit(&r, Interval::FORWARD, &mut rng).unwrap();
+        assert!(h.normal.x() > h.geometric_normal.x());
+        assert_eq!(Vec3::ZERO, flat.hit(&r, Interval::FORWARD, &mut rng).unwrap().shading_offset);
+    }
+
+    #[test]
+    fn test_subdivide() {
+        let obj =
+            "# tetrahedron\nv 1 1 1\nv 1 -1 -1\nv -1 1 -1\nv -1 -1 1\nf 1 2 3\nf 1/1 3/2 4/3\nf 1 4 2\nf -3 -1 -2\n";
+        let data = parse_obj(obj).unwrap();
+        assert_eq!(vec![[0, 1, 2], [0, 2, 3], [0, 3, 1], [1, 3, 2]], data.faces);
+        assert_eq!(
+            vec![[0, 1, 2], [0, 2, 3]],
+            parse_obj("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4").unwrap().faces
+        );
+        assert!(parse_obj("v 0 0 0\nf 1 2 3").is_err());
+        assert!(parse_obj("v 0 0").is_err());
+
+        // Every step keeps the vertices and adds one per edge, and splits every face in four; the corners are
+        // pulled in towards the middle, the edges' middles pushed out.
+        let once = data.subdivide();
+        assert_eq!((4 + 6, 16), (once.vertices.len(), once.faces.len()));
+        assert!((once.vertices[0].length() - 3f64.sqrt() / 4.0).abs() < 1e-12);
+        let twice = once.subdivide();
+        assert_eq!((10 + 24, 64), (twice.vertices.len(), twice.faces.len()));
+        let normals = twice.vertex_normals();
+        for (p, n) in twice.vertices.iter().zip(normals.iter()) {
+            assert!(p.length() < 3f64.sqrt() && p.dot(*n) > 0.0);
+        }
+
+        // A lone triangle only has boundary edges, which stay on its plane.
+        let triangle = MeshData { vertices: data.vertices[..3].to_vec(), faces: vec![[0, 1, 2]] }.subdivide();
+        let normal = (data.vertices[1] - data.vertices[0]).cross(data.vertices[2] - data.vertices[0]);
+        assert!(triangle.vertices.iter().all(|p| (*p - data.vertices[0]).dot(normal).abs() < 1e-12));
+    }
+}
@@ -0,0 +1,259 @@
+use crate::raytrace::{Aov, RGB, RGB16};
+use crate::vec::Color;
+use exr::prelude::{AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, WritableImage};
+use image::{ImageBuffer, Rgb, RgbImage, Rgba, RgbaImage};
+use std::io::{self, Write};
+use std::process::{Child, Command, Stdio};
+
+// Converts rendered lines (bottom to top, as produced by the renderer) into an image.
+pub fn to_image(lines: &[Vec<RGB>]) -> RgbImage {
+    let height = lines.len() as u32;
+    let width = lines.first().map_or(0, |l| l.len()) as u32;
+    RgbImage::from_fn(width, height, |x, y| {
+        let (r, g, b) = lines[(height - 1 - y) as usize][x as usize];
+        image::Rgb([r as u8, g as u8, b as u8])
+    })
+}
+
+// The same as 4 bytes per pixel from the top left, with opaque alpha, as canvases and most C libraries take it.
+pub fn to_rgba(lines: &[Vec<RGB>]) -> Vec<u8> {
+    lines.iter().rev().flat_map(|line| line.iter().flat_map(|&(r, g, b)| [r as u8, g as u8, b as u8, 255])).collect()
+}
+
+// Saves rendered lines to a file; the format is deduced from the extension.
+pub fn save_image(path: &str, lines: &[Vec<RGB>]) -> image::ImageResult<()> {
+    to_image(lines).save(path)
+}
+
+// The same with coverage from 0 to 1 as alpha, per pixel and bottom to top as well, for formats that store it,
+// like PNG.
+pub fn save_image_with_alpha(path: &str, lines: &[Vec<RGB>], alpha: &[Vec<f64>]) -> image::ImageResult<()> {
+    let rgb = to_image(lines);
+    let height = rgb.height();
+    RgbaImage::from_fn(rgb.width(), height, |x, y| {
+        let [r, g, b] = rgb.get_pixel(x, y).0;
+        let a = alpha[(height - 1 - y) as usize][x as usize];
+        image::Rgba([r, g, b, (255.0 * a.clamp(0.0, 1.0)).round() as u8])
+    })
+    .save(path)
+}
+
+// Saves lines of 16-bit values, with coverage as alpha if given as for `save_image_with_alpha`, to a format that
+// stores 16 bits per channel, like PNG or TIFF.
+pub fn save_image16(path: &str, lines: &[Vec<RGB16>], alpha: Option<&[Vec<f64>]>) -> image::ImageResult<()> {
+    let height = lines.len() as u32;
+    let width = lines.first().map_or(0, |l| l.len()) as u32;
+    match alpha {
+        None => ImageBuffer::from_fn(width, height, |x, y| {
+            let (r, g, b) = lines[(height - 1 - y) as usize][x as usize];
+            Rgb([r, g, b])
+        })
+        .save(path),
+        Some(alpha) => ImageBuffer::from_fn(width, height, |x, y| {
+            let (r, g, b) = lines[(height - 1 - y) as usize][x as usize];
+            let a = alpha[(height - 1 - y) as usize][x as usize];
+            Rgba([r, g, b, (65535.0 * a.clamp(0.0, 1.0)).round() as u16])
+        })
+        .save(path),
+    }
+}
+
+// Black through red and yellow to white, for `t` in [0, 1].
+pub fn heat(t: f64) -> RGB {
+    let channel = |from: f64| (255.0 * (3.0 * t - from).clamp(0.0, 1.0)) as i32;
+    (channel(0.0), channel(1.0), channel(2.0))
+}
+
+// Saves per-pixel sample counts (lines bottom to top) as a heatmap image scaled to the largest count.
+pub fn save_heatmap(path: &str, counts: &[Vec<usize>]) -> image::ImageResult<()> {
+    let max = counts.iter().flat_map(|line| line.iter()).copied().max().unwrap_or(0).max(1) as f64;
+    let lines: Vec<Vec<RGB>> = counts.iter().map(|line| line.iter().map(|&n| heat(n as f64 / max)).collect()).collect();
+    save_image(path, &lines)
+}
+
+// A video encoded from frames piped to a child process as raw RGB, 3 bytes per pixel from the top left.
+pub struct Video {
+    child: Child,
+    width: usize,
+    height: usize,
+}
+
+impl Video {
+    // Encodes to `path` with ffmpeg, which has to be on the PATH; the format is deduced from the extension.
+    pub fn ffmpeg(path: &str, width: usize, height: usize, fps: f64) -> io::Result<Video> {
+        let size = format!("{}x{}", width, height);
+        let fps = fps.to_string();
+        let args = ["-y", "-loglevel", "error", "-f", "rawvideo", "-pix_fmt", "rgb24", "-s", &size, "-r", &fps];
+        let mut command = Command::new("ffmpeg");
+        command.args(args).args(["-i", "-", "-pix_fmt", "yuv420p", path]);
+        Video::spawn(&mut command, width, height)
+    }
+
+    // Starts `command`, which reads the frames on stdin.
+    pub fn spawn(command: &mut Command, width: usize, height: usize) -> io::Result<Video> {
+        let child = command.stdin(Stdio::piped()).stdout(Stdio::null()).spawn()?;
+        Ok(Video { child, width, height })
+    }
+
+    pub fn add_frame(&mut self, lines: &[Vec<RGB>]) -> io::Result<()> {
+        let image = to_image(lines);
+        if (image.width() as usize, image.height() as usize) != (self.width, self.height) {
+            let error = format!("{}x{} frame in a {}x{} video", image.width(), image.height(), self.width, self.height);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, error));
+        }
+        self.child.stdin.as_mut().unwrap().write_all(&image.into_raw())
+    }
+
+    // Closes the pipe and waits for the encoder to finish.
+    pub fn finish(mut self) -> io::Result<()> {
+        drop(self.child.stdin.take());
+        let status = self.child.wait()?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(io::Error::other(format!("encoder failed: {}", status))),
+        }
+    }
+}
+
+// Writes the beauty image and the AOVs as channels of a single tiled EXR file, using the channel naming
+// compositing tools expect: R,G,B for beauty, and albedo.{R,G,B}, normal.{X,Y,Z}, depth.Z.
+// Named buffers such as light groups, if any, go to <name>.{R,G,B}, and the variance of the beauty image, if given, to
+// variance.{R,G,B}. With `alpha` the coverage of the AOVs goes to A.
+// All inputs are lines bottom to top, as produced by the renderer.
+pub fn save_exr(
+    path: &str,
+    beauty: &[Vec<Color>],
+    aovs: &[Vec<Aov>],
+    buffers: &[(String, Vec<Vec<Color>>)],
+    variance: &[Vec<Color>],
+    alpha: bool,
+) -> exr::error::Result<()> {
+    let height = beauty.len();
+    let width = beauty.first().map_or(0, |l| l.len());
+    let channel = |name: &str, value: &dyn Fn(usize, usize) -> f64| {
+        let mut samples = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                samples.push(value(x, height - 1 - y) as f32);
+            }
+        }
+        AnyChannel::new(name, FlatSamples::F32(samples))
+    };
+
+    let mut channels = vec![
+        channel("R", &|i, j| beauty[j][i].r()),
+        channel("G", &|i, j| beauty[j][i].g()),
+        channel("B", &|i, j| beauty[j][i].b()),
+        channel("albedo.R", &|i, j| aovs[j][i].albedo.r()),
+        channel("albedo.G", &|i, j| aovs[j][i].albedo.g()),
+        channel("albedo.B", &|i, j| aovs[j][i].albedo.b()),
+        channel("normal.X", &|i, j| aovs[j][i].normal.x()),
+        channel("normal.Y", &|i, j| aovs[j][i].normal.y()),
+        channel("normal.Z", &|i, j| aovs[j][i].normal.z()),
+        channel("depth.Z", &|i, j| aovs[j][i].depth),
+    ];
+    for (name, buffer) in buffers {
+        channels.push(channel(&format!("{}.R", name), &|i, j| buffer[j][i].r()));
+        channels.push(channel(&format!("{}.G", name), &|i, j| buffer[j][i].g()));
+        channels.push(channel(&format!("{}.B", name), &|i, j| buffer[j][i].b()));
+    }
+    if alpha {
+        channels.push(channel("A", &|i, j| aovs[j][i].alpha));
+    }
+    if !variance.is_empty() {
+        channels.push(channel("variance.R", &|i, j| variance[j][i].r()));
+        channels.push(channel("variance.G", &|i, j| variance[j][i].g()));
+        channels.push(channel("variance.B", &|i, j| variance[j][i].b()));
+    }
+    let layer = Layer::new(
+        (width, height),
+        LayerAttributes::named("render"),
+        Encoding::FAST_LOSSLESS, // RLE-compressed 64x64 tiles.
+        AnyChannels::sort(channels.into()),
+    );
+    Image::from_layer(layer).write().to_file(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::Vec3;
+    use exr::prelude::Vec2;
+
+    // Stands in for ffmpeg with a shell.
+    #[cfg(unix)]
+    #[test]
+    fn test_video() {
+        let path = std::env::temp_dir().join("raytracer_test_video.rgb");
+        let mut video = Video::spawn(Command::new("sh").args(["-c", "cat > \"$0\""]).arg(&path), 2, 1).unwrap();
+        video.add_frame(&[vec![(1, 2, 3), (4, 5, 6)]]).unwrap();
+        video.add_frame(&[vec![(7, 8, 9), (10, 11, 12)]]).unwrap();
+        assert!(video.add_frame(&[vec![(1, 2, 3)]]).is_err());
+        video.finish().unwrap();
+        assert_eq!((1..=12).collect::<Vec<u8>>(), std::fs::read(&path).unwrap());
+
+        let video = Video::spawn(Command::new("sh").args(["-c", "exit 1"]), 2, 1).unwrap();
+        assert!(video.finish().is_err());
+    }
+
+    #[test]
+    fn test_save_exr() {
+        let path = std::env::temp_dir().join(format!("raytracer_test_{}.exr", std::process::id()));
+        let path = path.to_str().unwrap();
+        // Bottom to top, a different value in every pixel of every channel.
+        let (width, height) = (3, 2);
+        let color = |k: f64| {
+            let lines = (0..height).map(|j| (0..width).map(|i| Color::new(k, i as f64, j as f64 + 0.5)).collect());
+            lines.collect::<Vec<Vec<Color>>>()
+        };
+        let aov = |i: usize, j: usize| Aov {
+            normal: Vec3::new(i as f64, j as f64, -1.0),
+            depth: (i + 10 * j) as f64,
+            albedo: Color::new(0.25, i as f64, j as f64),
+            alpha: 0.5 * j as f64,
+        };
+        let aovs: Vec<Vec<Aov>> = (0..height).map(|j| (0..width).map(|i| aov(i, j)).collect()).collect();
+        let buffers = [("key".to_string(), color(2.0))];
+        save_exr(path, &color(1.0), &aovs, &buffers, &color(3.0), true).unwrap();
+
+        let image = exr::prelude::read_first_flat_layer_from_file(path).unwrap();
+        assert_eq!(Vec2(width, height), image.layer_data.size);
+        let channels = &image.layer_data.channel_data.list;
+        let names: Vec<String> = channels.iter().map(|c| c.name.to_string()).collect();
+        let expected = "A B G R albedo.B albedo.G albedo.R depth.Z key.B key.G key.R normal.X normal.Y normal.Z \
+                        variance.B variance.G variance.R";
+        assert_eq!(expected, names.join(" "));
+        let value = |name: &str, i: usize, j: usize| {
+            let channel = channels.iter().find(|c| c.name.to_string() == name).unwrap();
+            // Top to bottom in the file.
+            channel.sample_data.value_by_flat_index(i + (height - 1 - j) * width).to_f32() as f64
+        };
+        for j in 0..height {
+            for i in 0..width {
+                let (c, a) = (Color::new(1.0, i as f64, j as f64 + 0.5), aov(i, j));
+                assert_eq!((c.r(), c.g(), c.b()), (value("R", i, j), value("G", i, j), value("B", i, j)));
+                assert_eq!((2.0, 3.0), (value("key.R", i, j), value("variance.R", i, j)));
+                assert_eq!(c.b(), value("variance.B", i, j));
+                assert_eq!(a.albedo.g(), value("albedo.G", i, j));
+                assert_eq!(a.normal.y(), value("normal.Y", i, j));
+                assert_eq!(a.depth, value("depth.Z", i, j));
+                assert_eq!(a.alpha, value("A", i, j));
+            }
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_save_image16() {
+        let path = std::env::temp_dir().join("raytracer_test_image16.png");
+        let path = path.to_str().unwrap();
+        // Bottom to top.
+        save_image16(path, &[vec![(1, 2, 3)], vec![(65535, 256, 0)]], None).unwrap();
+        let image = image::open(path).unwrap().into_rgb16();
+        assert_eq!([65535, 256, 0], image.get_pixel(0, 0).0);
+        assert_eq!([1, 2, 3], image.get_pixel(0, 1).0);
+
+        save_image16(path, &[vec![(1, 2, 3)]], Some(&[vec![0.5]])).unwrap();
+        assert_eq!([1, 2, 3, 32768], image::open(path).unwrap().into_rgba16().get_pixel(0, 0).0);
+    }
+}
@@ -0,0 +1,102 @@
+// The parallel iterators the renderer uses: rayon's with the parallel feature, otherwise the standard iterators
+// under the same names, so that the code is the same for targets without threads like wasm.
+#[cfg(feature = "parallel")]
+pub use rayon::prelude::*;
+
+#[cfg(feature = "parallel")]
+pub fn current_num_threads() -> usize {
+    rayon::current_num_threads()
+}
+
+// Runs `f` with its parallel iterators on a pool of `threads` threads of its own, leaving rayon's global pool as
+// it is, or on the global pool with all cores if None.
+#[cfg(feature = "parallel")]
+pub fn with_threads<R: Send>(threads: Option<usize>, f: impl FnOnce() -> R + Send) -> R {
+    match threads {
+        Some(threads) => rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap().install(f),
+        None => f(),
+    }
+}
+
+// Calls of `yield_periodically` between yields.
+#[cfg(feature = "parallel")]
+const YIELD_INTERVAL: usize = 64;
+
+#[cfg(feature = "parallel")]
+thread_local! {
+    static CALLS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+// Lets the other programs waiting for the core of this thread run, every `YIELD_INTERVAL` calls on the thread,
+// see `Renderer::with_nice`.
+#[cfg(feature = "parallel")]
+pub fn yield_periodically() {
+    let calls = CALLS.with(|c| {
+        c.set(c.get() + 1);
+        c.get()
+    });
+    if calls.is_multiple_of(YIELD_INTERVAL) {
+        std::thread::yield_now();
+    }
+}
+
+#[cfg(not(feature = "parallel"))]
+pub use sequential::*;
+
+#[cfg(not(feature = "parallel"))]
+mod sequential {
+    pub fn current_num_threads() -> usize {
+        1
+    }
+
+    pub fn with_threads<R: Send>(_: Option<usize>, f: impl FnOnce() -> R + Send) -> R {
+        f()
+    }
+
+    // Without threads there is nothing to yield to.
+    pub fn yield_periodically() {}
+
+    pub trait IntoParallelIterator: IntoIterator + Sized {
+        fn into_par_iter(self) -> Self::IntoIter {
+            self.into_iter()
+        }
+    }
+
+    impl<I: IntoIterator> IntoParallelIterator for I {}
+
+    pub trait ParallelSlice<T> {
+        fn par_iter(&self) -> std::slice::Iter<'_, T>;
+        fn par_iter_mut(&mut self) -> std::slice::IterMut<'_, T>;
+    }
+
+    impl<T> ParallelSlice<T> for [T] {
+        fn par_iter(&self) -> std::slice::Iter<'_, T> {
+            self.iter()
+        }
+
+        fn par_iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
+            self.iter_mut()
+        }
+    }
+
+    pub trait ParallelIterator: Iterator + Sized {
+        fn flat_map_iter<U: IntoIterator, F: FnMut(Self::Item) -> U>(self, f: F) -> std::iter::FlatMap<Self, U, F> {
+            self.flat_map(f)
+        }
+    }
+
+    impl<I: Iterator> ParallelIterator for I {}
+}
+
+#[cfg(all(test, feature = "parallel"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_threads() {
+        assert_eq!(3, with_threads(Some(3), current_num_threads));
+        // The global pool is untouched.
+        assert_eq!(rayon::current_num_threads(), with_threads(None, current_num_threads));
+        assert_eq!(2, with_threads(Some(2), || (0..100).into_par_iter().map(|_| current_num_threads()).max().unwrap()));
+    }
+}
@@ -0,0 +1,191 @@
+// Light path expressions: which rays the light took from its source to the camera, and filters picking out some of
+// those paths, see `Renderer::with_path_filters`. A path reads from the camera to the light, one letter per ray:
+//
+//   C    the camera ray
+//   D    a ray scattered by a diffuse surface or a volume, including those towards sampled lights
+//   S    a ray reflected or refracted by a smooth surface such as metal or glass
+//   L    the light at the end, an emitter or the background
+//
+// Filters are these letters, `.` for any of them or `[DS]` for a set, each optionally followed by `*` (any number
+// of times), `+` (at least once) or `?` (at most once), and match whole paths. For example CDL is light reaching
+// the camera directly from diffuse surfaces, CSL lights seen in mirrors, C[DS]*SD+L caustics and C.*L everything.
+use crate::raytrace::RayKind;
+use std::str::FromStr;
+
+// Rays a path keeps at most; the rays of longer paths after these are dropped.
+const MAX_RAYS: u32 = 64;
+
+// The rays of a path, two bits each from the camera ray in the lowest bits.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Path {
+    rays: u128,
+    len: u32,
+}
+
+impl Path {
+    // Just the camera ray.
+    pub const CAMERA: Path = Path { rays: 0, len: 1 };
+
+    // The path followed by a ray of `kind`.
+    pub fn then(self, kind: RayKind) -> Path {
+        if self.len >= MAX_RAYS {
+            return self;
+        }
+        Path { rays: self.rays | (Path::code(kind) << (2 * self.len)), len: self.len + 1 }
+    }
+
+    // The kind of the last ray.
+    pub fn kind(&self) -> RayKind {
+        self.ray(self.len - 1)
+    }
+
+    fn code(kind: RayKind) -> u128 {
+        match kind {
+            RayKind::Camera => 0,
+            RayKind::Diffuse => 1,
+            RayKind::Specular => 2,
+            RayKind::Shadow => 3,
+        }
+    }
+
+    fn ray(&self, k: u32) -> RayKind {
+        match (self.rays >> (2 * k)) & 3 {
+            0 => RayKind::Camera,
+            1 => RayKind::Diffuse,
+            2 => RayKind::Specular,
+            _ => RayKind::Shadow,
+        }
+    }
+
+    // The letters of the path, ending with the light.
+    fn letters(&self) -> Vec<u8> {
+        let letter = |kind| match kind {
+            RayKind::Camera => b'C',
+            RayKind::Diffuse | RayKind::Shadow => b'D',
+            RayKind::Specular => b'S',
+        };
+        (0..self.len).map(|k| letter(self.ray(k))).chain(std::iter::once(b'L')).collect()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Repeat {
+    Once,
+    Optional,
+    Any,
+    AtLeastOnce,
+}
+
+// A parsed light path expression, see the top of the file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathFilter {
+    // The letters each step matches, and how often it repeats.
+    steps: Vec<(Vec<u8>, Repeat)>,
+}
+
+impl PathFilter {
+    pub fn matches(&self, path: &Path) -> bool {
+        PathFilter::match_steps(&self.steps, &path.letters())
+    }
+
+    fn match_steps(steps: &[(Vec<u8>, Repeat)], letters: &[u8]) -> bool {
+        let ((set, repeat), rest) = match steps.split_first() {
+            None => return letters.is_empty(),
+            Some(step) => step,
+        };
+        // How many letters from the start the step can take; the rest of the steps have to match what is left.
+        let (min, max) = match repeat {
+            Repeat::Once => (1, 1),
+            Repeat::Optional => (0, 1),
+            Repeat::Any => (0, letters.len()),
+            Repeat::AtLeastOnce => (1, letters.len()),
+        };
+        let taken = letters.iter().take(max).take_while(|l| set.contains(l)).count();
+        (min..=taken).rev().any(|n| PathFilter::match_steps(rest, &letters[n..]))
+    }
+}
+
+impl FromStr for PathFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PathFilter, String> {
+        const LETTERS: &[u8] = b"CDSL";
+        let mut steps: Vec<(Vec<u8>, Repeat)> = Vec::new();
+        let mut chars = s.bytes();
+        while let Some(c) = chars.next() {
+            let set = match c {
+                b'.' => LETTERS.to_vec(),
+                b'[' => {
+                    let mut set = Vec::new();
+                    loop {
+                        match chars.next() {
+                            Some(b']') if !set.is_empty() => break set,
+                            Some(c) if LETTERS.contains(&c) => set.push(c),
+                            _ => return Err(format!("path '{}': sets are letters between [ and ]", s)),
+                        }
+                    }
+                }
+                c if LETTERS.contains(&c) => vec![c],
+                b'*' | b'+' | b'?' => {
+                    let repeat = match c {
+                        b'*' => Repeat::Any,
+                        b'+' => Repeat::AtLeastOnce,
+                        _ => Repeat::Optional,
+                    };
+                    match steps.last_mut() {
+                        Some((_, r)) if *r == Repeat::Once => *r = repeat,
+                        _ => return Err(format!("path '{}': '{}' repeats nothing", s, c as char)),
+                    }
+                    continue;
+                }
+                c => return Err(format!("path '{}': unknown letter '{}', expected C, D, S, L or .", s, c as char)),
+            };
+            steps.push((set, Repeat::Once));
+        }
+        if steps.is_empty() {
+            return Err("empty path".to_string());
+        }
+        Ok(PathFilter { steps })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_filter() {
+        let direct = Path::CAMERA.then(RayKind::Diffuse);
+        let mirrored = Path::CAMERA.then(RayKind::Specular);
+        let caustic = Path::CAMERA.then(RayKind::Diffuse).then(RayKind::Specular).then(RayKind::Diffuse);
+        assert_eq!(RayKind::Specular, mirrored.kind());
+        assert_eq!(4, caustic.len);
+        let filter = |s: &str| s.parse::<PathFilter>().unwrap();
+
+        assert!(filter("CL").matches(&Path::CAMERA));
+        assert!(filter("CDL").matches(&direct));
+        assert!(!filter("CDL").matches(&mirrored));
+        assert!(!filter("CDL").matches(&caustic));
+        assert!(filter("CS+L").matches(&mirrored));
+        assert!(!filter("CS+L").matches(&Path::CAMERA));
+        assert!(filter("C[DS]*SD+L").matches(&caustic));
+        assert!(!filter("C[DS]*SD+L").matches(&direct));
+        assert!(filter("CD?L").matches(&Path::CAMERA) && filter("CD?L").matches(&direct));
+        for path in [Path::CAMERA, direct, mirrored, caustic] {
+            assert!(filter("C.*L").matches(&path));
+            assert!(filter(".*").matches(&path));
+        }
+
+        let long = (0..100).fold(Path::CAMERA, |p, _| p.then(RayKind::Diffuse));
+        assert_eq!(MAX_RAYS, long.len);
+        assert!(filter("CD+L").matches(&long));
+
+        assert!("".parse::<PathFilter>().is_err());
+        assert!("*D".parse::<PathFilter>().is_err());
+        assert!("CD**L".parse::<PathFilter>().is_err());
+        assert!("CXL".parse::<PathFilter>().is_err());
+        assert!("C[]L".parse::<PathFilter>().is_err());
+        assert!("C[DX]L".parse::<PathFilter>().is_err());
+        assert!("C[D]L[S".parse::<PathFilter>().is_err());
+    }
+}
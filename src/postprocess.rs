@@ -0,0 +1,399 @@
+use crate::camera::CameraParams;
+use crate::par::*;
+use crate::raytrace::{RGB, RGB16};
+use crate::vec::Color;
+
+// Turns linear radiance into 8-bit (or 16-bit) display values, after rendering.
+#[derive(Copy, Clone)]
+pub struct PostProcess {
+    // Per-channel multiplier applied to the radiance first, see `exposure_from_stops`.
+    pub exposure: Color,
+    // Display gamma; the output is encoded with its inverse.
+    pub gamma: f64,
+    // Ordered dithering when quantizing, see `to_rgb`.
+    pub dither: bool,
+    // Only applied to whole images, see `apply`.
+    pub bloom: Option<Bloom>,
+    pub lens: Lens,
+}
+
+pub const DEFAULT_GAMMA: f64 = 2.2;
+
+impl PostProcess {
+    pub fn new() -> PostProcess {
+        PostProcess { exposure: Color::ONE, gamma: DEFAULT_GAMMA, dither: true, bloom: None, lens: Lens::default() }
+    }
+
+    // Display value of a pixel at (i, j) with average radiance `color`.
+    // With dithering the rounding threshold varies over an 8x8 Bayer pattern, so smooth gradients
+    // (e.g. backgrounds at low sample counts) come out without visible banding.
+    pub fn to_rgb(&self, color: &Color, i: usize, j: usize) -> RGB {
+        let color = self.exposure * *color;
+        let threshold = if self.dither { (bayer_index(i, j) as f64 + 0.5) / 64.0 } else { 0.5 };
+        let quantize = |c: f64| ((255.0 * c.max(0.0).powf(1.0 / self.gamma) + threshold) as i32).clamp(0, 255);
+        (quantize(color.r()), quantize(color.g()), quantize(color.b()))
+    }
+
+    // The same with 16 bits per channel, fine enough not to need dithering.
+    pub fn to_rgb16(&self, color: &Color) -> RGB16 {
+        let color = self.exposure * *color;
+        let quantize = |c: f64| (65535.0 * c.max(0.0).powf(1.0 / self.gamma)).round().min(65535.0) as u16;
+        (quantize(color.r()), quantize(color.g()), quantize(color.b()))
+    }
+
+    // Display values of a whole image of average radiance, lines bottom to top.
+    pub fn apply(&self, linear: &[Vec<Color>]) -> Vec<Vec<RGB>> {
+        self.develop(linear)
+            .iter()
+            .enumerate()
+            .map(|(j, line)| line.iter().enumerate().map(|(i, c)| self.to_rgb(c, i, j)).collect())
+            .collect()
+    }
+
+    // The same with 16 bits per channel, see `to_rgb16`.
+    pub fn apply16(&self, linear: &[Vec<Color>]) -> Vec<Vec<RGB16>> {
+        self.develop(linear).iter().map(|line| line.iter().map(|c| self.to_rgb16(c)).collect()).collect()
+    }
+
+    // The image with the effects of the whole image applied, before quantizing.
+    fn develop(&self, linear: &[Vec<Color>]) -> Vec<Vec<Color>> {
+        let mut linear = match &self.bloom {
+            None => linear.to_vec(),
+            Some(bloom) => bloom.apply(linear),
+        };
+        self.lens.apply(&mut linear);
+        linear
+    }
+}
+
+// Glow around bright pixels: whatever radiance exceeds `threshold` is blurred with a Gaussian of `radius`
+// (standard deviation, in pixels) and added back scaled by `strength`. Applied before exposure.
+#[derive(Copy, Clone)]
+pub struct Bloom {
+    pub threshold: f64,
+    pub radius: f64,
+    pub strength: f64,
+}
+
+impl Bloom {
+    pub fn new(threshold: f64, radius: f64, strength: f64) -> Bloom {
+        Bloom { threshold, radius, strength }
+    }
+
+    pub fn apply(&self, linear: &[Vec<Color>]) -> Vec<Vec<Color>> {
+        let excess = |c: f64| (c - self.threshold).max(0.0);
+        let bright: Vec<Vec<Color>> = linear
+            .iter()
+            .map(|line| line.iter().map(|c| Color::new(excess(c.r()), excess(c.g()), excess(c.b()))).collect())
+            .collect();
+        let kernel = gaussian_kernel(self.radius);
+        let blurred = transpose(&blur_lines(&transpose(&blur_lines(&bright, &kernel)), &kernel));
+        linear
+            .iter()
+            .zip(blurred.iter())
+            .map(|(line, glow)| line.iter().zip(glow).map(|(c, g)| *c + self.strength * *g).collect())
+            .collect()
+    }
+}
+
+// Normalized weights for offsets -n..=n.
+fn gaussian_kernel(sigma: f64) -> Vec<f64> {
+    let n = (3.0 * sigma).ceil().max(0.0) as i64;
+    let weights: Vec<f64> =
+        (-n..=n).map(|x| if sigma > 0.0 { (-((x * x) as f64) / (2.0 * sigma * sigma)).exp() } else { 1.0 }).collect();
+    let sum: f64 = weights.iter().sum();
+    weights.iter().map(|w| w / sum).collect()
+}
+
+// Convolves every line with `kernel`; light blurred past the image edges is lost.
+fn blur_lines(image: &[Vec<Color>], kernel: &[f64]) -> Vec<Vec<Color>> {
+    let n = (kernel.len() / 2) as i64;
+    image
+        .par_iter()
+        .map(|line| {
+            (0..line.len() as i64)
+                .map(|i| {
+                    let mut sum = Color::ZERO;
+                    for (k, w) in kernel.iter().enumerate() {
+                        let x = i + k as i64 - n;
+                        if x >= 0 && x < line.len() as i64 {
+                            sum = sum + *w * line[x as usize];
+                        }
+                    }
+                    sum
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn transpose(image: &[Vec<Color>]) -> Vec<Vec<Color>> {
+    let width = image.first().map_or(0, |line| line.len());
+    (0..width).map(|i| image.iter().map(|line| line[i]).collect()).collect()
+}
+
+// Imperfections of a real lens, applied after bloom. Zero disables either.
+#[derive(Copy, Clone, Default)]
+pub struct Lens {
+    // Natural vignetting, falling off with cos^4 of the angle off the optical axis. Given as the tangent of that angle
+    // at the image corners, see `natural_vignetting`.
+    pub vignetting: f64,
+    // Lateral chromatic aberration: red is magnified by 1 + chromatic_aberration around the image center, blue by
+    // 1 - chromatic_aberration.
+    pub chromatic_aberration: f64,
+}
+
+impl Lens {
+    pub fn apply(&self, linear: &mut [Vec<Color>]) {
+        if self.chromatic_aberration != 0.0 {
+            let source = linear.to_vec();
+            let (red, blue) = (1.0 + self.chromatic_aberration, 1.0 - self.chromatic_aberration);
+            for_pixels(linear, |c, x, y| {
+                *c = Color::new(
+                    bilinear(&source, x / red, y / red).r(),
+                    c.g(),
+                    bilinear(&source, x / blue, y / blue).b(),
+                )
+            });
+        }
+        if self.vignetting != 0.0 {
+            for_pixels(linear, |c, x, y| {
+                let tan_squared = self.vignetting * self.vignetting * (x * x + y * y);
+                *c = *c / ((1.0 + tan_squared) * (1.0 + tan_squared));
+            });
+        }
+    }
+}
+
+// Vignetting value of a camera with vertical field of view `field_of_view` (degrees).
+pub fn natural_vignetting(field_of_view: f64, aspect_ratio: f64) -> f64 {
+    (field_of_view.to_radians() / 2.0).tan() * (1.0 + aspect_ratio * aspect_ratio).sqrt()
+}
+
+// Calls `f` with every pixel and its position relative to the image center, scaled so that the corners are at
+// distance 1.
+fn for_pixels(image: &mut [Vec<Color>], f: impl Fn(&mut Color, f64, f64) + Sync) {
+    let (width, height) = (image.first().map_or(0, |line| line.len()) as f64, image.len() as f64);
+    let half_diagonal = (width * width + height * height).sqrt() / 2.0;
+    image.par_iter_mut().enumerate().for_each(|(j, line)| {
+        for (i, c) in line.iter_mut().enumerate() {
+            let x = (i as f64 + 0.5 - width / 2.0) / half_diagonal;
+            let y = (j as f64 + 0.5 - height / 2.0) / half_diagonal;
+            f(c, x, y);
+        }
+    });
+}
+
+// Interpolated value at a position given as in `for_pixels`, clamped to the image.
+fn bilinear(image: &[Vec<Color>], x: f64, y: f64) -> Color {
+    let (width, height) = (image[0].len(), image.len());
+    let half_diagonal = ((width * width + height * height) as f64).sqrt() / 2.0;
+    let px = (x * half_diagonal + width as f64 / 2.0 - 0.5).clamp(0.0, (width - 1) as f64);
+    let py = (y * half_diagonal + height as f64 / 2.0 - 0.5).clamp(0.0, (height - 1) as f64);
+    let (i, j) = (px as usize, py as usize);
+    let (i1, j1) = ((i + 1).min(width - 1), (j + 1).min(height - 1));
+    let (fx, fy) = (px - i as f64, py - j as f64);
+    let line = |j: usize| (1.0 - fx) * image[j][i] + fx * image[j][i1];
+    (1.0 - fy) * line(j) + fy * line(j1)
+}
+
+// Approximate depth of field for previews, from a pinhole image and its depth: every pixel is spread over its circle
+// of confusion for a thin lens of `aperture` focused at `focus_dist`.
+#[derive(Copy, Clone)]
+pub struct DepthOfField {
+    pub aperture: f64,
+    pub focus_dist: f64,
+    // Width of the image at distance 1 from the camera.
+    pub viewport_width: f64,
+}
+
+// Larger blur is clamped, it gets slow and the preview is rough anyway.
+const MAX_BLUR_RADIUS: f64 = 16.0;
+
+impl DepthOfField {
+    pub fn new(camera: &CameraParams) -> DepthOfField {
+        DepthOfField {
+            aperture: camera.aperture,
+            focus_dist: camera.focus_dist,
+            viewport_width: 2.0 * (camera.field_of_view.to_radians() / 2.0).tan() * camera.aspect_ratio,
+        }
+    }
+
+    // Radius of the circle of confusion of a point at `depth`, in pixels of an image `width` wide.
+    fn blur_radius(&self, depth: f64, width: usize) -> f64 {
+        // Size of the blur as seen at the focus distance; infinite depth gives the aperture.
+        let blur = 0.5 * self.aperture * (1.0 - self.focus_dist / depth).abs();
+        (blur / (self.focus_dist * self.viewport_width) * width as f64).min(MAX_BLUR_RADIUS)
+    }
+
+    pub fn apply(&self, linear: &[Vec<Color>], depth: &[Vec<f64>]) -> Vec<Vec<Color>> {
+        let (width, height) = (linear.first().map_or(0, |line| line.len()), linear.len());
+        let radius: Vec<Vec<f64>> =
+            depth.iter().map(|line| line.iter().map(|&d| self.blur_radius(d, width).max(0.5)).collect()).collect();
+        let reach = radius.iter().flatten().fold(0.0, |a: f64, &r| a.max(r)).ceil() as usize;
+        // Gathers the pixels whose circle of confusion covers (i, j), each spread evenly over its circle.
+        let gather = |i: usize, j: usize| {
+            let (mut sum, mut weights) = (Color::ZERO, 0.0);
+            for y in j.saturating_sub(reach)..(j + reach + 1).min(height) {
+                for x in i.saturating_sub(reach)..(i + reach + 1).min(width) {
+                    let r = radius[y][x];
+                    let (dx, dy) = (x as f64 - i as f64, y as f64 - j as f64);
+                    if dx * dx + dy * dy <= r * r {
+                        let w = 1.0 / (r * r);
+                        sum = sum + w * linear[y][x];
+                        weights += w;
+                    }
+                }
+            }
+            sum / weights
+        };
+        (0..height).into_par_iter().map(|j| (0..width).map(|i| gather(i, j)).collect()).collect()
+    }
+}
+
+// Gradient magnitude of the brightness of every pixel, from a 3x3 Sobel filter with the edges clamped. Brightness is
+// the average of the channels, gamma encoded and clipped to [0, 1] so that contrast counts as it is displayed; a step
+// from black to white gives 1.
+pub fn sobel(linear: &[Vec<Color>]) -> Vec<Vec<f64>> {
+    let (width, height) = (linear.first().map_or(0, |line| line.len()), linear.len());
+    let brightness: Vec<Vec<f64>> = linear
+        .iter()
+        .map(|line| {
+            line.iter().map(|c| ((c.r() + c.g() + c.b()) / 3.0).clamp(0.0, 1.0).powf(1.0 / DEFAULT_GAMMA)).collect()
+        })
+        .collect();
+    let at = |i: i64, j: i64| brightness[j.clamp(0, height as i64 - 1) as usize][i.clamp(0, width as i64 - 1) as usize];
+    let gradient = |i: usize, j: usize| {
+        let (i, j) = (i as i64, j as i64);
+        let gx = (at(i + 1, j - 1) + 2.0 * at(i + 1, j) + at(i + 1, j + 1))
+            - (at(i - 1, j - 1) + 2.0 * at(i - 1, j) + at(i - 1, j + 1));
+        let gy = (at(i - 1, j + 1) + 2.0 * at(i, j + 1) + at(i + 1, j + 1))
+            - (at(i - 1, j - 1) + 2.0 * at(i, j - 1) + at(i + 1, j - 1));
+        (gx * gx + gy * gy).sqrt() / 4.0
+    };
+    (0..height).into_par_iter().map(|j| (0..width).map(|i| gradient(i, j)).collect()).collect()
+}
+
+// Exposure multiplier for per-channel adjustments in stops, i.e. powers of two.
+pub fn exposure_from_stops(stops: Color) -> Color {
+    Color::new(stops.r().exp2(), stops.g().exp2(), stops.b().exp2())
+}
+
+// Position of pixel (i, j) in the 8x8 Bayer matrix, 0..64.
+fn bayer_index(i: usize, j: usize) -> usize {
+    let mut result = 0;
+    for bit in 0..3 {
+        let shift = 2 * (2 - bit);
+        result |= (((i ^ j) >> bit) & 1) << (shift + 1) | ((j >> bit) & 1) << shift;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bayer_index_is_permutation() {
+        let mut seen = [false; 64];
+        for j in 0..8 {
+            for i in 0..8 {
+                seen[bayer_index(i, j)] = true;
+                assert_eq!(bayer_index(i, j), bayer_index(i + 8, j + 16));
+            }
+        }
+        assert!(seen.iter().all(|&s| s));
+    }
+
+    #[test]
+    fn test_dithering_preserves_average() {
+        let post = PostProcess::new();
+        for &level in &[0.0, 0.1, 0.3337, 0.5, 0.91, 1.0] {
+            // Color value that maps to `level` after gamma correction.
+            let color = Color::ONE * f64::powf(level, post.gamma);
+            let mut sum = 0.0;
+            for j in 0..8 {
+                for i in 0..8 {
+                    sum += post.to_rgb(&color, i, j).0 as f64;
+                }
+            }
+            assert!((sum / 64.0 - 255.0 * level).abs() < 0.51, "level {}: {}", level, sum / 64.0);
+        }
+    }
+
+    #[test]
+    fn test_exposure_and_gamma() {
+        let post = PostProcess {
+            exposure: exposure_from_stops(Color::new(1.0, 0.0, -1.0)),
+            gamma: 2.0,
+            dither: false,
+            bloom: None,
+            lens: Lens::default(),
+        };
+        assert_eq!((255, 180, 180), post.to_rgb(&Color::new(0.5, 0.5, 1.0), 0, 0));
+        assert_eq!((65535, 46340, 46340), post.to_rgb16(&Color::new(0.5, 0.5, 1.0)));
+    }
+
+    #[test]
+    fn test_bloom() {
+        let mut image = vec![vec![Color::ONE * 0.5; 41]; 31];
+        image[15][20] = Color::new(11.0, 1.0, 0.5);
+        let bloomed = Bloom::new(1.0, 2.0, 0.5).apply(&image);
+
+        // Half of the excess of the bright pixel is spread around it, dimmer pixels add nothing.
+        let sum = |image: &[Vec<Color>]| image.iter().flatten().fold(Color::ZERO, |s, c| s + *c);
+        let glow = sum(&bloomed) - sum(&image);
+        assert!((glow.r() - 5.0).abs() < 1e-6, "{}", glow.r());
+        assert!(glow.g().abs() < 1e-6);
+        assert!(bloomed[15][21].r() > bloomed[15][22].r());
+        assert!((bloomed[15][19].r() - bloomed[15][21].r()).abs() < 1e-12);
+        assert_eq!(0.5, bloomed[0][0].r());
+    }
+
+    #[test]
+    fn test_lens() {
+        let gradient: Vec<Vec<Color>> =
+            (0..30).map(|_| (0..40).map(|i| Color::new(i as f64, 1.0, i as f64)).collect()).collect();
+
+        let mut vignetted = gradient.clone();
+        Lens { vignetting: 1.0, chromatic_aberration: 0.0 }.apply(&mut vignetted);
+        // Corner pixels are almost 45 degrees off-axis, cos^4 = 1/4.
+        assert!((vignetted[0][0].g() - 0.25).abs() < 0.02, "{}", vignetted[0][0].g());
+        assert!(vignetted[15][20].g() > 0.99);
+
+        let mut aberrated = gradient.clone();
+        Lens { vignetting: 0.0, chromatic_aberration: 0.1 }.apply(&mut aberrated);
+        // Red is magnified, so it changes slower across the image, blue faster.
+        assert!(aberrated[15][39].r() < 39.0 && aberrated[15][30].b() > 30.0);
+        assert_eq!(gradient[15][39].g(), aberrated[15][39].g());
+        assert!((aberrated[15][20].r() - 20.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_sobel() {
+        let image: Vec<Vec<Color>> =
+            (0..10).map(|_| (0..10).map(|i| if i < 5 { Color::ZERO } else { Color::ONE }).collect()).collect();
+        let edges = sobel(&image);
+        assert_eq!(1.0, edges[5][4]);
+        assert_eq!(1.0, edges[5][5]);
+        assert_eq!(0.0, edges[5][3]);
+        assert_eq!(0.0, edges[0][9]);
+    }
+
+    #[test]
+    fn test_depth_of_field() {
+        let mut image = vec![vec![Color::ZERO; 40]; 30];
+        image[10][10] = Color::ONE;
+        image[20][30] = Color::ONE;
+        let mut depth = vec![vec![5.0; 40]; 30];
+        depth[20][30] = f64::INFINITY;
+        let dof = DepthOfField { aperture: 0.5, focus_dist: 5.0, viewport_width: 1.0 };
+        let blurred = dof.apply(&image, &depth);
+
+        // In focus stays sharp, the far point is spread over a circle of 0.25 / 5 * 40 = 2 pixels.
+        assert_eq!(1.0, blurred[10][10].r());
+        assert_eq!(0.0, blurred[10][11].r());
+        assert!(blurred[20][32].r() > 0.0 && blurred[22][30].r() > 0.0);
+        assert_eq!(0.0, blurred[20][33].r());
+    }
+}
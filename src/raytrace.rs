@@ -1,12 +1,33 @@
 use crate::camera::Camera;
-use crate::hittable::Hittable;
+use crate::film::{Film, SplatFilm};
+use crate::filter::{Filter, FilterKind, FilterSampler};
+use crate::hittable::{self, Hit, Hittable};
+use crate::interval::Interval;
+use crate::logging::{self, Level};
+use crate::materials::Material;
+use crate::math::Onb;
+use crate::par::*;
+use crate::paths::{Path, PathFilter};
+use crate::postprocess::{self, PostProcess};
 use crate::rngator;
-use crate::vec::{Color, Point3, Ray};
+use crate::sampler::{PixelSamples, Sampler};
+use crate::textures::SolidColor;
+use crate::tiles::{self, Tile, TileOrder, SUBTILES};
+use crate::vec::{Color, Point3, Ray, Vec3};
+use crate::volumes::{self, FogParams, Isotropic};
 use rand::{Rng, RngCore};
-use rayon::prelude::*;
+use std::cell::Cell;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
 
 pub trait Background: Sync {
     fn color(&self, ray: &Ray) -> Color;
+
+    // For `--export_scene`: the value of a scene file's background line, None if it can't be written so.
+    fn export(&self) -> Option<String> {
+        None
+    }
 }
 
 pub struct GradientBackground {
@@ -32,6 +53,11 @@ impl Background for GradientBackground {
         let t = 0.5f64 * (unit_direction.y() + 1.0f64);
         return (1.0 - t) * self.bottom + t * self.top;
     }
+
+    fn export(&self) -> Option<String> {
+        let (t, b) = (self.top, self.bottom);
+        Some(format!("gradient {},{},{} {},{},{}", t.r(), t.g(), t.b(), b.r(), b.g(), b.b()))
+    }
 }
 
 pub struct BlackBackground {}
@@ -45,65 +71,568 @@ impl Background for BlackBackground {
     fn color(&self, _: &Ray) -> Color {
         Color::ZERO
     }
+
+    fn export(&self) -> Option<String> {
+        Some("black".to_string())
+    }
+}
+
+// The same color in every direction.
+pub struct SolidBackground {
+    color: Color,
+}
+
+impl SolidBackground {
+    pub fn new(color: Color) -> SolidBackground {
+        SolidBackground { color }
+    }
+}
+
+impl Background for SolidBackground {
+    fn color(&self, _: &Ray) -> Color {
+        self.color
+    }
+
+    fn export(&self) -> Option<String> {
+        Some(format!("color {},{},{}", self.color.r(), self.color.g(), self.color.b()))
+    }
 }
 
 #[derive(Copy, Clone)]
 pub struct RenderingParams {
-    pub samples_per_pixel: i32,
+    pub samples_per_pixel: usize,
     pub image_height: usize,
     pub image_width: usize,
+    pub post: PostProcess,
+    // Camera rays are cast at random times in between, for motion blur; see `Ray::time`.
+    pub shutter_open: f64,
+    pub shutter_close: f64,
 }
 
 pub type RGB = (i32, i32, i32);
+// 16 bits per channel, for images that keep the gradations of long renders, see `PostProcess::apply16`.
+pub type RGB16 = (u16, u16, u16);
 
-pub fn to_rgb(color: &Color, samples_per_pixel: i32) -> RGB {
-    let scale = 1.0f64 / samples_per_pixel as f64;
-    let r = (color.r() * scale).sqrt();
-    let g = (color.g() * scale).sqrt();
-    let b = (color.b() * scale).sqrt();
-    let ir = (255.999f64 * r.clamp(0.0, 0.99999999)) as i32;
-    let ig = (255.999f64 * g.clamp(0.0, 0.99999999)) as i32;
-    let ib = (255.999f64 * b.clamp(0.0, 0.99999999)) as i32;
-    (ir, ig, ib)
+// What a ray is cast for, so that the tracers can treat rays differently, e.g. hide surfaces from some of them
+// (see `materials::Visible`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RayKind {
+    // From the camera, before any bounce.
+    Camera,
+    // Scattered by a diffuse surface or a volume.
+    Diffuse,
+    // Reflected or refracted by a smooth surface such as metal or glass.
+    Specular,
+    // Towards a sampled light, to find out whether something is in between.
+    Shadow,
 }
 
 pub trait RayTracer: Sync {
     fn trace(&self, ray: &Ray, world: &dyn Hittable, background: &dyn Background, rng: &mut dyn RngCore) -> Color;
+
+    // Same as `trace`, but the light is reported to `sink` piece by piece, with the light group of its source (see
+    // `Material::light_group`) and the path it took to the camera (see `paths::Path`). Tracers that don't track
+    // light sources report everything at once, in group 0 and as seen by the camera ray.
+    fn trace_paths(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        background: &dyn Background,
+        rng: &mut dyn RngCore,
+        sink: &mut dyn FnMut(usize, Path, Color),
+    ) {
+        sink(0, Path::CAMERA, self.trace(ray, world, background, rng));
+    }
+
+    // Same as `trace`, but the light is split by the light group of its source and added to `groups`.
+    // Contributions of groups beyond the end of the slice go to the last element.
+    fn trace_light_groups(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        background: &dyn Background,
+        rng: &mut dyn RngCore,
+        groups: &mut [Color],
+    ) {
+        let last = groups.len() - 1;
+        let sink = &mut |group: usize, _, c| groups[group.min(last)] = groups[group.min(last)] + c;
+        self.trace_paths(ray, world, background, rng, sink);
+    }
+}
+
+impl<RT: RayTracer + ?Sized> RayTracer for &RT {
+    fn trace(&self, ray: &Ray, world: &dyn Hittable, background: &dyn Background, rng: &mut dyn RngCore) -> Color {
+        (**self).trace(ray, world, background, rng)
+    }
+
+    fn trace_paths(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        background: &dyn Background,
+        rng: &mut dyn RngCore,
+        sink: &mut dyn FnMut(usize, Path, Color),
+    ) {
+        (**self).trace_paths(ray, world, background, rng, sink)
+    }
+
+    fn trace_light_groups(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        background: &dyn Background,
+        rng: &mut dyn RngCore,
+        groups: &mut [Color],
+    ) {
+        (**self).trace_light_groups(ray, world, background, rng, groups)
+    }
+}
+
+// Bounces of a path by kind: counted along it, or as limits on top of `max_depth`. Glass-heavy worlds need long
+// chains of specular bounces, and hardly gain from as many diffuse ones.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bounces {
+    pub diffuse: i32,
+    // Reflections and refractions by smooth surfaces.
+    pub specular: i32,
+    // Scattering in volumes, see `Material::in_volume`.
+    pub volume: i32,
+}
+
+impl Bounces {
+    pub const ZERO: Bounces = Bounces { diffuse: 0, specular: 0, volume: 0 };
+    pub const UNLIMITED: Bounces = Bounces { diffuse: i32::MAX, specular: i32::MAX, volume: i32::MAX };
+
+    // Counts another bounce off `material`.
+    fn then(self, material: &dyn Material) -> Bounces {
+        match (material.in_volume(), material.bounce_kind()) {
+            (true, _) => Bounces { volume: self.volume + 1, ..self },
+            (false, RayKind::Specular) => Bounces { specular: self.specular + 1, ..self },
+            (false, _) => Bounces { diffuse: self.diffuse + 1, ..self },
+        }
+    }
+
+    fn within(&self, limits: &Bounces) -> bool {
+        self.diffuse <= limits.diffuse && self.specular <= limits.specular && self.volume <= limits.volume
+    }
+}
+
+// Path regularization: specular bounces after `after` diffuse ones are blurred by `roughness`, the way `Metal`'s
+// fuzz blurs reflections. Caustics seen through diffuse bounces otherwise only come from the rare paths that
+// happen to hit a light through glass or a mirror; blurred, many more paths carry their light, with much less
+// noise but slightly softer and biased. The specular surfaces the camera sees directly stay sharp.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Regularization {
+    pub roughness: f64,
+    pub after: i32,
+}
+
+impl Regularization {
+    // `scattered` off `h` by a path with `bounces` before it, blurred if it is a specular bounce late enough.
+    fn apply(&self, h: &Hit, bounces: &Bounces, scattered: Ray, rng: &mut dyn RngCore) -> Ray {
+        if h.material.bounce_kind() != RayKind::Specular || h.material.in_volume() || bounces.diffuse < self.after {
+            return scattered;
+        }
+        let dir = scattered.dir.unit() + self.roughness * Vec3::random_in_unit_sphere(rng);
+        // Blurred through the surface, a reflection would turn into a refraction or back.
+        if dir.dot(h.normal) * scattered.dir.dot(h.normal) <= 0.0 {
+            return scattered;
+        }
+        h.spawn_ray(dir)
+    }
 }
 
 pub struct RecursiveRayTracer {
     pub max_depth: i32,
+    // Of every kind, see `Bounces`.
+    pub max_bounces: Bounces,
+    pub regularization: Option<Regularization>,
+    // Panic on NaN or infinite values along the path, see `check_finite`.
+    pub check_nan: bool,
+}
+
+thread_local! {
+    // Pixel (from the top left) of the camera ray traced on this thread, for `check_finite`.
+    static PIXEL: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+// For `--check_nan`: panics with `problem`, the pixel, the bounce and the object hit, if any.
+fn report_nan(problem: String, bounce: i32, h: Option<&Hit>) -> ! {
+    let (x, y) = PIXEL.with(|p| p.get());
+    let object = match h {
+        Some(h) => format!("object hit at {} with normal {}, t={}", h.p, h.normal, h.t),
+        None => "no object hit".to_string(),
+    };
+    panic!("--check_nan: {} at pixel {},{}, bounce {}, {}", problem, x, y, bounce, object);
+}
+
+// NaN and infinite values otherwise end up as black or white speckles in the image.
+fn check_finite(what: &str, v: &Vec3, bounce: i32, h: Option<&Hit>) {
+    if !v.e.iter().all(|c| c.is_finite()) {
+        report_nan(format!("{} is {}", what, v), bounce, h);
+    }
+}
+
+fn check_direction(what: &str, v: &Vec3, bounce: i32, h: &Hit) {
+    check_finite(what, v, bounce, Some(h));
+    if v.length_squared() == 0.0 {
+        report_nan(format!("{} has zero length", what), bounce, Some(h));
+    }
+}
+
+fn check_hit(h: &Hit, bounce: i32) {
+    if !h.t.is_finite() {
+        report_nan(format!("hit t is {}", h.t), bounce, Some(h));
+    }
+    check_finite("hit point", &h.p, bounce, Some(h));
+    check_direction("normal", &h.normal, bounce, h);
 }
 
 impl RecursiveRayTracer {
+    // Follows the path and reports the light reaching the camera through it to `sink`, with its light group and
+    // the path it took.
+    #[allow(clippy::too_many_arguments)]
     fn trace_internal(
         &self,
         ray: &Ray,
         world: &dyn Hittable,
         background: &dyn Background,
         depth: i32,
+        bounces: Bounces,
+        path: Path,
+        throughput: Color,
         rng: &mut dyn RngCore,
-    ) -> Color {
-        if depth <= 0 {
-            return Color::ZERO;
+        sink: &mut dyn FnMut(usize, Path, Color),
+    ) {
+        if depth <= 0 || !bounces.within(&self.max_bounces) {
+            return;
         }
-        match world.hit(ray, 0.001, f64::INFINITY, rng) {
-            Some(h) => match h.material.scatter(ray, &h, rng) {
-                Some((attenuation, scattered)) => {
-                    return attenuation * self.trace_internal(&scattered, world, background, depth - 1, rng);
+        let bounce = self.max_depth - depth;
+        let h = match visible_hit(world, ray, path.kind(), rng) {
+            Some(h) => h,
+            None => {
+                let color = background.color(ray);
+                if self.check_nan {
+                    check_finite("background", &color, bounce, None);
                 }
-                None => {
-                    return h.material.emit(h.u, h.v, h.p);
+                return sink(0, path, throughput * color);
+            }
+        };
+        if self.check_nan {
+            check_hit(&h, bounce);
+        }
+        // Holdouts show the camera the background, see `materials::Holdout`.
+        if path == Path::CAMERA && h.material.holdout() {
+            return sink(0, path, throughput * background.color(ray));
+        }
+        match h.material.scatter(ray, &h, rng) {
+            Some((attenuation, scattered)) => {
+                if self.check_nan {
+                    check_finite("attenuation", &attenuation, bounce, Some(&h));
+                    check_direction("scatter direction", &scattered.dir, bounce, &h);
                 }
-            },
-            None => background.color(ray),
+                let scattered = match &self.regularization {
+                    Some(regularization) => regularization.apply(&h, &bounces, scattered, rng),
+                    None => scattered,
+                };
+                let throughput = throughput * attenuation;
+                let (bounces, path) = (bounces.then(h.material), path.then(h.material.bounce_kind()));
+                self.trace_internal(&scattered, world, background, depth - 1, bounces, path, throughput, rng, sink);
+            }
+            None => {
+                let emitted = h.material.emit(h.u, h.v, h.p);
+                if self.check_nan {
+                    check_finite("emitted light", &emitted, bounce, Some(&h));
+                }
+                sink(h.material.light_group(), path, throughput * emitted);
+            }
         }
     }
 }
 
 impl RayTracer for RecursiveRayTracer {
     fn trace(&self, ray: &Ray, world: &dyn Hittable, background: &dyn Background, rng: &mut dyn RngCore) -> Color {
-        self.trace_internal(ray, world, background, self.max_depth, rng)
+        let mut result = Color::ZERO;
+        let sink = &mut |_, _, c| result = result + c;
+        let depth = self.max_depth;
+        self.trace_internal(ray, world, background, depth, Bounces::ZERO, Path::CAMERA, Color::ONE, rng, sink);
+        result
+    }
+
+    fn trace_paths(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        background: &dyn Background,
+        rng: &mut dyn RngCore,
+        sink: &mut dyn FnMut(usize, Path, Color),
+    ) {
+        let depth = self.max_depth;
+        self.trace_internal(ray, world, background, depth, Bounces::ZERO, Path::CAMERA, Color::ONE, rng, sink);
+    }
+}
+
+// Path tracer with next-event estimation: at every diffuse hit, a point on one of the `lights` is sampled and
+// its light is added directly if nothing opaque is in between; glass in between lets its light through, dimmed
+// but not refracted, see `Material::transmission`. Light sampling converges much faster for small or
+// distant lights, which random bounces rarely hit.
+// `lights` must contain the emitters as they appear in the world, and only shapes that implement
+// `pdf_value` and `random_point`; emission of those reached by a bounce after a sampled hit is not counted
+// again.
+pub struct LightSamplingRayTracer<'a> {
+    pub max_depth: i32,
+    // See `RecursiveRayTracer::max_bounces`.
+    pub max_bounces: Bounces,
+    pub regularization: Option<Regularization>,
+    pub lights: &'a dyn Hittable,
+    // See `RecursiveRayTracer::check_nan`.
+    pub check_nan: bool,
+    // Fog traced as single scattering: along every ray the light of the sampled lights scattered towards it is
+    // added, at a distance picked by equiangular sampling, and whatever is behind is dimmed by the fog. Light
+    // shafts come out much less noisy than with a `volumes::Fog` around the world, which must not be used as well.
+    pub fog: Option<FogParams>,
+}
+
+// Relative distance short of a sampled light at which shadow rays stop, so they don't hit the light itself.
+const SHADOW_RAY_EPSILON: f64 = 1e-6;
+
+// Surfaces a ray passes through at most, glass for shadow rays or hidden ones for any ray, see
+// `materials::Visible`; behind more it counts as blocked.
+const MAX_PASSED_SURFACES: usize = 16;
+
+// The closest hit along `ray` of a surface that rays of its kind see, passing through the others. The hit's t is
+// along `ray`.
+pub fn visible_hit<'a>(world: &'a dyn Hittable, ray: &Ray, kind: RayKind, rng: &mut dyn RngCore) -> Option<Hit<'a>> {
+    let (mut segment, mut start) = (*ray, 0.0);
+    for _ in 0..MAX_PASSED_SURFACES {
+        let mut h = world.hit(&segment, Interval::FORWARD, rng)?;
+        if h.material.visibility().sees(kind) {
+            h.t += start;
+            return Some(h);
+        }
+        // The direction is the same, so distances along the ray add up.
+        start += h.t;
+        segment = h.spawn_ray(ray.dir);
+    }
+    None
+}
+
+impl<'a> LightSamplingRayTracer<'a> {
+    #[allow(clippy::too_many_arguments)]
+    fn trace_internal(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        background: &dyn Background,
+        depth: i32,
+        bounces: Bounces,
+        path: Path,
+        throughput: Color,
+        lights_sampled: bool,
+        rng: &mut dyn RngCore,
+        sink: &mut dyn FnMut(usize, Path, Color),
+    ) {
+        if depth <= 0 || !bounces.within(&self.max_bounces) {
+            return;
+        }
+        let bounce = self.max_depth - depth;
+        let hit = visible_hit(world, ray, path.kind(), rng);
+        let throughput = match &self.fog {
+            None => throughput,
+            Some(fog) => {
+                let fog_t = fog.clip(ray, Interval::new(0.0, hit.as_ref().map_or(f64::INFINITY, |h| h.t)));
+                if let Some((group, light)) = self.sample_fog(ray, fog, fog_t, world, rng) {
+                    if self.check_nan {
+                        check_finite("light scattered by fog", &light, bounce, None);
+                    }
+                    sink(group, path.then(RayKind::Diffuse), throughput * light);
+                }
+                fog.transmittance(ray, fog_t) * throughput
+            }
+        };
+        let h = match hit {
+            None => {
+                let color = background.color(ray);
+                if self.check_nan {
+                    check_finite("background", &color, bounce, None);
+                }
+                return sink(0, path, throughput * color);
+            }
+            Some(h) => h,
+        };
+        if self.check_nan {
+            check_hit(&h, bounce);
+        }
+        // Holdouts show the camera the background, see `materials::Holdout`.
+        if path == Path::CAMERA && h.material.holdout() {
+            return sink(0, path, throughput * background.color(ray));
+        }
+        if !(lights_sampled && self.is_light(ray, h.t, rng)) {
+            let emitted = h.material.emit(h.u, h.v, h.p);
+            if self.check_nan {
+                check_finite("emitted light", &emitted, bounce, Some(&h));
+            }
+            sink(h.material.light_group(), path, throughput * emitted);
+        }
+        let (attenuation, scattered) = match h.material.scatter(ray, &h, rng) {
+            None => return,
+            Some(s) => s,
+        };
+        if self.check_nan {
+            check_finite("attenuation", &attenuation, bounce, Some(&h));
+            check_direction("scatter direction", &scattered.dir, bounce, &h);
+        }
+        let scattered = match &self.regularization {
+            Some(regularization) => regularization.apply(&h, &bounces, scattered, rng),
+            None => scattered,
+        };
+
+        let direct = self.sample_light(ray, &h, world, rng);
+        if let Some((group, light)) = direct {
+            if self.check_nan {
+                check_finite("sampled light", &light, bounce, Some(&h));
+            }
+            sink(group, path.then(h.material.bounce_kind()), throughput * light);
+        }
+        // Light sampled through glass, which shadow rays pass straight through, isn't counted again when a ray
+        // refracted by it reaches the light.
+        let refracted = scattered.dir.dot(h.normal) < 0.0 && h.material.transmission(ray, &h).is_some();
+        let lights_sampled = direct.is_some() || (lights_sampled && refracted);
+        let throughput = throughput * attenuation;
+        let (bounces, path) = (bounces.then(h.material), path.then(h.material.bounce_kind()));
+        let depth = depth - 1;
+        self.trace_internal(&scattered, world, background, depth, bounces, path, throughput, lights_sampled, rng, sink);
+    }
+
+    // Light arriving at the hit from a randomly sampled point on the lights, with its light group.
+    // None if the material can't be light-sampled.
+    fn sample_light(&self, ray: &Ray, h: &Hit, world: &dyn Hittable, rng: &mut dyn RngCore) -> Option<(usize, Color)> {
+        let target = self.lights.random_point(&h.p, rng);
+        let to_light = h.spawn_ray(target - h.p);
+        let bsdf = h.material.eval(ray, h, &to_light.dir.unit())?;
+
+        let pdf = self.lights.pdf_value(&to_light.orig, &to_light.dir, rng);
+        let light = match self.lights.hit(&to_light, Interval::FORWARD, rng) {
+            Some(light) if pdf > 0.0 => light,
+            _ => return Some((0, Color::ZERO)),
+        };
+        let shadow_t = Interval::new(0.0, light.t * (1.0 - SHADOW_RAY_EPSILON));
+        let shadow = self.shadow_transmittance(&to_light, shadow_t, world, rng);
+        if shadow == Color::ZERO {
+            return Some((0, Color::ZERO));
+        }
+        let transmittance = self.fog.map_or(1.0, |fog| fog.transmittance(&to_light, fog.clip(&to_light, shadow_t)));
+        let emitted = light.material.emit(light.u, light.v, light.p);
+        Some((light.material.light_group(), transmittance * shadow * bsdf * emitted / pdf))
+    }
+
+    // Fraction of the light at the end of `shadow_t` along `to_light` that reaches its origin: none behind opaque
+    // surfaces, otherwise what all the surfaces in between let through; those hidden from shadow rays let all of
+    // it through.
+    fn shadow_transmittance(
+        &self,
+        to_light: &Ray,
+        shadow_t: Interval,
+        world: &dyn Hittable,
+        rng: &mut dyn RngCore,
+    ) -> Color {
+        // Most shadow rays hit nothing or something opaque, which the cheaper occlusion query tells apart first.
+        if !world.hit_any(to_light, shadow_t, rng) {
+            return Color::ONE;
+        }
+        let (mut ray, mut t_max) = (*to_light, shadow_t.max);
+        let mut transmittance = Color::ONE;
+        for _ in 0..MAX_PASSED_SURFACES {
+            let h = match world.hit(&ray, Interval::new(shadow_t.min, t_max), rng) {
+                None => return transmittance,
+                Some(h) => h,
+            };
+            let transmission = match h.material.visibility().sees(RayKind::Shadow) {
+                true => h.material.transmission(&ray, &h),
+                false => Some(Color::ONE),
+            };
+            match transmission {
+                None => return Color::ZERO,
+                Some(t) => transmittance = transmittance * t,
+            }
+            // The direction is the same, so distances along the ray stay the same.
+            t_max -= h.t;
+            ray = h.spawn_ray(ray.dir);
+        }
+        Color::ZERO
+    }
+
+    // Light of the lights scattered by the fog along `fog_t` of `ray` towards its origin, with its light group.
+    fn sample_fog(
+        &self,
+        ray: &Ray,
+        fog: &FogParams,
+        fog_t: Interval,
+        world: &dyn Hittable,
+        rng: &mut dyn RngCore,
+    ) -> Option<(usize, Color)> {
+        if fog_t.is_empty() || fog.density <= 0.0 {
+            return None;
+        }
+        // Any point of the lights will do to aim the sampling at, the light itself is sampled again from the
+        // scattering point.
+        let target = self.lights.random_point(&ray.at(fog_t.min), rng);
+        let (t, pdf) = volumes::sample_equiangular(ray, fog_t, target, rng);
+        if !(pdf > 0.0 && pdf.is_finite()) {
+            return None;
+        }
+        let phase_function = Isotropic::new(SolidColor::from_color(fog.color));
+        let p = ray.at(t);
+        let normal = -ray.dir.unit();
+        let (dpdu, dpdv) = hittable::tangents(&normal);
+        let h = Hit {
+            p,
+            normal,
+            geometric_normal: normal,
+            shading_offset: Vec3::ZERO,
+            dpdu,
+            dpdv,
+            t,
+            u: 0.0,
+            v: 0.0,
+            front_face: true,
+            material: &phase_function,
+            time: ray.time,
+        };
+        let (group, light) = self.sample_light(ray, &h, world, rng)?;
+        // The scattering point is t * |dir| away, where the density is per unit of distance.
+        let scattering = fog.density_at(p) * ray.dir.length() * fog.transmittance(ray, Interval::new(fog_t.min, t));
+        Some((group, scattering / pdf * light))
+    }
+
+    // Whether the surface hit by `ray` at `t` is one of the sampled lights.
+    fn is_light(&self, ray: &Ray, t: f64, rng: &mut dyn RngCore) -> bool {
+        match self.lights.hit(ray, Interval::FORWARD, rng) {
+            Some(light) => (light.t - t).abs() <= SHADOW_RAY_EPSILON * t.max(1.0),
+            None => false,
+        }
+    }
+}
+
+impl<'a> RayTracer for LightSamplingRayTracer<'a> {
+    fn trace(&self, ray: &Ray, world: &dyn Hittable, background: &dyn Background, rng: &mut dyn RngCore) -> Color {
+        let mut result = Color::ZERO;
+        let sink = &mut |_, _, c| result = result + c;
+        let (depth, path) = (self.max_depth, Path::CAMERA);
+        self.trace_internal(ray, world, background, depth, Bounces::ZERO, path, Color::ONE, false, rng, sink);
+        result
+    }
+
+    fn trace_paths(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        background: &dyn Background,
+        rng: &mut dyn RngCore,
+        sink: &mut dyn FnMut(usize, Path, Color),
+    ) {
+        let (depth, path) = (self.max_depth, Path::CAMERA);
+        self.trace_internal(ray, world, background, depth, Bounces::ZERO, path, Color::ONE, false, rng, sink);
     }
 }
 
@@ -114,7 +643,7 @@ pub struct SingleLightSourceRayTracer {
 
 impl RayTracer for SingleLightSourceRayTracer {
     fn trace(&self, ray: &Ray, world: &dyn Hittable, background: &dyn Background, rng: &mut dyn RngCore) -> Color {
-        match world.hit(ray, 0.001, f64::INFINITY, rng) {
+        match world.hit(ray, Interval::FORWARD, rng) {
             Some(hit) => match hit.material.scatter(ray, &hit, rng) {
                 Some((attenuation, _)) => {
                     let l = (self.light_source - hit.p).unit();
@@ -134,6 +663,134 @@ impl RayTracer for SingleLightSourceRayTracer {
     }
 }
 
+// The film of a render and what else the passes keep track of, kept around so that a render can be refined with
+// more samples later. Lines are stored bottom to top, same as the output of `Renderer::render`.
+pub struct Accumulator {
+    // Weighted by the filter, see `Renderer::with_filter`.
+    film: Film,
+    // Sums of the squared samples, per channel, for the variance.
+    squares: Vec<Vec<Color>>,
+    // Number of samples summed up in every pixel; they differ when a pass is cut short.
+    counts: Vec<Vec<usize>>,
+    // First RNG site not drawn from yet; every pass takes the next ones.
+    sites: usize,
+    // Seconds spent rendering every tile, by `Tile::index`, over the passes rendered by tiles; empty if none was.
+    tile_times: Vec<f64>,
+    tile_passes: usize,
+}
+
+impl Accumulator {
+    pub fn new(image_width: usize, image_height: usize) -> Accumulator {
+        Accumulator {
+            film: Film::new(image_width, image_height),
+            squares: vec![vec![Color::ZERO; image_width]; image_height],
+            counts: vec![vec![0; image_width]; image_height],
+            sites: 0,
+            tile_times: Vec::new(),
+            tile_passes: 0,
+        }
+    }
+
+    pub fn film(&self) -> &Film {
+        &self.film
+    }
+
+    // Adds the sum of `samples` samples of pixel (i, j) with the box filter, and the sum of their squares.
+    fn add(&mut self, i: usize, j: usize, sum: Color, sum_of_squares: Color, samples: usize) {
+        self.film.add(i, j, sum, samples as f64);
+        self.squares[j][i] = self.squares[j][i] + sum_of_squares;
+        self.counts[j][i] += samples;
+    }
+
+    // Samples that every pixel has at least.
+    pub fn samples_per_pixel(&self) -> usize {
+        self.counts.iter().flat_map(|line| line.iter()).copied().min().unwrap_or(0)
+    }
+
+    pub fn counts(&self) -> &[Vec<usize>] {
+        &self.counts
+    }
+
+    // The time every tile took, see `tiles::report`.
+    pub fn tile_times(&self) -> &[f64] {
+        &self.tile_times
+    }
+
+    pub fn clear(&mut self) {
+        self.film.clear();
+        for line in self.squares.iter_mut() {
+            line.iter_mut().for_each(|c| *c = Color::ZERO);
+        }
+        for line in self.counts.iter_mut() {
+            line.iter_mut().for_each(|n| *n = 0);
+        }
+        self.sites = 0;
+        self.tile_times.clear();
+        self.tile_passes = 0;
+    }
+
+    // Average radiance of every pixel, weighted by the filter.
+    pub fn to_linear(&self) -> Vec<Vec<Color>> {
+        self.film.to_linear()
+    }
+
+    // Per-channel variance of the average radiance of every pixel, i.e. of `to_linear`; infinite for pixels with
+    // fewer than two samples. Only the beauty accumulator tracks it, it is zero for light groups. With a filter other
+    // than the box it is an estimate from the samples taken in the pixel.
+    pub fn variance(&self) -> Vec<Vec<Color>> {
+        let mean = self.to_linear();
+        let variance = |mean: &Color, squares: &Color, n: usize| {
+            if n < 2 {
+                return Color::ONE * f64::INFINITY;
+            }
+            let n = n as f64;
+            let sample_variance = (squares / n - mean * mean) * (n / (n - 1.0));
+            Color::new(sample_variance.r().max(0.0), sample_variance.g().max(0.0), sample_variance.b().max(0.0)) / n
+        };
+        (0..mean.len())
+            .map(|j| {
+                (0..mean[j].len()).map(|i| variance(&mean[j][i], &self.squares[j][i], self.counts[j][i])).collect()
+            })
+            .collect()
+    }
+
+    pub fn to_rgb(&self, post: &PostProcess) -> Vec<Vec<RGB>> {
+        self.film.to_rgb(post)
+    }
+
+    // This image with `right` next to it, e.g. for side-by-side stereo.
+    pub fn side_by_side(&self, right: &Accumulator) -> Accumulator {
+        Accumulator {
+            film: self.film.side_by_side(&right.film),
+            squares: side_by_side(&self.squares, &right.squares),
+            counts: side_by_side(&self.counts, &right.counts),
+            sites: self.sites.max(right.sites),
+            // The tiles of the two images don't make a grid of the joined one.
+            tile_times: Vec::new(),
+            tile_passes: 0,
+        }
+    }
+}
+
+// Joins the lines of two images of the same height.
+pub fn side_by_side<T: Clone>(left: &[Vec<T>], right: &[Vec<T>]) -> Vec<Vec<T>> {
+    left.iter().zip(right).map(|(l, r)| l.iter().chain(r).cloned().collect()).collect()
+}
+
+// Rays per pixel the AOVs are rendered with by default, see `Renderer::render_aovs`; they converge much faster than
+// the image itself.
+pub const AOV_SAMPLES: usize = 16;
+
+// Auxiliary output variables: what the camera sees at the first hit, for compositing tools and denoisers.
+#[derive(Copy, Clone)]
+pub struct Aov {
+    pub normal: Vec3,
+    pub depth: f64,
+    pub albedo: Color,
+    // Coverage: the fraction of the pixel showing something other than the background or holdouts.
+    pub alpha: f64,
+}
+
 pub struct Renderer<'a, RT = RecursiveRayTracer, T = rngator::ThreadRngator>
 where
     RT: RayTracer,
@@ -145,6 +802,17 @@ where
     parameters: RenderingParams,
     tracer: RT,
     rng: T,
+    filter: Filter,
+    // Draws the offsets of the camera rays instead of `filter`, see `with_importance_filter`.
+    sampler: Option<FilterSampler>,
+    // Where in the pixel the camera rays go when `sampler` is None, see `with_sampler`.
+    pixel_sampler: Sampler,
+    tile_order: Option<TileOrder>,
+    // Seconds a tile may take per pass before it is split, see `with_tile_splitting`.
+    tile_split: Option<f64>,
+    nice: bool,
+    // Split the light by path instead of by light group, see `with_path_filters`.
+    path_filters: Vec<PathFilter>,
 }
 
 impl<'a, RT: RayTracer, T: rngator::Rngator> Renderer<'a, RT, T> {
@@ -156,7 +824,74 @@ impl<'a, RT: RayTracer, T: rngator::Rngator> Renderer<'a, RT, T> {
         tracer: RT,
         rng: T,
     ) -> Renderer<'a, RT, T> {
-        Renderer { camera, world, background, parameters, tracer, rng }
+        Renderer {
+            camera,
+            world,
+            background,
+            parameters,
+            tracer,
+            rng,
+            filter: Filter::BOX,
+            sampler: None,
+            pixel_sampler: Sampler::Random,
+            tile_order: None,
+            tile_split: None,
+            nice: false,
+            path_filters: Vec::new(),
+        }
+    }
+
+    // Reconstruction filter of the samples; other filters than the box reach into the pixels around the sample's.
+    // Light groups and `render` always use the box.
+    pub fn with_filter(mut self, filter: Filter) -> Renderer<'a, RT, T> {
+        self.filter = filter;
+        self
+    }
+
+    // Applies `filter` by drawing the offsets of the camera rays from its distribution instead of splatting, so
+    // that every sample stays in its own pixel and works with everything that samples pixels, e.g. light groups.
+    // Noisier than splatting at low sample counts.
+    pub fn with_importance_filter(mut self, filter: Filter) -> Renderer<'a, RT, T> {
+        self.filter = Filter::BOX;
+        self.sampler = Some(FilterSampler::new(filter));
+        self
+    }
+
+    // Places the camera rays of each pixel by `sampler` instead of at random. Filters that splat or are importance
+    // sampled draw their own positions.
+    pub fn with_sampler(mut self, sampler: Sampler) -> Renderer<'a, RT, T> {
+        self.pixel_sampler = sampler;
+        self
+    }
+
+    // Renders the passes of `accumulate` and friends by tiles taken in `order` instead of by lines; the image is the
+    // same for every order. Passes with light groups or a splatting filter still go by lines.
+    pub fn with_tile_order(mut self, order: TileOrder) -> Renderer<'a, RT, T> {
+        self.tile_order = Some(order);
+        self
+    }
+
+    // Splits the tiles that took more than `threshold` seconds per pass so far into quarters rendered by different
+    // threads, so that a few expensive tiles, e.g. of a glass ball seen up close, don't keep the other threads
+    // waiting at the end of every pass. Tiles draw from the same RNG sites split or not, so the image doesn't change.
+    pub fn with_tile_splitting(mut self, threshold: f64) -> Renderer<'a, RT, T> {
+        self.tile_split = Some(threshold);
+        self
+    }
+
+    // Yields the rendering threads to other programs every few camera rays, so that a render in the background
+    // doesn't starve the rest of the machine, at the cost of some speed when it is busy.
+    pub fn with_nice(mut self) -> Renderer<'a, RT, T> {
+        self.nice = true;
+        self
+    }
+
+    // Makes the light group accumulators of `accumulate_light_groups` and `accumulate_until` collect the light of the
+    // paths matching `filters` instead, one per filter. Paths can match several filters or none, so the beauty
+    // image is not their sum.
+    pub fn with_path_filters(mut self, filters: Vec<PathFilter>) -> Renderer<'a, RT, T> {
+        self.path_filters = filters;
+        self
     }
 
     pub fn render_line(&self, j: usize, result: &mut [RGB], rng: &mut T::R) {
@@ -185,15 +920,1182 @@ impl<'a, RT: RayTracer, T: rngator::Rngator> Renderer<'a, RT, T> {
             .collect()
     }
 
+    // Adds another `samples_per_pixel` samples to every pixel of the accumulator.
+    pub fn accumulate<Logger>(&self, accumulator: &mut Accumulator, logger: Logger)
+    where
+        Logger: Fn(usize, usize) -> () + Sync,
+    {
+        self.accumulate_pass(accumulator, &mut [], None, logger);
+    }
+
+    // Like `accumulate`, but additionally splits the light by light group into `groups`, or by path if there are path
+    // filters.
+    pub fn accumulate_light_groups<Logger>(&self, beauty: &mut Accumulator, groups: &mut [Accumulator], logger: Logger)
+    where
+        Logger: Fn(usize, usize) -> () + Sync,
+    {
+        self.accumulate_pass(beauty, groups, None, logger);
+    }
+
+    // Adds passes of `samples_per_pixel` samples, split by light group into `groups` if there are any, until
+    // there are `max_samples` per pixel or `deadline` is reached. The last pass then skips the lines (or tiles) it
+    // hasn't started yet, so pixels can end up with different sample counts. `on_pass` is called after each pass,
+    // and stops the passes early by returning false.
+    pub fn accumulate_until<OnPass>(
+        &self,
+        accumulator: &mut Accumulator,
+        groups: &mut [Accumulator],
+        deadline: Instant,
+        max_samples: usize,
+        mut on_pass: OnPass,
+    ) where
+        OnPass: FnMut(&Accumulator) -> bool,
+    {
+        loop {
+            let complete = self.accumulate_pass(accumulator, groups, Some(deadline), |_, _| {});
+            let go_on = on_pass(accumulator);
+            let samples_left = max_samples.saturating_sub(accumulator.samples_per_pixel());
+            if !go_on || !complete || samples_left < self.parameters.samples_per_pixel {
+                return;
+            }
+        }
+    }
+
+    // Adds `samples` samples to the pixels on high-contrast edges of the image accumulated so far, those where
+    // `postprocess::sobel` exceeds `threshold`. Returns the number of pixels sampled.
+    pub fn accumulate_edges<Logger>(
+        &self,
+        accumulator: &mut Accumulator,
+        samples: usize,
+        threshold: f64,
+        logger: Logger,
+    ) -> usize
+    where
+        Logger: Fn(usize, usize) -> () + Sync,
+    {
+        let height = self.parameters.image_height;
+        let edges = postprocess::sobel(&accumulator.to_linear());
+        let first_site = accumulator.sites;
+        accumulator.sites += height;
+        accumulator
+            .film
+            .lines_mut()
+            .par_iter_mut()
+            .zip(accumulator.squares.par_iter_mut())
+            .zip(accumulator.counts.par_iter_mut())
+            .zip(edges.par_iter())
+            .enumerate()
+            .map(|(j, (((line, squares), counts), edges))| {
+                let mut rng = self.rng.rng((first_site + j) as u64);
+                let mut sampled = 0;
+                for i in (0..edges.len()).filter(|&i| edges[i] > threshold) {
+                    let (sum, sum_of_squares) = self.sample_pixel_moments(i, j, samples, &mut rng);
+                    line.add(i, sum, samples as f64);
+                    squares[i] = squares[i] + sum_of_squares;
+                    counts[i] += samples;
+                    sampled += 1;
+                }
+                logger(j, height);
+                sampled
+            })
+            .sum()
+    }
+
+    // Adds `samples_per_pixel` light paths per pixel, traced forward from `lights`, e.g. for a quick look at caustics,
+    // which camera paths only find by chance. The paths bounce through the world for up to `max_depth` bounces,
+    // and every surface they reach that light sampling works on is joined to the lens and splatted into the pixel
+    // it shows up in, as are the lights themselves. What the camera only sees through smooth surfaces, e.g. in a
+    // mirror, stays black, as does the background. The directions leaving the lights follow the Halton points, see
+    // `Sampler`. Always the box filter, and the variance isn't tracked.
+    pub fn accumulate_light_paths<Logger>(
+        &self,
+        accumulator: &mut Accumulator,
+        lights: &dyn Hittable,
+        max_depth: i32,
+        logger: Logger,
+    ) where
+        Logger: Fn(usize, usize) -> () + Sync,
+    {
+        let (width, height) = (self.parameters.image_width, self.parameters.image_height);
+        let spp = self.parameters.samples_per_pixel;
+        // A site per line's worth of paths.
+        let first_site = accumulator.sites;
+        accumulator.sites += height;
+        let film = SplatFilm::new(width, height);
+        (0..height).into_par_iter().for_each(|site| {
+            let mut rng = self.rng.rng((first_site + site) as u64);
+            let mut directions = Sampler::Halton.pixel(&mut rng);
+            for _ in 0..width * spp {
+                let direction = directions.next(&mut rng);
+                self.trace_light_path(lights, max_depth, direction, &film, &mut rng);
+            }
+            logger(site, height);
+        });
+
+        accumulator.film.add_splats(&film);
+        for (line, counts) in accumulator.film.lines_mut().iter_mut().zip(accumulator.counts.iter_mut()) {
+            for (i, n) in counts.iter_mut().enumerate() {
+                line.add(i, Color::ZERO, spp as f64);
+                *n += spp;
+            }
+        }
+    }
+
+    // A path of `accumulate_light_paths` leaving the lights in the direction of `(u1, u2)` from [0, 1)², splatting
+    // into `film`.
+    fn trace_light_path(
+        &self,
+        lights: &dyn Hittable,
+        max_depth: i32,
+        (u1, u2): (f64, f64),
+        film: &SplatFilm,
+        rng: &mut T::R,
+    ) {
+        let (open, close) = (self.parameters.shutter_open, self.parameters.shutter_close);
+        let time = if close > open { rng.gen_range(open..close) } else { open };
+        let (light, pdf) = match lights.sample_surface(rng) {
+            Some((light, pdf)) if pdf > 0.0 => (Hit { time, ..light }, pdf),
+            _ => return,
+        };
+        let emitted = light.material.emit(light.u, light.v, light.p);
+        if emitted == Color::ZERO {
+            return;
+        }
+        let cosine = |to_camera: Vec3| Some(Color::ONE * to_camera.dot(light.normal).abs());
+        self.splat_to_camera(&light, emitted / pdf, cosine, film, rng);
+
+        // Lights shine from both sides: u1 picks the side, then the direction is cosine-distributed around it,
+        // with a density of cos / 2π.
+        let (side, u1) = if u1 < 0.5 { (light.normal, 2.0 * u1) } else { (-light.normal, 2.0 * u1 - 1.0) };
+        let (radius, phi) = (u1.sqrt(), 2.0 * std::f64::consts::PI * u2);
+        let direction = Onb::new(side).local(radius * phi.cos(), radius * phi.sin(), (1.0 - u1).sqrt());
+        let mut throughput = 2.0 * std::f64::consts::PI * emitted / pdf;
+        let mut ray = light.spawn_ray(direction);
+        for _ in 0..max_depth {
+            let h = match self.world.hit(&ray, Interval::FORWARD, rng) {
+                None => return,
+                Some(h) => h,
+            };
+            // The BSDF is taken the other way round, as light sampling does from the camera's side.
+            self.splat_to_camera(&h, throughput, |to_camera| h.material.eval(&ray, &h, &to_camera), film, rng);
+            let (attenuation, scattered) = match h.material.scatter(&ray, &h, rng) {
+                None => return,
+                Some(s) => s,
+            };
+            throughput = throughput * attenuation;
+            ray = scattered;
+        }
+    }
+
+    // Joins `h` to a random point of the lens and adds `throughput` times `bsdf` of the (unit) direction there to
+    // the pixel of `film` it shows up in, unless something is in between.
+    fn splat_to_camera(
+        &self,
+        h: &Hit,
+        throughput: Color,
+        bsdf: impl Fn(Vec3) -> Option<Color>,
+        film: &SplatFilm,
+        rng: &mut T::R,
+    ) {
+        let (width, height) = (self.parameters.image_width, self.parameters.image_height);
+        let (s, t, lens, importance) = match self.camera.project(h.p, h.time, rng) {
+            None => return,
+            Some(projected) => projected,
+        };
+        // As in `camera_ray_at`.
+        let (x, y) = (s * (width as f64 - 1.0), t * (height as f64 - 1.0));
+        if !(0.0..width as f64).contains(&x) || !(0.0..height as f64).contains(&y) {
+            return;
+        }
+        let to_camera = h.spawn_ray(lens - h.p);
+        let f = match bsdf(to_camera.dir.unit()) {
+            Some(f) if f != Color::ZERO => f,
+            _ => return,
+        };
+        if self.world.hit_any(&to_camera, Interval::new(0.0, 1.0 - SHADOW_RAY_EPSILON), rng) {
+            return;
+        }
+        // Pixels average over 1 / ((width - 1)(height - 1)) of (s, t), and there are width × height paths per sample.
+        let scale = ((width - 1) * (height - 1)) as f64 / (width * height) as f64;
+        film.splat(x as usize, y as usize, scale * importance * throughput * f, 0.0);
+    }
+
+    // Samples every line not started before `deadline`; returns false if lines were skipped.
+    fn accumulate_pass<Logger>(
+        &self,
+        beauty: &mut Accumulator,
+        groups: &mut [Accumulator],
+        deadline: Option<Instant>,
+        logger: Logger,
+    ) -> bool
+    where
+        Logger: Fn(usize, usize) -> () + Sync,
+    {
+        let (width, height) = (self.parameters.image_width, self.parameters.image_height);
+        if beauty.film.height() != height {
+            panic!()
+        }
+        let spp = self.parameters.samples_per_pixel;
+        let out_of_time = || deadline.is_some_and(|d| Instant::now() >= d);
+        // Every pass draws from its own RNG sites, one per line or quarter of a tile; the first pass matches `render`.
+        let first_site = beauty.sites;
+        let tiles = match self.tile_order {
+            Some(order) if groups.is_empty() && self.filter.kind == FilterKind::Box => {
+                tiles::tiles(width, height, order)
+            }
+            _ => Vec::new(),
+        };
+        for accumulator in std::iter::once(&mut *beauty).chain(groups.iter_mut()) {
+            accumulator.sites += if tiles.is_empty() { height } else { SUBTILES * tiles.len() };
+        }
+
+        if !tiles.is_empty() {
+            return self.accumulate_tiled_pass(beauty, &tiles, first_site, out_of_time, logger);
+        }
+        if groups.is_empty() && self.filter.kind != FilterKind::Box {
+            return self.accumulate_filtered_pass(beauty, first_site, out_of_time, logger);
+        }
+
+        if groups.is_empty() {
+            let sampled: Vec<bool> = beauty
+                .film
+                .lines_mut()
+                .par_iter_mut()
+                .zip(beauty.squares.par_iter_mut())
+                .zip(beauty.counts.par_iter_mut())
+                .enumerate()
+                .map(|(j, ((line, squares), counts))| {
+                    if out_of_time() {
+                        return false;
+                    }
+                    let mut rng = self.rng.rng((first_site + j) as u64);
+                    for i in 0..width {
+                        let (sum, sum_of_squares) = self.sample_pixel_moments(i, j, spp, &mut rng);
+                        line.add(i, sum, spp as f64);
+                        squares[i] = squares[i] + sum_of_squares;
+                        counts[i] += spp;
+                    }
+                    logger(j, height);
+                    true
+                })
+                .collect();
+            return sampled.iter().all(|&s| s);
+        }
+
+        let lines = (0..height)
+            .into_par_iter()
+            .map(|j| {
+                if out_of_time() {
+                    return None;
+                }
+                let mut rng = self.rng.rng((first_site + j) as u64);
+                // The group sums of all the pixels of the line in one buffer, `groups.len()` after another.
+                let mut pixels = vec![Color::ZERO; width * groups.len()];
+                let moments: Vec<_> = pixels
+                    .chunks_mut(groups.len())
+                    .enumerate()
+                    .map(|(i, pixel)| self.sample_pixel_light_groups(i, j, pixel, &mut rng))
+                    .collect();
+                logger(j, height);
+                Some((pixels, moments))
+            })
+            .collect::<Vec<_>>();
+
+        let mut complete = true;
+        for (j, line) in lines.iter().enumerate() {
+            let line = match line {
+                None => {
+                    complete = false;
+                    continue;
+                }
+                Some(line) => line,
+            };
+            let (pixels, moments) = line;
+            for (i, (pixel, (sum, sum_of_squares))) in pixels.chunks(groups.len()).zip(moments).enumerate() {
+                beauty.add(i, j, *sum, *sum_of_squares, spp);
+                for (g, c) in pixel.iter().enumerate() {
+                    groups[g].add(i, j, *c, Color::ZERO, spp);
+                }
+            }
+        }
+        complete
+    }
+
+    // Pass of `accumulate_pass` by tiles: the threads take the tiles in the given order, and the samples are added
+    // up once all are done, with the time each tile took. With `with_tile_splitting`, tiles that took longer than
+    // the threshold in the passes before are split into their quarters, taken by different threads.
+    fn accumulate_tiled_pass<Logger>(
+        &self,
+        beauty: &mut Accumulator,
+        tiles: &[Tile],
+        first_site: usize,
+        out_of_time: impl Fn() -> bool + Sync,
+        logger: Logger,
+    ) -> bool
+    where
+        Logger: Fn(usize, usize) -> () + Sync,
+    {
+        let spp = self.parameters.samples_per_pixel;
+        // Every job is a tile and the quarters of it to render.
+        let slow = |tile: &Tile| match (self.tile_split, beauty.tile_passes) {
+            (Some(threshold), passes) if passes > 0 => beauty.tile_times[tile.index] / passes as f64 > threshold,
+            _ => false,
+        };
+        let jobs: Vec<(&Tile, Range<usize>)> = tiles
+            .iter()
+            .flat_map(|tile| match slow(tile) {
+                true => (0..SUBTILES).map(|q| (tile, q..q + 1)).collect(),
+                false => vec![(tile, 0..SUBTILES)],
+            })
+            .collect();
+        if jobs.len() > tiles.len() && logging::enabled(Level::Trace) {
+            logging::trace(&format!("Split {} tiles", (jobs.len() - tiles.len()) / (SUBTILES - 1)));
+        }
+        let next = AtomicUsize::new(0);
+        // Sums and sums of squares of the pixels of every job done, quarter by quarter and line by line, and its time.
+        let rendered: Vec<_> = (0..current_num_threads())
+            .into_par_iter()
+            .flat_map_iter(|_| {
+                let mut rendered = Vec::new();
+                loop {
+                    let t = next.fetch_add(1, Ordering::Relaxed);
+                    if t >= jobs.len() || out_of_time() {
+                        return rendered;
+                    }
+                    let (tile, quarters) = &jobs[t];
+                    let start = Instant::now();
+                    let mut pixels: Vec<(Color, Color)> = Vec::new();
+                    for (q, (qi, qj)) in tile.quarters().iter().enumerate().take(quarters.end).skip(quarters.start) {
+                        let mut rng = self.rng.rng((first_site + SUBTILES * tile.index + q) as u64);
+                        for j in qj.clone() {
+                            for i in qi.clone() {
+                                pixels.push(self.sample_pixel_moments(i, j, spp, &mut rng));
+                            }
+                        }
+                    }
+                    logger(t, jobs.len());
+                    rendered.push((t, pixels, start.elapsed().as_secs_f64()));
+                }
+            })
+            .collect();
+
+        beauty.tile_times.resize(tiles.len(), 0.0);
+        beauty.tile_passes += 1;
+        for (t, pixels, seconds) in &rendered {
+            let (tile, quarters) = &jobs[*t];
+            beauty.tile_times[tile.index] += seconds;
+            let mut pixels = pixels.iter();
+            for (qi, qj) in tile.quarters()[quarters.clone()].iter() {
+                for j in qj.clone() {
+                    for i in qi.clone() {
+                        let (sum, sum_of_squares) = pixels.next().unwrap();
+                        beauty.add(i, j, *sum, *sum_of_squares, spp);
+                    }
+                }
+            }
+        }
+        rendered.len() == jobs.len()
+    }
+
+    // Pass of `accumulate_pass` with a filter that reaches into the neighbouring pixels: every line splats its
+    // samples into the pixels around them on a shared film, added up afterwards.
+    fn accumulate_filtered_pass<Logger>(
+        &self,
+        beauty: &mut Accumulator,
+        first_site: usize,
+        out_of_time: impl Fn() -> bool + Sync,
+        logger: Logger,
+    ) -> bool
+    where
+        Logger: Fn(usize, usize) -> () + Sync,
+    {
+        let (width, height) = (self.parameters.image_width, self.parameters.image_height);
+        let spp = self.parameters.samples_per_pixel;
+        let reach = self.filter.reach();
+        let film = SplatFilm::new(width, height);
+        // The squares of the samples of every line sampled.
+        let squares = (0..height)
+            .into_par_iter()
+            .map(|j| {
+                if out_of_time() {
+                    return None;
+                }
+                let mut rng = self.rng.rng((first_site + j) as u64);
+                let mut squares = vec![Color::ZERO; width];
+                for i in 0..width {
+                    for _ in 0..spp {
+                        let (x, y) = (i as f64 + rng.gen_range(0.0..1.0), j as f64 + rng.gen_range(0.0..1.0));
+                        let r = self.camera_ray_at(i, j, x, y, &mut rng);
+                        let sample = self.trace(&r, &mut rng);
+                        squares[i] = squares[i] + sample * sample;
+                        for pj in j.saturating_sub(reach)..(j + reach + 1).min(height) {
+                            for pi in i.saturating_sub(reach)..(i + reach + 1).min(width) {
+                                let w = self.filter.weight(x - (pi as f64 + 0.5), y - (pj as f64 + 0.5));
+                                film.splat(pi, pj, w * sample, w);
+                            }
+                        }
+                    }
+                }
+                logger(j, height);
+                Some(squares)
+            })
+            .collect::<Vec<_>>();
+
+        beauty.film.add_splats(&film);
+        let mut complete = true;
+        for (j, squares) in squares.iter().enumerate() {
+            let squares = match squares {
+                None => {
+                    complete = false;
+                    continue;
+                }
+                Some(squares) => squares,
+            };
+            for i in 0..width {
+                beauty.squares[j][i] = beauty.squares[j][i] + squares[i];
+                beauty.counts[j][i] += spp;
+            }
+        }
+        complete
+    }
+
+    // First-hit data for every pixel, lines bottom to top.
+    // Normals, albedo and alpha are averaged over `samples` rays per pixel, depth is the nearest hit.
+    pub fn render_aovs(&self, samples: usize) -> Vec<Vec<Aov>> {
+        (0..self.parameters.image_height)
+            .into_par_iter()
+            .map(|j| {
+                let mut rng = self.rng.rng(j as u64);
+                (0..self.parameters.image_width).map(|i| self.sample_aov(i, j, samples, &mut rng)).collect()
+            })
+            .collect()
+    }
+
+    fn sample_aov(&self, i: usize, j: usize, samples: usize, rng: &mut T::R) -> Aov {
+        let mut aov = Aov { normal: Vec3::ZERO, depth: f64::INFINITY, albedo: Color::ZERO, alpha: 0.0 };
+        let mut positions = self.pixel_sampler.pixel(rng);
+        for _ in 0..samples {
+            let (r, _) = self.camera_ray(i, j, &mut positions, rng);
+            let hit =
+                visible_hit(self.world, &r, RayKind::Camera, rng).filter(|h| !self.camera.beyond_far(h.p, r.time));
+            if let Some(h) = hit {
+                aov.normal = aov.normal + h.normal;
+                aov.depth = aov.depth.min(h.t * r.dir.length());
+                aov.albedo = aov.albedo
+                    + match h.material.scatter(&r, &h, rng) {
+                        Some((attenuation, _)) => attenuation,
+                        None => h.material.emit(h.u, h.v, h.p),
+                    };
+                if !h.material.holdout() {
+                    aov.alpha += 1.0;
+                }
+            }
+        }
+        let scale = 1.0 / samples.max(1) as f64;
+        Aov { normal: scale * aov.normal, depth: aov.depth, albedo: scale * aov.albedo, alpha: scale * aov.alpha }
+    }
+
+    // Ray through pixel (i, j) at the next of `positions`, at a random time while the shutter is open, and the weight
+    // of its radiance, which is 1 unless the filter is importance sampled.
+    fn camera_ray(&self, i: usize, j: usize, positions: &mut PixelSamples, rng: &mut T::R) -> (Ray, f64) {
+        let (x, y, weight) = match &self.sampler {
+            None => {
+                let (dx, dy) = positions.next(rng);
+                ((i as f64) + dx, (j as f64) + dy, 1.0)
+            }
+            Some(sampler) => {
+                let (dx, dy, weight) = sampler.sample(rng);
+                ((i as f64) + 0.5 + dx, (j as f64) + 0.5 + dy, weight)
+            }
+        };
+        (self.camera_ray_at(i, j, x, y, rng), weight)
+    }
+
+    // Ray through (x, y) in pixels, in pixel (i, j), at a random time while the shutter is open.
+    fn camera_ray_at(&self, i: usize, j: usize, x: f64, y: f64, rng: &mut T::R) -> Ray {
+        if self.nice {
+            yield_periodically();
+        }
+        PIXEL.with(|p| p.set((i, self.parameters.image_height - 1 - j)));
+        let u = x / (self.parameters.image_width as f64 - 1.0);
+        let v = y / (self.parameters.image_height as f64 - 1.0);
+        let (open, close) = (self.parameters.shutter_open, self.parameters.shutter_close);
+        let time = if close > open { rng.gen_range(open..close) } else { open };
+        self.camera.get_ray_at(u, v, time, rng)
+    }
+
+    // Radiance along camera ray `r`.
+    fn trace(&self, r: &Ray, rng: &mut T::R) -> Color {
+        match self.beyond_far(r, rng) {
+            true => self.background.color(r),
+            false => self.tracer.trace(r, self.world, self.background, rng),
+        }
+    }
+
+    // Whether camera ray `r` sees the background because what it hits first is beyond the far clipping plane, see
+    // `Camera::clipped`.
+    fn beyond_far(&self, r: &Ray, rng: &mut T::R) -> bool {
+        self.camera.clips_far()
+            && visible_hit(self.world, r, RayKind::Camera, rng).is_some_and(|h| self.camera.beyond_far(h.p, r.time))
+    }
+
+    // Adds the sums of the samples per light group (or path filter, see `with_path_filters`) to `groups`, and
+    // returns the sum of the samples and the sum of the squared samples.
+    fn sample_pixel_light_groups(&self, i: usize, j: usize, groups: &mut [Color], rng: &mut T::R) -> (Color, Color) {
+        let group_count = groups.len();
+        let (mut sum, mut sum_of_squares) = (Color::ZERO, Color::ZERO);
+        let mut positions = self.pixel_sampler.pixel(rng);
+        for _ in 0..self.parameters.samples_per_pixel {
+            let (r, weight) = self.camera_ray(i, j, &mut positions, rng);
+            let mut sample = Color::ZERO;
+            let sink = &mut |group: usize, path: Path, c: Color| {
+                let c = weight * c;
+                sample = sample + c;
+                match self.path_filters.is_empty() {
+                    true => groups[group.min(group_count - 1)] = groups[group.min(group_count - 1)] + c,
+                    false => {
+                        for (g, filter) in self.path_filters.iter().enumerate() {
+                            if filter.matches(&path) {
+                                groups[g] = groups[g] + c;
+                            }
+                        }
+                    }
+                }
+            };
+            match self.beyond_far(&r, rng) {
+                true => sink(0, Path::CAMERA, self.background.color(&r)),
+                false => self.tracer.trace_paths(&r, self.world, self.background, rng, sink),
+            }
+            sum = sum + sample;
+            sum_of_squares = sum_of_squares + sample * sample;
+        }
+        (sum, sum_of_squares)
+    }
+
     pub fn render_pixel(&self, i: usize, j: usize, rng: &mut T::R) -> RGB {
+        let color = self.sample_pixel(i, j, rng) / self.parameters.samples_per_pixel as f64;
+        self.parameters.post.to_rgb(&color, i, j)
+    }
+
+    // Sum of `samples_per_pixel` radiance samples for the pixel.
+    pub fn sample_pixel(&self, i: usize, j: usize, rng: &mut T::R) -> Color {
+        self.sample_pixel_moments(i, j, self.parameters.samples_per_pixel, rng).0
+    }
+
+    // Sum of `samples` samples for the pixel, and sum of the squared samples.
+    fn sample_pixel_moments(&self, i: usize, j: usize, samples: usize, rng: &mut T::R) -> (Color, Color) {
         let mut pixel_color = Color::ZERO;
-        for _ in 0..self.parameters.samples_per_pixel {
-            let u = ((i as f64) + rng.gen_range(0.0..1.0)) / (self.parameters.image_width as f64 - 1.0);
-            let v = ((j as f64) + rng.gen_range(0.0..1.0)) / (self.parameters.image_height as f64 - 1.0);
-            let r = self.camera.get_ray(u, v, rng);
-            pixel_color = pixel_color + self.tracer.trace(&r, self.world, self.background, rng);
+        let mut sum_of_squares = Color::ZERO;
+        let mut positions = self.pixel_sampler.pixel(rng);
+        for _ in 0..samples {
+            let (r, weight) = self.camera_ray(i, j, &mut positions, rng);
+            let sample = weight * self.trace(&r, rng);
+            pixel_color = pixel_color + sample;
+            sum_of_squares = sum_of_squares + sample * sample;
         }
+        (pixel_color, sum_of_squares)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variance() {
+        let mut accumulator = Accumulator::new(2, 1);
+        for &sample in &[1.0, 3.0, 2.0, 6.0] {
+            let c = Color::new(sample, 0.0, 2.0 * sample);
+            accumulator.add(0, 0, c, c * c, 1);
+        }
+        // Mean 3, sample variance 14 / 3, and the mean of 4 samples has a quarter of that.
+        let variance = accumulator.variance();
+        assert!((variance[0][0].r() - 14.0 / 12.0).abs() < 1e-12);
+        assert_eq!(0.0, variance[0][0].g());
+        assert!((variance[0][0].b() - 4.0 * 14.0 / 12.0).abs() < 1e-12);
+        assert_eq!(f64::INFINITY, variance[0][1].r());
+    }
+
+    #[test]
+    fn test_glass_shadows() {
+        use crate::hittable::HittableList;
+        use crate::materials::{Dielectric, Lambertian};
+        use crate::shapes::Sphere;
+        use rand::SeedableRng;
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        let lights = HittableList::new();
+        let tracer = LightSamplingRayTracer {
+            max_depth: 5,
+            max_bounces: Bounces::UNLIMITED,
+            regularization: None,
+            lights: &lights,
+            check_nan: false,
+            fog: None,
+        };
+        let to_light = Ray::new(Point3::new(0.0, -2.0, 0.0), Vec3::new(0.0, 4.0, 0.0));
+        let shadow = |world: &dyn Hittable, rng: &mut dyn RngCore| {
+            tracer.shadow_transmittance(&to_light, Interval::new(0.0, 1.0), world, rng)
+        };
+
+        let mut world = HittableList::new();
+        assert_eq!(Color::ONE, shadow(&world, &mut rng));
+        let absorption = Color::new(0.0, 0.5, 1.0);
+        world.add(Sphere::new(Point3::ZERO, 1.0, Dielectric::tinted(1.5, absorption)));
+        // Straight through both surfaces, each reflecting 4%, and 2 units of glass.
+        let glass = shadow(&world, &mut rng);
+        for (t, a) in [(glass.r(), 0.0f64), (glass.g(), 0.5), (glass.b(), 1.0)] {
+            assert!((t - 0.96 * 0.96 * (-2.0 * a).exp()).abs() < 1e-6, "{}", glass);
+        }
+        world.add(Sphere::new(Point3::new(0.0, 1.5, 0.0), 0.2, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5))));
+        assert_eq!(Color::ZERO, shadow(&world, &mut rng));
+    }
+
+    #[test]
+    fn test_max_bounces() {
+        use crate::materials::{Lambertian, Metal};
+        use crate::shapes::Sphere;
+        use rand::SeedableRng;
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        let background = SolidBackground::new(Color::new(0.2, 0.4, 0.6));
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let mirror = Sphere::new(Point3::ZERO, 1.0, Metal::new(Color::ONE, 0.0));
+        let matte = Sphere::new(Point3::ZERO, 1.0, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)));
+        let mut trace = |world: &dyn Hittable, max_bounces| {
+            RecursiveRayTracer { max_depth: 10, max_bounces, regularization: None, check_nan: false }.trace(
+                &ray,
+                world,
+                &background,
+                &mut rng,
+            )
+        };
+
+        let no_diffuse = Bounces { diffuse: 0, ..Bounces::UNLIMITED };
+        let no_specular = Bounces { specular: 0, ..Bounces::UNLIMITED };
+        assert_eq!(Color::new(0.2, 0.4, 0.6), trace(&mirror, no_diffuse));
+        assert_eq!(Color::ZERO, trace(&mirror, no_specular));
+        assert_eq!(Color::ZERO, trace(&matte, no_diffuse));
+        assert_eq!(Color::new(0.1, 0.2, 0.3), trace(&matte, no_specular));
+
+        let fog = Isotropic::new(SolidColor::new(0.5, 0.5, 0.5));
+        assert_eq!(Bounces { volume: 1, ..Bounces::ZERO }, Bounces::ZERO.then(&fog));
+    }
+
+    #[test]
+    fn test_regularization() {
+        use crate::materials::{Lambertian, Metal};
+        use rand::SeedableRng;
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        let (mirror, matte) = (Metal::new(Color::ONE, 0.0), Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)));
+        let hit = |material| Hit {
+            p: Point3::ZERO,
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            geometric_normal: Vec3::new(0.0, 1.0, 0.0),
+            shading_offset: Vec3::ZERO,
+            dpdu: Vec3::new(1.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 1.0),
+            t: 1.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: true,
+            material,
+            time: 0.0,
+        };
+        let regularization = Regularization { roughness: 0.2, after: 1 };
+        let scattered = Ray::new(Point3::ZERO, Vec3::new(1.0, 1.0, 0.0));
+        let after_diffuse = Bounces { diffuse: 1, ..Bounces::ZERO };
+
+        // Only specular bounces after enough diffuse ones are blurred.
+        assert_eq!(scattered.dir, regularization.apply(&hit(&mirror), &Bounces::ZERO, scattered, &mut rng).dir);
+        assert_eq!(scattered.dir, regularization.apply(&hit(&matte), &after_diffuse, scattered, &mut rng).dir);
+        for _ in 0..100 {
+            let blurred = regularization.apply(&hit(&mirror), &after_diffuse, scattered, &mut rng).dir;
+            assert_ne!(scattered.dir, blurred);
+            assert!(blurred.unit().dot(scattered.dir.unit()) > 0.97 && blurred.y() > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_holdout() {
+        use crate::camera::Camera;
+        use crate::hittable::HittableList;
+        use crate::materials::{Holdout, Lambertian};
+        use crate::shapes::Sphere;
+        use rand::SeedableRng;
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        let gray = Lambertian::new(SolidColor::new(0.5, 0.5, 0.5));
+        let mut world = HittableList::new();
+        world.add(Sphere::new(Point3::ZERO, 1.0, Holdout(gray)));
+        world.add(Sphere::new(Point3::new(0.0, 0.0, -3.0), 1.0, gray));
+        let background = SolidBackground::new(Color::new(0.2, 0.4, 0.6));
+        let tracer = RecursiveRayTracer {
+            max_depth: 1,
+            max_bounces: Bounces::UNLIMITED,
+            regularization: None,
+            check_nan: false,
+        };
+        // The camera sees the background in the holdout rather than the sphere behind it.
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(Color::new(0.2, 0.4, 0.6), tracer.trace(&ray, &world, &background, &mut rng));
+
+        let camera =
+            Camera::new(Point3::new(0.0, 0.0, 5.0), Point3::ZERO, Vec3::new(0.0, 1.0, 0.0), 60.0, 1.0, 0.0, 5.0);
+        let params = RenderingParams {
+            samples_per_pixel: 1,
+            image_width: 9,
+            image_height: 9,
+            post: PostProcess::new(),
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        };
+        let rngator = rngator::SeedableRngator::new(1);
+        let aovs = Renderer::new_with_rng(&camera, &world, &background, params, tracer, rngator).render_aovs(4);
+        assert_eq!(0.0, aovs[4][4].alpha);
+        assert!(aovs[4][4].depth < 5.0);
+    }
+
+    #[test]
+    fn test_path_filters() {
+        use crate::camera::Camera;
+        use crate::hittable::HittableList;
+        use crate::materials::{Lambertian, Metal};
+        use crate::shapes::Sphere;
+        let mut world = HittableList::new();
+        world.add(Sphere::new(Point3::ZERO, 1.0, Metal::new(Color::new(0.8, 0.8, 0.8), 0.0)));
+        world.add(Sphere::new(Point3::new(0.0, 0.0, -10.0), 4.0, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5))));
+        let background = SolidBackground::new(Color::new(0.2, 0.4, 0.6));
+        let tracer = RecursiveRayTracer {
+            max_depth: 10,
+            max_bounces: Bounces::UNLIMITED,
+            regularization: None,
+            check_nan: false,
+        };
+        let camera =
+            Camera::new(Point3::new(0.0, 0.0, -5.0), Point3::ZERO, Vec3::new(0.0, 1.0, 0.0), 60.0, 1.0, 0.0, 5.0);
+        let params = RenderingParams {
+            samples_per_pixel: 4,
+            image_width: 9,
+            image_height: 9,
+            post: PostProcess::new(),
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        };
+        let filters = ["CL", "CS+L", "CSD.*L", "C.*L"].iter().map(|f| f.parse().unwrap()).collect();
+        let rngator = rngator::SeedableRngator::new(1);
+        let renderer =
+            Renderer::new_with_rng(&camera, &world, &background, params, tracer, rngator).with_path_filters(filters);
+        let mut beauty = Accumulator::new(9, 9);
+        let mut buffers: Vec<_> = (0..4).map(|_| Accumulator::new(9, 9)).collect();
+        renderer.accumulate_light_groups(&mut beauty, &mut buffers, |_, _| ());
+        let beauty = beauty.to_linear();
+        let [direct, mirrored, mirrored_diffuse, all] = [0, 1, 2, 3].map(|b| buffers[b].to_linear());
+
+        // Every path matches the last filter, and the corners see the background straight away.
+        assert_eq!(beauty, all);
+        assert_eq!(beauty[0][0], direct[0][0]);
+        assert_eq!(Color::ZERO, mirrored[0][0]);
+        // The middle of the mirror shows the background and the diffuse sphere behind the camera.
+        assert_eq!(Color::ZERO, direct[4][4]);
+        assert!(mirrored[4][4].length() > 0.0 && mirrored_diffuse[4][4].length() > 0.0);
+        let split = mirrored[4][4] + mirrored_diffuse[4][4] - beauty[4][4];
+        assert!(split.length() < 1e-9);
+    }
+
+    #[test]
+    fn test_visibility_and_clipping() {
+        use crate::camera::Camera;
+        use crate::hittable::HittableList;
+        use crate::materials::{DiffuseLight, Visibility, Visible};
+        use crate::shapes::Sphere;
+        use rand::SeedableRng;
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        let light = |r, g, b| DiffuseLight::new(SolidColor::new(r, g, b));
+        let hidden = Visibility { camera: false, shadow: false, ..Visibility::ALL };
+        assert!(!hidden.sees(RayKind::Shadow) && hidden.sees(RayKind::Specular));
+        let mut world = HittableList::new();
+        world.add(Sphere::new(Point3::ZERO, 1.0, Visible(light(1.0, 0.0, 0.0), hidden)));
+        world.add(Sphere::new(Point3::new(0.0, 0.0, -3.0), 1.0, light(0.0, 1.0, 0.0)));
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!((visible_hit(&world, &ray, RayKind::Camera, &mut rng).unwrap().t - 7.0).abs() < 1e-6);
+        assert!((visible_hit(&world, &ray, RayKind::Diffuse, &mut rng).unwrap().t - 4.0).abs() < 1e-6);
+
+        let background = SolidBackground::new(Color::new(0.2, 0.4, 0.6));
+        let tracer = RecursiveRayTracer {
+            max_depth: 5,
+            max_bounces: Bounces::UNLIMITED,
+            regularization: None,
+            check_nan: false,
+        };
+        assert_eq!(Color::new(0.0, 1.0, 0.0), tracer.trace(&ray, &world, &background, &mut rng));
+        let lights = HittableList::new();
+        let light_sampling = LightSamplingRayTracer {
+            max_depth: 5,
+            max_bounces: Bounces::UNLIMITED,
+            regularization: None,
+            lights: &lights,
+            check_nan: false,
+            fog: None,
+        };
+        let shadow_t = Interval::new(0.0, 5.0);
+        assert_eq!(Color::ONE, light_sampling.shadow_transmittance(&ray, shadow_t, &world, &mut rng));
+
+        // The green sphere is 6 to 8 away from the camera, past the far plane.
+        let params = RenderingParams {
+            samples_per_pixel: 1,
+            image_width: 9,
+            image_height: 9,
+            post: PostProcess::new(),
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        };
+        for (far, color) in [(10.0, Color::new(0.0, 1.0, 0.0)), (5.0, Color::new(0.2, 0.4, 0.6))] {
+            let camera =
+                Camera::new(ray.orig, Point3::ZERO, Vec3::new(0.0, 1.0, 0.0), 30.0, 1.0, 0.0, 5.0).clipped(0.0, far);
+            let rngator = rngator::SeedableRngator::new(1);
+            let renderer = Renderer::new_with_rng(&camera, &world, &background, params, &tracer, rngator);
+            assert_eq!(color, renderer.sample_pixel(4, 4, &mut rand_pcg::Pcg64::seed_from_u64(1)));
+        }
+    }
+
+    #[test]
+    fn test_filtered_pass() {
+        use crate::camera::Camera;
+        use crate::filter::Filter;
+        use crate::materials::DiffuseLight;
+        use crate::textures::SolidColor;
+        let sphere = crate::shapes::Sphere::new(Point3::ZERO, 1.0, DiffuseLight::new(SolidColor::new(1.0, 1.0, 1.0)));
+        let camera =
+            Camera::new(Point3::new(0.0, 0.0, 5.0), Point3::ZERO, Vec3::new(0.0, 1.0, 0.0), 60.0, 1.0, 0.0, 5.0);
+        let params = RenderingParams {
+            samples_per_pixel: 4,
+            image_width: 20,
+            image_height: 20,
+            post: PostProcess::new(),
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        };
+        let tracer = RecursiveRayTracer {
+            max_depth: 5,
+            max_bounces: Bounces::UNLIMITED,
+            regularization: None,
+            check_nan: false,
+        };
+        let background = SolidBackground::new(Color::new(0.5, 0.5, 0.5));
+        let render = |filter: Option<(Filter, bool)>| {
+            let rngator = rngator::SeedableRngator::new(1);
+            let renderer = Renderer::new_with_rng(&camera, &sphere, &background, params, &tracer, rngator);
+            let renderer = match filter {
+                None => renderer,
+                Some((filter, false)) => renderer.with_filter(filter),
+                Some((filter, true)) => renderer.with_importance_filter(filter),
+            };
+            let mut accumulator = Accumulator::new(20, 20);
+            renderer.accumulate(&mut accumulator, |_, _| ());
+            // Every pixel has its own samples.
+            assert!(accumulator.counts().iter().flatten().all(|&n| n == 4));
+            accumulator.to_linear()
+        };
+        // Pixels that mix the outline of the sphere with the background.
+        let blended = |linear: &[Vec<Color>]| linear.iter().flatten().filter(|c| c.r() > 0.55 && c.r() < 0.95).count();
+        let unfiltered = blended(&render(None));
+        for (filter, importance) in
+            [("tent", false), ("gaussian", false), ("mitchell", false), ("tent,1.5", false), ("gaussian", true)]
+        {
+            let filter = filter.parse::<Filter>().unwrap();
+            let linear = render(Some((filter, importance)));
+            // The weighted averages are exact where the image is flat.
+            assert_eq!(0.5, (100.0 * linear[0][0].r()).round() / 100.0, "{:?}", filter);
+            assert_eq!(1.0, (100.0 * linear[10][10].r()).round() / 100.0, "{:?}", filter);
+            assert!(blended(&linear) > unfiltered, "{:?}: {} {}", filter, blended(&linear), unfiltered);
+        }
+    }
+
+    #[test]
+    fn test_light_paths() {
+        use crate::camera::Camera;
+        use crate::hittable::HittableList;
+        use crate::materials::{DiffuseLight, Lambertian};
+        use crate::shapes::XZRect;
+        // A floor lit by a lamp out of view, the same whether traced from the camera or from the lamp.
+        let light = || XZRect::new(-0.5, 0.5, -0.5, 0.5, 2.0, DiffuseLight::new(SolidColor::new(4.0, 4.0, 4.0)));
+        let mut world = HittableList::new();
+        world.add(XZRect::new(-4.0, 4.0, -4.0, 4.0, 0.0, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5))));
+        world.add(light());
+        let mut lights = HittableList::new();
+        lights.add(light());
+        let camera =
+            Camera::new(Point3::new(0.0, 1.5, 4.0), Point3::ZERO, Vec3::new(0.0, 1.0, 0.0), 40.0, 1.5, 0.0, 5.0);
+        let params = RenderingParams {
+            samples_per_pixel: 64,
+            image_width: 24,
+            image_height: 16,
+            post: PostProcess::new(),
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        };
+        let tracer = LightSamplingRayTracer {
+            max_depth: 5,
+            max_bounces: Bounces::UNLIMITED,
+            regularization: None,
+            lights: &lights,
+            check_nan: false,
+            fog: None,
+        };
+        let background = BlackBackground::new();
+        let renderer =
+            Renderer::new_with_rng(&camera, &world, &background, params, &tracer, rngator::SeedableRngator::new(1));
+        let (mut traced, mut light_traced) = (Accumulator::new(24, 16), Accumulator::new(24, 16));
+        renderer.accumulate(&mut traced, |_, _| ());
+        renderer.accumulate_light_paths(&mut light_traced, &lights, 5, |_, _| ());
+        assert!(light_traced.counts().iter().flatten().all(|&n| n == 64));
+        let total = |a: &Accumulator| a.to_linear().iter().flatten().fold(Color::ZERO, |sum, &c| sum + c);
+        let (traced, light_traced) = (total(&traced), total(&light_traced));
+        assert!((light_traced.r() / traced.r() - 1.0).abs() < 0.02, "{:?} {:?}", light_traced, traced);
+    }
+
+    #[test]
+    fn test_light_groups() {
+        use crate::camera::Camera;
+        use crate::hittable::HittableList;
+        use crate::materials::{DiffuseLight, Lambertian};
+        use crate::shapes::{Sphere, XZRect};
+        // A floor under the sky (group 0), lit by a lamp out of view (group 1) and a glowing ball in view (group 2).
+        let lamp =
+            || XZRect::new(-0.5, 0.5, -0.5, 0.5, 2.0, DiffuseLight::with_group(SolidColor::new(4.0, 4.0, 4.0), 1));
+        let ball = || {
+            Sphere::new(Point3::new(1.0, 0.5, 0.0), 0.5, DiffuseLight::with_group(SolidColor::new(0.0, 2.0, 1.0), 2))
+        };
+        let mut world = HittableList::new();
+        world.add(XZRect::new(-4.0, 4.0, -4.0, 4.0, 0.0, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5))));
+        world.add(lamp());
+        world.add(ball());
+        let mut lights = HittableList::new();
+        lights.add(lamp());
+        lights.add(ball());
+        let camera =
+            Camera::new(Point3::new(0.0, 1.5, 4.0), Point3::ZERO, Vec3::new(0.0, 1.0, 0.0), 40.0, 1.5, 0.0, 5.0);
+        let params = RenderingParams {
+            samples_per_pixel: 4,
+            image_width: 24,
+            image_height: 16,
+            post: PostProcess::new(),
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        };
+        let light_sampling = LightSamplingRayTracer {
+            max_depth: 5,
+            max_bounces: Bounces::UNLIMITED,
+            regularization: None,
+            lights: &lights,
+            check_nan: false,
+            fog: None,
+        };
+        let recursive = RecursiveRayTracer {
+            max_depth: 5,
+            max_bounces: Bounces::UNLIMITED,
+            regularization: None,
+            check_nan: false,
+        };
+        let background = SolidBackground::new(Color::new(0.2, 0.4, 0.6));
+        let tracers: [&dyn RayTracer; 2] = [&light_sampling, &recursive];
+        for tracer in tracers {
+            let rngator = rngator::SeedableRngator::new(1);
+            let renderer = Renderer::new_with_rng(&camera, &world, &background, params, tracer, rngator);
+            // Light of groups beyond the buffers goes to the last one.
+            for count in [3, 2] {
+                let mut beauty = Accumulator::new(24, 16);
+                let mut buffers: Vec<_> = (0..count).map(|_| Accumulator::new(24, 16)).collect();
+                renderer.accumulate_light_groups(&mut beauty, &mut buffers, |_, _| ());
+                let groups: Vec<_> = buffers.iter().map(|b| b.to_linear()).collect();
+                for (j, line) in beauty.to_linear().iter().enumerate() {
+                    for (i, pixel) in line.iter().enumerate() {
+                        let sum = groups.iter().fold(Color::ZERO, |sum, group| sum + group[j][i]);
+                        assert!((sum - *pixel).length() < 1e-9, "{} {}: {:?} {:?}", i, j, sum, pixel);
+                    }
+                }
+                let total = |g: &Vec<Vec<Color>>| g.iter().flatten().fold(Color::ZERO, |sum, &c| sum + c).length();
+                assert!(groups.iter().all(|g| total(g) > 0.0));
+            }
+        }
+    }
+
+    #[test]
+    fn test_accumulate_edges() {
+        use crate::camera::Camera;
+        use crate::materials::DiffuseLight;
+        use crate::textures::SolidColor;
+        let sphere = crate::shapes::Sphere::new(Point3::ZERO, 1.0, DiffuseLight::new(SolidColor::new(1.0, 1.0, 1.0)));
+        let camera =
+            Camera::new(Point3::new(0.0, 0.0, 5.0), Point3::ZERO, Vec3::new(0.0, 1.0, 0.0), 60.0, 1.0, 0.0, 5.0);
+        let params = RenderingParams {
+            samples_per_pixel: 1,
+            image_width: 20,
+            image_height: 20,
+            post: PostProcess::new(),
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        };
+        let tracer = RecursiveRayTracer {
+            max_depth: 5,
+            max_bounces: Bounces::UNLIMITED,
+            regularization: None,
+            check_nan: false,
+        };
+        let background = BlackBackground::new();
+        let rngator = rngator::SeedableRngator::new(1);
+        let renderer = Renderer::new_with_rng(&camera, &sphere, &background, params, tracer, rngator);
+        let mut accumulator = Accumulator::new(20, 20);
+        renderer.accumulate(&mut accumulator, |_, _| ());
+        let sampled = renderer.accumulate_edges(&mut accumulator, 7, 0.1, |_, _| ());
+
+        // Only the outline of the sphere gets more samples, not its inside or the background.
+        let counts = accumulator.counts();
+        assert!(sampled > 0 && sampled < 200, "{}", sampled);
+        assert_eq!(sampled, counts.iter().flatten().filter(|&&n| n == 8).count());
+        assert_eq!(1, counts[10][10]);
+        assert_eq!(1, counts[0][0]);
+
+        // Passes over the whole image add to every pixel, so the edges keep their lead.
+        let edges: Vec<(usize, usize)> =
+            (0..20 * 20).map(|k| (k % 20, k / 20)).filter(|&(i, j)| counts[j][i] == 8).collect();
+        renderer.accumulate(&mut accumulator, |_, _| ());
+        let counts = accumulator.counts();
+        assert_eq!(2, accumulator.samples_per_pixel());
+        assert!(edges.iter().all(|&(i, j)| counts[j][i] == 9));
+        assert_eq!(20 * 20 - sampled, counts.iter().flatten().filter(|&&n| n == 2).count());
+
+        // The heatmap shows them white, the rest of the image at 2 of 9 samples. Lines go bottom to top.
+        let path = std::env::temp_dir().join(format!("raytracer_test_heatmap_{}.png", std::process::id()));
+        let path = path.to_str().unwrap();
+        crate::output::save_heatmap(path, counts).unwrap();
+        let heatmap = image::open(path).unwrap().to_rgb8();
+        let (i, j) = edges[0];
+        assert_eq!([255, 255, 255], heatmap.get_pixel(i as u32, 19 - j as u32).0);
+        let (r, g, b) = crate::output::heat(2.0 / 9.0);
+        assert_eq!([r as u8, g as u8, b as u8], heatmap.get_pixel(10, 19 - 10).0);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_accumulate_until() {
+        use crate::camera::Camera;
+        use crate::materials::Lambertian;
+        let sphere = crate::shapes::Sphere::new(Point3::ZERO, 1.0, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)));
+        let camera =
+            Camera::new(Point3::new(0.0, 0.0, 5.0), Point3::ZERO, Vec3::new(0.0, 1.0, 0.0), 60.0, 1.0, 0.0, 5.0);
+        let params = RenderingParams {
+            samples_per_pixel: 4,
+            image_width: 10,
+            image_height: 10,
+            post: PostProcess::new(),
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        };
+        let tracer = RecursiveRayTracer {
+            max_depth: 5,
+            max_bounces: Bounces::UNLIMITED,
+            regularization: None,
+            check_nan: false,
+        };
+        let background = SolidBackground::new(Color::new(0.2, 0.4, 0.6));
+        let rngator = rngator::SeedableRngator::new(1);
+        let renderer = Renderer::new_with_rng(&camera, &sphere, &background, params, tracer, rngator);
+        let later = Instant::now() + std::time::Duration::from_secs(3600);
+        // The noise of the image, as `on_pass` may check it against a target.
+        let noise = |a: &Accumulator| a.variance().iter().flatten().map(|v| v.length()).sum::<f64>();
+
+        // Passes of 4 samples stop short of a cap of 22, which another pass would go over.
+        let mut accumulator = Accumulator::new(10, 10);
+        let mut noises = Vec::new();
+        renderer.accumulate_until(&mut accumulator, &mut [], later, 22, |a| {
+            noises.push(noise(a));
+            true
+        });
+        assert_eq!(20, accumulator.samples_per_pixel());
+        assert_eq!(5, noises.len());
+
+        // They stop at the first pass that reaches the noise target.
+        let target = noises[2];
+        let passes = noises.iter().position(|&n| n <= target).unwrap() + 1;
+        let mut accumulator = Accumulator::new(10, 10);
+        renderer.accumulate_until(&mut accumulator, &mut [], later, 22, |a| noise(a) > target);
+        assert_eq!(4 * passes, accumulator.samples_per_pixel());
+        assert!(accumulator.counts().iter().flatten().all(|&n| n == 4 * passes));
+
+        // Past the deadline the pass samples nothing and is the last.
+        let mut accumulator = Accumulator::new(10, 10);
+        let mut calls = 0;
+        renderer.accumulate_until(&mut accumulator, &mut [], Instant::now(), 22, |_| {
+            calls += 1;
+            true
+        });
+        assert_eq!(1, calls);
+        assert!(accumulator.counts().iter().flatten().all(|&n| n == 0));
+    }
+
+    #[test]
+    fn test_tile_orders() {
+        use crate::camera::Camera;
+        use crate::materials::Lambertian;
+        use crate::tiles::TileOrder;
+        let sphere = crate::shapes::Sphere::new(Point3::ZERO, 1.0, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)));
+        let camera =
+            Camera::new(Point3::new(0.0, 0.0, 5.0), Point3::ZERO, Vec3::new(0.0, 1.0, 0.0), 60.0, 1.0, 0.0, 5.0);
+        let params = RenderingParams {
+            samples_per_pixel: 2,
+            image_width: 40,
+            image_height: 30,
+            post: PostProcess::new(),
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        };
+        let tracer = RecursiveRayTracer {
+            max_depth: 5,
+            max_bounces: Bounces::UNLIMITED,
+            regularization: None,
+            check_nan: false,
+        };
+        let background = GradientBackground::default();
+        let render = |order, split: Option<f64>| {
+            let rngator = rngator::SeedableRngator::new(1);
+            let renderer =
+                Renderer::new_with_rng(&camera, &sphere, &background, params, &tracer, rngator).with_tile_order(order);
+            let renderer = match split {
+                Some(threshold) => renderer.with_tile_splitting(threshold),
+                None => renderer,
+            };
+            let mut accumulator = Accumulator::new(40, 30);
+            renderer.accumulate(&mut accumulator, |_, _| ());
+            renderer.accumulate(&mut accumulator, |_, _| ());
+            assert!(accumulator.counts().iter().flatten().all(|&n| n == 4));
+            // Every tile took some time, whatever the order.
+            assert_eq!(3 * 2, accumulator.tile_times().len());
+            assert!(accumulator.tile_times().iter().all(|&t| t > 0.0));
+            accumulator.to_linear()
+        };
+        // The tiles draw from the same RNG sites whatever the order and the threads.
+        let scanline = render(TileOrder::Scanline, None);
+        assert!(scanline == render(TileOrder::Hilbert, None));
+        assert!(scanline == render(TileOrder::Spiral, None));
+        // And whether they are split or not; the second pass splits every tile.
+        assert!(scanline == render(TileOrder::Spiral, Some(0.0)));
+    }
+
+    // Scatters into a zero direction, as a Lambertian can when the random vector cancels the normal.
+    struct Degenerate {}
+
+    impl crate::materials::Material for Degenerate {
+        fn scatter(&self, ray: &Ray, h: &Hit, _: &mut dyn RngCore) -> Option<(Color, Ray)> {
+            Some((Color::ONE, Ray::at_time(h.p, Vec3::ZERO, ray.time)))
+        }
+    }
 
-        to_rgb(&pixel_color, self.parameters.samples_per_pixel)
+    #[test]
+    #[should_panic(expected = "scatter direction has zero length at pixel 0,0, bounce 0")]
+    fn test_check_nan() {
+        let sphere = crate::shapes::Sphere::new(Point3::ZERO, 1.0, Degenerate {});
+        let tracer =
+            RecursiveRayTracer { max_depth: 5, max_bounces: Bounces::UNLIMITED, regularization: None, check_nan: true };
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        tracer.trace(&ray, &sphere, &BlackBackground::new(), &mut rand::thread_rng());
     }
 }
@@ -5,6 +5,13 @@ pub trait Rngator: Sync {
     fn rng(&self, site_id: u64) -> Self::R;
 }
 
+impl<T: Rngator + ?Sized> Rngator for &T {
+    type R = T::R;
+    fn rng(&self, site_id: u64) -> T::R {
+        (**self).rng(site_id)
+    }
+}
+
 pub struct ThreadRngator {}
 
 impl Rngator for ThreadRngator {
@@ -30,3 +37,43 @@ impl Rngator for SeedableRngator {
         rand_pcg::Pcg64::seed_from_u64(self.seed + site_id)
     }
 }
+
+// Rngs for building worlds: every site, e.g. a cell of a grid, draws from a stream of its own seeded by a hash of
+// its key, so what is built at a site doesn't depend on what was built before it, or on which thread, and sites
+// can be built in parallel.
+#[derive(Copy, Clone, Debug)]
+pub struct SiteRngator {
+    seed: u64,
+}
+
+impl SiteRngator {
+    pub fn new(seed: u64) -> SiteRngator {
+        SiteRngator { seed }
+    }
+
+    // Seeded by `rng`, e.g. the one `World::build` is given, which draws a single number from it.
+    pub fn from_rng(rng: &mut dyn rand::RngCore) -> SiteRngator {
+        SiteRngator::new(rng.next_u64())
+    }
+
+    // For a site keyed by several numbers, e.g. the coordinates of a cell.
+    pub fn site(&self, key: &[u64]) -> rand_pcg::Pcg64 {
+        let hash = key.iter().fold(mix(self.seed), |hash, k| mix(hash ^ mix(*k)));
+        rand_pcg::Pcg64::seed_from_u64(hash)
+    }
+}
+
+impl Rngator for SiteRngator {
+    type R = rand_pcg::Pcg64;
+    fn rng(&self, site_id: u64) -> rand_pcg::Pcg64 {
+        self.site(&[site_id])
+    }
+}
+
+// The finalizer of SplitMix64: nearby inputs give unrelated outputs.
+fn mix(x: u64) -> u64 {
+    let x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
@@ -0,0 +1,96 @@
+// Where in their pixels the camera rays go, see `Renderer::with_sampler`. Random positions clump and leave gaps;
+// the points of the Halton sequence spread evenly over the pixel, so edges and soft shadows converge faster.
+// The same points in every pixel would line up into visible patterns across the image, so every pixel shifts
+// them by a random offset of its own, wrapping around its edges (Cranley–Patterson rotation). Each pass of
+// samples draws new offsets, so the image stays unbiased.
+use rand::Rng;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Sampler {
+    // Independent uniform positions.
+    Random,
+    // Halton points in bases 2 and 3, rotated per pixel.
+    Halton,
+}
+
+// The positions of the samples taken in one pixel in one pass, from [0, 1)².
+pub struct PixelSamples {
+    // The Cranley–Patterson offset, None for random positions.
+    rotation: Option<(f64, f64)>,
+    index: u64,
+}
+
+impl Sampler {
+    // Starts the samples of a pixel, drawing its offset from `rng`.
+    pub fn pixel(&self, rng: &mut dyn rand::RngCore) -> PixelSamples {
+        let rotation = match self {
+            Sampler::Random => None,
+            Sampler::Halton => Some((rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0))),
+        };
+        PixelSamples { rotation, index: 0 }
+    }
+}
+
+impl PixelSamples {
+    pub fn next(&mut self, rng: &mut dyn rand::RngCore) -> (f64, f64) {
+        let (rx, ry) = match self.rotation {
+            None => return (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0)),
+            Some(rotation) => rotation,
+        };
+        let (x, y) = (radical_inverse(2, self.index), radical_inverse(3, self.index));
+        self.index += 1;
+        ((x + rx).fract(), (y + ry).fract())
+    }
+}
+
+// The digits of `index` in `base` mirrored around the point: 1, 2, 3 in base 2 give 0.5, 0.25, 0.75.
+pub fn radical_inverse(base: u64, mut index: u64) -> f64 {
+    let (mut result, mut scale) = (0.0, 1.0 / base as f64);
+    while index > 0 {
+        result += (index % base) as f64 * scale;
+        index /= base;
+        scale /= base as f64;
+    }
+    result
+}
+
+impl std::str::FromStr for Sampler {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Sampler, String> {
+        match s {
+            "random" => Ok(Sampler::Random),
+            "halton" => Ok(Sampler::Halton),
+            _ => Err(format!("unknown sampler '{}'", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_halton() {
+        assert_eq!([0.0, 0.5, 0.25, 0.75], [0, 1, 2, 3].map(|k| radical_inverse(2, k)));
+        assert!((radical_inverse(3, 5) - (2.0 / 3.0 + 1.0 / 9.0)).abs() < 1e-12);
+
+        // Rotated, 16 points still split evenly between any two halves of the pixel across and 9 of them between
+        // any three thirds up, and two pixels get different points.
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        let points = |rng: &mut rand_pcg::Pcg64| {
+            let mut samples = Sampler::Halton.pixel(rng);
+            (0..16).map(|_| samples.next(rng)).collect::<Vec<_>>()
+        };
+        let first = points(&mut rng);
+        for start in [0.0, 0.3, 0.7] {
+            assert_eq!(8, first.iter().filter(|&&(x, _)| (x - start).rem_euclid(1.0) < 0.5).count());
+            assert_eq!(3, first[..9].iter().filter(|&&(_, y)| (y - start).rem_euclid(1.0) < 1.0 / 3.0).count());
+        }
+        assert!(first.iter().all(|&(x, y)| (0.0..1.0).contains(&x) && (0.0..1.0).contains(&y)));
+        assert_ne!(first, points(&mut rng));
+        assert_eq!(Ok(Sampler::Halton), "halton".parse());
+        assert!("sobol".parse::<Sampler>().is_err());
+    }
+}
@@ -0,0 +1,216 @@
+// Many copies of a shape strewn over a surface or through a box, e.g. trees on the ground or the foam of the
+// final scene, described by a few parameters instead of a loop in every world, and spheres packed without
+// overlaps, see `pack_spheres`. Each candidate placement is drawn
+// from the rng of its own site, so placements don't move when others are added or thinned out, and is kept with
+// the probability the density map gives at it, to grow a forest only where the map is bright.
+use crate::bhv::{Bounded, AABB};
+use crate::hittable::Hittable;
+use crate::rngator::SiteRngator;
+use crate::textures::Texture;
+use crate::transforms::{self, Axis};
+use crate::vec::{Point3, Vec3};
+use rand::Rng;
+use std::collections::HashMap;
+
+// Where to place the copies.
+pub enum Region<'a> {
+    // Points sampled on the surface of a shape by `Hittable::sample_surface`, e.g. uniformly by area on
+    // spheres, rectangles and meshes. Shapes that can't be sampled get no copies.
+    Surface(&'a dyn Hittable),
+    // Points uniformly inside the box.
+    Inside(AABB),
+}
+
+pub struct ScatterParams<'a> {
+    // Candidate placements; where the density is 1 all of them are kept.
+    pub count: usize,
+    // The probability of keeping a candidate is the largest component of the texture there, at the (u, v) of
+    // the surface or (0, 0) inside a box. Everywhere if none.
+    pub density: Option<&'a dyn Texture>,
+    // Copies are scaled by a random factor in [min, max].
+    pub scale: (f64, f64),
+    // Copies are turned around y by a random angle.
+    pub turn: bool,
+}
+
+impl<'a> ScatterParams<'a> {
+    pub fn new(count: usize) -> ScatterParams<'a> {
+        ScatterParams { count, density: None, scale: (1.0, 1.0), turn: false }
+    }
+}
+
+// Where a copy goes and how it is scaled and turned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Placement {
+    pub position: Point3,
+    // The surface's normal there, straight up inside a box.
+    pub normal: Vec3,
+    pub scale: f64,
+    // Degrees around y.
+    pub angle: f64,
+}
+
+impl Placement {
+    // `shape`, made around the origin at scale 1 by the caller, turned and moved to the placement.
+    pub fn place<'a, T: Bounded + 'a>(&self, shape: T) -> Box<dyn Bounded + 'a> {
+        let turned: Box<dyn Bounded + 'a> = if self.angle != 0.0 {
+            Box::new(transforms::Rotate::new(Axis::Y, self.angle, shape))
+        } else {
+            Box::new(shape)
+        };
+        Box::new(transforms::Translate::new(self.position, turned))
+    }
+}
+
+// The placements kept of `params.count` candidates in `region`, in the order of their sites.
+pub fn scatter(region: &Region, params: &ScatterParams, sites: &SiteRngator) -> Vec<Placement> {
+    (0..params.count as u64)
+        .filter_map(|k| {
+            let rng = &mut sites.site(&[k]);
+            let (position, normal, (u, v)) = match region {
+                Region::Surface(surface) => {
+                    let (h, _) = surface.sample_surface(rng)?;
+                    (h.p, h.normal, (h.u, h.v))
+                }
+                Region::Inside(bounds) => {
+                    let (min, max) = (bounds.min(), bounds.max());
+                    let position = Point3::new(
+                        rng.gen_range(min.x()..max.x()),
+                        rng.gen_range(min.y()..max.y()),
+                        rng.gen_range(min.z()..max.z()),
+                    );
+                    (position, Vec3::new(0.0, 1.0, 0.0), (0.0, 0.0))
+                }
+            };
+            if let Some(density) = params.density {
+                let keep = density.value(u, v, position);
+                if rng.gen_range(0.0..1.0) >= keep.x().max(keep.y()).max(keep.z()) {
+                    return None;
+                }
+            }
+            let (min, max) = params.scale;
+            let scale = if max > min { rng.gen_range(min..max) } else { min };
+            let angle = if params.turn { rng.gen_range(0.0..360.0) } else { 0.0 };
+            Some(Placement { position, normal, scale, angle })
+        })
+        .collect()
+}
+
+// Places tried for every sphere of `pack_spheres` before it is given up.
+const PACKING_TRIES: u64 = 32;
+
+// Up to `count` spheres with radii in [min, max] and centers inside `bounds`, none overlapping another or the
+// spheres of `obstacles`, e.g. the big spheres of a world. Each sphere tries random places, each from the rng of
+// its own site, until one is free, and is left out if none of `PACKING_TRIES` is, so crowded bounds get fewer.
+// Bounds flat along an axis, e.g. y = r for balls on the ground, put all the centers on that plane.
+pub fn pack_spheres(
+    bounds: &AABB,
+    radius: (f64, f64),
+    count: usize,
+    obstacles: &[(Point3, f64)],
+    sites: &SiteRngator,
+) -> Vec<(Point3, f64)> {
+    let (min, max) = (bounds.min(), bounds.max());
+    // The spheres so far by the cells of a grid twice the largest radius wide, so only the spheres in the 27
+    // cells around a candidate can overlap it.
+    let cell = (2.0 * radius.1).max(1e-9);
+    let key = |p: &Point3| p.e.map(|c| (c / cell).floor() as i64);
+    let mut grid: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+    let mut spheres: Vec<(Point3, f64)> = Vec::new();
+    let free = |spheres: &[(Point3, f64)], grid: &HashMap<[i64; 3], Vec<usize>>, center: &Point3, r: f64| {
+        let [x, y, z] = key(center);
+        let apart = |&(c, rc): &(Point3, f64)| (c - *center).length() >= r + rc;
+        obstacles.iter().all(apart)
+            && (-1..=1).all(|i| {
+                (-1..=1).all(|j| {
+                    (-1..=1)
+                        .all(|k| grid.get(&[x + i, y + j, z + k]).is_none_or(|s| s.iter().all(|&n| apart(&spheres[n]))))
+                })
+            })
+    };
+    for n in 0..count as u64 {
+        for attempt in 0..PACKING_TRIES {
+            let rng = &mut sites.site(&[n, attempt]);
+            let mut center = min;
+            for a in 0..3 {
+                if max.e[a] > min.e[a] {
+                    center.e[a] = rng.gen_range(min.e[a]..max.e[a]);
+                }
+            }
+            let r = if radius.1 > radius.0 { rng.gen_range(radius.0..radius.1) } else { radius.0 };
+            if free(&spheres, &grid, &center, r) {
+                grid.entry(key(&center)).or_default().push(spheres.len());
+                spheres.push((center, r));
+                break;
+            }
+        }
+    }
+    spheres
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Lambertian;
+    use crate::shapes::{Sphere, XZRect};
+    use crate::textures::{Checker, SolidColor};
+
+    #[test]
+    fn test_scatter() {
+        let sites = SiteRngator::new(1);
+        let material = Lambertian::new(SolidColor::new(0.5, 0.5, 0.5));
+        let ground = XZRect::new(-10.0, 10.0, -10.0, 10.0, 0.0, material.clone());
+        let params = ScatterParams { scale: (0.5, 2.0), turn: true, ..ScatterParams::new(1000) };
+        let trees = scatter(&Region::Surface(&ground), &params, &sites);
+        assert_eq!(1000, trees.len());
+        for tree in trees.iter() {
+            assert_eq!(0.0, tree.position.y());
+            assert!(tree.position.x().abs() <= 10.0 && tree.position.z().abs() <= 10.0);
+            assert!((0.5..2.0).contains(&tree.scale) && (0.0..360.0).contains(&tree.angle));
+            assert!((tree.normal.y().abs() - 1.0).abs() < 1e-12);
+        }
+        // The same sites give the same placements.
+        assert_eq!(trees, scatter(&Region::Surface(&ground), &params, &sites));
+
+        // A checkered density map keeps about half, each on a white square.
+        let bounds = AABB::new(Point3::ZERO, Point3::new(1.0, 2.0, 3.0));
+        let inside = |p: &Placement| (0..3).all(|a| (bounds.min().e[a]..=bounds.max().e[a]).contains(&p.position.e[a]));
+        let foam = scatter(&Region::Inside(bounds), &ScatterParams::new(1000), &sites);
+        assert!(foam.iter().all(|p| inside(p) && p.scale == 1.0 && p.angle == 0.0));
+        let checker = Checker::new(SolidColor::new(0.0, 0.0, 0.0), SolidColor::new(1.0, 1.0, 1.0));
+        let params = ScatterParams { density: Some(&checker), ..ScatterParams::new(1000) };
+        let thinned = scatter(&Region::Inside(bounds), &params, &sites);
+        assert!((400..600).contains(&thinned.len()), "{}", thinned.len());
+        assert!(thinned.iter().all(|p| inside(p) && checker.value(0.0, 0.0, p.position).x() == 1.0));
+        // Kept placements are where they were before thinning.
+        assert!(thinned.iter().all(|p| foam.iter().any(|q| q.position == p.position)));
+
+        // Placed copies end up around their placements.
+        let placed = trees[0].place(Sphere::new(Point3::ZERO, trees[0].scale, material));
+        let center = 0.5 * (placed.bounding_box().min() + placed.bounding_box().max());
+        assert!((center - trees[0].position).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_pack_spheres() {
+        let sites = SiteRngator::new(2);
+        // Balls on the ground around a big one.
+        let bounds = AABB::new(Point3::new(-5.0, 0.2, -5.0), Point3::new(5.0, 0.2, 5.0));
+        let big = (Point3::new(0.0, 1.0, 0.0), 1.0);
+        let balls = pack_spheres(&bounds, (0.2, 0.2), 300, &[big], &sites);
+        assert!(balls.len() > 200, "{}", balls.len());
+        for (i, &(c, r)) in balls.iter().enumerate() {
+            assert_eq!((0.2, 0.2), (c.y(), r));
+            assert!(c.x().abs() <= 5.0 && c.z().abs() <= 5.0);
+            assert!((c - big.0).length() >= r + big.1);
+            assert!(balls[..i].iter().all(|&(d, s)| (c - d).length() >= r + s));
+        }
+        assert_eq!(balls, pack_spheres(&bounds, (0.2, 0.2), 300, &[big], &sites));
+
+        // More than fit leaves some out.
+        let cube = AABB::new(Point3::ZERO, Point3::ONE);
+        let packed = pack_spheres(&cube, (0.1, 0.3), 1000, &[], &sites);
+        assert!(!packed.is_empty() && packed.len() < 1000);
+        assert!(packed.iter().all(|&(_, r)| (0.1..0.3).contains(&r)));
+    }
+}
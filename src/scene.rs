@@ -0,0 +1,878 @@
+// Scene files, for driving the renderer from other programs through the C API, the HTTP server or `--scene`: a
+// built-in or registered world, or shapes of their own, with camera settings, one per line, in the syntax of the
+// server's commands (see `server::Server`):
+//
+//   world cornell_box          the world to render, unless the file has shapes
+//   lookfrom x,y,z | lookat x,y,z | up x,y,z
+//   fov deg | aperture a | focus_dist d
+//
+// or instead of the world:
+//
+//   material name options      a material for the shapes after it, with the options of `--material`
+//   sphere x,y,z radius material
+//   rect xy|xz|yz a0,a1,b0,b1 k material
+//                              an axis-aligned rectangle from a0 to a1 and b0 to b1 along the axes it is named
+//                              for, at k along the third
+//   block x0,y0,z0 x1,y1,z1 material
+//   mesh path.obj material [subdivide=n] [lod=path.obj@distance ...]
+//                              the triangles of a Wavefront OBJ file (see `mesh::parse_obj`), smoothed by n steps of
+//                              Loop subdivision (see `mesh::MeshData::subdivide`) and then shaded smoothly; each
+//                              lod is a coarser version for when the camera is at least distance away from the
+//                              middle of the mesh, picked when the world is built. Meshes of the same file share it.
+//   pack count radius[:max_radius] x0,y0,z0 x1,y1,z1 material [seed=n]
+//                              up to count spheres with centers in the box, not overlapping each other or the
+//                              spheres before them, see `scatter::pack_spheres`
+//   background black | color r,g,b | gradient r,g,b r,g,b
+//                              the gradient from the color straight up to the one straight down, black if not given
+//
+// Shapes can be followed by transforms, applied in order: rotate_x=deg, rotate_y=deg, rotate_z=deg (as
+// `transforms::Rotate`), translate=x,y,z and scale=factor. Meshes also take the conventions of their file, to
+// convert from before the transforms: up=y|z, unit=length of a unit of the file, e.g. 0.01 for centimeters, and
+// handedness=right|left. Shapes of light materials are the lights light sampling samples.
+// `--export_scene` writes built-in worlds in this form, see `export`.
+//
+// Empty lines and lines starting with # are skipped. The camera defaults to the world's, or for shapes to looking
+// at the origin from 1 along z. The files of meshes and measured BRDFs are read as `Files` allows.
+use crate::assets;
+use crate::bhv::{self, Bounded, BvhConfig, AABB};
+use crate::camera::CameraParams;
+use crate::hittable::{Hittable, HittableList};
+use crate::math::Mat3;
+use crate::mesh::MeshData;
+use crate::postprocess::PostProcess;
+use crate::raytrace::{
+    Accumulator, Background, BlackBackground, Bounces, GradientBackground, LightSamplingRayTracer, RayTracer,
+    RecursiveRayTracer, Renderer, RenderingParams, SolidBackground, RGB,
+};
+use crate::rngator::{Rngator, SeedableRngator, SiteRngator};
+use crate::scatter;
+use crate::shapes::{Block, Sphere, XYRect, XZRect, YZRect};
+use crate::transforms::{self, Axis};
+use crate::vec::{Color, Point3, Vec3};
+use crate::worlds::{self, MaterialSpec, World, WorldCamera};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+// A parsed scene file, before its world is built.
+#[derive(Clone)]
+pub struct SceneFile {
+    world: Arc<dyn World>,
+    camera: CameraParams,
+}
+
+// Which files a scene file may read, meshes and measured BRDFs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Files {
+    // Any, for the user's own scene files.
+    Any,
+    // Only relative paths inside the directory, which they are relative to, for scene files from others, e.g.
+    // posted to the HTTP server.
+    Under(PathBuf),
+    Nowhere,
+}
+
+impl Files {
+    // The path to read the file a scene file names at `path` from.
+    fn resolve(&self, path: &str) -> Result<String, String> {
+        let inside = || Path::new(path).components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        match self {
+            Files::Any => Ok(path.to_string()),
+            Files::Under(dir) if inside() => Ok(dir.join(path).to_string_lossy().into_owned()),
+            Files::Under(dir) => Err(format!("'{}' isn't a path inside {}", path, dir.display())),
+            Files::Nowhere => Err(format!("can't read '{}', scene files can't read files here", path)),
+        }
+    }
+}
+
+// How a scene is rendered, besides the image size.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SceneSettings {
+    pub samples_per_pixel: usize,
+    // Bounces of a ray at most.
+    pub max_depth: i32,
+    // Sample the world's lights directly, like --light_sampling.
+    pub light_sampling: bool,
+    // The same seed renders the same image.
+    pub seed: u64,
+}
+
+impl SceneSettings {
+    pub fn new() -> SceneSettings {
+        SceneSettings { samples_per_pixel: 100, max_depth: 50, light_sampling: false, seed: 0 }
+    }
+}
+
+// The world of a scene file, built.
+pub struct Scene {
+    world: Arc<dyn World>,
+    shapes: Box<dyn Hittable>,
+    background: Box<dyn Background>,
+    lights: HittableList<'static>,
+    camera: CameraParams,
+}
+
+impl SceneFile {
+    pub fn parse(text: &str) -> Result<SceneFile, String> {
+        SceneFile::parse_reading(text, &Files::Any)
+    }
+
+    // Reads only the files that `files` allows.
+    pub fn parse_reading(text: &str, files: &Files) -> Result<SceneFile, String> {
+        let mut world = None;
+        let mut shapes = SceneWorld { shapes: Vec::new(), background: SceneBackground::Black, eye: Point3::ZERO };
+        let mut materials = HashMap::new();
+        let mut meshes = HashMap::new();
+        let mut settings = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once(char::is_whitespace) {
+                Some((key, value)) => (key, value.trim()),
+                None => return Err(format!("line {}: '{}' has no value", n + 1, line)),
+            };
+            let error = |e: String| format!("line {}: {}", n + 1, e);
+            match key {
+                "world" => {
+                    world = Some(
+                        worlds::worlds()
+                            .into_iter()
+                            .find(|w| w.name() == value)
+                            .ok_or_else(|| format!("line {}: unknown world '{}'", n + 1, value))?,
+                    )
+                }
+                "material" => {
+                    let (name, options) = value.split_once(char::is_whitespace).ok_or_else(|| {
+                        format!("line {}: a material needs a name and options, e.g. 'material red lambertian'", n + 1)
+                    })?;
+                    // The BRDF file of measured materials is resolved before the options are parsed, which reads it.
+                    let options = options
+                        .trim()
+                        .split(',')
+                        .map(|option| match option.trim().split_once('=') {
+                            Some(("file", path)) => files.resolve(path).map(|path| format!("file={}", path)),
+                            _ => Ok(option.to_string()),
+                        })
+                        .collect::<Result<Vec<String>, String>>()
+                        .map_err(error)?
+                        .join(",");
+                    materials.insert(name.to_string(), options.parse::<MaterialSpec>().map_err(error)?);
+                }
+                "sphere" | "rect" | "block" | "mesh" => {
+                    shapes.shapes.push(SceneShape::parse(key, value, &materials, &mut meshes, files).map_err(error)?)
+                }
+                "pack" => {
+                    let spheres = SceneShape::pack(value, &materials, &shapes.shapes).map_err(error)?;
+                    shapes.shapes.extend(spheres)
+                }
+                "background" => shapes.background = value.parse().map_err(error)?,
+                _ => settings.push((n + 1, key, value)),
+            }
+        }
+        let view = match world {
+            Some(_) if !shapes.shapes.is_empty() => return Err("a world and shapes of its own".to_string()),
+            Some(ref world) => world.camera(),
+            None if !shapes.shapes.is_empty() => {
+                WorldCamera { lookfrom: Point3::new(0.0, 0.0, 1.0), lookat: Point3::ZERO, field_of_view: 40.0 }
+            }
+            None => return Err("no world".to_string()),
+        };
+
+        let mut camera = CameraParams {
+            lookfrom: view.lookfrom,
+            lookat: view.lookat,
+            up: Vec3::new(0.0, 1.0, 0.0),
+            field_of_view: view.field_of_view,
+            aspect_ratio: 16.0 / 9.0,
+            aperture: 0.0,
+            focus_dist: (view.lookat - view.lookfrom).length(),
+            shift: 0.0,
+            near: 0.0,
+            far: f64::INFINITY,
+        };
+        for (n, key, value) in settings {
+            let number = || value.parse::<f64>().map_err(|e| format!("line {}: '{}': {}", n, value, e));
+            let vector = || value.parse::<Vec3>().map_err(|e| format!("line {}: {}", n, e));
+            match key {
+                "lookfrom" => camera.lookfrom = vector()?,
+                "lookat" => camera.lookat = vector()?,
+                "up" => camera.up = vector()?,
+                "fov" => camera.field_of_view = number()?,
+                "aperture" => camera.aperture = number()?,
+                "focus_dist" => camera.focus_dist = number()?,
+                _ => return Err(format!("line {}: unknown setting '{}'", n, key)),
+            }
+        }
+        shapes.eye = camera.lookfrom;
+        let world = world.unwrap_or_else(|| Arc::new(shapes));
+        Ok(SceneFile { world, camera })
+    }
+
+    pub fn load(path: &str) -> Result<SceneFile, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+        SceneFile::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn world(&self) -> Arc<dyn World> {
+        self.world.clone()
+    }
+
+    // Where the camera is and what it looks at.
+    pub fn view(&self) -> WorldCamera {
+        let camera = &self.camera;
+        WorldCamera { lookfrom: camera.lookfrom, lookat: camera.lookat, field_of_view: camera.field_of_view }
+    }
+
+    pub fn build(&self) -> Scene {
+        let rngator = SeedableRngator::new(0);
+        Scene {
+            world: self.world.clone(),
+            shapes: self.world.build(BvhConfig::default(), &mut rngator.rng(0)),
+            background: self.world.background(),
+            lights: self.world.lights(BvhConfig::default(), &mut rngator.rng(0)),
+            camera: self.camera,
+        }
+    }
+}
+
+impl Scene {
+    // Renders a width by height image, calling `logger` after every line like `Renderer::accumulate`.
+    pub fn render<Logger>(&self, settings: &SceneSettings, width: usize, height: usize, logger: Logger) -> Vec<Vec<RGB>>
+    where
+        Logger: Fn(usize, usize) -> () + Sync,
+    {
+        let camera = CameraParams { aspect_ratio: width as f64 / height as f64, ..self.camera }.camera();
+        let params = RenderingParams {
+            samples_per_pixel: settings.samples_per_pixel,
+            image_width: width,
+            image_height: height,
+            post: PostProcess { lens: self.world.lens(), ..PostProcess::new() },
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        };
+        let max_depth = settings.max_depth;
+        let recursive =
+            RecursiveRayTracer { max_depth, max_bounces: Bounces::UNLIMITED, regularization: None, check_nan: false };
+        let light_sampling = LightSamplingRayTracer {
+            max_depth,
+            max_bounces: Bounces::UNLIMITED,
+            regularization: None,
+            lights: &self.lights,
+            check_nan: false,
+            fog: None,
+        };
+        let tracer: &dyn RayTracer = match settings.light_sampling && !self.lights.is_empty() {
+            true => &light_sampling,
+            false => &recursive,
+        };
+        let rngator = SeedableRngator::new(settings.seed);
+        let renderer =
+            Renderer::new_with_rng(&camera, self.shapes.as_ref(), self.background.as_ref(), params, tracer, rngator);
+        let mut accumulator = Accumulator::new(width, height);
+        renderer.accumulate(&mut accumulator, logger);
+        accumulator.to_rgb(&params.post)
+    }
+}
+
+// The world of a scene file with shapes of its own.
+struct SceneWorld {
+    shapes: Vec<SceneShape>,
+    background: SceneBackground,
+    // Where the camera of the file is, to pick the detail of meshes by.
+    eye: Point3,
+}
+
+impl World for SceneWorld {
+    fn name(&self) -> &'static str {
+        "scene"
+    }
+
+    fn background(&self) -> Box<dyn Background> {
+        match self.background {
+            SceneBackground::Black => Box::new(BlackBackground::new()),
+            SceneBackground::Color(color) => Box::new(SolidBackground::new(color)),
+            SceneBackground::Gradient(top, bottom) => Box::new(GradientBackground::new(top, bottom)),
+        }
+    }
+
+    // Replaced by the camera settings of the file, see `SceneFile::parse`.
+    fn camera(&self) -> WorldCamera {
+        WorldCamera { lookfrom: Point3::new(0.0, 0.0, 1.0), lookat: Point3::ZERO, field_of_view: 40.0 }
+    }
+
+    fn build(&self, bvh: BvhConfig, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+        let mut world = bhv::SceneBuilder::with_config(bvh);
+        for shape in self.shapes.iter() {
+            world.add(shape.build(&self.eye, bvh, rng));
+        }
+        Box::new(bhv::BHV::new(&mut world, rng))
+    }
+
+    fn lights(&self, bvh: BvhConfig, rng: &mut dyn rand::RngCore) -> HittableList<'static> {
+        let mut lights = HittableList::new();
+        for shape in self.shapes.iter().filter(|s| matches!(s.material, MaterialSpec::Light { .. })) {
+            lights.add(shape.build(&self.eye, bvh, rng));
+        }
+        lights
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum SceneBackground {
+    Black,
+    Color(Color),
+    // Straight up and straight down.
+    Gradient(Color, Color),
+}
+
+impl std::str::FromStr for SceneBackground {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SceneBackground, String> {
+        let colors = s.split_whitespace().skip(1).map(|c| c.parse::<Vec3>()).collect::<Result<Vec<Color>, _>>()?;
+        match (s.split_whitespace().next(), &colors[..]) {
+            (Some("black"), []) => Ok(SceneBackground::Black),
+            (Some("color"), [color]) => Ok(SceneBackground::Color(*color)),
+            (Some("gradient"), [top, bottom]) => Ok(SceneBackground::Gradient(*top, *bottom)),
+            _ => Err(format!("background '{}' is neither black, color r,g,b nor gradient r,g,b r,g,b", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Shape {
+    Sphere(Point3, f64),
+    // The axes of the plane, the bounds along them and the position along the third.
+    Rect(Axis, Axis, [f64; 4], f64),
+    Block(Point3, Point3),
+    // Versions by the distance from the camera they're used from, the first from 0, already subdivided by the
+    // number of steps.
+    Mesh(Vec<(f64, Arc<MeshData>)>, usize),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Transform {
+    Rotate(Axis, f64),
+    Translate(Vec3),
+    Scale(f64),
+}
+
+// A shape line of a scene file.
+#[derive(Clone, Debug, PartialEq)]
+struct SceneShape {
+    shape: Shape,
+    material: MaterialSpec,
+    transforms: Vec<Transform>,
+}
+
+impl SceneShape {
+    // The line after `kind`, with materials by name and the meshes loaded so far by file, subdivision steps and
+    // the axis they were mirrored along, read from `files`.
+    fn parse<'a>(
+        kind: &str,
+        line: &'a str,
+        materials: &HashMap<String, MaterialSpec>,
+        meshes: &mut HashMap<(String, usize, Option<Axis>), Arc<MeshData>>,
+        files: &Files,
+    ) -> Result<SceneShape, String> {
+        let mut words = line.split_whitespace();
+        let mut next = |what: &str| words.next().ok_or_else(|| format!("{} without {}", kind, what));
+        let number = |s: &str| s.parse::<f64>().map_err(|e| format!("'{}': {}", s, e));
+        let positive = |s: &str| match number(s)? {
+            x if x > 0.0 => Ok(x),
+            x => Err(format!("{} isn't positive", x)),
+        };
+        let mut mesh_path = None;
+        let shape = match kind {
+            "sphere" => Shape::Sphere(next("center")?.parse()?, number(next("radius")?)?),
+            "rect" => {
+                let (a0, a1) = match next("plane")? {
+                    "xy" => (Axis::X, Axis::Y),
+                    "xz" => (Axis::X, Axis::Z),
+                    "yz" => (Axis::Y, Axis::Z),
+                    plane => return Err(format!("rect in plane '{}', expected xy, xz or yz", plane)),
+                };
+                let bounds = next("bounds")?.split(',').map(number).collect::<Result<Vec<f64>, String>>()?;
+                let bounds: [f64; 4] = bounds.try_into().map_err(|_| "rect bounds aren't a0,a1,b0,b1".to_string())?;
+                Shape::Rect(a0, a1, bounds, number(next("position")?)?)
+            }
+            "mesh" => {
+                mesh_path = Some(next("path")?);
+                Shape::Mesh(Vec::new(), 0)
+            }
+            _ => Shape::Block(next("corner")?.parse()?, next("corner")?.parse()?),
+        };
+        let name = next("material")?;
+        let material = materials.get(name).ok_or_else(|| format!("unknown material '{}'", name))?.clone();
+        let (mut levels, mut lods, mut left_handed) = (0, Vec::new(), false);
+        // Transforms from the conventions of the file to the scene's, before those of the line.
+        let (mut unit, mut z_up) = (1.0, false);
+        let mut mesh_option = |key: &str, value: &'a str| {
+            match key {
+                "subdivide" => levels = value.parse::<usize>().map_err(|e| format!("'{}': {}", value, e))?,
+                "lod" => {
+                    let (path, distance) =
+                        value.rsplit_once('@').ok_or_else(|| format!("lod '{}' isn't path@distance", value))?;
+                    lods.push((number(distance)?, path));
+                }
+                "up" => {
+                    z_up = match value {
+                        "y" => false,
+                        "z" => true,
+                        _ => return Err(format!("up '{}', expected y or z", value)),
+                    }
+                }
+                "unit" => unit = positive(value)?,
+                "handedness" => {
+                    left_handed = match value {
+                        "right" => false,
+                        "left" => true,
+                        _ => return Err(format!("handedness '{}', expected right or left", value)),
+                    }
+                }
+                _ => return Ok(false),
+            }
+            Ok(true)
+        };
+        let mut transforms = Vec::new();
+        for word in words {
+            let (key, value) = word.split_once('=').ok_or_else(|| format!("'{}' isn't a transform", word))?;
+            if kind == "mesh" && mesh_option(key, value)? {
+                continue;
+            }
+            transforms.push(match key {
+                "rotate_x" => Transform::Rotate(Axis::X, number(value)?),
+                "rotate_y" => Transform::Rotate(Axis::Y, number(value)?),
+                "rotate_z" => Transform::Rotate(Axis::Z, number(value)?),
+                "translate" => Transform::Translate(value.parse()?),
+                "scale" => Transform::Scale(positive(value)?),
+                _ => return Err(format!("unknown transform '{}'", key)),
+            });
+        }
+        // Z up turns to y up by rotating -90 degrees around x, which keeps the handedness.
+        let import =
+            [(unit != 1.0).then_some(Transform::Scale(unit)), z_up.then_some(Transform::Rotate(Axis::X, -90.0))];
+        let transforms = import.iter().flatten().copied().chain(transforms).collect();
+        let shape = match (shape, mesh_path) {
+            (Shape::Mesh(..), Some(path)) => {
+                // Left-handed files are mirrored along the axis that becomes z, so up stays up.
+                let mirror = left_handed.then_some(if z_up { Axis::Y } else { Axis::Z });
+                let mut load = |path: &str| {
+                    let key = (path.to_string(), levels, mirror);
+                    match meshes.get(&key) {
+                        Some(data) => Ok(data.clone()),
+                        None => {
+                            // The file as read is shared with every other scene using it.
+                            let data = assets::mesh(&files.resolve(path)?)?;
+                            let data = match mirror {
+                                Some(axis) => Arc::new(data.mirror(axis).subdivided(levels)),
+                                None if levels > 0 => Arc::new(data.subdivided(levels)),
+                                None => data,
+                            };
+                            meshes.insert(key, data.clone());
+                            Ok::<_, String>(data)
+                        }
+                    }
+                };
+                let mut versions = vec![(0.0, load(path)?)];
+                for (distance, path) in lods {
+                    versions.push((distance, load(path)?));
+                }
+                versions.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+                Shape::Mesh(versions, levels)
+            }
+            (shape, _) => shape,
+        };
+        Ok(SceneShape { shape, material, transforms })
+    }
+
+    // The spheres of a pack line, avoiding the spheres among `before`.
+    fn pack(
+        line: &str,
+        materials: &HashMap<String, MaterialSpec>,
+        before: &[SceneShape],
+    ) -> Result<Vec<SceneShape>, String> {
+        let mut words = line.split_whitespace();
+        let mut next = |what: &str| words.next().ok_or_else(|| format!("pack without {}", what));
+        let count = next("count")?;
+        let count = count.parse::<usize>().map_err(|e| format!("'{}': {}", count, e))?;
+        let number = |s: &str| s.parse::<f64>().map_err(|e| format!("'{}': {}", s, e));
+        let radius = next("radius")?;
+        let radius = match radius.split_once(':') {
+            Some((min, max)) => (number(min)?, number(max)?),
+            None => (number(radius)?, number(radius)?),
+        };
+        let bounds = AABB::new(next("corner")?.parse()?, next("corner")?.parse()?);
+        let name = next("material")?;
+        let material = materials.get(name).ok_or_else(|| format!("unknown material '{}'", name))?;
+        let mut seed = 0;
+        for word in words {
+            match word.split_once('=') {
+                Some(("seed", value)) => seed = value.parse::<u64>().map_err(|e| format!("'{}': {}", value, e))?,
+                _ => return Err(format!("'{}' isn't seed=n", word)),
+            }
+        }
+        let obstacles: Vec<(Point3, f64)> = before
+            .iter()
+            .filter_map(|s| match s.shape {
+                Shape::Sphere(center, radius) => Some((s.place(&center), s.size() * radius)),
+                _ => None,
+            })
+            .collect();
+        let spheres = scatter::pack_spheres(&bounds, radius, count, &obstacles, &SiteRngator::new(seed));
+        Ok(spheres
+            .into_iter()
+            .map(|(center, radius)| SceneShape {
+                shape: Shape::Sphere(center, radius),
+                material: material.clone(),
+                transforms: Vec::new(),
+            })
+            .collect())
+    }
+
+    // Built as seen from `eye`.
+    fn build(&self, eye: &Point3, bvh: BvhConfig, rng: &mut dyn rand::RngCore) -> Box<dyn Bounded> {
+        let material = self.material.build();
+        let mut shape: Box<dyn Bounded> = match self.shape {
+            Shape::Sphere(center, radius) => Box::new(Sphere::new(center, radius, material)),
+            Shape::Rect(Axis::X, Axis::Y, [x0, x1, y0, y1], z) => Box::new(XYRect::new(x0, x1, y0, y1, z, material)),
+            Shape::Rect(Axis::X, _, [x0, x1, z0, z1], y) => Box::new(XZRect::new(x0, x1, z0, z1, y, material)),
+            Shape::Rect(_, _, [y0, y1, z0, z1], x) => Box::new(YZRect::new(y0, y1, z0, z1, x, material)),
+            Shape::Block(p0, p1) => Box::new(Block::new(p0, p1, material)),
+            Shape::Mesh(ref versions, levels) => {
+                let distance = (self.place(&versions[0].1.center()) - *eye).length();
+                let (_, data) = versions.iter().rev().find(|(from, _)| distance >= *from).unwrap_or(&versions[0]);
+                Box::new(data.build(levels > 0, material, bvh, rng))
+            }
+        };
+        for transform in self.transforms.iter() {
+            shape = match *transform {
+                Transform::Rotate(axis, angle) => Box::new(transforms::Rotate::new(axis, angle, shape)),
+                Transform::Translate(offset) => Box::new(transforms::Translate::new(offset, shape)),
+                Transform::Scale(factor) => Box::new(transforms::Scale::new(factor, shape)),
+            };
+        }
+        shape
+    }
+
+    // Where the transforms put `p`.
+    fn place(&self, p: &Point3) -> Point3 {
+        self.transforms.iter().fold(*p, |p, transform| match *transform {
+            Transform::Rotate(axis, angle) => {
+                let mut axis_vector = Vec3::ZERO;
+                axis_vector.e[transforms::index(axis)] = 1.0;
+                Mat3::rotation(axis_vector, angle) * p
+            }
+            Transform::Translate(offset) => p + offset,
+            Transform::Scale(factor) => factor * p,
+        })
+    }
+
+    // How much the transforms scale lengths.
+    fn size(&self) -> f64 {
+        self.transforms.iter().map(|t| if let Transform::Scale(factor) = t { *factor } else { 1.0 }).product()
+    }
+}
+
+// The shapes of a world as lines of a scene file, written by `Hittable::export`: each with its material, listed
+// before them, and the transforms it is inside of.
+pub struct SceneWriter {
+    materials: Vec<MaterialSpec>,
+    shapes: Vec<String>,
+    // Around the shapes being written, the innermost last.
+    transforms: Vec<String>,
+}
+
+impl SceneWriter {
+    fn new() -> SceneWriter {
+        SceneWriter { materials: Vec::new(), shapes: Vec::new(), transforms: Vec::new() }
+    }
+
+    // Adds a shape, given as its kind and numbers, e.g. "sphere 0,0,0 1". Fails without a material, for the
+    // materials scene files can't describe.
+    pub fn shape(&mut self, shape: String, material: Option<MaterialSpec>) -> Result<(), String> {
+        let material = material.ok_or_else(|| format!("can't write the material of '{}' to a scene file", shape))?;
+        let k = match self.materials.iter().position(|m| *m == material) {
+            Some(k) => k,
+            None => {
+                self.materials.push(material);
+                self.materials.len() - 1
+            }
+        };
+        let mut line = format!("{} material_{}", shape, k);
+        for transform in self.transforms.iter().rev() {
+            line += &format!(" {}", transform);
+        }
+        self.shapes.push(line);
+        Ok(())
+    }
+
+    // Writes the shapes `inside` adds with `transform` after their own, e.g. "translate=1,0,0".
+    pub fn transformed<F>(&mut self, transform: String, inside: F) -> Result<(), String>
+    where
+        F: FnOnce(&mut SceneWriter) -> Result<(), String>,
+    {
+        self.transforms.push(transform);
+        let result = inside(self);
+        self.transforms.pop();
+        result
+    }
+}
+
+// "x,y,z", as scene files take points and vectors.
+pub fn vector(v: Vec3) -> String {
+    format!("{},{},{}", v.x(), v.y(), v.z())
+}
+
+// A scene file of `world` as seen by `camera`, with its shapes, materials and background, to start a scene from
+// a built-in world. Fails for worlds of other shapes or materials than scene files have, e.g. textured ones.
+pub fn export(world: &dyn World, camera: &CameraParams, rng: &mut dyn rand::RngCore) -> Result<String, String> {
+    let mut writer = SceneWriter::new();
+    world.build(BvhConfig::default(), rng).export(&mut writer).map_err(|e| format!("world {}: {}", world.name(), e))?;
+    let background = world
+        .background()
+        .export()
+        .ok_or_else(|| format!("world {}: can't write the background to a scene file", world.name()))?;
+
+    let mut text = format!("# The {} world.\n", world.name());
+    text +=
+        &format!("lookfrom {}\nlookat {}\nup {}\n", vector(camera.lookfrom), vector(camera.lookat), vector(camera.up));
+    text += &format!("fov {}\naperture {}\nfocus_dist {}\n", camera.field_of_view, camera.aperture, camera.focus_dist);
+    text += &format!("background {}\n\n", background);
+    for (k, material) in writer.materials.iter().enumerate() {
+        text += &format!("material material_{} {}\n", k, material);
+    }
+    text += "\n";
+    for shape in writer.shapes.iter() {
+        text += &format!("{}\n", shape);
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interval::Interval;
+    use crate::vec::Ray;
+
+    #[test]
+    fn test_scene_file() {
+        let scene = SceneFile::parse("# Looking from the side\nworld simple\n\nlookfrom 0,0,3\nfov 40\n").unwrap();
+        assert_eq!(Vec3::new(0.0, 0.0, 3.0), scene.camera.lookfrom);
+        assert_eq!(40.0, scene.camera.field_of_view);
+        assert_eq!("simple", scene.world.name());
+        assert_eq!(Err("no world".to_string()), SceneFile::parse("fov 40").map(|_| ()));
+        assert_eq!(
+            Err("line 2: unknown setting 'zoom'".to_string()),
+            SceneFile::parse("world simple\nzoom 2").map(|_| ())
+        );
+        assert!(SceneFile::parse("world simple\nlookat 1,2").is_err());
+        assert!(SceneFile::parse("world atlantis").is_err());
+    }
+
+    #[test]
+    fn test_scene_shapes() {
+        let text = "material red lambertian,color=0.8:0.1:0.1\nmaterial lamp light,color=4:4:4\n\
+                    sphere 0,1,0 0.5 red\nrect xz -1,1,-1,1 3 lamp\nblock 0,0,0 1,1,1 red rotate_y=30 translate=2,0,0\n\
+                    background gradient 1,1,1 0,0,0\nlookfrom 0,1,5\n";
+        let scene = SceneFile::parse(text).unwrap();
+        assert_eq!(Vec3::new(0.0, 1.0, 5.0), scene.camera.lookfrom);
+        assert_eq!(Point3::ZERO, scene.camera.lookat);
+        assert_eq!("scene", scene.world.name());
+        let mut rng = SeedableRngator::new(0).rng(0);
+        assert_eq!(1, scene.world.lights(BvhConfig::default(), &mut rng).len());
+        let red = MaterialSpec::Lambertian { color: Color::new(0.8, 0.1, 0.1) };
+        let block = "block 0,0,0 1,1,1 red rotate_y=30 translate=2,0,0";
+        assert_eq!(
+            Ok(SceneShape {
+                shape: Shape::Block(Point3::ZERO, Point3::ONE),
+                material: red.clone(),
+                transforms: vec![Transform::Rotate(Axis::Y, 30.0), Transform::Translate(Vec3::new(2.0, 0.0, 0.0))],
+            }),
+            SceneShape::parse(
+                "block",
+                &block[6..],
+                &vec![("red".to_string(), red)].into_iter().collect(),
+                &mut HashMap::new(),
+                &Files::Any
+            )
+        );
+
+        assert!(SceneFile::parse("sphere 0,0,0 1 red").map(|_| ()).unwrap_err().contains("red"));
+        assert!(SceneFile::parse("material m glass\nrect xw 0,1,0,1 0 m").is_err());
+        assert!(SceneFile::parse("material m glass\nsphere 0,0,0 m").is_err());
+        assert!(SceneFile::parse("background gradient 1,1,1").is_err());
+        assert_eq!(
+            Err("a world and shapes of its own".to_string()),
+            SceneFile::parse("world simple\nmaterial m glass\nsphere 0,0,0 1 m").map(|_| ())
+        );
+    }
+
+    #[test]
+    fn test_scene_pack() {
+        let text = "material red lambertian,color=0.8:0.1:0.1\nsphere 0,0,0 0.5 red translate=0,1,0\n\
+                    pack 200 0.2 -3,0.2,-3 3,0.2,3 red seed=3\npack 10 0.1:0.2 0,0,0 1,1,1 red\n";
+        let scene = SceneFile::parse(text).unwrap();
+        let mut rng = SeedableRngator::new(0).rng(0);
+        let world = scene.world.build(BvhConfig::default(), &mut rng);
+        let center = Ray::new(Point3::new(0.0, 1.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        // The big sphere is kept clear.
+        assert!((world.hit(&center, Interval::FORWARD, &mut rng).unwrap().t - 4.5).abs() < 1e-9);
+        let materials = vec![("red".to_string(), MaterialSpec::Lambertian { color: Color::ONE })].into_iter().collect();
+        let before =
+            [SceneShape::parse("sphere", "0,0,0 1 red translate=0,1,0", &materials, &mut HashMap::new(), &Files::Any)
+                .unwrap()];
+        let balls = SceneShape::pack("200 0.2 -3,0.2,-3 3,0.2,3 red seed=3", &materials, &before).unwrap();
+        assert!(balls.len() > 100);
+        for ball in balls.iter() {
+            match ball.shape {
+                Shape::Sphere(c, r) => {
+                    assert!(r == 0.2 && c.y() == 0.2 && (c - Point3::new(0.0, 1.0, 0.0)).length() >= 1.2)
+                }
+                _ => panic!("not a sphere"),
+            }
+        }
+        assert_ne!(balls, SceneShape::pack("200 0.2 -3,0.2,-3 3,0.2,3 red", &materials, &before).unwrap());
+
+        assert!(SceneFile::parse("material m glass\npack 10 0.1 0,0,0 1,1,1 m size=2").is_err());
+        assert!(SceneFile::parse("material m glass\npack 10 0.1:x 0,0,0 1,1,1 m").is_err());
+        assert!(SceneFile::parse("material m glass\npack 10 0.1 0,0,0 m").is_err());
+    }
+
+    #[test]
+    fn test_scene_mesh() {
+        let obj = |name: &str, size: f64| {
+            let path = std::env::temp_dir().join(format!("scene_mesh_{}_{}.obj", name, std::process::id()));
+            let v = [[1, 1, 1], [1, -1, -1], [-1, 1, -1], [-1, -1, 1]];
+            let mut text = String::new();
+            for p in v.iter() {
+                text += &format!("v {} {} {}\n", size * p[0] as f64, size * p[1] as f64, size * p[2] as f64);
+            }
+            std::fs::write(&path, text + "f 1 2 3\nf 1 3 4\nf 1 4 2\nf 2 4 3\n").unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let (near, far) = (obj("near", 1.0), obj("far", 0.5));
+        let text = format!("material lamp light,color=4:4:4\nmesh {} lamp subdivide=2 translate=0,1,0\n", near);
+        let scene = SceneFile::parse(&text).unwrap();
+        let mut rng = SeedableRngator::new(0).rng(0);
+        assert_eq!(1, scene.world.lights(BvhConfig::default(), &mut rng).len());
+        let materials = vec![("lamp".to_string(), MaterialSpec::Light { color: Color::ONE, temperature: None })];
+        let materials = materials.into_iter().collect();
+        let mut meshes = HashMap::new();
+        let line = format!("{} lamp subdivide=1 lod={}@10 translate=0,0,-20", near, far);
+        let shape = SceneShape::parse("mesh", &line, &materials, &mut meshes, &Files::Any).unwrap();
+        assert!(matches!(&shape.shape, Shape::Mesh(versions, 1)
+            if versions.len() == 2 && versions[0].0 == 0.0 && versions[1].0 == 10.0 && versions[0].1.faces.len() == 16));
+        assert_eq!(vec![Transform::Translate(Vec3::new(0.0, 0.0, -20.0))], shape.transforms);
+
+        // Meshes of the same file and steps are loaded once.
+        let again =
+            SceneShape::parse("mesh", &format!("{} lamp subdivide=1", near), &materials, &mut meshes, &Files::Any)
+                .unwrap();
+        match (&shape.shape, &again.shape) {
+            (Shape::Mesh(a, _), Shape::Mesh(b, _)) => assert!(Arc::ptr_eq(&a[0].1, &b[0].1)),
+            _ => panic!("not meshes"),
+        }
+        assert_eq!(2, meshes.len());
+
+        // Seen from up close, the mesh is the near one, from afar the far one.
+        let size = |eye: Point3, rng: &mut dyn rand::RngCore| {
+            let bounds = shape.build(&eye, BvhConfig::default(), rng).bounding_box();
+            (bounds.max() - bounds.min()).x()
+        };
+        assert!(size(Point3::new(0.0, 0.0, -15.0), &mut rng) > 0.75);
+        assert!(size(Point3::ZERO, &mut rng) < 0.75);
+
+        // A left-handed z-up file in centimeters: the top at z = 100 ends up at y = 1, the corner at x = 100 at
+        // x = 1, and the one at y = 100 in the file at z = 1.
+        let triangle = std::env::temp_dir().join(format!("scene_mesh_z_{}.obj", std::process::id()));
+        std::fs::write(&triangle, "v 0 0 100\nv 100 0 0\nv 0 100 0\nf 1 2 3\n").unwrap();
+        let triangle = triangle.to_str().unwrap();
+        let line = format!("{} lamp up=z unit=0.01 handedness=left translate=0,0,1", triangle);
+        let shape = SceneShape::parse("mesh", &line, &materials, &mut meshes, &Files::Any).unwrap();
+        assert_eq!(
+            vec![
+                Transform::Scale(0.01),
+                Transform::Rotate(Axis::X, -90.0),
+                Transform::Translate(Vec3::new(0.0, 0.0, 1.0))
+            ],
+            shape.transforms
+        );
+        let bounds = shape.build(&Point3::ZERO, BvhConfig::default(), &mut rng).bounding_box();
+        assert!((bounds.max() - Point3::new(1.0, 1.0, 2.0)).length() < 1e-9, "{:?}", bounds.max());
+        assert!((bounds.min() - Point3::new(0.0, 0.0, 1.0)).length() < 1e-9, "{:?}", bounds.min());
+        // Mirrored along y in the file, and the front still faces away from the origin as the corners were reversed.
+        match &shape.shape {
+            Shape::Mesh(versions, _) => {
+                let data = &versions[0].1;
+                assert_eq!(Point3::new(0.0, -100.0, 0.0), data.vertices[2]);
+                let [a, b, c] = data.faces[0].map(|i| data.vertices[i]);
+                assert!((b - a).cross(c - a).dot(a + b + c) > 0.0);
+            }
+            _ => panic!("not a mesh"),
+        }
+        std::fs::remove_file(triangle).unwrap();
+        assert!(SceneFile::parse(&format!("material m glass\nmesh {} m up=x", near)).is_err());
+        assert!(SceneFile::parse(&format!("material m glass\nmesh {} m unit=0", near)).is_err());
+        assert!(SceneFile::parse("material m glass\nsphere 0,0,0 1 m up=z").is_err());
+        assert!(SceneFile::parse(&format!("material m glass\nmesh {} m subdivide=x", near)).is_err());
+        assert!(SceneFile::parse(&format!("material m glass\nmesh {} m lod={}", near, far)).is_err());
+        assert!(SceneFile::parse("material m glass\nsphere 0,0,0 1 m subdivide=2").is_err());
+        assert!(SceneFile::parse("material m glass\nmesh missing.obj m")
+            .map(|_| ())
+            .unwrap_err()
+            .contains("missing.obj"));
+        std::fs::remove_file(near).unwrap();
+        std::fs::remove_file(far).unwrap();
+    }
+
+    #[test]
+    fn test_scene_files() {
+        let dir = std::env::temp_dir().join(format!("scene_files_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("meshes")).unwrap();
+        std::fs::write(dir.join("meshes/triangle.obj"), "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let under = Files::Under(dir.clone());
+        let mesh = |path: &str| format!("material m lambertian\nmesh {} m\n", path);
+        assert!(SceneFile::parse_reading(&mesh("meshes/triangle.obj"), &under).is_ok());
+        assert!(SceneFile::parse_reading(&mesh("./meshes/triangle.obj"), &under).is_ok());
+        // Nothing outside the directory.
+        let absolute = dir.join("meshes/triangle.obj");
+        assert!(SceneFile::parse_reading(&mesh(absolute.to_str().unwrap()), &under).is_err());
+        assert!(SceneFile::parse_reading(&mesh("meshes/../../triangle.obj"), &under).is_err());
+        assert!(SceneFile::parse_reading(&mesh("meshes/triangle.obj"), &Files::Nowhere).is_err());
+        let brdf = "material m measured,file=/etc/passwd\nsphere 0,0,0 1 m\n";
+        let error = SceneFile::parse_reading(brdf, &under).map(|_| ()).unwrap_err();
+        assert!(error.contains("isn't a path inside"), "{}", error);
+        assert_eq!(Ok(dir.join("a.obj").to_string_lossy().into_owned()), under.resolve("a.obj"));
+        assert_eq!(Ok("/a.obj".to_string()), Files::Any.resolve("/a.obj"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_export() {
+        let world = worlds::worlds().into_iter().find(|w| w.name() == "cornell_box").unwrap();
+        let original = SceneFile::parse("world cornell_box").unwrap();
+        let text = export(world.as_ref(), &original.camera, &mut SeedableRngator::new(0).rng(0)).unwrap();
+        let exported = SceneFile::parse(&text).unwrap();
+        let (view, exported_view) = (original.view(), exported.view());
+        assert_eq!(
+            (view.lookfrom, view.lookat, view.field_of_view),
+            (exported_view.lookfrom, exported_view.lookat, exported_view.field_of_view)
+        );
+        let settings = SceneSettings { samples_per_pixel: 2, ..SceneSettings::new() };
+        assert_eq!(
+            original.build().render(&settings, 16, 16, |_, _| ()),
+            exported.build().render(&settings, 16, 16, |_, _| ())
+        );
+
+        // Textures and fog have no lines.
+        let camera = original.camera;
+        for name in ["material_preview", "final_scene"] {
+            let world = worlds::worlds().into_iter().find(|w| w.name() == name).unwrap();
+            assert!(export(world.as_ref(), &camera, &mut SeedableRngator::new(0).rng(0)).is_err());
+        }
+    }
+}
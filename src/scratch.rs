@@ -0,0 +1,82 @@
+// Memory the rays traced on a thread reuse instead of allocating their own, e.g. the stacks of the BVH traversal.
+// Every thread keeps a pool of buffers; taking one pops it from the pool, or makes a new one if the pool is empty,
+// and dropping it clears it and puts it back with its capacity. Once the buffers have grown to what the world
+// needs, tracing allocates nothing. Nested uses, e.g. a BVH in a group in a BVH, each take their own buffer.
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+use std::thread::LocalKey;
+
+// Element types with a pool of buffers on every thread.
+pub trait Pooled: Sized + 'static {
+    fn pool() -> &'static LocalKey<RefCell<Vec<Vec<Self>>>>;
+}
+
+thread_local! {
+    // Traversal stacks of node indices.
+    static INDICES: RefCell<Vec<Vec<usize>>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Pooled for usize {
+    fn pool() -> &'static LocalKey<RefCell<Vec<Vec<usize>>>> {
+        &INDICES
+    }
+}
+
+// An empty buffer from the pool of the current thread, back in the pool when dropped.
+pub struct Scratch<T: Pooled> {
+    items: Vec<T>,
+}
+
+impl<T: Pooled> Scratch<T> {
+    pub fn take() -> Scratch<T> {
+        let items = T::pool().with(|pool| pool.borrow_mut().pop()).unwrap_or_default();
+        Scratch { items }
+    }
+}
+
+impl<T: Pooled> Deref for Scratch<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.items
+    }
+}
+
+impl<T: Pooled> DerefMut for Scratch<T> {
+    fn deref_mut(&mut self) -> &mut Vec<T> {
+        &mut self.items
+    }
+}
+
+impl<T: Pooled> Drop for Scratch<T> {
+    fn drop(&mut self) {
+        let mut items = std::mem::take(&mut self.items);
+        items.clear();
+        // The pool is gone when the thread exits; then the buffer is simply freed.
+        let _ = T::pool().try_with(|pool| pool.borrow_mut().push(items));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse() {
+        let mut stack = Scratch::<usize>::take();
+        stack.extend(0..100);
+        let capacity = stack.capacity();
+        {
+            // Nested buffers are separate.
+            let mut inner = Scratch::<usize>::take();
+            assert!(inner.is_empty());
+            inner.push(7);
+        }
+        assert_eq!(100, stack.len());
+        drop(stack);
+        // The last buffer back is the first out, cleared but with its capacity.
+        let stack = Scratch::<usize>::take();
+        assert!(stack.is_empty());
+        assert_eq!(capacity, stack.capacity());
+    }
+}
@@ -0,0 +1,449 @@
+// Shapes given by signed distance functions: negative inside, positive outside, and never more than the distance
+// to the surface. They are rendered by sphere tracing: the ray steps forward by the distance at its current point,
+// which can't take it past the surface, until it gets close enough to count as a hit.
+use crate::bhv::{Bounded, AABB};
+use crate::hittable::{Hit, Hittable};
+use crate::interval::Interval;
+use crate::materials::Material;
+use crate::shapes;
+use crate::textures::{Perlin, Worley};
+use crate::validate::SceneStats;
+use crate::vec::{Point3, Ray, Vec3};
+use rand::SeedableRng;
+
+pub trait Sdf: Sync {
+    fn distance(&self, p: Point3) -> f64;
+
+    // Box containing everything with a negative distance.
+    fn bounding_box(&self) -> AABB;
+}
+
+pub struct SdfSphere {
+    pub center: Point3,
+    pub radius: f64,
+}
+
+impl Sdf for SdfSphere {
+    fn distance(&self, p: Point3) -> f64 {
+        (p - self.center).length() - self.radius
+    }
+
+    fn bounding_box(&self) -> AABB {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        AABB::new(self.center - r, self.center + r)
+    }
+}
+
+// Box with edges rounded off by `rounding`, which is part of `half_size`.
+pub struct SdfBox {
+    pub center: Point3,
+    pub half_size: Vec3,
+    pub rounding: f64,
+}
+
+impl Sdf for SdfBox {
+    fn distance(&self, p: Point3) -> f64 {
+        let r = Vec3::new(self.rounding, self.rounding, self.rounding);
+        box_distance(abs(p - self.center) - self.half_size + r) - self.rounding
+    }
+
+    fn bounding_box(&self) -> AABB {
+        AABB::new(self.center - self.half_size, self.center + self.half_size)
+    }
+}
+
+// The twelve edges of a box as square bars `thickness` wide, inside the box.
+pub struct SdfBoxFrame {
+    pub center: Point3,
+    pub half_size: Vec3,
+    pub thickness: f64,
+}
+
+impl Sdf for SdfBoxFrame {
+    fn distance(&self, p: Point3) -> f64 {
+        let e = 0.5 * Vec3::new(self.thickness, self.thickness, self.thickness);
+        let p = abs(p - self.center) - self.half_size;
+        // Within `thickness` of the sides, where the bars are, q is negative.
+        let q = abs(p + e) - e;
+        let x = box_distance(Vec3::new(p.x(), q.y(), q.z()));
+        let y = box_distance(Vec3::new(q.x(), p.y(), q.z()));
+        let z = box_distance(Vec3::new(q.x(), q.y(), p.z()));
+        x.min(y).min(z)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        AABB::new(self.center - self.half_size, self.center + self.half_size)
+    }
+}
+
+fn abs(v: Vec3) -> Vec3 {
+    Vec3::new(v.x().abs(), v.y().abs(), v.z().abs())
+}
+
+// Distance to a box centered at the origin, given `q`, the absolute value of the point less the half size.
+fn box_distance(q: Vec3) -> f64 {
+    let outside = Vec3::new(q.x().max(0.0), q.y().max(0.0), q.z().max(0.0)).length();
+    let inside = q.x().max(q.y()).max(q.z()).min(0.0);
+    outside + inside
+}
+
+// Ring around the y axis through `center`, `major` from the center to the middle of the tube, which is `minor` thick.
+pub struct SdfTorus {
+    pub center: Point3,
+    pub major: f64,
+    pub minor: f64,
+}
+
+impl Sdf for SdfTorus {
+    fn distance(&self, p: Point3) -> f64 {
+        let q = p - self.center;
+        let ring = (q.x() * q.x() + q.z() * q.z()).sqrt() - self.major;
+        (ring * ring + q.y() * q.y()).sqrt() - self.minor
+    }
+
+    fn bounding_box(&self) -> AABB {
+        let r = self.major + self.minor;
+        AABB::new(self.center - Vec3::new(r, self.minor, r), self.center + Vec3::new(r, self.minor, r))
+    }
+}
+
+// Line from `a` to `b` with rounded ends, `radius` thick.
+pub struct SdfCapsule {
+    pub a: Point3,
+    pub b: Point3,
+    pub radius: f64,
+}
+
+impl Sdf for SdfCapsule {
+    fn distance(&self, p: Point3) -> f64 {
+        let (pa, ba) = (p - self.a, self.b - self.a);
+        let h = (pa.dot(ba) / ba.length_squared()).clamp(0.0, 1.0);
+        (pa - h * ba).length() - self.radius
+    }
+
+    fn bounding_box(&self) -> AABB {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        AABB::new(self.a - r, self.a + r).surround(&AABB::new(self.b - r, self.b + r))
+    }
+}
+
+// Both shapes, blended together where they are closer than about `k` to each other.
+pub struct SmoothUnion<A: Sdf, B: Sdf> {
+    pub a: A,
+    pub b: B,
+    pub k: f64,
+}
+
+impl<A: Sdf, B: Sdf> Sdf for SmoothUnion<A, B> {
+    fn distance(&self, p: Point3) -> f64 {
+        let (a, b) = (self.a.distance(p), self.b.distance(p));
+        if self.k <= 0.0 {
+            return a.min(b);
+        }
+        // Polynomial smooth minimum, at most k / 4 below the minimum.
+        let h = (0.5 + 0.5 * (b - a) / self.k).clamp(0.0, 1.0);
+        b + (a - b) * h - self.k * h * (1.0 - h)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        let grow = Vec3::new(0.25 * self.k, 0.25 * self.k, 0.25 * self.k);
+        let b = self.a.bounding_box().surround(&self.b.bounding_box());
+        AABB::new(b.minimum - grow, b.maximum + grow)
+    }
+}
+
+// Distance function given as a closure, with the box it is negative in.
+pub struct DistanceFn<F: Fn(Point3) -> f64 + Sync> {
+    pub distance: F,
+    pub bounds: AABB,
+}
+
+impl<F: Fn(Point3) -> f64 + Sync> Sdf for DistanceFn<F> {
+    fn distance(&self, p: Point3) -> f64 {
+        (self.distance)(p)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bounds
+    }
+}
+
+// Noise that an `Isosurface` is the surface of.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IsoNoise {
+    // Smooth Perlin noise, from -1 to 1.
+    Perlin,
+    // Octaves of Perlin noise, from 0 to about 2.
+    Turbulence,
+    // Distance to the nearest of random points about one unit apart, from 0 to about 1: above a threshold it
+    // leaves round holes.
+    Worley,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct IsosurfaceParams {
+    pub noise: IsoNoise,
+    // Frequency of the noise in world space.
+    pub scale: f64,
+    // Inside is where the noise is above this.
+    pub threshold: f64,
+    // Octaves of turbulence.
+    pub depth: i32,
+    // How much the noise is lowered towards the sides of the box: by nothing at its center and by `falloff` on the
+    // ellipsoid touching its sides, so that the shape closes off inside the box, e.g. for a rock.
+    pub falloff: f64,
+    // Seed of the noise tables; if None, they are drawn from the RNG the world is built with.
+    pub seed: Option<u64>,
+}
+
+impl IsosurfaceParams {
+    pub fn new(noise: IsoNoise, threshold: f64) -> IsosurfaceParams {
+        IsosurfaceParams { noise, scale: 1.0, threshold, depth: 4, falloff: 0.0, seed: None }
+    }
+}
+
+// Gradients or points in the noise tables; the noise repeats after this many units.
+const NOISE_TABLE_SIZE: usize = 256;
+
+// Perlin noise changes by at most this much per unit.
+const PERLIN_SLOPE: f64 = 2.5;
+
+enum NoiseField {
+    Perlin(Perlin),
+    Worley(Worley),
+}
+
+// Surface where noise crosses a threshold, inside a box. Noise isn't a distance, but it changes no faster than a
+// known slope, so divided by that it never overestimates the distance to the surface, and sphere tracing works.
+pub struct Isosurface {
+    field: NoiseField,
+    params: IsosurfaceParams,
+    bounds: AABB,
+    // Steepest slope of the noise and the falloff in the box, in world space.
+    slope: f64,
+}
+
+impl Isosurface {
+    pub fn new(params: &IsosurfaceParams, bounds: AABB, rng: &mut dyn rand::RngCore) -> Isosurface {
+        let mut seeded = params.seed.map(rand_pcg::Pcg64::seed_from_u64);
+        let rng: &mut dyn rand::RngCore = match seeded.as_mut() {
+            Some(seeded) => seeded,
+            None => rng,
+        };
+        let (field, noise_slope) = match params.noise {
+            IsoNoise::Perlin => (NoiseField::Perlin(Perlin::new(NOISE_TABLE_SIZE, 1, rng)), PERLIN_SLOPE),
+            IsoNoise::Turbulence => (
+                NoiseField::Perlin(Perlin::new(NOISE_TABLE_SIZE, params.depth, rng)),
+                params.depth.max(1) as f64 * PERLIN_SLOPE,
+            ),
+            IsoNoise::Worley => (NoiseField::Worley(Worley::new(NOISE_TABLE_SIZE, rng)), 1.0),
+        };
+        let half = 0.5 * (bounds.max() - bounds.min());
+        let falloff_slope = 2.0 * params.falloff.abs() * (0..3).map(|a| half.e[a].powi(-2)).sum::<f64>().sqrt();
+        let slope = params.scale * noise_slope + falloff_slope;
+        Isosurface { field, params: *params, bounds, slope }
+    }
+
+    fn noise(&self, p: Point3) -> f64 {
+        let p = self.params.scale * p;
+        match (&self.field, self.params.noise) {
+            (NoiseField::Perlin(perlin), IsoNoise::Turbulence) => perlin.turbulence(&p),
+            (NoiseField::Perlin(perlin), _) => perlin.noise(&p),
+            (NoiseField::Worley(worley), _) => worley.noise(&p),
+        }
+    }
+}
+
+impl Sdf for Isosurface {
+    fn distance(&self, p: Point3) -> f64 {
+        let center = 0.5 * (self.bounds.min() + self.bounds.max());
+        let half = 0.5 * (self.bounds.max() - self.bounds.min());
+        let q = p - center;
+        let ellipsoid = (0..3).map(|a| (q.e[a] / half.e[a]).powi(2)).sum::<f64>();
+        let value = self.noise(p) - self.params.falloff * ellipsoid;
+        let inside_box = SdfBox { center, half_size: half, rounding: 0.0 }.distance(p);
+        ((self.params.threshold - value) / self.slope).max(inside_box)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bounds
+    }
+}
+
+// Rays give up after this many steps, e.g. when they graze the surface.
+const MAX_STEPS: i32 = 256;
+
+// Points closer to the surface than this fraction of the diagonal of the bounding box count as hits.
+const RELATIVE_EPSILON: f64 = 1e-5;
+
+pub struct SdfShape<S: Sdf, T: Material> {
+    sdf: S,
+    material: T,
+    bounds: AABB,
+    epsilon: f64,
+}
+
+impl<S: Sdf, T: Material> SdfShape<S, T> {
+    pub fn new(sdf: S, material: T) -> SdfShape<S, T> {
+        let bounds = sdf.bounding_box();
+        let epsilon = RELATIVE_EPSILON * (bounds.max() - bounds.min()).length();
+        // Rays entering the box must start away from the surface, see `march`, which is found to within epsilon.
+        let grow = Vec3::new(2.0 * epsilon, 2.0 * epsilon, 2.0 * epsilon);
+        SdfShape { sdf, material, bounds: AABB::new(bounds.min() - grow, bounds.max() + grow).pad(), epsilon }
+    }
+
+    // Distance along the ray of the first hit in `ray_t`.
+    fn march(&self, r: &Ray, ray_t: Interval) -> Option<f64> {
+        let range = self.bounds.clip(r, ray_t);
+        if range.is_empty() {
+            return None;
+        }
+        let length = r.dir.length();
+        let mut t = range.min;
+        // Rays leaving the surface, e.g. after a reflection, start within epsilon of it; they only hit once they
+        // got away from it. Rays from outside start on the box, which is grown to be far enough from the surface.
+        let mut away = false;
+        for _ in 0..MAX_STEPS {
+            if t > range.max {
+                return None;
+            }
+            let d = self.sdf.distance(r.at(t)).abs();
+            if d < self.epsilon {
+                if away {
+                    return Some(t);
+                }
+            } else {
+                away = true;
+            }
+            t += d.max(self.epsilon) / length;
+        }
+        None
+    }
+
+    // Gradient of the distance from four samples around `p`, at the corners of a tetrahedron.
+    fn normal(&self, p: Point3) -> Vec3 {
+        let h = self.epsilon;
+        [Vec3::new(1.0, -1.0, -1.0), Vec3::new(-1.0, -1.0, 1.0), Vec3::new(-1.0, 1.0, -1.0), Vec3::new(1.0, 1.0, 1.0)]
+            .iter()
+            .fold(Vec3::ZERO, |n, k| n + self.sdf.distance(p + h * *k) * *k)
+    }
+}
+
+impl<S: Sdf, T: Material> Hittable for SdfShape<S, T> {
+    fn hit(&self, r: &Ray, ray_t: Interval, _: &mut dyn rand::RngCore) -> Option<Hit<'_>> {
+        let t = self.march(r, ray_t)?;
+        let p = r.at(t);
+        let normal = self.normal(p);
+        let normal = if normal.length_squared() > 0.0 { normal.unit() } else { -r.dir.unit() };
+        let (u, v) = shapes::sphere_uv(&normal);
+        Some(Hit::new_with_face_normal(&p, t, u, v, &normal, r, &self.material))
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, _: &mut dyn rand::RngCore) -> bool {
+        self.march(r, ray_t).is_some()
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.check_point("SDF box corner", &self.bounds.min());
+        stats.check_point("SDF box corner", &self.bounds.max());
+        stats.object(std::mem::size_of_val(self), Some(self.bounds));
+        self.material.inspect(stats);
+    }
+}
+
+impl<S: Sdf, T: Material> Bounded for SdfShape<S, T> {
+    fn bounding_box(&self) -> AABB {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Lambertian;
+    use crate::textures::SolidColor;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_sdf_hit() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        let material = || Lambertian::new(SolidColor::new(0.5, 0.5, 0.5));
+        let sphere = SdfShape::new(SdfSphere { center: Point3::ZERO, radius: 1.0 }, material());
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -2.0));
+        let h = sphere.hit(&r, Interval::FORWARD, &mut rng).unwrap();
+        assert!((h.t - 2.0).abs() < 1e-4, "{}", h.t);
+        assert!((h.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-4, "{}", h.normal);
+        assert!(h.front_face);
+        assert!(sphere.hit(&r, Interval::new(0.0, 1.9), &mut rng).is_none());
+        assert!(!sphere.hit_any(&Ray::new(Point3::new(1.1, 0.0, 5.0), r.dir), Interval::FORWARD, &mut rng));
+
+        // A ray leaving the surface from the inside goes on to the other side.
+        let inside = Ray::new(h.p, r.dir);
+        let h = sphere.hit(&inside, Interval::FORWARD, &mut rng).unwrap();
+        assert!((h.p.z() + 1.0).abs() < 1e-4, "{}", h.p);
+        assert!(!h.front_face);
+
+        // The box of a box is the box itself, but rays still stop at the face they enter through.
+        let cube = SdfBox { center: Point3::ZERO, half_size: Vec3::new(1.0, 1.0, 1.0), rounding: 0.2 };
+        let h = SdfShape::new(cube, material()).hit(&r, Interval::FORWARD, &mut rng).map(|h| (h.t, h.front_face));
+        assert!(matches!(h, Some((t, true)) if (t - 2.0).abs() < 1e-4), "{:?}", h);
+
+        // Two spheres smoothly joined fill the gap between them.
+        let blob = SmoothUnion {
+            a: SdfSphere { center: Point3::new(-1.2, 0.0, 0.0), radius: 1.0 },
+            b: SdfSphere { center: Point3::new(1.2, 0.0, 0.0), radius: 1.0 },
+            k: 1.0,
+        };
+        assert!(blob.distance(Point3::ZERO) < 0.0);
+        let blob = SdfShape::new(blob, material());
+        assert!(blob.hit_any(&r, Interval::FORWARD, &mut rng));
+        let apart = SmoothUnion {
+            a: SdfSphere { center: Point3::new(-1.2, 0.0, 0.0), radius: 1.0 },
+            b: SdfSphere { center: Point3::new(1.2, 0.0, 0.0), radius: 1.0 },
+            k: 0.0,
+        };
+        assert!(!SdfShape::new(apart, material()).hit_any(&r, Interval::FORWARD, &mut rng));
+
+        let plane = DistanceFn {
+            distance: |p: Point3| p.y(),
+            bounds: AABB::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 0.0, 1.0)),
+        };
+        let down = Ray::new(Point3::new(0.5, 3.0, 0.5), Vec3::new(0.0, -1.0, 0.0));
+        let plane = SdfShape::new(plane, material());
+        let h = plane.hit(&down, Interval::FORWARD, &mut rng).unwrap();
+        assert!((h.t - 3.0).abs() < 1e-4, "{}", h.t);
+    }
+
+    #[test]
+    fn test_isosurface() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        let bounds = AABB::new(Point3::new(-2.0, -1.0, -2.0), Point3::new(2.0, 1.0, 2.0));
+        for noise in [IsoNoise::Perlin, IsoNoise::Turbulence, IsoNoise::Worley] {
+            let params = IsosurfaceParams { scale: 2.0, falloff: 0.5, ..IsosurfaceParams::new(noise, 0.3) };
+            let iso = Isosurface::new(&params, bounds, &mut rng);
+            // Every point outside is at least its distance away from the inside.
+            for _ in 0..1000 {
+                let p = Vec3::random(-2.0, 2.0, &mut rng);
+                let q = p + 0.1 * Vec3::random_unit_vector(&mut rng);
+                let (d, e) = (iso.distance(p), iso.distance(q));
+                assert!(d <= 0.1 || e > 0.0, "{:?} at {}: {} but {} at {}", noise, p, d, e, q);
+            }
+            let shape = SdfShape::new(iso, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)));
+            let mut hits = 0;
+            for _ in 0..100 {
+                let (x, z) = (rng.gen_range(-2.0..2.0), rng.gen_range(-2.0..2.0));
+                let r = Ray::new(Point3::new(x, 5.0, z), Vec3::new(0.0, -1.0, 0.0));
+                if let Some(h) = shape.hit(&r, Interval::FORWARD, &mut rng) {
+                    hits += 1;
+                    assert!(shape.sdf.distance(h.p).abs() < 1e-3, "{:?} at {}", noise, h.p);
+                    // Nothing inside was skipped on the way.
+                    for i in 0..100 {
+                        let t = 4.0 + (h.t - 4.0) * i as f64 / 100.0;
+                        assert!(shape.sdf.distance(r.at(t)) > -1e-3, "{:?} at {}", noise, r.at(t));
+                    }
+                }
+            }
+            assert!(hits > 0, "{:?}", noise);
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use crate::camera::{Camera, CameraParams};
+use crate::hittable::Hittable;
+use crate::output;
+use crate::postprocess::DepthOfField;
+use crate::raytrace::{Accumulator, Background, RayTracer, Renderer, RenderingParams, AOV_SAMPLES};
+use crate::rngator::Rngator;
+use crate::vec::Vec3;
+use std::io::{BufRead, Write};
+
+// Keeps the world and an accumulation buffer in memory and renders on request.
+// Commands are read one per line; every command is answered with a single line starting with "ok" or "error":
+//
+//   lookfrom x,y,z | lookat x,y,z | up x,y,z   move the camera (clears the accumulated samples)
+//   fov deg | aperture a | focus_dist d        change the lens (clears the accumulated samples, except for
+//                                              aperture and focus_dist in preview mode)
+//   autofocus x,y                              set focus_dist to what pixel x,y sees and report it
+//   preview on|off                             depth of field preview: render through a pinhole and blur by depth
+//                                              when saving, see `DepthOfField`
+//   samples [n]                                add n samples per pixel (default: --samples_per_pixel)
+//   save path                                  write the current image; the format is deduced from the extension,
+//                                              .exr files get the HDR image together with the AOVs
+//   status                                     report the accumulated samples per pixel
+//   reset                                      drop the accumulated samples
+//   quit
+pub struct Server<'a, RT: RayTracer, T: Rngator> {
+    world: &'a dyn Hittable,
+    background: &'a dyn Background,
+    render: RenderingParams,
+    camera: CameraParams,
+    tracer: RT,
+    rngator: T,
+    accumulator: Accumulator,
+    dof_preview: bool,
+}
+
+impl<'a, RT: RayTracer, T: Rngator> Server<'a, RT, T> {
+    pub fn new(
+        world: &'a dyn Hittable,
+        background: &'a dyn Background,
+        render: RenderingParams,
+        camera: CameraParams,
+        tracer: RT,
+        rngator: T,
+    ) -> Server<'a, RT, T> {
+        let accumulator = Accumulator::new(render.image_width, render.image_height);
+        Server { world, background, render, camera, tracer, rngator, accumulator, dof_preview: false }
+    }
+
+    pub fn with_dof_preview(mut self, dof_preview: bool) -> Server<'a, RT, T> {
+        self.dof_preview = dof_preview;
+        self
+    }
+
+    pub fn run<I: BufRead, O: Write>(&mut self, input: I, mut output: O) -> std::io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "quit" {
+                writeln!(output, "ok")?;
+                break;
+            }
+            match self.execute(line) {
+                Ok(reply) if reply.is_empty() => writeln!(output, "ok")?,
+                Ok(reply) => writeln!(output, "ok {}", reply)?,
+                Err(e) => writeln!(output, "error {}", e)?,
+            }
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, line: &str) -> Result<String, String> {
+        let (command, arg) = match line.find(char::is_whitespace) {
+            Some(pos) => (&line[..pos], line[pos..].trim()),
+            None => (line, ""),
+        };
+        fn number(arg: &str) -> Result<f64, String> {
+            arg.parse::<f64>().map_err(|e| format!("'{}': {}", arg, e))
+        }
+
+        match command {
+            "lookfrom" => self.camera.lookfrom = arg.parse::<Vec3>()?,
+            "lookat" => self.camera.lookat = arg.parse::<Vec3>()?,
+            "up" => self.camera.up = arg.parse::<Vec3>()?,
+            "fov" => self.camera.field_of_view = number(arg)?,
+            "aperture" if self.dof_preview => {
+                self.camera.aperture = number(arg)?;
+                return Ok(String::new());
+            }
+            "focus_dist" if self.dof_preview => {
+                self.camera.focus_dist = number(arg)?;
+                return Ok(String::new());
+            }
+            "aperture" => self.camera.aperture = number(arg)?,
+            "focus_dist" => self.camera.focus_dist = number(arg)?,
+            "autofocus" => {
+                let pixel = arg.split(',').map(number).collect::<Result<Vec<f64>, String>>()?;
+                if pixel.len() != 2 {
+                    return Err(format!("expected x,y, got '{}'", arg));
+                }
+                let size = (self.render.image_width, self.render.image_height);
+                let mut rng = self.rngator.rng(0);
+                self.camera.focus_dist = self
+                    .camera
+                    .autofocus(self.world, (pixel[0], pixel[1]), size, &mut rng)
+                    .ok_or_else(|| format!("nothing to focus on at {}", arg))?;
+                if !self.dof_preview {
+                    self.accumulator.clear();
+                }
+                return Ok(format!("{}", self.camera.focus_dist));
+            }
+            "preview" => {
+                self.dof_preview = match arg {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(format!("preview needs on or off, not '{}'", arg)),
+                }
+            }
+            "reset" => {}
+            "samples" => {
+                let samples_per_pixel = match arg {
+                    "" => self.render.samples_per_pixel,
+                    n => n.parse::<usize>().map_err(|e| format!("'{}': {}", n, e))?,
+                };
+                self.add_samples(samples_per_pixel);
+                return Ok(format!("{}", self.accumulator.samples_per_pixel()));
+            }
+            "save" => {
+                if arg.is_empty() {
+                    return Err("save needs a file name".to_string());
+                }
+                if arg.ends_with(".exr") {
+                    let aovs = self.renderer(&self.camera.camera(), AOV_SAMPLES).render_aovs(AOV_SAMPLES);
+                    output::save_exr(arg, &self.accumulator.to_linear(), &aovs, &[], &[], false)
+                        .map_err(|e| e.to_string())?;
+                } else if self.dof_preview {
+                    let aovs = self.renderer(&self.render_camera(), AOV_SAMPLES).render_aovs(AOV_SAMPLES);
+                    let depth: Vec<Vec<f64>> =
+                        aovs.iter().map(|line| line.iter().map(|aov| aov.depth).collect()).collect();
+                    let linear = DepthOfField::new(&self.camera).apply(&self.accumulator.to_linear(), &depth);
+                    output::save_image(arg, &self.render.post.apply(&linear)).map_err(|e| e.to_string())?;
+                } else {
+                    output::save_image(arg, &self.accumulator.to_rgb(&self.render.post)).map_err(|e| e.to_string())?;
+                }
+                return Ok(String::new());
+            }
+            "status" => return Ok(format!("{}", self.accumulator.samples_per_pixel())),
+            _ => return Err(format!("unknown command '{}'", command)),
+        }
+        // Everything that gets here changes the view.
+        self.accumulator.clear();
+        Ok(String::new())
+    }
+
+    fn renderer<'b>(&'b self, camera: &'b Camera, samples_per_pixel: usize) -> Renderer<'b, &'b RT, &'b T> {
+        let parameters = RenderingParams { samples_per_pixel, ..self.render };
+        Renderer::new_with_rng(camera, self.world, self.background, parameters, &self.tracer, &self.rngator)
+    }
+
+    // The camera rays are shot from; a pinhole in preview mode.
+    fn render_camera(&self) -> Camera {
+        match self.dof_preview {
+            true => CameraParams { aperture: 0.0, ..self.camera }.camera(),
+            false => self.camera.camera(),
+        }
+    }
+
+    fn add_samples(&mut self, samples_per_pixel: usize) {
+        let camera = self.render_camera();
+        let mut accumulator = std::mem::replace(&mut self.accumulator, Accumulator::new(0, 0));
+        self.renderer(&camera, samples_per_pixel).accumulate(&mut accumulator, |_, _| {});
+        self.accumulator = accumulator;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::HittableList;
+    use crate::materials::Lambertian;
+    use crate::postprocess::PostProcess;
+    use crate::raytrace::{Bounces, RecursiveRayTracer, SolidBackground};
+    use crate::rngator::SeedableRngator;
+    use crate::shapes::Sphere;
+    use crate::textures::SolidColor;
+    use crate::vec::{Color, Point3};
+
+    fn world() -> HittableList<'static> {
+        let mut world = HittableList::new();
+        world.add(Sphere::new(Point3::ZERO, 1.0, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5))));
+        world
+    }
+
+    fn server<'a>(
+        world: &'a HittableList,
+        background: &'a SolidBackground,
+    ) -> Server<'a, RecursiveRayTracer, SeedableRngator> {
+        let render = RenderingParams {
+            samples_per_pixel: 3,
+            image_width: 9,
+            image_height: 7,
+            post: PostProcess::new(),
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        };
+        let camera = CameraParams {
+            lookfrom: Point3::new(0.0, 0.0, 5.0),
+            lookat: Point3::ZERO,
+            up: Vec3::new(0.0, 1.0, 0.0),
+            field_of_view: 40.0,
+            aspect_ratio: 9.0 / 7.0,
+            aperture: 0.0,
+            focus_dist: 5.0,
+            shift: 0.0,
+            near: 0.0,
+            far: f64::INFINITY,
+        };
+        let tracer = RecursiveRayTracer {
+            max_depth: 3,
+            max_bounces: Bounces::UNLIMITED,
+            regularization: None,
+            check_nan: false,
+        };
+        Server::new(world, background, render, camera, tracer, SeedableRngator::new(1))
+    }
+
+    #[test]
+    fn test_commands() {
+        let (world, background) = (world(), SolidBackground::new(Color::new(0.2, 0.4, 0.6)));
+        let mut server = server(&world, &background);
+        let input = [
+            "samples 2",
+            "",
+            "samples",
+            "status",
+            "fov thirty",
+            "lookat 0,0,-1",
+            "status",
+            "autofocus 3.5,3.5",
+            "autofocus 4",
+            "autofocus 0,0",
+            "preview maybe",
+            "save",
+            "shoot",
+            "quit",
+            "status",
+        ];
+        let mut output = Vec::new();
+        server.run(input.join("\n").as_bytes(), &mut output).unwrap();
+        let replies: Vec<&str> = std::str::from_utf8(&output).unwrap().lines().collect();
+        let expected = [
+            "ok 2",
+            "ok 5",
+            "ok 5",
+            "error 'thirty': invalid float literal",
+            "ok",
+            "ok 0",
+            "ok 4",
+            "error expected x,y, got '4'",
+            "error nothing to focus on at 0,0",
+            "error preview needs on or off, not 'maybe'",
+            "error save needs a file name",
+            "error unknown command 'shoot'",
+            "ok",
+        ];
+        assert_eq!(&expected[..], &replies[..]);
+        assert_eq!(Point3::new(0.0, 0.0, -1.0), server.camera.lookat);
+    }
+
+    #[test]
+    fn test_add_samples() {
+        let (world, background) = (world(), SolidBackground::new(Color::new(0.2, 0.4, 0.6)));
+        let mut server = server(&world, &background);
+        server.add_samples(2);
+        assert_eq!(2, server.accumulator.samples_per_pixel());
+        let mut expected = Accumulator::new(9, 7);
+        server.renderer(&server.camera.camera(), 2).accumulate(&mut expected, |_, _| {});
+        assert_eq!(expected.to_linear(), server.accumulator.to_linear());
+        server.add_samples(1);
+        assert_eq!(3, server.accumulator.samples_per_pixel());
+
+        // In preview mode the lens may change without starting over, as rays go through a pinhole.
+        let mut server = server.with_dof_preview(true);
+        assert_eq!(Ok(String::new()), server.execute("aperture 0.5"));
+        assert_eq!(Ok(String::new()), server.execute("focus_dist 4"));
+        assert_eq!(Ok("3".to_string()), server.execute("status"));
+        server.accumulator.clear();
+        server.add_samples(2);
+        assert_eq!(expected.to_linear(), server.accumulator.to_linear());
+        assert_eq!(Ok(String::new()), server.execute("preview off"));
+        assert_eq!(Ok(String::new()), server.execute("aperture 0.25"));
+        assert_eq!(Ok("0".to_string()), server.execute("status"));
+    }
+}
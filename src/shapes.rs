@@ -1,9 +1,16 @@
 use crate::aarects::AARect;
 use crate::bhv::{Bounded, AABB};
-use crate::hittable::{Hit, Hittable, HittableList};
+use crate::hittable::{self, Hit, Hittable};
+use crate::interval::Interval;
 use crate::materials::Material;
+use crate::math::Onb;
+use crate::scene::{self, SceneWriter};
+use crate::sdf::{SdfBox, SdfBoxFrame, SdfShape};
 use crate::transforms::Axis;
+use crate::validate::SceneStats;
 use crate::vec::{Point3, Ray, Vec3};
+use crate::worlds::MaterialSpec;
+use rand::Rng;
 
 pub struct Empty {}
 
@@ -11,14 +18,24 @@ impl Empty {
     pub const INSTANCE: Empty = Empty {};
 }
 impl Hittable for Empty {
-    fn hit(&self, _: &Ray, _: f64, _: f64, _: &mut dyn rand::RngCore) -> Option<Hit> {
+    fn hit(&self, _: &Ray, _: Interval, _: &mut dyn rand::RngCore) -> Option<Hit<'_>> {
         None
     }
+
+    fn hit_any(&self, _: &Ray, _: Interval, _: &mut dyn rand::RngCore) -> bool {
+        false
+    }
+
+    fn inspect(&self, _: &mut SceneStats) {}
+
+    fn export(&self, _: &mut SceneWriter) -> Result<(), String> {
+        Ok(())
+    }
 }
 
 impl Bounded for Empty {
     fn bounding_box(&self) -> AABB {
-        AABB::new(Point3::ZERO, Point3::ZERO)
+        AABB::EMPTY
     }
 }
 
@@ -41,7 +58,7 @@ impl<T: Material> Sphere<T> {
     }
 }
 
-fn sphere_uv(normal: &Vec3) -> (f64, f64) {
+pub(crate) fn sphere_uv(normal: &Vec3) -> (f64, f64) {
     // normal: a given point on the sphere of radius one, centered at the origin.
     // u: returned value [0,1] of angle around the Y axis from X=-1.
     // v: returned value [0,1] of angle from Y=-1 to Y=+1.
@@ -54,8 +71,22 @@ fn sphere_uv(normal: &Vec3) -> (f64, f64) {
     (phi / (2.0 * std::f64::consts::PI), theta / std::f64::consts::PI)
 }
 
-impl<T: Material + Sync> Hittable for Sphere<T> {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, _: &mut dyn rand::RngCore) -> Option<Hit> {
+// Derivatives of the point at `normal` on a sphere of `radius` by the coordinates of `sphere_uv`. At the poles,
+// where u is undefined, some tangents around the normal.
+pub(crate) fn sphere_tangents(normal: &Vec3, radius: f64) -> (Vec3, Vec3) {
+    let sin_theta = (normal.x() * normal.x() + normal.z() * normal.z()).sqrt();
+    if sin_theta < 1e-9 {
+        return hittable::tangents(normal);
+    }
+    let pi = std::f64::consts::PI;
+    let dpdu = 2.0 * pi * radius * Vec3::new(normal.z(), 0.0, -normal.x());
+    let (x, y, z) = (normal.x(), normal.y(), normal.z());
+    let dpdv = pi * radius * Vec3::new(-x * y / sin_theta, sin_theta, -y * z / sin_theta);
+    (dpdu, dpdv)
+}
+
+impl<T: Material> Sphere<T> {
+    fn hit_t(&self, r: &Ray, ray_t: Interval) -> Option<f64> {
         let oc = &r.orig - &self.center;
         let a = r.dir.length_squared();
         let half_b = oc.dot(r.dir);
@@ -67,17 +98,109 @@ impl<T: Material + Sync> Hittable for Sphere<T> {
 
         let sqrtd = discriminant.sqrt();
         let mut root = (-half_b - sqrtd) / a;
-        if root < t_min || t_max < root {
+        if !ray_t.contains(root) {
             root = (-half_b + sqrtd) / a;
-            if root < t_min || t_max < root {
+            if !ray_t.contains(root) {
                 return None;
             }
         }
-        let t = root;
+        Some(root)
+    }
+}
+
+impl<T: Material + Sync> Hittable for Sphere<T> {
+    fn hit(&self, r: &Ray, ray_t: Interval, _: &mut dyn rand::RngCore) -> Option<Hit<'_>> {
+        let t = self.hit_t(r, ray_t)?;
         let p = r.at(t);
         let normal = (p - self.center) / self.radius;
         let (u, v) = sphere_uv(&normal);
-        Some(Hit::new_with_face_normal(&p, t, u, v, &normal, r, &self.material))
+        let (dpdu, dpdv) = sphere_tangents(&normal, self.radius);
+        Some(Hit::new_with_face_normal(&p, t, u, v, &normal, r, &self.material).with_tangents(dpdu, dpdv))
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, _: &mut dyn rand::RngCore) -> bool {
+        self.hit_t(r, ray_t).is_some()
+    }
+
+    // Seen from outside, the sphere covers a cone of directions, which is sampled uniformly. From inside,
+    // points are sampled uniformly by area.
+    fn pdf_value(&self, o: &Point3, v: &Vec3, rng: &mut dyn rand::RngCore) -> f64 {
+        let radius_squared = self.radius * self.radius;
+        let distance_squared = (self.center - *o).length_squared();
+        if distance_squared <= radius_squared {
+            return match self.hit(&Ray::new(*o, *v), Interval::FORWARD, rng) {
+                None => 0.0,
+                Some(h) => {
+                    let distance = h.t * v.length();
+                    let cosine = v.unit().dot(h.normal).abs();
+                    distance * distance / (cosine * 4.0 * std::f64::consts::PI * radius_squared)
+                }
+            };
+        }
+        if self.hit_t(&Ray::new(*o, *v), Interval::FORWARD).is_none() {
+            return 0.0;
+        }
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        1.0 / (2.0 * std::f64::consts::PI * (1.0 - cos_theta_max))
+    }
+
+    fn random_point(&self, o: &Point3, rng: &mut dyn rand::RngCore) -> Point3 {
+        let to_center = self.center - *o;
+        let radius_squared = self.radius * self.radius;
+        let distance_squared = to_center.length_squared();
+        if distance_squared <= radius_squared {
+            return self.center + self.radius.abs() * Vec3::random_unit_vector(rng);
+        }
+
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let z = 1.0 + rng.gen_range(0.0..1.0) * (cos_theta_max - 1.0);
+        let phi = 2.0 * std::f64::consts::PI * rng.gen_range(0.0..1.0);
+        let sin_theta = (1.0 - z * z).sqrt();
+
+        let direction = Onb::new(to_center).local(sin_theta * phi.cos(), sin_theta * phi.sin(), z);
+
+        let r = Ray::new(*o, direction);
+        match self.hit_t(&r, Interval::FORWARD) {
+            Some(t) => r.at(t),
+            // Grazing direction lost to rounding: take the closest point of the ray instead.
+            None => r.at(direction.dot(to_center)),
+        }
+    }
+
+    fn sample_surface<'a>(&'a self, rng: &mut dyn rand::RngCore) -> Option<(Hit<'a>, f64)> {
+        let normal = Vec3::random_unit_vector(rng);
+        let (u, v) = sphere_uv(&normal);
+        let p = self.center + self.radius * normal;
+        let (dpdu, dpdv) = sphere_tangents(&normal, self.radius);
+        let h = Hit {
+            p,
+            normal,
+            geometric_normal: normal,
+            shading_offset: Vec3::ZERO,
+            dpdu,
+            dpdv,
+            t: 0.0,
+            u,
+            v,
+            front_face: true,
+            material: &self.material,
+            time: 0.0,
+        };
+        Some((h, 1.0 / (4.0 * std::f64::consts::PI * self.radius * self.radius)))
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.check_point("sphere", &self.center);
+        stats.check_finite("sphere radius", &[self.radius]);
+        if self.radius == 0.0 {
+            stats.problem(format!("sphere with zero radius at {}", self.center));
+        }
+        stats.object(std::mem::size_of_val(self), Some(self.bounding_box()));
+        self.material.inspect(stats);
+    }
+
+    fn export(&self, scene: &mut SceneWriter) -> Result<(), String> {
+        scene.shape(format!("sphere {} {}", scene::vector(self.center), self.radius), self.material.export())
     }
 }
 
@@ -102,8 +225,34 @@ impl<T: Material> XYRect<T> {
 }
 
 impl<T: Material + Sync> Hittable for XYRect<T> {
-    fn hit(&self, r: &Ray, tmin: f64, tmax: f64, _: &mut dyn rand::RngCore) -> Option<Hit> {
-        self.r.hit(r, tmin, tmax, &self.material)
+    fn hit(&self, r: &Ray, ray_t: Interval, _: &mut dyn rand::RngCore) -> Option<Hit<'_>> {
+        self.r.hit(r, ray_t, &self.material)
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, _: &mut dyn rand::RngCore) -> bool {
+        self.r.hit_any(r, ray_t)
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, _: &mut dyn rand::RngCore) -> f64 {
+        self.r.pdf_value(o, v)
+    }
+
+    fn random_point(&self, _: &Point3, rng: &mut dyn rand::RngCore) -> Point3 {
+        self.r.random_point(rng)
+    }
+
+    fn sample_surface<'a>(&'a self, rng: &mut dyn rand::RngCore) -> Option<(Hit<'a>, f64)> {
+        Some(self.r.sample_surface(&self.material, rng))
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        self.r.inspect(stats);
+        stats.object(std::mem::size_of_val(self), Some(self.r.bounding_box()));
+        self.material.inspect(stats);
+    }
+
+    fn export(&self, scene: &mut SceneWriter) -> Result<(), String> {
+        scene.shape(format!("rect xy {}", self.r.export()), self.material.export())
     }
 }
 
@@ -127,8 +276,34 @@ impl<T: Material> XZRect<T> {
 }
 
 impl<T: Material + Sync> Hittable for XZRect<T> {
-    fn hit(&self, r: &Ray, tmin: f64, tmax: f64, _: &mut dyn rand::RngCore) -> Option<Hit> {
-        self.r.hit(r, tmin, tmax, &self.material)
+    fn hit(&self, r: &Ray, ray_t: Interval, _: &mut dyn rand::RngCore) -> Option<Hit<'_>> {
+        self.r.hit(r, ray_t, &self.material)
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, _: &mut dyn rand::RngCore) -> bool {
+        self.r.hit_any(r, ray_t)
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, _: &mut dyn rand::RngCore) -> f64 {
+        self.r.pdf_value(o, v)
+    }
+
+    fn random_point(&self, _: &Point3, rng: &mut dyn rand::RngCore) -> Point3 {
+        self.r.random_point(rng)
+    }
+
+    fn sample_surface<'a>(&'a self, rng: &mut dyn rand::RngCore) -> Option<(Hit<'a>, f64)> {
+        Some(self.r.sample_surface(&self.material, rng))
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        self.r.inspect(stats);
+        stats.object(std::mem::size_of_val(self), Some(self.r.bounding_box()));
+        self.material.inspect(stats);
+    }
+
+    fn export(&self, scene: &mut SceneWriter) -> Result<(), String> {
+        scene.shape(format!("rect xz {}", self.r.export()), self.material.export())
     }
 }
 
@@ -152,8 +327,34 @@ impl<T: Material> YZRect<T> {
 }
 
 impl<T: Material + Sync> Hittable for YZRect<T> {
-    fn hit(&self, r: &Ray, tmin: f64, tmax: f64, _: &mut dyn rand::RngCore) -> Option<Hit> {
-        self.r.hit(r, tmin, tmax, &self.material)
+    fn hit(&self, r: &Ray, ray_t: Interval, _: &mut dyn rand::RngCore) -> Option<Hit<'_>> {
+        self.r.hit(r, ray_t, &self.material)
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, _: &mut dyn rand::RngCore) -> bool {
+        self.r.hit_any(r, ray_t)
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, _: &mut dyn rand::RngCore) -> f64 {
+        self.r.pdf_value(o, v)
+    }
+
+    fn random_point(&self, _: &Point3, rng: &mut dyn rand::RngCore) -> Point3 {
+        self.r.random_point(rng)
+    }
+
+    fn sample_surface<'a>(&'a self, rng: &mut dyn rand::RngCore) -> Option<(Hit<'a>, f64)> {
+        Some(self.r.sample_surface(&self.material, rng))
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        self.r.inspect(stats);
+        stats.object(std::mem::size_of_val(self), Some(self.r.bounding_box()));
+        self.material.inspect(stats);
+    }
+
+    fn export(&self, scene: &mut SceneWriter) -> Result<(), String> {
+        scene.shape(format!("rect yz {}", self.r.export()), self.material.export())
     }
 }
 
@@ -163,31 +364,196 @@ impl<T: Material + Sync> Bounded for YZRect<T> {
     }
 }
 
+// Corner and edges of a triangle, without a material; see `Triangle` and `mesh::Mesh`.
+#[derive(Clone, Copy)]
+pub struct TriangleGeometry {
+    p0: Point3,
+    e1: Vec3,
+    e2: Vec3,
+}
+
+impl TriangleGeometry {
+    pub fn new(p0: Point3, p1: Point3, p2: Point3) -> TriangleGeometry {
+        TriangleGeometry { p0, e1: p1 - p0, e2: p2 - p0 }
+    }
+
+    // Möller–Trumbore; returns (t, u, v) with u, v the barycentric coordinates of the hit.
+    pub fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<(f64, f64, f64)> {
+        let pvec = r.dir.cross(self.e2);
+        let det = self.e1.dot(pvec);
+        if det == 0.0 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = r.orig - self.p0;
+        let u = tvec.dot(pvec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let qvec = tvec.cross(self.e1);
+        let v = r.dir.dot(qvec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = self.e2.dot(qvec) * inv_det;
+        if !ray_t.contains(t) {
+            return None;
+        }
+        Some((t, u, v))
+    }
+
+    pub fn hit<'a>(&self, r: &Ray, ray_t: Interval, material: &'a dyn Material) -> Option<Hit<'a>> {
+        let (t, u, v) = self.intersect(r, ray_t)?;
+        let normal = self.e1.cross(self.e2).unit();
+        Some(Hit::new_with_face_normal(&r.at(t), t, u, v, &normal, r, material).with_tangents(self.e1, self.e2))
+    }
+
+    // Checks the coordinates, but doesn't count an object.
+    pub fn inspect(&self, stats: &mut SceneStats) {
+        stats.check_point("triangle", &self.p0);
+        stats.check_point("triangle edge", &self.e1);
+        stats.check_point("triangle edge", &self.e2);
+        if self.e1.cross(self.e2).length_squared() == 0.0 {
+            stats.problem(format!("degenerate triangle at {}", self.p0));
+        }
+    }
+
+    pub fn bounding_box(&self) -> AABB {
+        let (p1, p2) = (self.p0 + self.e1, self.p0 + self.e2);
+        AABB::new(self.p0, p1).surround(&AABB::new(p2, p2)).pad()
+    }
+}
+
+#[derive(Clone)]
+pub struct Triangle<T: Material> {
+    geometry: TriangleGeometry,
+    material: T,
+}
+
+impl<T: Material> Triangle<T> {
+    pub fn new(p0: Point3, p1: Point3, p2: Point3, material: T) -> Triangle<T> {
+        Triangle { geometry: TriangleGeometry::new(p0, p1, p2), material }
+    }
+}
+
+impl<T: Material + Sync> Hittable for Triangle<T> {
+    fn hit(&self, r: &Ray, ray_t: Interval, _: &mut dyn rand::RngCore) -> Option<Hit<'_>> {
+        self.geometry.hit(r, ray_t, &self.material)
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, _: &mut dyn rand::RngCore) -> bool {
+        self.geometry.intersect(r, ray_t).is_some()
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        self.geometry.inspect(stats);
+        stats.object(std::mem::size_of_val(self), Some(self.bounding_box()));
+        self.material.inspect(stats);
+    }
+}
+
+impl<T: Material + Sync> Bounded for Triangle<T> {
+    fn bounding_box(&self) -> AABB {
+        self.geometry.bounding_box()
+    }
+}
+
+// Side of a `Block`, named as seen from +z looking towards -z.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Face {
+    // +z
+    Front,
+    // -z
+    Back,
+    // -y
+    Bottom,
+    // +y
+    Top,
+    // -x
+    Left,
+    // +x
+    Right,
+}
+
+impl Face {
+    pub const ALL: [Face; 6] = [Face::Front, Face::Back, Face::Bottom, Face::Top, Face::Left, Face::Right];
+}
+
 pub struct Block<'a> {
     min: Point3,
     max: Point3,
-    sides: HittableList<'a>,
+    // In the order of `Face::ALL`.
+    faces: Vec<Box<dyn Bounded + 'a>>,
+    // Of all the faces, for `--export_scene`; None once they differ.
+    material: Option<MaterialSpec>,
 }
 
 impl<'a> Block<'a> {
-    pub fn new<T: Material + Copy + 'a>(p0: Point3, p1: Point3, material: T) -> Block<'a> {
-        let mut sides = HittableList::new();
-
-        sides.add(XYRect::new(p0.x(), p1.x(), p0.y(), p1.y(), p1.z(), material));
-        sides.add(XYRect::new(p0.x(), p1.x(), p0.y(), p1.y(), p0.z(), material));
+    pub fn new<T: Material + Clone + 'a>(p0: Point3, p1: Point3, material: T) -> Block<'a> {
+        let mut block = Block { min: p0, max: p1, faces: Vec::with_capacity(6), material: material.export() };
+        for face in Face::ALL {
+            let side = block.side(face, material.clone());
+            block.faces.push(side);
+        }
+        block
+    }
 
-        sides.add(XZRect::new(p0.x(), p1.x(), p0.z(), p1.z(), p0.y(), material));
-        sides.add(XZRect::new(p0.x(), p1.x(), p0.z(), p1.z(), p1.y(), material));
+    // Replaces the material of one face, e.g. for a textured front with plain sides.
+    pub fn with_face<T: Material + 'a>(mut self, face: Face, material: T) -> Block<'a> {
+        self.faces[face as usize] = self.side(face, material);
+        self.material = None;
+        self
+    }
 
-        sides.add(YZRect::new(p0.y(), p1.y(), p0.z(), p1.z(), p0.x(), material));
-        sides.add(YZRect::new(p0.y(), p1.y(), p0.z(), p1.z(), p1.x(), material));
+    pub fn face(&self, face: Face) -> &(dyn Bounded + 'a) {
+        self.faces[face as usize].as_ref()
+    }
 
-        Block { min: p0, max: p1, sides }
+    fn side<T: Material + 'a>(&self, face: Face, material: T) -> Box<dyn Bounded + 'a> {
+        let (p0, p1) = (self.min, self.max);
+        match face {
+            Face::Front => Box::new(XYRect::new(p0.x(), p1.x(), p0.y(), p1.y(), p1.z(), material)),
+            Face::Back => Box::new(XYRect::new(p0.x(), p1.x(), p0.y(), p1.y(), p0.z(), material)),
+            Face::Bottom => Box::new(XZRect::new(p0.x(), p1.x(), p0.z(), p1.z(), p0.y(), material)),
+            Face::Top => Box::new(XZRect::new(p0.x(), p1.x(), p0.z(), p1.z(), p1.y(), material)),
+            Face::Left => Box::new(YZRect::new(p0.y(), p1.y(), p0.z(), p1.z(), p0.x(), material)),
+            Face::Right => Box::new(YZRect::new(p0.y(), p1.y(), p0.z(), p1.z(), p1.x(), material)),
+        }
     }
 }
+
 impl<'a> Hittable for Block<'a> {
-    fn hit(&self, r: &Ray, tmin: f64, tmax: f64, rng: &mut dyn rand::RngCore) -> Option<Hit> {
-        self.sides.hit(r, tmin, tmax, rng)
+    fn hit(&self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> Option<Hit<'_>> {
+        let mut result: Option<Hit> = None;
+        let mut closest_so_far = ray_t.max;
+        for face in self.faces.iter() {
+            if let Some(h) = face.hit(r, ray_t.with_max(closest_so_far), rng) {
+                closest_so_far = h.t;
+                result = Some(h);
+            }
+        }
+        result
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> bool {
+        self.faces.iter().any(|face| face.hit_any(r, ray_t, rng))
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        for face in self.faces.iter() {
+            face.inspect(stats);
+        }
+    }
+
+    // As its faces if they have different materials.
+    fn export(&self, scene: &mut SceneWriter) -> Result<(), String> {
+        match &self.material {
+            Some(material) => {
+                let shape = format!("block {} {}", scene::vector(self.min), scene::vector(self.max));
+                scene.shape(shape, Some(material.clone()))
+            }
+            None => self.faces.iter().try_for_each(|face| face.export(scene)),
+        }
     }
 }
 
@@ -197,9 +563,83 @@ impl<'a> Bounded for Block<'a> {
     }
 }
 
+// Box from `p0` to `p1` with its edges and corners rounded off to `radius`, sphere traced as an `sdf::SdfBox`.
+pub struct RoundedBox<T: Material> {
+    shape: SdfShape<SdfBox, T>,
+}
+
+impl<T: Material> RoundedBox<T> {
+    pub fn new(p0: Point3, p1: Point3, radius: f64, material: T) -> RoundedBox<T> {
+        let b = AABB::new(p0, p1);
+        let half_size = 0.5 * (b.max() - b.min());
+        let rounding = radius.clamp(0.0, half_size.x().min(half_size.y()).min(half_size.z()));
+        let sdf = SdfBox { center: b.min() + half_size, half_size, rounding };
+        RoundedBox { shape: SdfShape::new(sdf, material) }
+    }
+}
+
+impl<T: Material> Hittable for RoundedBox<T> {
+    fn hit(&self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> Option<Hit<'_>> {
+        self.shape.hit(r, ray_t, rng)
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> bool {
+        self.shape.hit_any(r, ray_t, rng)
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        self.shape.inspect(stats);
+    }
+}
+
+impl<T: Material> Bounded for RoundedBox<T> {
+    fn bounding_box(&self) -> AABB {
+        self.shape.bounding_box()
+    }
+}
+
+// Wireframe of the box from `p0` to `p1`: its twelve edges as square bars `thickness` wide, sphere traced as an
+// `sdf::SdfBoxFrame`, so that the bars join cleanly at the corners, even in glass.
+pub struct BoxFrame<T: Material> {
+    shape: SdfShape<SdfBoxFrame, T>,
+}
+
+impl<T: Material> BoxFrame<T> {
+    pub fn new(p0: Point3, p1: Point3, thickness: f64, material: T) -> BoxFrame<T> {
+        let b = AABB::new(p0, p1);
+        let half_size = 0.5 * (b.max() - b.min());
+        let thickness = thickness.clamp(0.0, half_size.x().min(half_size.y()).min(half_size.z()));
+        let sdf = SdfBoxFrame { center: b.min() + half_size, half_size, thickness };
+        BoxFrame { shape: SdfShape::new(sdf, material) }
+    }
+}
+
+impl<T: Material> Hittable for BoxFrame<T> {
+    fn hit(&self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> Option<Hit<'_>> {
+        self.shape.hit(r, ray_t, rng)
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> bool {
+        self.shape.hit_any(r, ray_t, rng)
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        self.shape.inspect(stats);
+    }
+}
+
+impl<T: Material> Bounded for BoxFrame<T> {
+    fn bounding_box(&self) -> AABB {
+        self.shape.bounding_box()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::materials::Lambertian;
+    use crate::textures::SolidColor;
+    use rand::SeedableRng;
 
     #[test]
     fn test_sphere_uv() {
@@ -211,4 +651,159 @@ mod tests {
         assert_eq!((0.5, 0.0), sphere_uv(&Vec3::new(0.0, -1.0, 0.0)));
         assert_eq!((0.75, 0.5), sphere_uv(&Vec3::new(0.0, 0.0, -1.0)));
     }
+
+    #[test]
+    fn test_tangents() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(5);
+        let material = Lambertian::new(SolidColor::new(0.5, 0.5, 0.5));
+        // Moving along dpdu or dpdv by a small step moves u or v by the same step.
+        let sphere = Sphere::new(Point3::new(1.0, 2.0, 3.0), 2.0, material);
+        for _ in 0..100 {
+            let orig = Point3::new(1.0, 2.0, 3.0) + 5.0 * Vec3::random_unit_vector(&mut rng);
+            let h = match sphere.hit(&Ray::new(orig, sphere.center - orig), Interval::FORWARD, &mut rng) {
+                Some(h) => h,
+                None => continue,
+            };
+            let uv = |p: Point3| sphere_uv(&(p - sphere.center).unit());
+            let (du, _) = uv(h.p + 1e-6 * h.dpdu);
+            let (_, dv) = uv(h.p + 1e-6 * h.dpdv);
+            assert!((du - h.u - 1e-6).abs() < 1e-8 && (dv - h.v - 1e-6).abs() < 1e-8, "{} {}", du - h.u, dv - h.v);
+            assert!(h.dpdu.dot(h.geometric_normal).abs() < 1e-9 && h.dpdv.dot(h.geometric_normal).abs() < 1e-9);
+        }
+
+        let triangle = Triangle::new(Point3::ZERO, Point3::new(2.0, 0.0, 0.0), Point3::new(0.0, 3.0, 0.0), material);
+        let h =
+            triangle.hit(&Ray::new(Point3::new(0.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0)), Interval::FORWARD, &mut rng);
+        let h = h.unwrap();
+        assert_eq!(Point3::new(0.5, 0.5, 0.0), h.p);
+        assert!((h.p - h.u * h.dpdu - h.v * h.dpdv).length() < 1e-12);
+    }
+
+    #[test]
+    fn test_spawned_rays_do_not_self_intersect() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(42);
+        let material = Lambertian::new(SolidColor::new(0.5, 0.5, 0.5));
+        for &(center, radius) in &[(Point3::new(0.0, -1000.0, 0.0), 1000.0), (Point3::new(1e-3, 0.0, 0.0), 1e-4)] {
+            let sphere = Sphere::new(center, radius, material);
+            for _ in 0..1000 {
+                let target = center + radius * Vec3::random_unit_vector(&mut rng);
+                let orig = target + 3.0 * radius * Vec3::random_unit_vector(&mut rng);
+                let h = match sphere.hit(&Ray::new(orig, target - orig), Interval::FORWARD, &mut rng) {
+                    Some(h) => h,
+                    None => continue,
+                };
+                // Leaving the surface: must not hit the sphere again.
+                let outward = h.spawn_ray(Vec3::random_in_hemisphere(&h.normal, &mut rng));
+                assert!(!sphere.hit_any(&outward, Interval::FORWARD, &mut rng));
+                // Entering the sphere: must hit the far side, not the starting point.
+                let inward = h.spawn_ray(-h.normal + 0.5 * Vec3::random_in_unit_sphere(&mut rng));
+                let far = sphere.hit(&inward, Interval::FORWARD, &mut rng).unwrap();
+                assert!((far.p - h.p).length() > 0.01 * radius);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sphere_light_sampling() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(7);
+        let sphere = Sphere::new(Point3::new(1.0, 2.0, 3.0), 1.5, Lambertian::new(SolidColor::new(1.0, 1.0, 1.0)));
+        for &o in &[Point3::new(4.0, 2.0, 3.0), Point3::new(1.5, 2.0, 3.0)] {
+            // Sampled points are on the sphere and visible from `o`.
+            for _ in 0..100 {
+                let p = sphere.random_point(&o, &mut rng);
+                assert!(((p - sphere.center()).length() - sphere.radius()).abs() < 1e-9);
+                assert!(sphere.pdf_value(&o, &(p - o), &mut rng) > 0.0);
+            }
+            // The density integrates to one over all directions.
+            let n = 200000;
+            let mut integral = 0.0;
+            for _ in 0..n {
+                integral += sphere.pdf_value(&o, &Vec3::random_unit_vector(&mut rng), &mut rng);
+            }
+            integral *= 4.0 * std::f64::consts::PI / n as f64;
+            assert!((integral - 1.0).abs() < 0.03, "{}", integral);
+        }
+    }
+
+    #[test]
+    fn test_rect_light_sampling() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(7);
+        let rect = XZRect::new(213.0, 343.0, 227.0, 332.0, 554.0, Lambertian::new(SolidColor::new(1.0, 1.0, 1.0)));
+        let o = Point3::new(250.0, 500.0, 300.0);
+        for _ in 0..100 {
+            let p = rect.random_point(&o, &mut rng);
+            assert_eq!(554.0, p.y());
+            assert!(rect.pdf_value(&o, &(p - o), &mut rng) > 0.0);
+        }
+        let n = 200000;
+        let mut integral = 0.0;
+        for _ in 0..n {
+            integral += rect.pdf_value(&o, &Vec3::random_unit_vector(&mut rng), &mut rng);
+        }
+        integral *= 4.0 * std::f64::consts::PI / n as f64;
+        assert!((integral - 1.0).abs() < 0.03, "{}", integral);
+    }
+
+    #[test]
+    fn test_rounded_box_and_frame() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        let material = Lambertian::new(SolidColor::new(0.5, 0.5, 0.5));
+        let (p0, p1) = (Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+        let rounded = RoundedBox::new(p0, p1, 0.5, material);
+        let frame = BoxFrame::new(p0, p1, 0.2, material);
+        let along_z = |x: f64, y: f64| Ray::new(Point3::new(x, y, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        // Through the middle of a face: the box is flat there, the frame has a hole.
+        let h = rounded.hit(&along_z(0.0, 0.0), Interval::FORWARD, &mut rng).unwrap();
+        assert!((h.t - 4.0).abs() < 1e-4 && (h.normal.z() - 1.0).abs() < 1e-4, "{} {}", h.t, h.normal);
+        assert!(!frame.hit_any(&along_z(0.0, 0.0), Interval::FORWARD, &mut rng));
+        // Near a corner: cut off by the rounding, solid in the frame.
+        assert!(!rounded.hit_any(&along_z(0.95, 0.95), Interval::FORWARD, &mut rng));
+        let h = frame.hit(&along_z(0.95, 0.95), Interval::FORWARD, &mut rng).unwrap();
+        assert!((h.t - 4.0).abs() < 1e-4, "{}", h.t);
+        // Along an edge of the frame, between the corners.
+        assert!(frame.hit_any(&along_z(0.0, 0.9), Interval::FORWARD, &mut rng));
+        assert!(!frame.hit_any(&along_z(0.0, 0.7), Interval::FORWARD, &mut rng));
+        assert_eq!(1.0, frame.bounding_box().max().y().round());
+    }
+
+    #[test]
+    fn test_block_faces() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        let plain = Lambertian::new(SolidColor::new(0.5, 0.5, 0.5));
+        let red = Lambertian::new(SolidColor::new(1.0, 0.0, 0.0));
+        let block = Block::new(Point3::ZERO, Point3::new(1.0, 2.0, 3.0), plain).with_face(Face::Front, red);
+        for face in Face::ALL {
+            // The face's normal points away from the block's center.
+            let b = block.face(face).bounding_box();
+            let middle = 0.5 * (b.min() + b.max());
+            let orig = middle + 2.0 * (middle - Point3::new(0.5, 1.0, 1.5));
+            let h = block.hit(&Ray::new(orig, middle - orig), Interval::FORWARD, &mut rng).unwrap();
+            assert!((h.p - middle).length() < 1e-9, "{:?}: {}", face, h.p);
+            let color = h.material.scatter(&Ray::new(orig, middle - orig), &h, &mut rng).unwrap().0;
+            assert_eq!(if face == Face::Front { 0.0 } else { 0.5 }, color.y(), "{:?}", face);
+        }
+    }
+
+    #[test]
+    fn test_shared_material() {
+        use crate::hittable::HittableList;
+        use crate::materials::{DiffuseLight, SharedMaterial};
+        use crate::textures::SharedTexture;
+        use crate::vec::Color;
+        use std::sync::Arc;
+
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(7);
+        let texture: SharedTexture = Arc::new(SolidColor::new(1.0, 2.0, 3.0));
+        let light: SharedMaterial = Arc::new(DiffuseLight::new(texture));
+        let mut world = HittableList::new();
+        for i in 0..3 {
+            world.add(Sphere::new(Point3::new(3.0 * i as f64, 0.0, 0.0), 1.0, light.clone()));
+        }
+        assert_eq!(4, Arc::strong_count(&light));
+
+        let r = Ray::new(Point3::new(6.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let h = world.hit(&r, Interval::FORWARD, &mut rng).unwrap();
+        assert_eq!(Color::new(1.0, 2.0, 3.0), h.material.emit(h.u, h.v, h.p));
+    }
 }
@@ -1,8 +1,37 @@
+use crate::validate::SceneStats;
 use crate::vec::{Color, Point3, Vec3};
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use std::sync::Arc;
 
-pub trait Texture: Sync {
+pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: Point3) -> Color;
+
+    // For `--validate`, see `Hittable::inspect`.
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.textures += 1;
+    }
+
+    // The color of a texture that is the same everywhere, for `--export_scene`; None for any other.
+    fn constant(&self) -> Option<Color> {
+        None
+    }
+}
+
+// Texture that can be shared by many materials without copying it.
+pub type SharedTexture = Arc<dyn Texture>;
+
+impl<T: Texture + ?Sized> Texture for Arc<T> {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        (**self).value(u, v, p)
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        (**self).inspect(stats)
+    }
+
+    fn constant(&self) -> Option<Color> {
+        (**self).constant()
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -23,6 +52,10 @@ impl Texture for SolidColor {
     fn value(&self, _: f64, _: f64, _: Point3) -> Color {
         self.color
     }
+
+    fn constant(&self) -> Option<Color> {
+        Some(self.color)
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -46,34 +79,124 @@ impl<TOdd: Texture, TEven: Texture> Texture for Checker<TOdd, TEven> {
             self.even.value(u, v, p)
         }
     }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.textures += 1;
+        self.odd.inspect(stats);
+        self.even.inspect(stats);
+    }
+}
+
+// The CIE 1931 color matching functions at `lambda` nanometers, as the sums of piecewise Gaussians fitted by
+// Wyman, Sloan and Shirley in "Simple Analytic Approximations to the CIE XYZ Color Matching Functions".
+fn color_matching(lambda: f64) -> Vec3 {
+    let g = |mu: f64, below: f64, above: f64| {
+        let sigma = if lambda < mu { below } else { above };
+        (-0.5 * ((lambda - mu) / sigma).powi(2)).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
 }
 
-const POINT_COUNT: usize = 1024;
+// CIE XYZ of the light a blackbody at `kelvin` emits, up to a constant factor: Planck's law integrated against the
+// color matching functions over the visible range. Zero below 500 K, where next to nothing visible is emitted.
+fn blackbody_xyz(kelvin: f64) -> Vec3 {
+    // Second radiation constant hc/k, in nanometer kelvins.
+    const C2: f64 = 1.4387769e7;
+    if kelvin.is_nan() || kelvin < 500.0 {
+        return Vec3::ZERO;
+    }
+    (380..=780).step_by(5).fold(Vec3::ZERO, |xyz, lambda| {
+        let lambda = lambda as f64;
+        let radiance = (lambda / 1000.0).powi(-5) / (C2 / (lambda * kelvin)).exp_m1();
+        xyz + radiance * color_matching(lambda)
+    })
+}
 
-struct Perlin {
-    ranvec: [Vec3; POINT_COUNT],
-    perm_x: [usize; POINT_COUNT],
-    perm_y: [usize; POINT_COUNT],
-    perm_z: [usize; POINT_COUNT],
+// Linear sRGB of CIE XYZ, with the colors outside of the gamut clipped.
+fn xyz_to_rgb(xyz: Vec3) -> Color {
+    let (x, y, z) = (xyz.x(), xyz.y(), xyz.z());
+    Color::new(
+        (3.2406 * x - 1.5372 * y - 0.4986 * z).max(0.0),
+        (-0.9689 * x + 1.8758 * y + 0.0415 * z).max(0.0),
+        (0.0557 * x - 0.2040 * y + 1.0570 * z).max(0.0),
+    )
+}
+
+// Linear RGB color of a blackbody at `kelvin`, with a luminance of 1 so that the temperature only picks the hue of
+// a light: about 1800 is a candle, 2700 a light bulb, 5500 daylight and 10000 a blue sky. Black below 500 K.
+pub fn blackbody(kelvin: f64) -> Color {
+    let xyz = blackbody_xyz(kelvin);
+    match xyz.y() > 0.0 {
+        true => xyz_to_rgb(xyz / xyz.y()),
+        false => Color::ZERO,
+    }
+}
+
+// Temperature field glowing like a blackbody, e.g. as the emission of a fire: the first channel of `temperature`
+// from 0 to 1 maps to `cold` to `hot` kelvins. The brightness follows the temperature too, `brightness` at 6500 K,
+// so that the cool parts only smoulder.
+#[derive(Clone)]
+pub struct Blackbody<T: Texture> {
+    temperature: T,
+    cold: f64,
+    hot: f64,
+    // Brightness per unit of `blackbody_xyz`.
+    scale: f64,
+}
+
+impl<T: Texture> Blackbody<T> {
+    pub fn new(temperature: T, cold: f64, hot: f64, brightness: f64) -> Blackbody<T> {
+        Blackbody { temperature, cold, hot, scale: brightness / blackbody_xyz(6500.0).y() }
+    }
+}
+
+impl<T: Texture> Texture for Blackbody<T> {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        let t = self.temperature.value(u, v, p).x().clamp(0.0, 1.0);
+        let kelvin = self.cold + t * (self.hot - self.cold);
+        self.scale * xyz_to_rgb(blackbody_xyz(kelvin))
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.textures += 1;
+        self.temperature.inspect(stats);
+    }
+}
+
+// Gradients of the Perlin noise, shuffled by three permutations of their indices.
+pub(crate) struct Perlin {
+    ranvec: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
     turbulence_depth: i32,
 }
 
 impl Perlin {
-    pub fn new(rng: &mut dyn rand::RngCore) -> Perlin {
-        let mut ranvec: [Vec3; POINT_COUNT] = [Vec3::ZERO; POINT_COUNT];
-        for i in 0..POINT_COUNT {
-            ranvec[i] = Vec3::random(-1.0, 1.0, rng).unit();
-        }
+    // `point_count` must be a power of two.
+    pub fn new(point_count: usize, turbulence_depth: i32, rng: &mut dyn rand::RngCore) -> Perlin {
+        assert!(point_count.is_power_of_two(), "Perlin table size {} is not a power of two", point_count);
+        let ranvec = (0..point_count).map(|_| Vec3::random(-1.0, 1.0, rng).unit()).collect();
         Perlin {
             ranvec,
-            perm_x: Perlin::permute(rng),
-            perm_y: Perlin::permute(rng),
-            perm_z: Perlin::permute(rng),
-            turbulence_depth: 7,
+            perm_x: Perlin::permute(point_count, rng),
+            perm_y: Perlin::permute(point_count, rng),
+            perm_z: Perlin::permute(point_count, rng),
+            turbulence_depth,
         }
     }
 
-    fn turbulence(&self, p: &Point3) -> f64 {
+    fn memory(&self) -> usize {
+        std::mem::size_of::<Perlin>()
+            + std::mem::size_of_val(self.ranvec.as_slice())
+            + 3 * std::mem::size_of_val(self.perm_x.as_slice())
+    }
+
+    pub(crate) fn turbulence(&self, p: &Point3) -> f64 {
         let mut accum = 0.0f64;
         let mut temp_p = *p;
         let mut weight = 1.0;
@@ -87,7 +210,7 @@ impl Perlin {
         accum.abs()
     }
 
-    fn noise(&self, p: &Point3) -> f64 {
+    pub(crate) fn noise(&self, p: &Point3) -> f64 {
         let u = p.x() - p.x().floor();
         let v = p.y() - p.y().floor();
         let w = p.z() - p.z().floor();
@@ -96,14 +219,15 @@ impl Perlin {
         let j = p.y().floor() as isize;
         let k = p.z().floor() as isize;
 
+        let point_count = self.ranvec.len() as isize;
         let mut c = [[[Vec3::ZERO; 2]; 2]; 2];
 
         for di in 0..2 {
             for dj in 0..2 {
                 for dk in 0..2 {
-                    let ii = (i + di as isize).rem_euclid(POINT_COUNT as isize) as usize;
-                    let jj = (j + dj as isize).rem_euclid(POINT_COUNT as isize) as usize;
-                    let kk = (k + dk as isize).rem_euclid(POINT_COUNT as isize) as usize;
+                    let ii = (i + di as isize).rem_euclid(point_count) as usize;
+                    let jj = (j + dj as isize).rem_euclid(point_count) as usize;
+                    let kk = (k + dk as isize).rem_euclid(point_count) as usize;
                     c[di][dj][dk] = self.ranvec[self.perm_x[ii] ^ self.perm_y[jj] ^ self.perm_z[kk]];
                 }
             }
@@ -133,12 +257,9 @@ impl Perlin {
         accum
     }
 
-    fn permute(rng: &mut dyn rand::RngCore) -> [usize; POINT_COUNT] {
-        let mut result: [usize; POINT_COUNT] = [0; POINT_COUNT];
-        for i in 0..POINT_COUNT {
-            result[i] = i
-        }
-        for i in (1..POINT_COUNT).rev() {
+    fn permute(point_count: usize, rng: &mut dyn rand::RngCore) -> Vec<usize> {
+        let mut result: Vec<usize> = (0..point_count).collect();
+        for i in (1..point_count).rev() {
             let j = rng.gen_range(0..i);
             let tmp = result[i];
             result[i] = result[j];
@@ -148,20 +269,214 @@ impl Perlin {
     }
 }
 
+// Cellular noise: the distance to the nearest of random points, one in each unit cell, shuffled like the
+// gradients of `Perlin`. It changes no faster than the point it is taken at.
+pub(crate) struct Worley {
+    points: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Worley {
+    // `point_count` must be a power of two.
+    pub fn new(point_count: usize, rng: &mut dyn rand::RngCore) -> Worley {
+        assert!(point_count.is_power_of_two(), "Worley table size {} is not a power of two", point_count);
+        let points = (0..point_count).map(|_| Vec3::random(0.0, 1.0, rng)).collect();
+        Worley {
+            points,
+            perm_x: Perlin::permute(point_count, rng),
+            perm_y: Perlin::permute(point_count, rng),
+            perm_z: Perlin::permute(point_count, rng),
+        }
+    }
+
+    pub(crate) fn noise(&self, p: &Point3) -> f64 {
+        let (i, j, k) = (p.x().floor() as isize, p.y().floor() as isize, p.z().floor() as isize);
+        let point_count = self.points.len() as isize;
+        let mut nearest = f64::INFINITY;
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let ii = (i + di).rem_euclid(point_count) as usize;
+                    let jj = (j + dj).rem_euclid(point_count) as usize;
+                    let kk = (k + dk).rem_euclid(point_count) as usize;
+                    let cell = Vec3::new((i + di) as f64, (j + dj) as f64, (k + dk) as f64);
+                    let point = cell + self.points[self.perm_x[ii] ^ self.perm_y[jj] ^ self.perm_z[kk]];
+                    nearest = nearest.min((point - *p).length_squared());
+                }
+            }
+        }
+        nearest.sqrt()
+    }
+}
+
+// What `NoiseTexture` makes of the Perlin noise.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NoiseMode {
+    // Smooth noise around mid gray.
+    Noise,
+    // Sum of octaves of noise, like clouds.
+    Turbulence,
+    // Stripes along z bent by turbulence, as in the book.
+    Marble,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct NoiseParams {
+    // Frequency of the noise in world space.
+    pub scale: f64,
+    pub mode: NoiseMode,
+    // Octaves of turbulence.
+    pub depth: i32,
+    // How far turbulence shifts the marble stripes.
+    pub contrast: f64,
+    // Gradients in the Perlin table, a power of two; the noise repeats after this many units.
+    pub table_size: usize,
+    // Seed of the Perlin table, so the texture looks the same in every render; if None, the table is drawn
+    // from the RNG the world is built with.
+    pub seed: Option<u64>,
+}
+
+impl NoiseParams {
+    pub fn new(scale: f64) -> NoiseParams {
+        NoiseParams { scale, mode: NoiseMode::Marble, depth: 7, contrast: 10.0, table_size: 1024, seed: None }
+    }
+}
+
 #[derive(Clone)]
 pub struct NoiseTexture {
     noise: std::sync::Arc<Perlin>,
     scale: f64,
+    mode: NoiseMode,
+    contrast: f64,
 }
 
 impl NoiseTexture {
+    // The book's marble.
     pub fn new(scale: f64, rng: &mut dyn rand::RngCore) -> NoiseTexture {
-        NoiseTexture { noise: std::sync::Arc::new(Perlin::new(rng)), scale }
+        NoiseTexture::with_params(&NoiseParams::new(scale), rng)
+    }
+
+    pub fn with_params(params: &NoiseParams, rng: &mut dyn rand::RngCore) -> NoiseTexture {
+        let noise = match params.seed {
+            Some(seed) => Perlin::new(params.table_size, params.depth, &mut rand_pcg::Pcg64::seed_from_u64(seed)),
+            None => Perlin::new(params.table_size, params.depth, rng),
+        };
+        NoiseTexture {
+            noise: std::sync::Arc::new(noise),
+            scale: params.scale,
+            mode: params.mode,
+            contrast: params.contrast,
+        }
     }
 }
 
 impl Texture for NoiseTexture {
     fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
-        Color::ONE * 0.5 * (1.0 + (self.scale * p.z() + 10.0 * self.noise.turbulence(&(self.scale * p))).sin())
+        let p = self.scale * p;
+        let value = match self.mode {
+            NoiseMode::Noise => 0.5 * (1.0 + self.noise.noise(&p)),
+            NoiseMode::Turbulence => self.noise.turbulence(&p),
+            NoiseMode::Marble => 0.5 * (1.0 + (p.z() + self.contrast * self.noise.turbulence(&p)).sin()),
+        };
+        Color::ONE * value
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.textures += 1;
+        stats.texture_data(self.noise.as_ref(), self.noise.memory());
+    }
+}
+
+// Where `bake` takes the values of a texture from.
+#[derive(Copy, Clone, Debug)]
+pub enum BakeDomain {
+    // u and v over [0, 1], for textures that depend on them, e.g. images.
+    Uv,
+    // Square of side `size` around `center` in the plane of constant z, for solid textures, e.g. noise.
+    Slice { center: Point3, size: f64 },
+}
+
+// Values of `texture` at the pixel centers of a `width` x `height` image spanning `domain`, to look at a texture
+// without rendering a scene. Lines are bottom to top, like the output of the renderer.
+pub fn bake(texture: &dyn Texture, width: usize, height: usize, domain: BakeDomain) -> Vec<Vec<Color>> {
+    (0..height)
+        .map(|j| {
+            (0..width)
+                .map(|i| {
+                    let u = (i as f64 + 0.5) / width as f64;
+                    let v = (j as f64 + 0.5) / height as f64;
+                    let p = match domain {
+                        BakeDomain::Uv => Point3::new(u, v, 0.0),
+                        BakeDomain::Slice { center, size } => {
+                            let aspect = height as f64 / width as f64;
+                            center + Vec3::new((u - 0.5) * size, (v - 0.5) * size * aspect, 0.0)
+                        }
+                    };
+                    texture.value(u, v, p)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_noise() {
+        let params = NoiseParams { seed: Some(42), ..NoiseParams::new(1.0) };
+        let a = NoiseTexture::with_params(&params, &mut rand_pcg::Pcg64::seed_from_u64(1));
+        let b = NoiseTexture::with_params(&params, &mut rand::thread_rng());
+        let c = NoiseTexture::with_params(&NoiseParams { seed: Some(43), ..params }, &mut rand::thread_rng());
+        let p = Point3::new(0.3, 1.7, -2.2);
+        assert_eq!(a.value(0.0, 0.0, p), b.value(0.0, 0.0, p));
+        assert_ne!(a.value(0.0, 0.0, p), c.value(0.0, 0.0, p));
+    }
+
+    #[test]
+    fn test_bake() {
+        let checker = Checker::new(SolidColor::new(0.0, 0.0, 0.0), SolidColor::new(1.0, 1.0, 1.0));
+        let square = std::f64::consts::PI / 5.0;
+        let slice = BakeDomain::Slice { center: Point3::new(0.0, 0.0, square / 2.0), size: 4.0 * square };
+        let texels = bake(&checker, 4, 2, slice);
+        assert_eq!(2, texels.len());
+        assert_eq!(4, texels[0].len());
+        // Neighboring texels are a square apart.
+        assert_ne!(texels[0][0], texels[0][1]);
+        assert_ne!(texels[0][0], texels[1][0]);
+        assert_eq!(texels[0][0], texels[1][1]);
+
+        let gradient = bake(&SolidColor::new(0.2, 0.4, 0.6), 3, 3, BakeDomain::Uv);
+        assert!(gradient.iter().flatten().all(|&c| c == Color::new(0.2, 0.4, 0.6)));
+    }
+
+    #[test]
+    fn test_blackbody() {
+        let luminance = |c: Color| 0.2126 * c.r() + 0.7152 * c.g() + 0.0722 * c.b();
+        // Close to white at the temperature of daylight, redder below and bluer above.
+        let daylight = blackbody(6500.0);
+        assert!((daylight - Color::ONE).length() < 0.1, "{:?}", daylight);
+        let candle = blackbody(1800.0);
+        assert!(candle.r() > candle.g() && candle.g() > candle.b());
+        let sky = blackbody(12000.0);
+        assert!(sky.b() > sky.g() && sky.g() > sky.r());
+        for kelvin in [1800.0, 2700.0, 6500.0, 12000.0] {
+            assert!((luminance(blackbody(kelvin)) - 1.0).abs() < 0.02, "{}", kelvin);
+        }
+        assert_eq!(Color::ZERO, blackbody(300.0));
+
+        // Hotter parts of a fire are brighter as well as bluer.
+        let fire = Blackbody::new(
+            Checker::new(SolidColor::new(0.0, 0.0, 0.0), SolidColor::new(1.0, 1.0, 1.0)),
+            1000.0,
+            6500.0,
+            2.0,
+        );
+        let (cold, hot) = (Point3::new(-0.1, 0.1, 0.1), Point3::new(0.1, 0.1, 0.1));
+        assert!((fire.value(0.0, 0.0, hot) - 2.0 * daylight).length() < 1e-9);
+        assert!(luminance(fire.value(0.0, 0.0, cold)) < 0.01);
     }
 }
@@ -0,0 +1,329 @@
+// Image textures too large to decode into memory, e.g. planet maps of hundreds of megapixels. The image is
+// converted once into a file of square tiles, see `write`, and the file is mapped into memory, so only the tiles
+// that rays look up are read from disk, and the system can drop them again when memory runs short. The tiles used
+// last are also kept decoded to linear colors in a small cache, so a tile is decoded once while it is in use.
+use crate::image_texture::{srgb_to_linear, Encoding};
+use crate::textures::Texture;
+use crate::validate::SceneStats;
+use crate::vec::{Color, Point3};
+use memmap2::Mmap;
+use std::convert::TryInto;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+// The file starts with these bytes, then the width, height and side of the tiles in pixels as little-endian
+// 32-bit integers. The tiles follow row by row from the top left, each its lines from the top, 8-bit RGB, and
+// padded with black to the full size on the right and bottom edges.
+const MAGIC: &[u8; 8] = b"RTTILES1";
+const HEADER_SIZE: usize = 20;
+
+pub const DEFAULT_TILE_SIZE: usize = 128;
+// About 50 MB of 128x128 tiles.
+pub const DEFAULT_CACHE_TILES: usize = 256;
+
+// The cache is split by tile index, so that threads looking up different tiles don't wait for each other.
+const SHARDS: usize = 16;
+
+type Texels = Arc<Vec<[f32; 3]>>;
+
+// Tiles found decoded in the caches of all tiled images, and tiles decoded, for `assets::report`.
+static HITS: AtomicUsize = AtomicUsize::new(0);
+static LOADS: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TiledImage {
+    path: String,
+    map: Mmap,
+    width: usize,
+    height: usize,
+    tile_size: usize,
+    columns: usize,
+    encoding: Encoding,
+    // Decoded tiles by index, `SHARDS` lists of up to `shard_capacity` each, the least recently used first.
+    shards: Vec<Mutex<Vec<(usize, Texels)>>>,
+    shard_capacity: usize,
+}
+
+impl TiledImage {
+    pub fn open(path: &str) -> Result<TiledImage, String> {
+        let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        // Safety: the file is only read, and isn't expected to change while it is rendered from.
+        let map = unsafe { Mmap::map(&file) }.map_err(|e| format!("{}: {}", path, e))?;
+        if map.len() < HEADER_SIZE || &map[..8] != MAGIC {
+            return Err(format!("{}: not a tiled image", path));
+        }
+        let int = |k: usize| u32::from_le_bytes(map[8 + 4 * k..12 + 4 * k].try_into().unwrap()) as usize;
+        let (width, height, tile_size) = (int(0), int(1), int(2));
+        if width == 0 || height == 0 || tile_size == 0 {
+            return Err(format!("{}: empty tiled image", path));
+        }
+        let (columns, rows) = (width.div_ceil(tile_size), height.div_ceil(tile_size));
+        let size = [rows, tile_size, tile_size, 3].iter().try_fold(columns, |size, &n| size.checked_mul(n));
+        match size.and_then(|size| size.checked_add(HEADER_SIZE)) {
+            Some(size) if size == map.len() => {}
+            Some(_) => return Err(format!("{}: truncated tiled image", path)),
+            None => return Err(format!("{}: tiled image of {}x{} is too large", path, width, height)),
+        }
+        let image = TiledImage {
+            path: path.to_string(),
+            map,
+            width,
+            height,
+            tile_size,
+            columns,
+            encoding: Encoding::Linear,
+            shards: Vec::new(),
+            shard_capacity: 0,
+        };
+        Ok(image.with_cache_tiles(DEFAULT_CACHE_TILES))
+    }
+
+    // Empties the cache, which holds the tiles decoded the old way.
+    pub fn with_encoding(self, encoding: Encoding) -> TiledImage {
+        let tiles = self.shards.len() * self.shard_capacity;
+        TiledImage { encoding, ..self.with_cache_tiles(tiles) }
+    }
+
+    // Keeps about `tiles` tiles decoded, at least one per shard.
+    pub fn with_cache_tiles(self, tiles: usize) -> TiledImage {
+        TiledImage {
+            shards: (0..SHARDS).map(|_| Mutex::new(Vec::new())).collect(),
+            shard_capacity: tiles.div_ceil(SHARDS).max(1),
+            ..self
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // Memory of the cache when full.
+    pub fn cache_bytes(&self) -> usize {
+        self.shards.len() * self.shard_capacity * self.tile_size * self.tile_size * std::mem::size_of::<[f32; 3]>()
+    }
+
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    // The decoded texels of tile `index`, from the cache or decoded now and cached.
+    fn texels(&self, index: usize) -> Texels {
+        let shard = &self.shards[index % SHARDS];
+        {
+            let mut tiles = shard.lock().unwrap();
+            if let Some(k) = tiles.iter().position(|(i, _)| *i == index) {
+                let entry = tiles.remove(k);
+                let texels = entry.1.clone();
+                tiles.push(entry);
+                HITS.fetch_add(1, Ordering::Relaxed);
+                return texels;
+            }
+        }
+        // Decoded without holding the lock; two threads may decode the same tile, and both get the same colors.
+        LOADS.fetch_add(1, Ordering::Relaxed);
+        let size = self.tile_size * self.tile_size * 3;
+        let bytes = &self.map[HEADER_SIZE + index * size..HEADER_SIZE + (index + 1) * size];
+        let channel = |c: u8| match self.encoding {
+            Encoding::Linear => c as f32 / 255.0,
+            Encoding::Srgb => srgb_to_linear(c as f64 / 255.0) as f32,
+        };
+        let texels: Texels = Arc::new(bytes.chunks(3).map(|p| [channel(p[0]), channel(p[1]), channel(p[2])]).collect());
+        let mut tiles = shard.lock().unwrap();
+        if tiles.len() >= self.shard_capacity {
+            tiles.remove(0);
+        }
+        tiles.push((index, texels.clone()));
+        texels
+    }
+}
+
+impl crate::assets::Asset for TiledImage {
+    fn bytes(&self) -> usize {
+        self.cache_bytes()
+    }
+}
+
+impl Texture for TiledImage {
+    // Like `image_texture::Image`.
+    fn value(&self, u: f64, v: f64, _: Point3) -> Color {
+        let u = u.clamp(0.0, 1.0);
+        let v = (1.0 - v).clamp(0.0, 1.0);
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+        let t = self.tile_size;
+        let texels = self.texels((j / t) * self.columns + i / t);
+        let [r, g, b] = texels[(j % t) * t + i % t];
+        Color::new(r as f64, g as f64, b as f64)
+    }
+
+    // Counts the cache when full, not the file, which stays on disk.
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.textures += 1;
+        stats.texture_data(self, self.cache_bytes());
+    }
+}
+
+// Whether the file at `path` is a tiled image, which `image_texture::Image::open` then streams from.
+pub fn is_tiled(path: &str) -> bool {
+    let mut magic = [0; 8];
+    std::fs::File::open(path).and_then(|mut file| file.read_exact(&mut magic)).is_ok() && &magic == MAGIC
+}
+
+// Tiles found decoded and tiles decoded since the process started.
+pub fn stats() -> (usize, usize) {
+    (HITS.load(Ordering::Relaxed), LOADS.load(Ordering::Relaxed))
+}
+
+// Converts the image at `input` into a tiled image at `output` with tiles of `tile_size` pixels. Binary PPM files
+// (P6) are converted a row of tiles at a time, so that images larger than memory can be converted; other formats
+// are decoded whole first.
+pub fn write(input: &str, output: &str, tile_size: usize) -> Result<(), String> {
+    let out = std::fs::File::create(output).map_err(|e| format!("{}: {}", output, e))?;
+    let mut out = BufWriter::new(out);
+    let written = match input.ends_with(".ppm") {
+        true => {
+            let file = std::fs::File::open(input).map_err(|e| format!("{}: {}", input, e))?;
+            let mut reader = BufReader::new(file);
+            let (width, height) = read_ppm_header(&mut reader).map_err(|e| format!("{}: {}", input, e))?;
+            write_rows(&mut out, width, height, tile_size, |_, row| reader.read_exact(row).map_err(|e| e.to_string()))
+        }
+        false => {
+            let image = image::open(input).map_err(|e| format!("{}: {}", input, e))?.to_rgb8();
+            let (width, height) = (image.width() as usize, image.height() as usize);
+            write_rows(&mut out, width, height, tile_size, |j, row| {
+                row.copy_from_slice(&image.as_raw()[j * width * 3..(j + 1) * width * 3]);
+                Ok(())
+            })
+        }
+    };
+    written.and_then(|()| out.flush().map_err(|e| e.to_string())).map_err(|e| format!("{}: {}", output, e))
+}
+
+// Writes the header and tiles of a width by height image, whose rows from the top `next_row` fills in.
+fn write_rows(
+    out: &mut impl Write,
+    width: usize,
+    height: usize,
+    tile_size: usize,
+    mut next_row: impl FnMut(usize, &mut [u8]) -> Result<(), String>,
+) -> Result<(), String> {
+    let io = |e: std::io::Error| e.to_string();
+    out.write_all(MAGIC).map_err(io)?;
+    for n in [width, height, tile_size] {
+        out.write_all(&(n as u32).to_le_bytes()).map_err(io)?;
+    }
+    let line = width * 3;
+    let padding = vec![0; tile_size * 3];
+    // The rows of one row of tiles, black below the image.
+    let mut band = vec![0; tile_size * line];
+    for y in 0..height.div_ceil(tile_size) {
+        band.iter_mut().for_each(|b| *b = 0);
+        for r in 0..tile_size.min(height - y * tile_size) {
+            next_row(y * tile_size + r, &mut band[r * line..(r + 1) * line])?;
+        }
+        for x in 0..width.div_ceil(tile_size) {
+            let (x0, x1) = (x * tile_size, ((x + 1) * tile_size).min(width));
+            for r in 0..tile_size {
+                out.write_all(&band[r * line + x0 * 3..r * line + x1 * 3]).map_err(io)?;
+                out.write_all(&padding[..(tile_size - (x1 - x0)) * 3]).map_err(io)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Reads "P6 width height 255" and the single whitespace after it, skipping comments.
+fn read_ppm_header(reader: &mut impl BufRead) -> Result<(usize, usize), String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    while fields.len() < 4 {
+        let mut byte = [0];
+        reader.read_exact(&mut byte).map_err(|e| e.to_string())?;
+        match byte[0] {
+            b'#' if field.is_empty() => {
+                reader.read_line(&mut String::new()).map_err(|e| e.to_string())?;
+            }
+            c if c.is_ascii_whitespace() => {
+                if !field.is_empty() {
+                    fields.push(std::mem::take(&mut field));
+                }
+            }
+            c => field.push(c as char),
+        }
+    }
+    let number = |s: &str| s.parse::<usize>().map_err(|_| format!("bad PPM header field '{}'", s));
+    match (fields[0].as_str(), number(&fields[3])?) {
+        ("P6", 255) => Ok((number(&fields[1])?, number(&fields[2])?)),
+        _ => Err("only binary PPM (P6) with 8 bits per channel can be streamed".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Hit;
+    use crate::image_texture::Image;
+    use crate::materials::Material;
+    use crate::vec::{Ray, Vec3};
+    use crate::worlds::MaterialSpec;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_tiled_image() {
+        let dir = std::env::temp_dir();
+        let file =
+            |name: &str| dir.join(format!("tiled_{}_{}", std::process::id(), name)).to_str().unwrap().to_string();
+        // An image whose size isn't a multiple of the tiles, with a different color in every pixel.
+        let (width, height) = (37, 21);
+        let image = RgbImage::from_fn(width, height, |x, y| Rgb([(x * 6) as u8, (y * 12) as u8, ((x + y) * 4) as u8]));
+        let (png, ppm, from_png, from_ppm) = (file("a.png"), file("a.ppm"), file("png.tiles"), file("ppm.tiles"));
+        image.save(&png).unwrap();
+        let mut bytes = format!("P6\n# comment\n{} {}\n255\n", width, height).into_bytes();
+        bytes.extend_from_slice(image.as_raw());
+        std::fs::write(&ppm, bytes).unwrap();
+        write(&png, &from_png, 4).unwrap();
+        write(&ppm, &from_ppm, 4).unwrap();
+        assert_eq!(std::fs::read(&from_png).unwrap(), std::fs::read(&from_ppm).unwrap());
+
+        // Looks up the same colors as the image in memory, with a cache of 16 of its 60 tiles.
+        let reference = Image::new(image.clone()).with_encoding(Encoding::Srgb);
+        let tiled = TiledImage::open(&from_ppm).unwrap().with_cache_tiles(3).with_encoding(Encoding::Srgb);
+        assert_eq!((37, 21), tiled.dimensions());
+        for k in 0..500 {
+            let (u, v) = ((k * 37 % 101) as f64 / 100.0, (k * 53 % 103) as f64 / 102.0);
+            let (a, b) = (reference.value(u, v, Point3::ZERO), tiled.value(u, v, Point3::ZERO));
+            assert!((a - b).length() < 1e-6, "{} {}: {:?} {:?}", u, v, a, b);
+        }
+        assert!(tiled.shards.iter().all(|s| s.lock().unwrap().len() <= 1));
+
+        // Image textures stream tiled files, sharing them by path.
+        assert!(is_tiled(&from_ppm) && !is_tiled(&ppm));
+        let opened = Image::open(&from_ppm).with_encoding(Encoding::Srgb);
+        let material = format!("image,file={},encoding=srgb", from_ppm).parse::<MaterialSpec>().unwrap();
+        assert_eq!(material.to_string().parse::<MaterialSpec>(), Ok(material.clone()));
+        let built = material.build();
+        let (normal, ray) = (Vec3::new(0.0, 0.0, 1.0), Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0)));
+        for k in 0..50 {
+            let (u, v) = ((k * 37 % 101) as f64 / 100.0, (k * 53 % 103) as f64 / 102.0);
+            let (a, b) = (reference.value(u, v, Point3::ZERO), opened.value(u, v, Point3::ZERO));
+            assert!((a - b).length() < 1e-6, "{} {}: {:?} {:?}", u, v, a, b);
+            let hit = Hit::new_with_face_normal(&Point3::ZERO, 1.0, u, v, &normal, &ray, built.as_ref());
+            let c = built.eval(&ray, &hit, &normal).unwrap() * std::f64::consts::PI;
+            assert!((a - c).length() < 1e-6, "{} {}: {:?} {:?}", u, v, a, c);
+        }
+
+        // Headers of sizes that overflow are errors.
+        let mut header = b"RTTILES1".to_vec();
+        for n in [u32::MAX, u32::MAX, 1] {
+            header.extend_from_slice(&n.to_le_bytes());
+        }
+        std::fs::write(&from_png, header).unwrap();
+        assert!(TiledImage::open(&from_png).err().unwrap().contains("too large"));
+        std::fs::write(&from_png, b"RTTILES1\x01\0\0\0").unwrap();
+        assert!(TiledImage::open(&from_png).is_err());
+        assert!(TiledImage::open(&png).is_err());
+        for path in [png, ppm, from_png, from_ppm] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
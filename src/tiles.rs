@@ -0,0 +1,227 @@
+// Splitting the image into square tiles and the order they are rendered in, see `Renderer::with_tile_order`.
+// Starting from the center shows the interesting part of the image first, and is what a render cut short by a
+// time limit keeps.
+use std::ops::Range;
+
+// Side of a tile in pixels.
+pub const TILE_SIZE: usize = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TileOrder {
+    // Row by row, like the lines.
+    Scanline,
+    // Along a Hilbert curve, which keeps the tiles rendered at about the same time close together.
+    Hilbert,
+    // Ring by ring around the center of the image.
+    Spiral,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tile {
+    // Position in the scanline order; it picks the RNG sites, so that the image doesn't depend on the order.
+    pub index: usize,
+    pub i: Range<usize>,
+    pub j: Range<usize>,
+}
+
+// RNG sites of a tile, one for each of its quarters.
+pub const SUBTILES: usize = 4;
+
+impl Tile {
+    // The pixels of the quarters of the tile, each drawing from its own RNG site, so that a tile renders the same
+    // whether its quarters are rendered one after the other or split among threads. Quarters of the tiles on the
+    // right and top edges can be empty.
+    pub fn quarters(&self) -> [(Range<usize>, Range<usize>); SUBTILES] {
+        let half = TILE_SIZE / 2;
+        let (mi, mj) = ((self.i.start + half).min(self.i.end), (self.j.start + half).min(self.j.end));
+        let (left, right) = (self.i.start..mi, mi..self.i.end);
+        let (bottom, top) = (self.j.start..mj, mj..self.j.end);
+        [(left.clone(), bottom.clone()), (right.clone(), bottom), (left, top.clone()), (right, top)]
+    }
+}
+
+// The tiles covering a width by height image, in `order`. Tiles on the right and top edges can be smaller.
+pub fn tiles(width: usize, height: usize, order: TileOrder) -> Vec<Tile> {
+    let (columns, rows) = (width.div_ceil(TILE_SIZE), height.div_ceil(TILE_SIZE));
+    let tile = |x: usize, y: usize| Tile {
+        index: y * columns + x,
+        i: x * TILE_SIZE..((x + 1) * TILE_SIZE).min(width),
+        j: y * TILE_SIZE..((y + 1) * TILE_SIZE).min(height),
+    };
+    let grid = (0..rows).flat_map(|y| (0..columns).map(move |x| (x, y)));
+    match order {
+        TileOrder::Scanline => grid.map(|(x, y)| tile(x, y)).collect(),
+        TileOrder::Hilbert => {
+            // The curve fills a power of two square, skipping the cells outside the image.
+            let n = columns.max(rows).next_power_of_two();
+            (0..n * n)
+                .map(|d| hilbert_cell(n, d))
+                .filter(|&(x, y)| x < columns && y < rows)
+                .map(|(x, y)| tile(x, y))
+                .collect()
+        }
+        TileOrder::Spiral => {
+            // Rings are the tiles at the same distance from the center in the maximum norm, which turn around
+            // it by angle.
+            let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+            let key = |&(x, y): &(usize, usize)| {
+                let dx = ((x as f64 + 0.5) * TILE_SIZE as f64 - cx) / TILE_SIZE as f64;
+                let dy = ((y as f64 + 0.5) * TILE_SIZE as f64 - cy) / TILE_SIZE as f64;
+                (dx.abs().max(dy.abs()).round(), dy.atan2(dx))
+            };
+            let mut cells: Vec<_> = grid.collect();
+            cells.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+            cells.into_iter().map(|(x, y)| tile(x, y)).collect()
+        }
+    }
+}
+
+// Cell `d` along the Hilbert curve through an n by n grid, n a power of two.
+fn hilbert_cell(n: usize, d: usize) -> (usize, usize) {
+    let (mut x, mut y, mut t) = (0, 0, d);
+    let mut s = 1;
+    while s < n {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
+
+// Where a tile of a width by height image is, in pixels from the top left, as images are viewed; `Tile::j`
+// counts lines from the bottom like the film.
+fn pixels(tile: &Tile, height: usize) -> (Range<usize>, Range<usize>) {
+    (tile.i.clone(), height - tile.j.end..height - tile.j.start)
+}
+
+// The `count` tiles of a width by height image that took longest by `times`, see `Accumulator::tile_times`, as
+// lines to print: where they are, and how their time compares with the median tile's and the total, to find what
+// is expensive to render, e.g. glass or a mesh seen up close.
+pub fn report(width: usize, height: usize, times: &[f64], count: usize) -> Vec<String> {
+    let mut tiles = tiles(width, height, TileOrder::Scanline);
+    if times.len() != tiles.len() {
+        return Vec::new();
+    }
+    let mut sorted = times.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let (median, total) = (sorted[sorted.len() / 2], sorted.iter().sum::<f64>());
+    tiles.sort_by(|a, b| times[b.index].total_cmp(&times[a.index]));
+    let slowest = tiles.iter().take(count).map(|tile| {
+        let (x, y) = pixels(tile, height);
+        let t = times[tile.index];
+        format!(
+            "  x {}..{}, y {}..{}: {:.3}s, {:.1}x the median, {:.1}% of the time",
+            x.start,
+            x.end,
+            y.start,
+            y.end,
+            t,
+            t / median.max(f64::MIN_POSITIVE),
+            100.0 * t / total.max(f64::MIN_POSITIVE)
+        )
+    });
+    let header = format!("Slowest tiles of {} (median {:.3}s, total {:.3}s):", times.len(), median, total);
+    std::iter::once(header).chain(slowest).collect()
+}
+
+// The times of all the tiles as CSV, a tile per line row by row from the top left, with their pixels.
+pub fn csv(width: usize, height: usize, times: &[f64]) -> String {
+    let mut csv = String::from("index,x0,x1,y0,y1,seconds\n");
+    let mut tiles: Vec<_> =
+        tiles(width, height, TileOrder::Scanline).into_iter().filter(|t| t.index < times.len()).collect();
+    tiles.sort_by_key(|tile| (pixels(tile, height).1.start, tile.i.start));
+    for tile in tiles.iter() {
+        let (x, y) = pixels(tile, height);
+        csv += &format!("{},{},{},{},{},{}\n", tile.index, x.start, x.end, y.start, y.end, times[tile.index]);
+    }
+    csv
+}
+
+impl std::str::FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TileOrder, String> {
+        match s {
+            "scanline" => Ok(TileOrder::Scanline),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            "spiral" => Ok(TileOrder::Spiral),
+            _ => Err(format!("unknown tile order '{}'", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tiles() {
+        for order in [TileOrder::Scanline, TileOrder::Hilbert, TileOrder::Spiral] {
+            // Every pixel is in exactly one tile.
+            let (width, height) = (70, 40);
+            let tiles = tiles(width, height, order);
+            assert_eq!(5 * 3, tiles.len(), "{:?}", order);
+            let mut covered = vec![vec![0; width]; height];
+            for tile in &tiles {
+                for j in tile.j.clone() {
+                    for i in tile.i.clone() {
+                        covered[j][i] += 1;
+                    }
+                }
+            }
+            assert!(covered.iter().flatten().all(|&n| n == 1), "{:?}", order);
+            let mut indices: Vec<_> = tiles.iter().map(|t| t.index).collect();
+            indices.sort();
+            assert_eq!((0..15).collect::<Vec<_>>(), indices, "{:?}", order);
+        }
+        // Consecutive tiles of the Hilbert curve are neighbours.
+        let hilbert = tiles(64, 64, TileOrder::Hilbert);
+        for pair in hilbert.windows(2) {
+            let distance = pair[0].i.start.abs_diff(pair[1].i.start) + pair[0].j.start.abs_diff(pair[1].j.start);
+            assert_eq!(TILE_SIZE, distance);
+        }
+        // The spiral starts in the middle.
+        let spiral = tiles(80, 48, TileOrder::Spiral);
+        assert_eq!((32..48, 16..32), (spiral[0].i.clone(), spiral[0].j.clone()));
+        assert_eq!(0..16, spiral.last().unwrap().i.start..spiral.last().unwrap().i.end);
+        assert_eq!(Ok(TileOrder::Spiral), "spiral".parse());
+        assert!("zigzag".parse::<TileOrder>().is_err());
+
+        // The quarters of a tile cover it, even on the edges.
+        for tile in tiles(70, 40, TileOrder::Scanline) {
+            let quarters = tile.quarters();
+            let pixels: usize = quarters.iter().map(|(i, j)| i.len() * j.len()).sum();
+            assert_eq!(tile.i.len() * tile.j.len(), pixels);
+            let inside = |(i, j): &(Range<usize>, Range<usize>)| {
+                tile.i.start <= i.start && i.end <= tile.i.end && tile.j.start <= j.start && j.end <= tile.j.end
+            };
+            assert!(quarters.iter().all(inside));
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        // Three by two tiles, the one at the top right ten times slower.
+        let times = [1.0, 1.0, 1.0, 1.0, 1.0, 10.0];
+        let report = report(40, 30, &times, 2);
+        assert_eq!(3, report.len());
+        assert_eq!("Slowest tiles of 6 (median 1.000s, total 15.000s):", report[0]);
+        assert_eq!("  x 32..40, y 0..14: 10.000s, 10.0x the median, 66.7% of the time", report[1]);
+        assert!(super::report(40, 30, &[], 2).is_empty());
+        let csv = csv(40, 30, &times);
+        assert_eq!(7, csv.lines().count());
+        let rows: Vec<_> = csv.lines().skip(1).collect();
+        assert_eq!(("3,0,16,0,14,1", "5,32,40,0,14,10"), (rows[0], rows[2]));
+        assert_eq!("2,32,40,14,30,1", rows[5]);
+    }
+}
@@ -1,8 +1,12 @@
 use crate::bhv::{Bounded, AABB};
 use crate::hittable::{Hit, Hittable};
+use crate::interval::Interval;
+use crate::math::Mat3;
+use crate::scene::{self, SceneWriter};
+use crate::validate::SceneStats;
 use crate::vec::{Point3, Ray, Vec3};
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Axis {
     X,
     Y,
@@ -17,6 +21,13 @@ pub fn index(a: Axis) -> usize {
     }
 }
 
+// `h` of the original shape at `p` of the transformed one, with the geometric normal `normal` and the
+// tangents and shading offset of `h`.
+fn moved<'a>(h: &Hit<'a>, p: Point3, normal: &Vec3, r: &Ray) -> Hit<'a> {
+    let moved = Hit::new_with_face_normal(&p, h.t, h.u, h.v, normal, r, h.material).with_tangents(h.dpdu, h.dpdv);
+    Hit { shading_offset: h.shading_offset, ..moved }
+}
+
 pub struct Translate<T: Hittable> {
     original: T,
     offset: Vec3,
@@ -29,116 +40,302 @@ impl<T: Hittable> Translate<T> {
 }
 
 impl<T: Hittable> Hittable for Translate<T> {
-    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, rng: &mut dyn rand::RngCore) -> Option<Hit<'a>> {
-        let moved_r = Ray { orig: r.orig - self.offset, dir: r.dir };
+    fn hit<'a>(&'a self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> Option<Hit<'a>> {
+        let moved_r = Ray { orig: r.orig - self.offset, ..*r };
 
-        match self.original.hit(&moved_r, t_min, t_max, rng) {
+        match self.original.hit(&moved_r, ray_t, rng) {
             None => None,
-            Some(h) => {
-                Some(Hit::new_with_face_normal(&(h.p + self.offset), h.t, h.u, h.v, &h.normal, &moved_r, h.material))
-            }
+            Some(h) => Some(moved(&h, h.p + self.offset, &h.geometric_normal, &moved_r).with_shading_normal(&h.normal)),
         }
     }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> bool {
+        let moved_r = Ray { orig: r.orig - self.offset, ..*r };
+        self.original.hit_any(&moved_r, ray_t, rng)
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, rng: &mut dyn rand::RngCore) -> f64 {
+        self.original.pdf_value(&(o - &self.offset), v, rng)
+    }
+
+    fn random_point(&self, o: &Point3, rng: &mut dyn rand::RngCore) -> Point3 {
+        self.original.random_point(&(o - &self.offset), rng) + self.offset
+    }
+
+    fn sample_surface<'a>(&'a self, rng: &mut dyn rand::RngCore) -> Option<(Hit<'a>, f64)> {
+        let (h, pdf) = self.original.sample_surface(rng)?;
+        Some((Hit { p: h.p + self.offset, ..h }, pdf))
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.check_point("translation", &self.offset);
+        let mut original = SceneStats::new();
+        self.original.inspect(&mut original);
+        stats.merge(original, |b| AABB::new(b.min() + self.offset, b.max() + self.offset));
+    }
+
+    fn export(&self, scene: &mut SceneWriter) -> Result<(), String> {
+        scene.transformed(format!("translate={}", scene::vector(self.offset)), |scene| self.original.export(scene))
+    }
 }
 
 impl<T: Bounded> Bounded for Translate<T> {
+    fn bounding_box(&self) -> AABB {
+        self.original.bounding_box().translate(self.offset)
+    }
+}
+
+// Uniform scaling about the origin by a positive `factor`, e.g. from the units of an imported mesh to the
+// world's. Directions and so solid angles are unchanged, only lengths and areas.
+pub struct Scale<T: Hittable> {
+    original: T,
+    factor: f64,
+}
+
+impl<T: Hittable> Scale<T> {
+    pub fn new(factor: f64, original: T) -> Scale<T> {
+        assert!(factor > 0.0, "scale by {}", factor);
+        Scale { original, factor }
+    }
+
+    // The ray in the original's space, scaled down along with its direction so `t` stays the same.
+    fn scale_back(&self, r: &Ray) -> Ray {
+        Ray { orig: r.orig / self.factor, dir: r.dir / self.factor, ..*r }
+    }
+
+    fn scaled(&self, b: &AABB) -> AABB {
+        if b.is_empty() {
+            *b
+        } else {
+            AABB::new(self.factor * b.min(), self.factor * b.max())
+        }
+    }
+}
+
+impl<T: Hittable> Hittable for Scale<T> {
+    fn hit<'a>(&'a self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> Option<Hit<'a>> {
+        let scaled_r = self.scale_back(r);
+        let h = self.original.hit(&scaled_r, ray_t, rng)?;
+        let s = self.factor;
+        let h = Hit { dpdu: s * h.dpdu, dpdv: s * h.dpdv, shading_offset: s * h.shading_offset, ..h };
+        Some(moved(&h, s * h.p, &h.geometric_normal, &scaled_r).with_shading_normal(&h.normal))
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> bool {
+        self.original.hit_any(&self.scale_back(r), ray_t, rng)
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, rng: &mut dyn rand::RngCore) -> f64 {
+        self.original.pdf_value(&(*o / self.factor), v, rng)
+    }
+
+    fn random_point(&self, o: &Point3, rng: &mut dyn rand::RngCore) -> Point3 {
+        self.factor * self.original.random_point(&(*o / self.factor), rng)
+    }
+
+    // Areas grow by the square of the factor, so the density by area shrinks by it.
+    fn sample_surface<'a>(&'a self, rng: &mut dyn rand::RngCore) -> Option<(Hit<'a>, f64)> {
+        let (h, pdf) = self.original.sample_surface(rng)?;
+        let s = self.factor;
+        let h = Hit { p: s * h.p, dpdu: s * h.dpdu, dpdv: s * h.dpdv, shading_offset: s * h.shading_offset, ..h };
+        Some((h, pdf / (s * s)))
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        let mut original = SceneStats::new();
+        self.original.inspect(&mut original);
+        stats.merge(original, |b| self.scaled(&b));
+    }
+
+    fn export(&self, scene: &mut SceneWriter) -> Result<(), String> {
+        scene.transformed(format!("scale={}", self.factor), |scene| self.original.export(scene))
+    }
+}
+
+impl<T: Bounded> Bounded for Scale<T> {
+    fn bounding_box(&self) -> AABB {
+        self.scaled(&self.original.bounding_box())
+    }
+}
+
+// Translation that changes linearly from `offset0` at `time0` to `offset1` at `time1`, e.g. a moving sphere.
+// Rays are moved by the offset at their time; before `time0` and after `time1` the object stands still.
+// Light sampling sees the object at `time0`.
+pub struct Moving<T: Hittable> {
+    original: T,
+    offset0: Vec3,
+    offset1: Vec3,
+    time0: f64,
+    time1: f64,
+}
+
+impl<T: Hittable> Moving<T> {
+    pub fn new(offset0: Vec3, offset1: Vec3, time0: f64, time1: f64, original: T) -> Moving<T> {
+        Moving { original, offset0, offset1, time0, time1 }
+    }
+
+    pub fn offset(&self, time: f64) -> Vec3 {
+        if self.time1 <= self.time0 {
+            return self.offset0;
+        }
+        let f = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        (1.0 - f) * self.offset0 + f * self.offset1
+    }
+}
+
+impl<T: Hittable> Hittable for Moving<T> {
+    fn hit<'a>(&'a self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> Option<Hit<'a>> {
+        let offset = self.offset(r.time);
+        let moved_r = Ray { orig: r.orig - offset, ..*r };
+
+        match self.original.hit(&moved_r, ray_t, rng) {
+            None => None,
+            Some(h) => Some(moved(&h, h.p + offset, &h.geometric_normal, &moved_r).with_shading_normal(&h.normal)),
+        }
+    }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> bool {
+        let moved_r = Ray { orig: r.orig - self.offset(r.time), ..*r };
+        self.original.hit_any(&moved_r, ray_t, rng)
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, rng: &mut dyn rand::RngCore) -> f64 {
+        self.original.pdf_value(&(o - &self.offset0), v, rng)
+    }
+
+    fn random_point(&self, o: &Point3, rng: &mut dyn rand::RngCore) -> Point3 {
+        self.original.random_point(&(o - &self.offset0), rng) + self.offset0
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.check_point("motion start", &self.offset0);
+        stats.check_point("motion end", &self.offset1);
+        let mut original = SceneStats::new();
+        self.original.inspect(&mut original);
+        let (offset0, offset1) = (self.offset0, self.offset1);
+        stats.merge(original, |b| {
+            AABB::new(b.min() + offset0, b.max() + offset0).surround(&AABB::new(b.min() + offset1, b.max() + offset1))
+        });
+    }
+}
+
+impl<T: Bounded> Bounded for Moving<T> {
+    // Covers the whole motion.
     fn bounding_box(&self) -> AABB {
         let aabb = self.original.bounding_box();
-        AABB::new(aabb.min() + self.offset, aabb.max() + self.offset)
+        aabb.translate(self.offset0).surround(&aabb.translate(self.offset1))
     }
 }
 
 pub struct Rotate<T: Bounded> {
-    a1: usize,
-    sin_theta: f64,
-    cos_theta: f64,
+    axis: Axis,
+    angle: f64,
+    rotation: Mat3,
     bounding_box: AABB,
     original: T,
 }
 
 impl<T: Bounded> Rotate<T> {
+    // Counter-clockwise by `angle` degrees, looking down `axis`.
     pub fn new(axis: Axis, angle: f64, original: T) -> Rotate<T> {
-        let a1 = index(axis); // if this is Y...
-        let a2 = (a1 + 1) % 3; // ...this is Z...
-        let a0 = (a1 + 2) % 3; // ...and this is X
-
-        let theta = angle * std::f64::consts::PI / 180.0;
-        let sin_theta = theta.sin();
-        let cos_theta = theta.cos();
+        let mut axis_vector = Vec3::ZERO;
+        axis_vector.e[index(axis)] = 1.0;
+        let rotation = Mat3::rotation(axis_vector, angle);
 
         let b = original.bounding_box();
-        let mut min = Point3 { e: [std::f64::NEG_INFINITY; 3] };
+        let mut min = Point3 { e: [std::f64::INFINITY; 3] };
         let mut max = Point3 { e: [std::f64::NEG_INFINITY; 3] };
 
         for i in 0..2 {
             for j in 0..2 {
                 for k in 0..2 {
-                    let a0_v = if i == 1 { b.max().e[a0] } else { b.min().e[a0] };
-                    let a1_v = if j == 1 { b.max().e[a1] } else { b.min().e[a1] };
-                    let a2_v = if k == 1 { b.max().e[a2] } else { b.min().e[a2] };
-
-                    let new_a0_v = cos_theta * a0_v + sin_theta * a2_v;
-                    let new_a2_v = -sin_theta * a0_v + cos_theta * a2_v;
-
-                    let mut tester = [0.0f64; 3];
-                    tester[a0] = new_a0_v;
-                    tester[a1] = a1_v;
-                    tester[a2] = new_a2_v;
+                    let x = if i == 1 { b.max().x() } else { b.min().x() };
+                    let y = if j == 1 { b.max().y() } else { b.min().y() };
+                    let z = if k == 1 { b.max().z() } else { b.min().z() };
+                    let tester = rotation * Vec3::new(x, y, z);
 
                     for c in 0..3 {
-                        min.e[c] = min.e[c].min(tester[c]);
-                        max.e[c] = max.e[c].max(tester[c]);
+                        min.e[c] = min.e[c].min(tester.e[c]);
+                        max.e[c] = max.e[c].max(tester.e[c]);
                     }
                 }
             }
         }
 
-        Rotate { a1, sin_theta, cos_theta, original, bounding_box: AABB::new(min, max) }
-    }
-
-    fn a0(&self) -> usize {
-        (self.a1 + 2) % 3
-    }
-    fn a2(&self) -> usize {
-        (self.a1 + 1) % 3
+        let bounding_box = if b.is_empty() { AABB::EMPTY } else { AABB::new(min, max) };
+        Rotate { axis, angle, rotation, original, bounding_box }
     }
 
     fn rotate_back(&self, v: &Vec3) -> Vec3 {
-        let a0 = self.a0();
-        let a2 = self.a2();
-
-        let mut result = *v;
-        result.e[a0] = self.cos_theta * v.e[a0] - self.sin_theta * v.e[a2];
-        result.e[a2] = self.sin_theta * v.e[a0] + self.cos_theta * v.e[a2];
-        result
+        // The inverse of a rotation is its transpose.
+        self.rotation.transpose() * *v
     }
 
     fn rotate(&self, v: &Vec3) -> Vec3 {
-        let a0 = self.a0();
-        let a2 = self.a2();
-
-        let mut result = *v;
-        result.e[a0] = self.cos_theta * v.e[a0] + self.sin_theta * v.e[a2];
-        result.e[a2] = -self.sin_theta * v.e[a0] + self.cos_theta * v.e[a2];
-        result
+        self.rotation * *v
     }
 }
 
 impl<T: Bounded> Hittable for Rotate<T> {
-    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, rng: &mut dyn rand::RngCore) -> Option<Hit<'a>> {
+    fn hit<'a>(&'a self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> Option<Hit<'a>> {
         let o = self.rotate_back(&r.orig);
         let d = self.rotate_back(&r.dir);
 
-        let rotated_r = Ray::new(o, d);
-        match self.original.hit(&rotated_r, t_min, t_max, rng) {
+        let rotated_r = Ray::at_time(o, d, r.time);
+        match self.original.hit(&rotated_r, ray_t, rng) {
             None => None,
             Some(h) => {
                 let p = self.rotate(&h.p);
-                let normal = self.rotate(&h.normal);
-                Some(Hit::new_with_face_normal(&p, h.t, h.u, h.v, &normal, &rotated_r, h.material))
+                let normal = self.rotate(&h.geometric_normal);
+                let h = Hit {
+                    dpdu: self.rotate(&h.dpdu),
+                    dpdv: self.rotate(&h.dpdv),
+                    shading_offset: self.rotate(&h.shading_offset),
+                    ..h
+                };
+                Some(moved(&h, p, &normal, &rotated_r).with_shading_normal(&self.rotate(&h.normal)))
             }
         }
     }
+
+    fn hit_any(&self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> bool {
+        let rotated_r = Ray::at_time(self.rotate_back(&r.orig), self.rotate_back(&r.dir), r.time);
+        self.original.hit_any(&rotated_r, ray_t, rng)
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, rng: &mut dyn rand::RngCore) -> f64 {
+        self.original.pdf_value(&self.rotate_back(o), &self.rotate_back(v), rng)
+    }
+
+    fn random_point(&self, o: &Point3, rng: &mut dyn rand::RngCore) -> Point3 {
+        self.rotate(&self.original.random_point(&self.rotate_back(o), rng))
+    }
+
+    fn sample_surface<'a>(&'a self, rng: &mut dyn rand::RngCore) -> Option<(Hit<'a>, f64)> {
+        let (h, pdf) = self.original.sample_surface(rng)?;
+        let h = Hit {
+            p: self.rotate(&h.p),
+            normal: self.rotate(&h.normal),
+            geometric_normal: self.rotate(&h.geometric_normal),
+            shading_offset: self.rotate(&h.shading_offset),
+            dpdu: self.rotate(&h.dpdu),
+            dpdv: self.rotate(&h.dpdv),
+            ..h
+        };
+        Some((h, pdf))
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.check_finite("rotation", &self.rotation.m.concat());
+        let mut original = SceneStats::new();
+        self.original.inspect(&mut original);
+        stats.merge(original, |_| self.bounding_box);
+    }
+
+    fn export(&self, scene: &mut SceneWriter) -> Result<(), String> {
+        let axis = ["x", "y", "z"][index(self.axis)];
+        scene.transformed(format!("rotate_{}={}", axis, self.angle), |scene| self.original.export(scene))
+    }
 }
 
 impl<T: Bounded> Bounded for Rotate<T> {
@@ -146,3 +343,66 @@ impl<T: Bounded> Bounded for Rotate<T> {
         self.bounding_box
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Lambertian;
+    use crate::shapes::Sphere;
+    use crate::textures::SolidColor;
+
+    #[test]
+    fn test_moving() {
+        let sphere = Sphere::new(Point3::ZERO, 1.0, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)));
+        let moving = Moving::new(Vec3::ZERO, Vec3::new(0.0, 4.0, 0.0), 0.0, 1.0, sphere);
+        let mut rng = rand::thread_rng();
+        let mut hit_at = |y: f64, time: f64| {
+            let r = Ray::at_time(Point3::new(0.0, y, 5.0), Vec3::new(0.0, 0.0, -1.0), time);
+            moving.hit(&r, Interval::FORWARD, &mut rng).map(|h| (h.p.z(), h.time))
+        };
+        assert_eq!(Some((1.0, 0.0)), hit_at(0.0, 0.0));
+        assert_eq!(None, hit_at(0.0, 1.0));
+        assert_eq!(Some((1.0, 0.5)), hit_at(2.0, 0.5));
+        assert_eq!(Some((1.0, 2.0)), hit_at(4.0, 2.0));
+
+        let bounds = moving.bounding_box();
+        assert_eq!((-1.0, 5.0), (bounds.min().y(), bounds.max().y()));
+    }
+
+    #[test]
+    fn test_rotate_bounds() {
+        // The box starts from the corners of the original rather than from minus infinity, so it stays finite.
+        let sphere = Sphere::new(Point3::new(3.0, 0.0, 0.0), 1.0, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)));
+        let rotated = Rotate::new(Axis::Y, 90.0, sphere);
+        let bounds = rotated.bounding_box();
+        assert!((bounds.min() - Point3::new(-1.0, -1.0, -4.0)).length() < 1e-9, "{:?}", bounds.min());
+        assert!((bounds.max() - Point3::new(1.0, 1.0, -2.0)).length() < 1e-9, "{:?}", bounds.max());
+    }
+
+    #[test]
+    fn test_scale() {
+        let sphere = Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)));
+        let scaled = Scale::new(2.0, sphere);
+        let mut rng = rand::thread_rng();
+        let r = Ray::new(Point3::new(0.0, 2.0, 10.0), Vec3::new(0.0, 0.0, -1.0));
+        let h = scaled.hit(&r, Interval::FORWARD, &mut rng).unwrap();
+        assert!((h.t - 8.0).abs() < 1e-12 && (h.p - Point3::new(0.0, 2.0, 2.0)).length() < 1e-12);
+        assert!((h.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-12 && h.front_face);
+        let bounds = scaled.bounding_box();
+        assert_eq!((Point3::new(-2.0, 0.0, -2.0), Point3::new(2.0, 4.0, 2.0)), (bounds.min(), bounds.max()));
+
+        // Sampled by area over four times the area.
+        let (h, pdf) = scaled.sample_surface(&mut rng).unwrap();
+        assert!(((h.p - Point3::new(0.0, 2.0, 0.0)).length() - 2.0).abs() < 1e-9);
+        assert!((pdf - 1.0 / (16.0 * std::f64::consts::PI)).abs() < 1e-12);
+        // The solid angle a sphere covers doesn't change with the scale of the scene.
+        let o = Point3::new(0.0, 2.0, 10.0);
+        let v = Vec3::new(0.0, 0.0, -1.0);
+        let unscaled = Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)));
+        assert!((scaled.pdf_value(&o, &v, &mut rng) - unscaled.pdf_value(&(o / 2.0), &v, &mut rng)).abs() < 1e-12);
+        // Tangents grow with the surface.
+        let h = scaled.hit(&r, Interval::FORWARD, &mut rng).unwrap();
+        let small = unscaled.hit(&Ray::new(o / 2.0, v), Interval::FORWARD, &mut rng).unwrap();
+        assert!((h.dpdu - 2.0 * small.dpdu).length() < 1e-9 && (h.dpdv - 2.0 * small.dpdv).length() < 1e-9);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::bhv::AABB;
+use crate::vec::Vec3;
+use std::collections::HashMap;
+use std::fmt;
+
+// What `--validate` found walking the world, see `Hittable::inspect`.
+#[derive(Default)]
+pub struct SceneStats {
+    pub objects: usize,
+    pub materials: usize,
+    pub textures: usize,
+    // None if nothing in the world reported bounds.
+    pub bounds: Option<AABB>,
+    // Rough size of the world in memory, in bytes, split by what it's used for; see `memory`.
+    pub geometry_memory: usize,
+    pub bvh_memory: usize,
+    // Size of texture data by its address, so data shared between textures is counted once.
+    texture_data: HashMap<usize, usize>,
+
+    pub bvh_nodes: usize,
+    pub bvh_leaves: usize,
+    pub bvh_max_depth: usize,
+    // Name and object count of each `bhv::Group`.
+    pub groups: Vec<(String, usize)>,
+
+    pub problems: Vec<String>,
+}
+
+impl SceneStats {
+    pub fn new() -> SceneStats {
+        SceneStats::default()
+    }
+
+    // Counts a shape of `size` bytes covering `bounds`.
+    pub fn object(&mut self, size: usize, bounds: Option<AABB>) {
+        self.objects += 1;
+        self.geometry_memory += size;
+        if let Some(b) = bounds {
+            self.add_bounds(b);
+        }
+    }
+
+    pub fn add_bounds(&mut self, b: AABB) {
+        self.bounds = Some(match self.bounds {
+            None => b,
+            Some(bounds) => bounds.surround(&b),
+        });
+    }
+
+    // Counts `size` bytes of texture data at `data`, unless another texture already counted it.
+    pub fn texture_data<T>(&mut self, data: &T, size: usize) {
+        self.texture_data.insert(data as *const T as usize, size);
+    }
+
+    pub fn texture_memory(&self) -> usize {
+        self.texture_data.values().sum()
+    }
+
+    pub fn memory(&self) -> usize {
+        self.geometry_memory + self.bvh_memory + self.texture_memory()
+    }
+
+    // E.g. "~12.0 KiB (geometry ~4.0 KiB, bvh ~2.0 KiB, textures ~6.0 KiB)".
+    pub fn memory_summary(&self) -> String {
+        let kib = |bytes: usize| format!("~{:.1} KiB", bytes as f64 / 1024.0);
+        format!(
+            "{} (geometry {}, bvh {}, textures {})",
+            kib(self.memory()),
+            kib(self.geometry_memory),
+            kib(self.bvh_memory),
+            kib(self.texture_memory())
+        )
+    }
+
+    pub fn problem(&mut self, problem: String) {
+        self.problems.push(problem);
+    }
+
+    // Reports a problem if any of `values` is NaN or infinite.
+    pub fn check_finite(&mut self, what: &str, values: &[f64]) {
+        if values.iter().any(|v| !v.is_finite()) {
+            self.problem(format!("{} has non-finite coordinates {:?}", what, values));
+        }
+    }
+
+    pub fn check_point(&mut self, what: &str, p: &Vec3) {
+        self.check_finite(what, &p.e);
+    }
+
+    // Adds the stats of a transformed subtree, whose bounds in world space are `bounds`.
+    pub fn merge(&mut self, other: SceneStats, bounds: impl FnOnce(AABB) -> AABB) {
+        self.objects += other.objects;
+        self.materials += other.materials;
+        self.textures += other.textures;
+        self.geometry_memory += other.geometry_memory;
+        self.bvh_memory += other.bvh_memory;
+        self.texture_data.extend(other.texture_data);
+        self.bvh_nodes += other.bvh_nodes;
+        self.bvh_leaves += other.bvh_leaves;
+        self.bvh_max_depth = self.bvh_max_depth.max(other.bvh_max_depth);
+        self.groups.extend(other.groups);
+        self.problems.extend(other.problems);
+        if let Some(b) = other.bounds {
+            self.add_bounds(bounds(b));
+        }
+    }
+}
+
+impl fmt::Display for SceneStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "objects:   {}", self.objects)?;
+        writeln!(f, "materials: {}", self.materials)?;
+        writeln!(f, "textures:  {}", self.textures)?;
+        match self.bounds {
+            None => writeln!(f, "bounds:    unknown")?,
+            Some(b) => writeln!(f, "bounds:    {}", b)?,
+        }
+        writeln!(f, "memory:    {}", self.memory_summary())?;
+        writeln!(
+            f,
+            "bvh:       {} nodes, {} leaves, max depth {}",
+            self.bvh_nodes, self.bvh_leaves, self.bvh_max_depth
+        )?;
+        for (name, objects) in self.groups.iter() {
+            writeln!(f, "group:     {}, {} objects", name, objects)?;
+        }
+        if self.problems.is_empty() {
+            writeln!(f, "no problems found")
+        } else {
+            for p in self.problems.iter() {
+                writeln!(f, "problem:   {}", p)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::{Hittable, HittableList};
+    use crate::image_texture::Image;
+    use crate::materials::Lambertian;
+    use crate::shapes::{Sphere, XZRect};
+    use crate::textures::SolidColor;
+    use crate::transforms::Translate;
+    use crate::vec::Point3;
+    use crate::volumes::ConstantMedium;
+
+    #[test]
+    fn test_counts_and_bounds() {
+        let white = Lambertian::new(SolidColor::new(0.73, 0.73, 0.73));
+        let mut world = HittableList::new();
+        world.add(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, white));
+        world.add(Translate::new(Vec3::new(0.0, 10.0, 0.0), XZRect::new(-1.0, 1.0, -1.0, 1.0, 0.0, white)));
+        let mut stats = SceneStats::new();
+        world.inspect(&mut stats);
+
+        assert_eq!(2, stats.objects);
+        assert_eq!(2, stats.materials);
+        assert_eq!(2, stats.textures);
+        let bounds = stats.bounds.unwrap();
+        assert_eq!(-1.0, bounds.min().y());
+        assert!((bounds.max().y() - 10.0).abs() < 0.01);
+        assert!(stats.problems.is_empty());
+    }
+
+    #[test]
+    fn test_problems() {
+        let white = Lambertian::new(SolidColor::new(0.73, 0.73, 0.73));
+        let mut world = HittableList::new();
+        world.add(Sphere::new(Point3::new(f64::NAN, 0.0, 0.0), 1.0, white));
+        world.add(ConstantMedium::from_color(Sphere::new(Point3::ZERO, 1.0, white), 0.0, Vec3::ONE));
+        let mut stats = SceneStats::new();
+        world.inspect(&mut stats);
+
+        assert_eq!(2, stats.problems.len(), "{:?}", stats.problems);
+    }
+
+    #[test]
+    fn test_shared_texture_memory() {
+        let image = Image::new(image::RgbImage::new(16, 16));
+        let mut world = HittableList::new();
+        world.add(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, Lambertian::new(image.clone())));
+        world.add(Sphere::new(Point3::new(0.0, 3.0, 0.0), 1.0, Lambertian::new(image)));
+        let mut stats = SceneStats::new();
+        world.inspect(&mut stats);
+
+        assert_eq!(2, stats.textures);
+        assert_eq!(16 * 16 * 3, stats.texture_memory());
+        assert!(stats.geometry_memory > 0);
+        assert_eq!(stats.geometry_memory + 16 * 16 * 3, stats.memory());
+    }
+}
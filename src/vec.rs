@@ -8,7 +8,7 @@ pub struct Vec3 {
 }
 
 impl Vec3 {
-    pub fn new(e0: f64, e1: f64, e2: f64) -> Vec3 {
+    pub const fn new(e0: f64, e1: f64, e2: f64) -> Vec3 {
         Vec3 { e: { [e0, e1, e2] } }
     }
 
@@ -42,6 +42,14 @@ impl Vec3 {
         }
     }
 
+    // Direction in the hemisphere around +z, drawn with a density of cos(theta) / pi, that of the light a diffuse
+    // surface scatters; see `math::Onb` to turn it around a normal.
+    pub fn random_cosine_direction(r: &mut dyn rand::RngCore) -> Vec3 {
+        let (r1, r2): (f64, f64) = (r.gen_range(0.0..1.0), r.gen_range(0.0..1.0));
+        let phi = 2.0 * std::f64::consts::PI * r1;
+        Vec3::new(phi.cos() * r2.sqrt(), phi.sin() * r2.sqrt(), (1.0 - r2).sqrt())
+    }
+
     pub fn random_in_unit_disk(r: &mut dyn rand::RngCore) -> Vec3 {
         loop {
             let p = Vec3::new(r.gen_range(-1.0..1.0), r.gen_range(-1.0..1.0), 0.0);
@@ -111,6 +119,22 @@ impl fmt::Display for Vec3 {
     }
 }
 
+// Parses "x,y,z".
+impl std::str::FromStr for Vec3 {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Vec3, String> {
+        let input: Vec<&str> = s.split(',').collect();
+        if input.len() != 3 {
+            return Err(format!("expected 3 comma-separated components, got '{}'", s));
+        }
+        let mut e = [0.0, 0.0, 0.0];
+        for i in 0..3 {
+            e[i] = input[i].trim().parse::<f64>().map_err(|err| format!("'{}': {}", input[i], err))?;
+        }
+        Ok(Vec3 { e })
+    }
+}
+
 impl ops::Neg for &Vec3 {
     type Output = Vec3;
     fn neg(self) -> Vec3 {
@@ -216,11 +240,16 @@ pub type Color = Vec3;
 pub struct Ray {
     pub orig: Point3,
     pub dir: Vec3,
+    // When the ray is cast, for motion blur.
+    pub time: f64,
 }
 
 impl Ray {
     pub fn new(orig: Point3, dir: Vec3) -> Ray {
-        Ray { orig, dir }
+        Ray { orig, dir, time: 0.0 }
+    }
+    pub fn at_time(orig: Point3, dir: Vec3, time: f64) -> Ray {
+        Ray { orig, dir, time }
     }
     pub fn at(&self, t: f64) -> Point3 {
         &self.orig + &(t * &self.dir)
@@ -1,7 +1,9 @@
 use crate::hittable::{Hit, Hittable};
+use crate::interval::Interval;
 use crate::materials::Material;
 use crate::textures::{SolidColor, Texture};
-use crate::vec::{Color, Ray, Vec3};
+use crate::validate::SceneStats;
+use crate::vec::{Color, Point3, Ray, Vec3};
 use rand::Rng;
 
 pub struct ConstantMedium<O: Hittable, T: Texture> {
@@ -23,18 +25,18 @@ impl<O: Hittable> ConstantMedium<O, SolidColor> {
 }
 
 impl<O: Hittable, T: Texture> Hittable for ConstantMedium<O, T> {
-    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, rng: &mut dyn rand::RngCore) -> Option<Hit<'a>> {
-        let mut h1 = match self.boundary.hit(r, f64::NEG_INFINITY, f64::INFINITY, rng) {
+    fn hit<'a>(&'a self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> Option<Hit<'a>> {
+        let mut h1 = match self.boundary.hit(r, Interval::UNIVERSE, rng) {
             None => return None,
             Some(h) => h,
         };
-        let mut h2 = match self.boundary.hit(r, h1.t + 0.001, f64::INFINITY, rng) {
+        let mut h2 = match self.boundary.hit(r, Interval::new(h1.t + 0.001, f64::INFINITY), rng) {
             None => return None,
             Some(h) => h,
         };
 
-        h1.t = h1.t.max(t_min);
-        h2.t = h2.t.min(t_max);
+        h1.t = h1.t.max(ray_t.min);
+        h2.t = h2.t.min(ray_t.max);
 
         if h1.t >= h2.t {
             return None;
@@ -58,10 +60,206 @@ impl<O: Hittable, T: Texture> Hittable for ConstantMedium<O, T> {
             u: 0.0,
             v: 0.0,
             normal: Vec3::new(1.0, 0.0, 0.0),
+            geometric_normal: Vec3::new(1.0, 0.0, 0.0),
+            shading_offset: Vec3::ZERO,
+            dpdu: Vec3::new(0.0, 1.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 1.0),
             front_face: true,
             material: &self.phase_function,
+            time: r.time,
         })
     }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        if !(self.neg_inv_density < 0.0 && self.neg_inv_density.is_finite()) {
+            stats.problem(format!("medium with density {}", -1.0 / self.neg_inv_density));
+        }
+        stats.geometry_memory += std::mem::size_of_val(self) - std::mem::size_of::<O>();
+        self.boundary.inspect(stats);
+        self.phase_function.inspect(stats);
+    }
+}
+
+// A participating medium filling the space outside of the objects: constant, or exponential height fog whose density
+// falls off by a factor e every 1 / falloff units above `height` (and grows below it). It ends at distance `radius`
+// from the origin, if that is finite.
+#[derive(Copy, Clone, Debug)]
+pub struct FogParams {
+    pub density: f64,
+    pub color: Color,
+    pub falloff: f64,
+    pub height: f64,
+    pub radius: f64,
+}
+
+impl FogParams {
+    pub fn new(density: f64, color: Color) -> FogParams {
+        FogParams { density, color, falloff: 0.0, height: 0.0, radius: f64::INFINITY }
+    }
+
+    // Part of `ray_t` that is inside the fog's radius.
+    pub fn clip(&self, r: &Ray, ray_t: Interval) -> Interval {
+        let radius = self.radius;
+        if radius.is_infinite() {
+            return ray_t;
+        }
+        let a = r.dir.length_squared();
+        let half_b = r.orig.dot(r.dir);
+        let c = r.orig.length_squared() - radius * radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return Interval::EMPTY;
+        }
+        let root = discriminant.sqrt();
+        Interval::new(ray_t.min.max((-half_b - root) / a), ray_t.max.min((-half_b + root) / a))
+    }
+
+    // Distance along `r`, in units of t, from `t0` to a random scattering event; None if the ray gets through.
+    pub fn scatter_distance(&self, r: &Ray, t0: f64, rng: &mut dyn rand::RngCore) -> Option<f64> {
+        let (a, b) = self.optical_depth_coefficients(r, t0);
+        let depth = -rng.gen_range(0.0f64..1.0f64).ln();
+        if b.abs() < 1e-12 {
+            return if a > 0.0 { Some(depth / a) } else { None };
+        }
+        // Rays going up only see a finite optical depth, a / b.
+        let remaining = 1.0 - depth * b / a;
+        if remaining <= 0.0 {
+            return None;
+        }
+        Some(-remaining.ln() / b)
+    }
+
+    // Fraction of the light that gets through the fog along `ray_t` of `r`, which should be clipped already.
+    pub fn transmittance(&self, r: &Ray, ray_t: Interval) -> f64 {
+        if ray_t.is_empty() {
+            return 1.0;
+        }
+        let (a, b) = self.optical_depth_coefficients(r, ray_t.min);
+        let x = ray_t.size();
+        let depth = if b.abs() < 1e-12 { a * x } else { a * -(-b * x).exp_m1() / b };
+        if depth.is_nan() {
+            // 0 * infinity: no fog along an endless ray.
+            return 1.0;
+        }
+        (-depth).exp()
+    }
+
+    pub fn density_at(&self, p: Point3) -> f64 {
+        self.density * (-self.falloff * (p.y() - self.height)).exp()
+    }
+
+    // (a, b) such that the optical depth from t0 to t0 + x is a * x for constant fog, and a * (1 - exp(-b * x)) / b
+    // with a falloff.
+    fn optical_depth_coefficients(&self, r: &Ray, t0: f64) -> (f64, f64) {
+        (self.density_at(r.at(t0)) * r.dir.length(), self.falloff * r.dir.y())
+    }
+}
+
+impl std::str::FromStr for FogParams {
+    type Err = String;
+
+    // The density followed by options: color= as r:g:b or one gray value, falloff=, height= and radius=.
+    fn from_str(s: &str) -> Result<FogParams, String> {
+        let mut options = s.split(',').map(|o| o.trim());
+        let density = options.next().unwrap();
+        let mut fog =
+            FogParams::new(density.parse::<f64>().map_err(|e| format!("bad fog density: {}", e))?, Color::ONE);
+        for option in options {
+            let (key, value) = option.split_once('=').ok_or(format!("fog option '{}' has no value", option))?;
+            let number = |value: &str| value.parse::<f64>().map_err(|e| format!("bad value of {}: {}", key, e));
+            match key {
+                "color" => {
+                    let channels = value.split(':').map(number).collect::<Result<Vec<f64>, String>>()?;
+                    fog.color = match channels[..] {
+                        [gray] => Color::new(gray, gray, gray),
+                        [r, g, b] => Color::new(r, g, b),
+                        _ => return Err(format!("color {} is neither r:g:b nor one value", value)),
+                    };
+                }
+                "falloff" => fog.falloff = number(value)?,
+                "height" => fog.height = number(value)?,
+                "radius" => fog.radius = number(value)?,
+                _ => return Err(format!("unknown fog option '{}'", option)),
+            }
+        }
+        Ok(fog)
+    }
+}
+
+// Equiangular sampling (Kulla and Fajardo, 2012): a t in `ray_t` of `r` with density proportional to the inverse
+// square distance of r.at(t) to `target`, and that density. Single scattering of the light at `target` has most of
+// its contribution near it, which distance sampling would rarely pick. `ray_t` may be infinite.
+pub fn sample_equiangular(r: &Ray, ray_t: Interval, target: Point3, rng: &mut dyn rand::RngCore) -> (f64, f64) {
+    let length = r.dir.length();
+    // Closest point of the ray to the target, in units of t, and its distance.
+    let closest = (target - r.orig).dot(r.dir) / (length * length);
+    let distance = (r.at(closest) - target).length().max(1e-9);
+    let angle = |t: f64| ((t - closest) * length / distance).atan();
+    let (angle_min, angle_max) = (angle(ray_t.min), angle(ray_t.max));
+    let theta = angle_min + rng.gen_range(0.0..1.0) * (angle_max - angle_min);
+    let offset = distance * theta.tan();
+    let t = (closest + offset / length).clamp(ray_t.min, ray_t.max);
+    (t, distance * length / ((angle_max - angle_min) * (distance * distance + offset * offset)))
+}
+
+// `world` surrounded by fog: rays scatter in the fog before reaching the objects, or instead of escaping to the
+// background. Unlike `ConstantMedium` it needs no boundary.
+pub struct Fog<'a> {
+    world: Box<dyn Hittable + 'a>,
+    params: FogParams,
+    phase_function: Isotropic<SolidColor>,
+}
+
+impl<'a> Fog<'a> {
+    pub fn new(world: Box<dyn Hittable + 'a>, params: FogParams) -> Fog<'a> {
+        Fog { world, params, phase_function: Isotropic::new(SolidColor::from_color(params.color)) }
+    }
+}
+
+impl<'a> Hittable for Fog<'a> {
+    fn hit<'b>(&'b self, r: &Ray, ray_t: Interval, rng: &mut dyn rand::RngCore) -> Option<Hit<'b>> {
+        let hit = self.world.hit(r, ray_t, rng);
+        let fog_t = self.params.clip(r, ray_t.with_max(hit.as_ref().map_or(ray_t.max, |h| h.t)));
+        if fog_t.is_empty() {
+            return hit;
+        }
+        let t = match self.params.scatter_distance(r, fog_t.min, rng) {
+            Some(x) if fog_t.min + x < fog_t.max => fog_t.min + x,
+            _ => return hit,
+        };
+        Some(Hit {
+            p: r.at(t),
+            t,
+            u: 0.0,
+            v: 0.0,
+            normal: Vec3::new(1.0, 0.0, 0.0),
+            geometric_normal: Vec3::new(1.0, 0.0, 0.0),
+            shading_offset: Vec3::ZERO,
+            dpdu: Vec3::new(0.0, 1.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 1.0),
+            front_face: true,
+            material: &self.phase_function,
+            time: r.time,
+        })
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, rng: &mut dyn rand::RngCore) -> f64 {
+        self.world.pdf_value(o, v, rng)
+    }
+
+    fn random_point(&self, o: &Point3, rng: &mut dyn rand::RngCore) -> Point3 {
+        self.world.random_point(o, rng)
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        let FogParams { density, falloff, height, radius, .. } = self.params;
+        if !(density >= 0.0 && density.is_finite() && falloff.is_finite() && height.is_finite() && radius > 0.0) {
+            stats.problem(format!("fog with density {}, falloff {}, height {}", density, falloff, height));
+        }
+        stats.geometry_memory += std::mem::size_of_val(self);
+        self.world.inspect(stats);
+        self.phase_function.inspect(stats);
+    }
 }
 
 pub struct Isotropic<T: Texture> {
@@ -76,8 +274,75 @@ impl<T: Texture> Isotropic<T> {
 
 impl<T: Texture> Material for Isotropic<T> {
     fn scatter(&self, _: &Ray, h: &Hit, rng: &mut dyn rand::RngCore) -> Option<(Color, Ray)> {
-        let scattered = Ray::new(h.p, Vec3::random_in_unit_sphere(rng));
+        let scattered = Ray::at_time(h.p, Vec3::random_in_unit_sphere(rng), h.time);
         let attenuation = self.albedo.value(h.u, h.v, h.p);
         Some((attenuation, scattered))
     }
+
+    fn eval(&self, _: &Ray, h: &Hit, _: &Vec3) -> Option<Color> {
+        Some(self.albedo.value(h.u, h.v, h.p) / (4.0 * std::f64::consts::PI))
+    }
+
+    fn in_volume(&self) -> bool {
+        true
+    }
+
+    fn inspect(&self, stats: &mut SceneStats) {
+        stats.materials += 1;
+        self.albedo.inspect(stats);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::Empty;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_fog_transmittance() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        let fog = |params| Fog::new(Box::new(Empty {}), params);
+        // Fraction of rays getting 10 units through.
+        let mut through = |fog: &Fog, orig, dir| {
+            let r = Ray::new(orig, dir);
+            let n = 20000;
+            (0..n).filter(|_| fog.hit(&r, Interval::new(0.0, 10.0), &mut rng).is_none()).count() as f64 / n as f64
+        };
+
+        let constant = fog(FogParams::new(0.1, Color::ONE));
+        let expected = (-1.0f64).exp();
+        assert!((through(&constant, Point3::ZERO, Vec3::new(0.0, 0.0, 1.0)) - expected).abs() < 0.01);
+        // Parameters are in units of t, not of distance.
+        assert!((through(&constant, Point3::ZERO, Vec3::new(0.0, 0.0, 0.5)) - (-0.5f64).exp()).abs() < 0.01);
+
+        // Going up from where the density is 0.1, the optical depth is 0.1 * (1 - e^-2) / 0.2.
+        let height = fog("0.1,falloff=0.2,height=3".parse::<FogParams>().unwrap());
+        let expected = (-0.5 * (1.0 - (-2.0f64).exp())).exp();
+        assert!((through(&height, Point3::new(0.0, 3.0, 0.0), Vec3::new(0.0, 1.0, 0.0)) - expected).abs() < 0.01);
+
+        // Only 2 of the 10 units are inside the radius.
+        let bounded = fog(FogParams { radius: 2.0, ..FogParams::new(0.5, Color::ONE) });
+        let expected = (-1.0f64).exp();
+        assert!((through(&bounded, Point3::ZERO, Vec3::new(0.0, 0.0, 1.0)) - expected).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_sample_equiangular() {
+        let mut rng = rand_pcg::Pcg64::seed_from_u64(1);
+        let r = Ray::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(2.0, 0.0, 0.0));
+        let (ray_t, target) = (Interval::new(0.5, 3.0), Point3::new(3.0, 0.0, 0.0));
+        // Samples are in the interval, and their density is right: the mean of 1 / pdf is the interval's size.
+        let n = 100000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let (t, pdf) = sample_equiangular(&r, ray_t, target, &mut rng);
+            assert!(ray_t.contains(t));
+            sum += 1.0 / pdf;
+        }
+        assert!((sum / n as f64 - 2.5).abs() < 0.05, "{}", sum / n as f64);
+        // Most samples are close to the target.
+        let near = (0..1000).filter(|_| (sample_equiangular(&r, ray_t, target, &mut rng).0 - 1.5).abs() < 0.5).count();
+        assert!(near > 500, "{}", near);
+    }
 }
@@ -0,0 +1,146 @@
+// Rendering a built-in world progressively into an RGBA buffer, for embedding the tracer in a web page: with the
+// wasm feature, `Canvas` is exported to JavaScript, see web/index.html.
+use crate::bhv::BvhConfig;
+use crate::camera::{Camera, CameraParams};
+use crate::hittable::Hittable;
+use crate::output;
+use crate::postprocess::PostProcess;
+use crate::raytrace::{Accumulator, Background, Bounces, RecursiveRayTracer, Renderer, RenderingParams};
+use crate::rngator::{Rngator, SeedableRngator};
+use crate::vec::Vec3;
+use crate::worlds;
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub struct Canvas {
+    world: Box<dyn Hittable>,
+    background: Box<dyn Background>,
+    camera: Camera,
+    params: RenderingParams,
+    rngator: SeedableRngator,
+    accumulator: Accumulator,
+}
+
+impl Canvas {
+    pub fn new(world: &str, width: usize, height: usize, seed: u64) -> Result<Canvas, String> {
+        let world = worlds::worlds()
+            .into_iter()
+            .find(|w| w.name() == world)
+            .ok_or_else(|| format!("unknown world '{}'", world))?;
+        let rngator = SeedableRngator::new(seed);
+        let view = world.camera();
+        let camera = CameraParams {
+            lookfrom: view.lookfrom,
+            lookat: view.lookat,
+            up: Vec3::new(0.0, 1.0, 0.0),
+            field_of_view: view.field_of_view,
+            aspect_ratio: width as f64 / height as f64,
+            aperture: 0.0,
+            focus_dist: (view.lookat - view.lookfrom).length(),
+            shift: 0.0,
+            near: 0.0,
+            far: f64::INFINITY,
+        }
+        .camera();
+        let params = RenderingParams {
+            samples_per_pixel: 1,
+            image_width: width,
+            image_height: height,
+            post: PostProcess { lens: world.lens(), ..PostProcess::new() },
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        };
+        Ok(Canvas {
+            world: world.build(BvhConfig::default(), &mut rngator.rng(0)),
+            background: world.background(),
+            camera,
+            params,
+            rngator,
+            accumulator: Accumulator::new(width, height),
+        })
+    }
+
+    // Adds `samples` samples to every pixel; a page calls this once per frame to refine the image as it shows it.
+    pub fn add_samples(&mut self, samples: usize) {
+        let params = RenderingParams { samples_per_pixel: samples, ..self.params };
+        let tracer = RecursiveRayTracer {
+            max_depth: 50,
+            max_bounces: Bounces::UNLIMITED,
+            regularization: None,
+            check_nan: false,
+        };
+        let renderer = Renderer::new_with_rng(
+            &self.camera,
+            self.world.as_ref(),
+            self.background.as_ref(),
+            params,
+            tracer,
+            &self.rngator,
+        );
+        renderer.accumulate(&mut self.accumulator, |_, _| ());
+    }
+
+    pub fn samples_per_pixel(&self) -> usize {
+        self.accumulator.samples_per_pixel()
+    }
+
+    // The image so far, 4 bytes per pixel from the top left, as a canvas' ImageData takes it.
+    pub fn rgba(&self) -> Vec<u8> {
+        output::to_rgba(&self.accumulator.to_rgb(&self.params.post))
+    }
+}
+
+// The same in JavaScript, which has no usize and gets errors as exceptions.
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+impl Canvas {
+    #[wasm_bindgen(constructor)]
+    pub fn create(world: &str, width: u32, height: u32, seed: u32) -> Result<Canvas, JsValue> {
+        Canvas::new(world, width as usize, height as usize, seed as u64).map_err(|e| JsValue::from_str(&e))
+    }
+
+    #[wasm_bindgen(js_name = addSamples)]
+    pub fn add_samples_js(&mut self, samples: u32) {
+        self.add_samples(samples as usize)
+    }
+
+    #[wasm_bindgen(js_name = samplesPerPixel)]
+    pub fn samples_per_pixel_js(&self) -> u32 {
+        self.samples_per_pixel() as u32
+    }
+
+    #[wasm_bindgen(js_name = rgba)]
+    pub fn rgba_js(&self) -> Vec<u8> {
+        self.rgba()
+    }
+}
+
+// Names of the worlds `Canvas::new` takes, for a page to choose from.
+#[cfg_attr(feature = "wasm", wasm_bindgen(js_name = worldNames))]
+pub fn world_names() -> Vec<String> {
+    worlds::worlds().iter().map(|w| w.name().to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canvas() {
+        let mut canvas = Canvas::new("simple", 16, 9, 1).unwrap();
+        canvas.add_samples(2);
+        canvas.add_samples(2);
+        assert_eq!(4, canvas.samples_per_pixel());
+        let rgba = canvas.rgba();
+        assert_eq!(16 * 9 * 4, rgba.len());
+        assert!(rgba.chunks(4).all(|pixel| pixel[3] == 255));
+        // Rows from the top, like the saved images.
+        let image = output::to_image(&canvas.accumulator.to_rgb(&canvas.params.post));
+        assert_eq!(image.get_pixel(3, 1).0[..], rgba[4 * (16 + 3)..4 * (16 + 3) + 3]);
+        assert_eq!(image.get_pixel(15, 8).0[..], rgba[4 * (16 * 9 - 1)..4 * (16 * 9 - 1) + 3]);
+        assert!(Canvas::new("nowhere", 16, 9, 1).is_err());
+        assert!(world_names().contains(&"simple".to_string()));
+    }
+}
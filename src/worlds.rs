@@ -1,21 +1,58 @@
-use crate::bhv;
+use crate::assets;
+use crate::bhv::{self, BvhConfig, AABB};
+use crate::curves;
 use crate::hittable::{Hittable, HittableList};
 use crate::image_texture;
-use crate::materials::{Dielectric, DiffuseLight, Lambertian, Metal};
-use crate::raytrace::{Background, BlackBackground, GradientBackground};
+use crate::materials::{
+    Conductor, Dielectric, DiffuseLight, Holdout, Lambertian, Material, Metal, SharedMaterial, Velvet, Visibility,
+    Visible,
+};
+use crate::measured::{Measured, MerlBrdf};
+use crate::mesh::Mesh;
+use crate::par::*;
+use crate::postprocess::Lens;
+use crate::raytrace::{Background, BlackBackground, GradientBackground, SolidBackground};
+use crate::rngator::SiteRngator;
+use crate::scatter;
+use crate::sdf;
 use crate::shapes::{Block, Sphere, XYRect, XZRect, YZRect};
-use crate::textures::{self, NoiseTexture, SolidColor};
+use crate::textures::{self, NoiseMode, NoiseParams, NoiseTexture, SolidColor};
 use crate::transforms::{self, Axis};
 use crate::vec::{Color, Point3, Vec3};
 use crate::volumes;
-use image;
 use rand::Rng;
+use std::sync::{Arc, Mutex};
 
-pub trait World {
+pub trait World: Send + Sync {
     fn name(&self) -> &'static str;
     fn camera(&self) -> WorldCamera;
     fn background(&self) -> Box<dyn Background>;
-    fn build(&self, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable>;
+    fn build(&self, bvh: BvhConfig, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable>;
+
+    // Emitters that the light sampling tracer samples directly; they must match the lights in `build`.
+    fn lights(&self, _: BvhConfig, _: &mut dyn rand::RngCore) -> HittableList<'static> {
+        HittableList::new()
+    }
+
+    // Lens effects the world is rendered with unless given on the command line.
+    fn lens(&self) -> Lens {
+        Lens::default()
+    }
+
+    // What the world shows, in one line, for `--list_worlds`.
+    fn description(&self) -> &'static str {
+        ""
+    }
+
+    // Settings the world is meant to be rendered with, for `--list_worlds`.
+    fn recommended(&self) -> RecommendedSettings {
+        RecommendedSettings::default()
+    }
+
+    // Files `build` reads, relative to the working directory.
+    fn assets(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
 }
 
 pub struct WorldCamera {
@@ -24,6 +61,47 @@ pub struct WorldCamera {
     pub field_of_view: f64,
 }
 
+// The defaults of the command line unless a world needs more samples, another shape of image or light sampling.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RecommendedSettings {
+    pub samples_per_pixel: usize,
+    // Width and height.
+    pub aspect_ratio: (u32, u32),
+    pub light_sampling: bool,
+}
+
+impl Default for RecommendedSettings {
+    fn default() -> RecommendedSettings {
+        RecommendedSettings { samples_per_pixel: 200, aspect_ratio: (16, 9), light_sampling: false }
+    }
+}
+
+// The world's name, description, camera, recommended settings and assets, as `--list_worlds` prints them.
+pub fn summary(world: &dyn World) -> String {
+    let camera = world.camera();
+    let settings = world.recommended();
+    let mut summary = format!("{}\n", world.name());
+    if !world.description().is_empty() {
+        summary += &format!("    {}\n", world.description());
+    }
+    summary += &format!(
+        "    camera {} looking at {}, field of view {}\n",
+        camera.lookfrom, camera.lookat, camera.field_of_view
+    );
+    summary += &format!(
+        "    --samples_per_pixel={} --aspect_ratio={}:{}{}\n",
+        settings.samples_per_pixel,
+        settings.aspect_ratio.0,
+        settings.aspect_ratio.1,
+        if settings.light_sampling { " --light_sampling" } else { "" }
+    );
+    let assets = world.assets();
+    if !assets.is_empty() {
+        summary += &format!("    needs {}\n", assets.join(", "));
+    }
+    summary
+}
+
 struct Simple {}
 
 impl World for Simple {
@@ -31,6 +109,10 @@ impl World for Simple {
         "simple"
     }
 
+    fn description(&self) -> &'static str {
+        "Diffuse, glass and metal balls on a yellow ground, from the first book."
+    }
+
     fn background(&self) -> Box<dyn Background> {
         Box::new(GradientBackground::default())
     }
@@ -39,13 +121,13 @@ impl World for Simple {
         WorldCamera { lookfrom: Point3::new(-2.0, 2.0, 1.0), lookat: Point3::new(0.0, 0.0, -1.0), field_of_view: 20.0 }
     }
 
-    fn build(&self, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+    fn build(&self, bvh: BvhConfig, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
         let mat_ground = Lambertian::new(SolidColor::new(0.8, 0.8, 0.0));
         let mat_center = Lambertian::new(SolidColor::new(0.1, 0.3, 0.5));
         let mat_left = Dielectric::new(1.5);
         let mat_right = Metal::new(Color::new(0.8, 0.6, 0.2), 0.0);
 
-        let mut world = bhv::SceneBuilder::new();
+        let mut world = bhv::SceneBuilder::with_config(bvh);
 
         world
             .add(Sphere::new(Point3::new(0.0, -100.5, -1.0), 100.0, mat_ground))
@@ -63,43 +145,145 @@ fn rnd01(rng: &mut dyn rand::RngCore) -> f64 {
     rng.gen_range(0.0..1.0)
 }
 
-struct Random {}
+// The book's final scene of small random spheres around three big ones, generalized for benchmarking:
+// `count` spheres on a jittered grid covering [-area, area]² with materials drawn by the weights of
+// `material_mix` (diffuse, metal, glass). The defaults give the book scene.
+pub struct RandomSpheres {
+    pub name: &'static str,
+    pub count: usize,
+    pub area: f64,
+    pub material_mix: [f64; 3],
+    pub checker: bool,
+    // Diffuse spheres bounce up during time [0, 1], see `--shutter`.
+    pub bounce: bool,
+}
+
+impl RandomSpheres {
+    pub fn new(name: &'static str) -> RandomSpheres {
+        RandomSpheres {
+            name,
+            count: 22 * 22,
+            area: 11.0,
+            material_mix: [0.8, 0.15, 0.05],
+            checker: false,
+            bounce: false,
+        }
+    }
+
+    // Weights that pick no material, e.g. all zero, are rejected.
+    pub fn with_material_mix(self, mix: [f64; 3]) -> Result<RandomSpheres, String> {
+        if mix.iter().any(|w| !w.is_finite() || *w < 0.0) || mix.iter().sum::<f64>() <= 0.0 {
+            return Err(format!("material mix {:?} needs weights of at least 0, not all 0", mix));
+        }
+        Ok(RandomSpheres { material_mix: mix, ..self })
+    }
+
+    // The small spheres, each drawn from the rng of its cell of the grid, so they don't move when the spheres
+    // before them change, e.g. with `material_mix`.
+    fn small_spheres(&self, sites: &SiteRngator) -> Vec<SmallSphere> {
+        let total: f64 = self.material_mix.iter().sum();
+        let diffuse = self.material_mix[0] / total;
+        let metal = diffuse + self.material_mix[1] / total;
+
+        let side = (self.count as f64).sqrt().ceil() as usize;
+        let cell = 2.0 * self.area / side as f64;
+        let radius = 0.2 * cell;
+        (0..self.count)
+            .into_par_iter()
+            .filter_map(|i| {
+                let (a, b) = (i / side, i % side);
+                let rng = &mut sites.site(&[a as u64, b as u64]);
+                let choose_mat = rnd01(rng);
+                let x = -self.area + cell * (a as f64 + 0.9 * rnd01(rng));
+                let z = -self.area + cell * (b as f64 + 0.9 * rnd01(rng));
+                let center = Point3::new(x, radius, z);
+                if (center - Point3::new(4.0, radius, 0.0)).length() <= 0.9 {
+                    return None;
+                }
+                let material = if choose_mat < diffuse {
+                    let albedo = Color::random_unit(rng) * Color::random_unit(rng);
+                    SmallMaterial::Diffuse(albedo, Vec3::new(0.0, rng.gen_range(0.0..2.5 * radius), 0.0))
+                } else if choose_mat < metal {
+                    SmallMaterial::Metal(Color::random(0.5, 1.0, rng), rng.gen_range(0.0..0.5))
+                } else {
+                    SmallMaterial::Glass
+                };
+                Some(SmallSphere { center, radius, material })
+            })
+            .collect()
+    }
+}
+
+struct SmallSphere {
+    center: Point3,
+    radius: f64,
+    material: SmallMaterial,
+}
+
+enum SmallMaterial {
+    // How high it bounces with `RandomSpheres::bounce`.
+    Diffuse(Color, Vec3),
+    // With its fuzz.
+    Metal(Color, f64),
+    Glass,
+}
 
-impl World for Random {
+impl World for RandomSpheres {
     fn name(&self) -> &'static str {
-        "random"
+        self.name
     }
+
+    fn description(&self) -> &'static str {
+        match (self.checker, self.bounce) {
+            (_, true) => "The cover of the first book with bouncing balls, for motion blur with --shutter=1.",
+            (true, _) => "The cover of the first book on a checkered ground.",
+            _ => "The cover of the first book: many small random balls around three large ones.",
+        }
+    }
+
+    fn recommended(&self) -> RecommendedSettings {
+        RecommendedSettings { samples_per_pixel: 500, aspect_ratio: (3, 2), ..RecommendedSettings::default() }
+    }
+
     fn background(&self) -> Box<dyn Background> {
         Box::new(GradientBackground::default())
     }
     fn camera(&self) -> WorldCamera {
-        WorldCamera { lookfrom: Point3::new(13.0, 2.0, 3.0), lookat: Point3::new(0.0, 0.0, 0.0), field_of_view: 20.0 }
+        let scale = self.area.max(11.0) / 11.0;
+        WorldCamera {
+            lookfrom: scale * Point3::new(13.0, 2.0, 3.0),
+            lookat: Point3::new(0.0, 0.0, 0.0),
+            field_of_view: 20.0,
+        }
     }
 
-    fn build(&self, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
-        let mut world = bhv::SceneBuilder::new();
+    fn build(&self, bvh: BvhConfig, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+        let mut world = bhv::SceneBuilder::with_config(bvh);
 
-        let ground_material = Lambertian::new(SolidColor::new(0.5, 0.5, 0.5));
-        world.add(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, ground_material));
+        if self.checker {
+            let checker = textures::Checker::new(SolidColor::new(0.2, 0.3, 0.1), SolidColor::new(0.9, 0.9, 0.9));
+            world.add(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::new(checker)));
+        } else {
+            let ground_material = Lambertian::new(SolidColor::new(0.5, 0.5, 0.5));
+            world.add(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, ground_material));
+        }
 
-        for a in -11..11 {
-            for b in -11..11 {
-                let choose_mat = rnd01(rng);
-                let center = Point3::new(a as f64 + 0.9 * rnd01(rng), 0.2, b as f64 + 0.9 * rnd01(rng));
-
-                if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                    if choose_mat < 0.8 {
-                        let albedo = Color::random_unit(rng) * Color::random_unit(rng);
-                        let solid = SolidColor::from_color(albedo);
-                        world.add(Sphere::new(center, 0.2, Lambertian::new(solid)));
-                    } else if choose_mat < 0.95 {
-                        let albedo = Color::random(0.5, 1.0, rng);
-                        let fuzz = rng.gen_range(0.0..0.5);
-                        world.add(Sphere::new(center, 0.2, Metal::new(albedo, fuzz)));
+        for SmallSphere { center, radius, material } in self.small_spheres(&SiteRngator::from_rng(rng)) {
+            match material {
+                SmallMaterial::Diffuse(albedo, up) => {
+                    let sphere = Sphere::new(center, radius, Lambertian::new(SolidColor::from_color(albedo)));
+                    if self.bounce {
+                        world.add(transforms::Moving::new(Vec3::ZERO, up, 0.0, 1.0, sphere));
                     } else {
-                        world.add(Sphere::new(center, 0.2, Dielectric::new(1.5)));
+                        world.add(sphere);
                     }
                 }
+                SmallMaterial::Metal(albedo, fuzz) => {
+                    world.add(Sphere::new(center, radius, Metal::new(albedo, fuzz)));
+                }
+                SmallMaterial::Glass => {
+                    world.add(Sphere::new(center, radius, Dielectric::new(1.5)));
+                }
             }
         }
 
@@ -112,12 +296,21 @@ impl World for Random {
     }
 }
 
-struct RandomChk {}
+struct Earth {}
 
-impl World for RandomChk {
+impl World for Earth {
     fn name(&self) -> &'static str {
-        "random_chk"
+        "earth"
+    }
+
+    fn description(&self) -> &'static str {
+        "A globe with an image texture of the Earth."
+    }
+
+    fn assets(&self) -> Vec<&'static str> {
+        vec!["earthmap.jpg"]
     }
+
     fn background(&self) -> Box<dyn Background> {
         Box::new(GradientBackground::default())
     }
@@ -126,63 +319,131 @@ impl World for RandomChk {
         WorldCamera { lookfrom: Point3::new(13.0, 2.0, 3.0), lookat: Point3::new(0.0, 0.0, 0.0), field_of_view: 20.0 }
     }
 
-    fn build(&self, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
-        let mut world = bhv::SceneBuilder::new();
+    fn build(&self, _: BvhConfig, _: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+        let earth_texture = image_texture::Image::open("earthmap.jpg");
+        let earth_surface = Lambertian::new(earth_texture);
+        let globe = Sphere::new(Point3::ZERO, 2.0, earth_surface);
 
-        let checker = textures::Checker::new(SolidColor::new(0.2, 0.3, 0.1), SolidColor::new(0.9, 0.9, 0.9));
-        let ground_material = Lambertian::new(checker);
-        world.add(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, ground_material));
+        Box::new(globe)
+    }
+}
 
-        for a in -11..11 {
-            for b in -11..11 {
-                let choose_mat = rnd01(rng);
-                let center = Point3::new(a as f64 + 0.9 * rnd01(rng), 0.2, b as f64 + 0.9 * rnd01(rng));
-
-                if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                    if choose_mat < 0.8 {
-                        let albedo = Color::random_unit(rng) * Color::random_unit(rng);
-                        world.add(Sphere::new(center, 0.2, Lambertian::new(SolidColor::from_color(albedo))));
-                    } else if choose_mat < 0.95 {
-                        let albedo = Color::random(0.5, 1.0, rng);
-                        let fuzz = rng.gen_range(0.0..0.5);
-                        world.add(Sphere::new(center, 0.2, Metal::new(albedo, fuzz)));
-                    } else {
-                        world.add(Sphere::new(center, 0.2, Dielectric::new(1.5)));
-                    }
-                }
-            }
-        }
+// A lawn of grass blades around a furry ball, both made of `curves`.
+struct Grass {}
 
-        world
-            .add(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, Dielectric::new(1.5)))
-            .add(Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, Lambertian::new(SolidColor::new(0.4, 0.2, 0.1))))
-            .add(Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, Metal::new(Color::new(0.7, 0.6, 0.5), 0.0)));
+impl World for Grass {
+    fn name(&self) -> &'static str {
+        "grass"
+    }
 
+    fn description(&self) -> &'static str {
+        "A lawn of grass blades around a furry ball, made of curves."
+    }
+
+    fn background(&self) -> Box<dyn Background> {
+        Box::new(GradientBackground::default())
+    }
+
+    fn camera(&self) -> WorldCamera {
+        WorldCamera { lookfrom: Point3::new(0.0, 1.5, 6.0), lookat: Point3::new(0.0, 0.6, 0.0), field_of_view: 30.0 }
+    }
+
+    fn build(&self, bvh: BvhConfig, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+        let mut world = bhv::SceneBuilder::with_config(bvh);
+        world.add(XZRect::new(-20.0, 20.0, -20.0, 20.0, 0.0, Lambertian::new(SolidColor::new(0.3, 0.2, 0.1))));
+        let blades = curves::grass(4000, 3.0, 0.4, 0.02, rng);
+        let grass = Lambertian::new(SolidColor::new(0.2, 0.5, 0.1));
+        world.add(curves::Curves::new(&blades, curves::CurveShape::Flat, grass, bvh, rng));
+        let center = Point3::new(0.0, 0.7, 0.0);
+        world.add(Sphere::new(center, 0.5, Lambertian::new(SolidColor::new(0.6, 0.4, 0.2))));
+        let strands = curves::fur(center, 0.5, 3000, 0.25, 0.01, rng);
+        let fur = Lambertian::new(SolidColor::new(0.7, 0.5, 0.3));
+        world.add(curves::Curves::new(&strands, curves::CurveShape::Cylinder, fur, bvh, rng));
         Box::new(bhv::BHV::new(&mut world, rng))
     }
 }
 
-struct Earth {}
+// Organic shapes made of signed distance functions: a blob of smoothly joined spheres and capsules, a glass
+// torus and a rounded metal box.
+struct SdfShapes {}
 
-impl World for Earth {
+impl World for SdfShapes {
     fn name(&self) -> &'static str {
-        "earth"
+        "sdf"
     }
+
+    fn description(&self) -> &'static str {
+        "Shapes of signed distance functions: a blob, a glass torus and a rounded metal box."
+    }
+
     fn background(&self) -> Box<dyn Background> {
         Box::new(GradientBackground::default())
     }
 
     fn camera(&self) -> WorldCamera {
-        WorldCamera { lookfrom: Point3::new(13.0, 2.0, 3.0), lookat: Point3::new(0.0, 0.0, 0.0), field_of_view: 20.0 }
+        WorldCamera { lookfrom: Point3::new(0.0, 2.5, 8.0), lookat: Point3::new(0.0, 0.8, 0.0), field_of_view: 30.0 }
     }
 
-    fn build(&self, _: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
-        let img = image::open("earthmap.jpg").unwrap();
-        let earth_texture = image_texture::Image::new(img.to_rgb8());
-        let earth_surface = Lambertian::new(earth_texture);
-        let globe = Sphere::new(Point3::ZERO, 2.0, earth_surface);
+    fn build(&self, bvh: BvhConfig, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+        let mut world = bhv::SceneBuilder::with_config(bvh);
+        world.add(XZRect::new(-20.0, 20.0, -20.0, 20.0, 0.0, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5))));
+        let body = sdf::SmoothUnion {
+            a: sdf::SdfSphere { center: Point3::new(0.0, 0.8, 0.0), radius: 0.7 },
+            b: sdf::SdfSphere { center: Point3::new(0.0, 1.7, 0.0), radius: 0.45 },
+            k: 0.5,
+        };
+        let arms = sdf::SmoothUnion {
+            a: sdf::SdfCapsule { a: Point3::new(-1.1, 1.4, 0.0), b: Point3::new(1.1, 1.4, 0.0), radius: 0.15 },
+            b: sdf::SdfCapsule { a: Point3::new(-0.3, 0.1, 0.2), b: Point3::new(0.3, 0.1, 0.2), radius: 0.12 },
+            k: 0.3,
+        };
+        let blob = sdf::SmoothUnion { a: body, b: arms, k: 0.4 };
+        world.add(sdf::SdfShape::new(blob, Lambertian::new(SolidColor::new(0.8, 0.3, 0.3))));
+        let torus = sdf::SdfTorus { center: Point3::new(-2.3, 0.3, 0.5), major: 0.6, minor: 0.3 };
+        world.add(sdf::SdfShape::new(torus, Dielectric::new(1.5)));
+        let rounded =
+            sdf::SdfBox { center: Point3::new(2.3, 0.6, 0.3), half_size: Vec3::new(0.6, 0.6, 0.6), rounding: 0.2 };
+        world.add(sdf::SdfShape::new(rounded, Metal::new(Color::new(0.8, 0.8, 0.9), 0.1)));
+        Box::new(bhv::BHV::new(&mut world, rng))
+    }
+}
 
-        Box::new(globe)
+// Rocks made of noise isosurfaces: lumpy Perlin noise, craggy turbulence and pitted Worley noise.
+struct Rocks {}
+
+impl World for Rocks {
+    fn name(&self) -> &'static str {
+        "rocks"
+    }
+
+    fn description(&self) -> &'static str {
+        "Rocks made of Perlin, turbulence and Worley noise isosurfaces."
+    }
+
+    fn background(&self) -> Box<dyn Background> {
+        Box::new(GradientBackground::default())
+    }
+
+    fn camera(&self) -> WorldCamera {
+        WorldCamera { lookfrom: Point3::new(0.0, 2.5, 8.0), lookat: Point3::new(0.0, 0.7, 0.0), field_of_view: 30.0 }
+    }
+
+    fn build(&self, bvh: BvhConfig, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+        let mut world = bhv::SceneBuilder::with_config(bvh);
+        world.add(XZRect::new(-20.0, 20.0, -20.0, 20.0, 0.0, Lambertian::new(SolidColor::new(0.5, 0.45, 0.4))));
+        // x, noise, threshold, scale and falloff of each rock.
+        let rocks = [
+            (-2.3, sdf::IsoNoise::Perlin, -0.6, 1.5, 1.5),
+            (0.0, sdf::IsoNoise::Turbulence, -0.3, 1.0, 1.0),
+            (2.3, sdf::IsoNoise::Worley, 0.15, 2.5, 0.8),
+        ];
+        for (x, noise, threshold, scale, falloff) in rocks {
+            let params = sdf::IsosurfaceParams { scale, falloff, ..sdf::IsosurfaceParams::new(noise, threshold) };
+            let bounds = AABB::new(Point3::new(x - 1.0, -0.5, -1.0), Point3::new(x + 1.0, 1.5, 1.0));
+            let rock = sdf::Isosurface::new(&params, bounds, rng);
+            world.add(sdf::SdfShape::new(rock, Lambertian::new(SolidColor::new(0.45, 0.4, 0.35))));
+        }
+        Box::new(bhv::BHV::new(&mut world, rng))
     }
 }
 
@@ -192,6 +453,11 @@ impl World for TwoSpheres {
     fn name(&self) -> &'static str {
         "two_spheres"
     }
+
+    fn description(&self) -> &'static str {
+        "Two balls with a Perlin noise texture, from the second book."
+    }
+
     fn background(&self) -> Box<dyn Background> {
         Box::new(GradientBackground::default())
     }
@@ -200,7 +466,7 @@ impl World for TwoSpheres {
         WorldCamera { lookfrom: Point3::new(13.0, 2.0, 3.0), lookat: Point3::new(0.0, 0.0, 0.0), field_of_view: 20.0 }
     }
 
-    fn build(&self, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+    fn build(&self, _: BvhConfig, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
         let mut shapes = HittableList::new();
         let pertext = NoiseTexture::new(4.0, rng);
         shapes.add(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::new(pertext.clone())));
@@ -216,6 +482,15 @@ impl World for SimpleLight {
     fn name(&self) -> &'static str {
         "simple_light"
     }
+
+    fn description(&self) -> &'static str {
+        "A noise-textured ball lit by a green rectangle and a red ball, in two light groups."
+    }
+
+    fn recommended(&self) -> RecommendedSettings {
+        RecommendedSettings { light_sampling: true, ..RecommendedSettings::default() }
+    }
+
     fn background(&self) -> Box<dyn Background> {
         Box::new(BlackBackground::new())
     }
@@ -224,27 +499,116 @@ impl World for SimpleLight {
         WorldCamera { lookfrom: Point3::new(20.0, 3.0, 6.0), lookat: Point3::new(0.0, 2.0, 0.0), field_of_view: 20.0 }
     }
 
-    fn build(&self, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+    fn build(&self, _: BvhConfig, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
         let mut shapes = HittableList::new();
         let pertext = NoiseTexture::new(4.0, rng);
         shapes.add(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, Lambertian::new(pertext.clone())));
         shapes.add(Sphere::new(Point3::new(0.0, 2.0, 0.0), 2.0, Lambertian::new(pertext)));
 
-        let difflight = DiffuseLight::new(SolidColor::new(0.0, 7.0, 0.0));
+        let difflight = DiffuseLight::with_group(SolidColor::new(0.0, 7.0, 0.0), 1);
         shapes.add(XYRect::new(3.0, 5.0, 1.0, 3.0, -2.0, difflight));
-        let difflight = DiffuseLight::new(SolidColor::new(7.0, 0.0, 0.0));
+        let difflight = DiffuseLight::with_group(SolidColor::new(7.0, 0.0, 0.0), 2);
         shapes.add(Sphere::new(Point3::new(0.0, 6.0, 0.0), 1.5, difflight));
 
         Box::new(shapes)
     }
+
+    fn lights(&self, _: BvhConfig, _: &mut dyn rand::RngCore) -> HittableList<'static> {
+        let mut lights = HittableList::new();
+        let difflight = DiffuseLight::with_group(SolidColor::new(0.0, 7.0, 0.0), 1);
+        lights.add(XYRect::new(3.0, 5.0, 1.0, 3.0, -2.0, difflight));
+        let difflight = DiffuseLight::with_group(SolidColor::new(7.0, 0.0, 0.0), 2);
+        lights.add(Sphere::new(Point3::new(0.0, 6.0, 0.0), 1.5, difflight));
+        lights
+    }
+}
+
+fn cornell_light() -> XZRect<DiffuseLight<SolidColor>> {
+    XZRect::new(113.0, 443.0, 127.0, 432.0, 554.0, DiffuseLight::new(SolidColor::new(7.0, 7.0, 7.0)))
+}
+
+// What stands in the Cornell box.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CornellContents {
+    Blocks,
+    // The blocks filled with black and white smoke.
+    Smoke,
+    Spheres,
+}
+
+// The Cornell box with its contents and variants: `glass` replaces the small block (or sphere) with a glass
+// sphere, `mirror` turns the back wall into a mirror, `holdout` makes the large block (or sphere) a holdout,
+// see `materials::Holdout`, and `closed` closes the box with a front wall that only the camera sees through.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Cornell {
+    pub name: &'static str,
+    pub contents: CornellContents,
+    pub glass: bool,
+    pub mirror: bool,
+    pub holdout: bool,
+    pub closed: bool,
+}
+
+impl Cornell {
+    pub fn new(name: &'static str, contents: CornellContents) -> Cornell {
+        Cornell { name, contents, glass: false, mirror: false, holdout: false, closed: false }
+    }
 }
 
-struct CornellBox {}
+impl std::str::FromStr for Cornell {
+    type Err = String;
+
+    // Comma separated sub-options: contents (blocks, smoke or spheres), glass, mirror, holdout, closed.
+    fn from_str(s: &str) -> Result<Cornell, String> {
+        let mut cornell = Cornell::new("cornell", CornellContents::Blocks);
+        for option in s.split(',').map(|o| o.trim()) {
+            match option {
+                "blocks" => cornell.contents = CornellContents::Blocks,
+                "smoke" => cornell.contents = CornellContents::Smoke,
+                "spheres" => cornell.contents = CornellContents::Spheres,
+                "glass" => cornell.glass = true,
+                "mirror" => cornell.mirror = true,
+                "holdout" => cornell.holdout = true,
+                "closed" => cornell.closed = true,
+                _ => return Err(format!("unknown Cornell box option '{}'", option)),
+            }
+        }
+        if cornell.holdout && cornell.contents == CornellContents::Smoke {
+            return Err("smoke can't be a holdout".to_string());
+        }
+        Ok(cornell)
+    }
+}
 
-impl World for CornellBox {
+fn cornell_block<T: Material + Copy + 'static>(
+    size: Vec3,
+    angle: f64,
+    offset: Vec3,
+    material: T,
+) -> transforms::Translate<transforms::Rotate<Block<'static>>> {
+    let block = Block::new(Point3::ZERO, size, material);
+    transforms::Translate::new(offset, transforms::Rotate::new(Axis::Y, angle, block))
+}
+
+impl World for Cornell {
     fn name(&self) -> &'static str {
-        "cornell_box"
+        self.name
+    }
+
+    fn description(&self) -> &'static str {
+        match self.contents {
+            _ if self.glass => "The Cornell box with a glass ball.",
+            _ if self.mirror => "The Cornell box with a mirror for the back wall.",
+            CornellContents::Blocks => "The Cornell box with two white blocks, from the second book.",
+            CornellContents::Smoke => "The Cornell box with blocks of black and white smoke.",
+            CornellContents::Spheres => "The Cornell box with two white balls.",
+        }
+    }
+
+    fn recommended(&self) -> RecommendedSettings {
+        RecommendedSettings { aspect_ratio: (1, 1), light_sampling: true, ..RecommendedSettings::default() }
     }
+
     fn background(&self) -> Box<dyn Background> {
         Box::new(BlackBackground::new())
     }
@@ -257,120 +621,587 @@ impl World for CornellBox {
         }
     }
 
-    fn build(&self, _: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+    fn build(&self, _: BvhConfig, _: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
         let mut shapes = HittableList::new();
         let red = Lambertian::new(SolidColor::new(0.65, 0.05, 0.05));
         let white = Lambertian::new(SolidColor::new(0.73, 0.73, 0.73));
         let green = Lambertian::new(SolidColor::new(0.12, 0.45, 0.15));
-        let light = DiffuseLight::new(SolidColor::new(7.0, 7.0, 7.0));
+        let glass = Dielectric::new(1.5);
 
         shapes.add(YZRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green));
         shapes.add(YZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red));
 
-        shapes.add(XZRect::new(113.0, 443.0, 127.0, 432.0, 554.0, light));
+        shapes.add(cornell_light());
 
         shapes.add(XZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, white));
         shapes.add(XZRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white));
-        shapes.add(XYRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white));
-
-        let large_block = Block::new(Point3::ZERO, Point3::new(165.0, 330.0, 165.0), white);
-        let large_block = transforms::Rotate::new(Axis::Y, 15.0, large_block);
-        let large_block = transforms::Translate::new(Vec3::new(265.0, 0.0, 295.0), large_block);
-        shapes.add(large_block);
+        if self.mirror {
+            shapes.add(XYRect::new(0.0, 555.0, 0.0, 555.0, 555.0, Metal::new(Color::new(0.8, 0.85, 0.88), 0.0)));
+        } else {
+            shapes.add(XYRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white));
+        }
+        if self.closed {
+            let hidden = Visibility { camera: false, ..Visibility::ALL };
+            shapes.add(XYRect::new(0.0, 555.0, 0.0, 555.0, 0.0, Visible(white, hidden)));
+        }
 
-        let small_block = Block::new(Point3::ZERO, Point3::new(165.0, 165.0, 165.0), white);
-        let small_block = transforms::Rotate::new(Axis::Y, -18.0, small_block);
-        let small_block = transforms::Translate::new(Vec3::new(130.0, 0.0, 65.0), small_block);
-        shapes.add(small_block);
+        let large_block = cornell_block(Vec3::new(165.0, 330.0, 165.0), 15.0, Vec3::new(265.0, 0.0, 295.0), white);
+        let small_block = cornell_block(Vec3::new(165.0, 165.0, 165.0), -18.0, Vec3::new(130.0, 0.0, 65.0), white);
+        let small_sphere = Point3::new(190.0, 90.0, 190.0);
+        let large_sphere = Point3::new(370.0, 120.0, 350.0);
+        match self.contents {
+            CornellContents::Blocks if self.holdout => shapes.add(cornell_block(
+                Vec3::new(165.0, 330.0, 165.0),
+                15.0,
+                Vec3::new(265.0, 0.0, 295.0),
+                Holdout(white),
+            )),
+            CornellContents::Blocks => shapes.add(large_block),
+            CornellContents::Smoke => shapes.add(volumes::ConstantMedium::from_color(large_block, 0.01, Color::ZERO)),
+            CornellContents::Spheres if self.holdout => shapes.add(Sphere::new(large_sphere, 120.0, Holdout(white))),
+            CornellContents::Spheres => shapes.add(Sphere::new(large_sphere, 120.0, white)),
+        }
+        match self.contents {
+            _ if self.glass => shapes.add(Sphere::new(small_sphere, 90.0, glass)),
+            CornellContents::Blocks => shapes.add(small_block),
+            CornellContents::Smoke => shapes.add(volumes::ConstantMedium::from_color(small_block, 0.01, Color::ONE)),
+            CornellContents::Spheres => shapes.add(Sphere::new(small_sphere, 90.0, white)),
+        }
 
         Box::new(shapes)
     }
+
+    fn lights(&self, _: BvhConfig, _: &mut dyn rand::RngCore) -> HittableList<'static> {
+        let mut lights = HittableList::new();
+        lights.add(cornell_light());
+        lights
+    }
+}
+
+// A sphere with a noise texture, for trying out `NoiseParams`.
+#[derive(Clone)]
+pub struct DebugPerlin {
+    pub noise: NoiseParams,
 }
 
-struct CornellSmoke {}
+impl DebugPerlin {
+    const CENTER: Point3 = Point3 { e: [278.0, 278.0, 0.0] };
+    const RADIUS: f64 = 80.0;
 
-impl World for CornellSmoke {
+    pub fn new() -> DebugPerlin {
+        DebugPerlin { noise: NoiseParams::new(0.1) }
+    }
+
+    // The texture on a slice through the sphere facing the camera, see `textures::bake`.
+    pub fn bake(&self, width: usize, height: usize, rng: &mut dyn rand::RngCore) -> Vec<Vec<Color>> {
+        let texture = NoiseTexture::with_params(&self.noise, rng);
+        let slice = textures::BakeDomain::Slice { center: DebugPerlin::CENTER, size: 2.0 * DebugPerlin::RADIUS };
+        textures::bake(&texture, width, height, slice)
+    }
+}
+
+impl std::str::FromStr for DebugPerlin {
+    type Err = String;
+
+    // Comma separated sub-options: the mode (noise, turbulence or marble), scale=, depth=, contrast=, table= and
+    // seed=.
+    fn from_str(s: &str) -> Result<DebugPerlin, String> {
+        let mut world = DebugPerlin::new();
+        let noise = &mut world.noise;
+        for option in s.split(',').map(|o| o.trim()) {
+            let (key, value) = match option.split_once('=') {
+                Some((key, value)) => (key, value),
+                None => (option, ""),
+            };
+            let number = || value.parse::<f64>().map_err(|e| format!("bad value of {}: {}", key, e));
+            match key {
+                "noise" => noise.mode = NoiseMode::Noise,
+                "turbulence" => noise.mode = NoiseMode::Turbulence,
+                "marble" => noise.mode = NoiseMode::Marble,
+                "scale" => noise.scale = number()?,
+                "depth" => noise.depth = number()? as i32,
+                "contrast" => noise.contrast = number()?,
+                "seed" => noise.seed = Some(value.parse::<u64>().map_err(|e| format!("bad seed: {}", e))?),
+                "table" => {
+                    noise.table_size = number()? as usize;
+                    if !noise.table_size.is_power_of_two() {
+                        return Err(format!("table size {} is not a power of two", value));
+                    }
+                }
+                _ => return Err(format!("unknown noise option '{}'", option)),
+            }
+        }
+        Ok(world)
+    }
+}
+
+impl World for DebugPerlin {
     fn name(&self) -> &'static str {
-        "cornell_smoke"
+        "debug_perlin"
+    }
+
+    fn description(&self) -> &'static str {
+        "A ball with a noise texture, for tuning noise options, see --noise."
     }
+
     fn background(&self) -> Box<dyn Background> {
-        Box::new(BlackBackground::new())
+        Box::new(GradientBackground::default())
     }
 
     fn camera(&self) -> WorldCamera {
         WorldCamera {
-            lookfrom: Point3::new(278.0, 278.0, -800.0),
+            lookfrom: Point3::new(278.0, 278.0, -600.0),
             lookat: Point3::new(278.0, 278.0, 0.0),
             field_of_view: 40.0,
         }
     }
 
-    fn build(&self, _: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+    fn build(&self, _: BvhConfig, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
         let mut shapes = HittableList::new();
-        let red = Lambertian::new(SolidColor::new(0.65, 0.05, 0.05));
-        let white = Lambertian::new(SolidColor::new(0.73, 0.73, 0.73));
-        let green = Lambertian::new(SolidColor::new(0.12, 0.45, 0.15));
-        let light = DiffuseLight::new(SolidColor::new(7.0, 7.0, 7.0));
 
-        shapes.add(YZRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green));
-        shapes.add(YZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red));
+        {
+            // Noise.
+            let pertext = NoiseTexture::with_params(&self.noise, rng);
+            shapes.add(Sphere::new(DebugPerlin::CENTER, DebugPerlin::RADIUS, Lambertian::new(pertext)));
+        }
 
-        shapes.add(XZRect::new(113.0, 443.0, 127.0, 432.0, 554.0, light));
+        Box::new(shapes)
+    }
+}
 
-        shapes.add(XZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, white));
-        shapes.add(XZRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white));
-        shapes.add(XYRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white));
+// A material described by comma separated options, for `MaterialPreview`. To preview a new material of
+// materials.rs, add it here and to `from_str`.
+#[derive(Clone, PartialEq, Debug)]
+pub enum MaterialSpec {
+    Lambertian { color: Color },
+    // Reflecting like `conductor` tinted by `color` if given, see `materials::Conductor`.
+    Metal { color: Color, fuzz: f64, conductor: Option<Conductor> },
+    Dielectric { index_of_refraction: f64, absorption: Color },
+    // Tinted like a blackbody at `temperature` kelvins if given, see `textures::blackbody`.
+    Light { color: Color, temperature: Option<f64> },
+    // A measured BRDF, see `measured::Measured`; None until its file= is given.
+    Measured { brdf: Option<Arc<MerlBrdf>> },
+    // Cloth, see `materials::Velvet`.
+    Velvet { color: Color, sheen: Color, roughness: f64 },
+    // Lambertian with the albedo of an image texture, see `image_texture::Image::open`; None until its file= is
+    // given.
+    Image { path: Option<String>, encoding: image_texture::Encoding },
+}
 
-        let large_block = Block::new(Point3::ZERO, Point3::new(165.0, 330.0, 165.0), white);
-        let large_block = transforms::Rotate::new(Axis::Y, 15.0, large_block);
-        let large_block = transforms::Translate::new(Vec3::new(265.0, 0.0, 295.0), large_block);
-        shapes.add(volumes::ConstantMedium::from_color(large_block, 0.01, Color::ZERO));
+impl MaterialSpec {
+    pub fn build(&self) -> SharedMaterial {
+        match self.clone() {
+            MaterialSpec::Lambertian { color } => Arc::new(Lambertian::new(SolidColor::from_color(color))),
+            MaterialSpec::Metal { color, fuzz, conductor } => Arc::new(Metal { conductor, ..Metal::new(color, fuzz) }),
+            MaterialSpec::Dielectric { index_of_refraction, absorption } => {
+                Arc::new(Dielectric::tinted(index_of_refraction, absorption))
+            }
+            MaterialSpec::Light { color, temperature } => {
+                let tint = temperature.map_or(Color::ONE, textures::blackbody);
+                Arc::new(DiffuseLight::new(SolidColor::from_color(tint * color)))
+            }
+            MaterialSpec::Measured { brdf } => Arc::new(Measured::new(brdf.expect("measured material without file="))),
+            MaterialSpec::Velvet { color, sheen, roughness } => {
+                Arc::new(Velvet::new(SolidColor::from_color(color), sheen, roughness))
+            }
+            MaterialSpec::Image { path, encoding } => {
+                let path = path.expect("image material without file=");
+                Arc::new(Lambertian::new(image_texture::Image::open(&path).with_encoding(encoding)))
+            }
+        }
+    }
+}
 
-        let small_block = Block::new(Point3::ZERO, Point3::new(165.0, 165.0, 165.0), white);
-        let small_block = transforms::Rotate::new(Axis::Y, -18.0, small_block);
-        let small_block = transforms::Translate::new(Vec3::new(130.0, 0.0, 65.0), small_block);
-        shapes.add(volumes::ConstantMedium::from_color(small_block, 0.01, Color::ONE));
+// The options `from_str` parses, e.g. for writing scene files.
+impl std::fmt::Display for MaterialSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let color = |c: &Color| format!("{}:{}:{}", c.r(), c.g(), c.b());
+        match self {
+            MaterialSpec::Lambertian { color: c } => write!(f, "lambertian,color={}", color(c)),
+            MaterialSpec::Metal { color: c, fuzz, conductor } => {
+                write!(f, "metal")?;
+                if let Some(name) = conductor.and_then(|c| c.name()) {
+                    write!(f, ",conductor={}", name)?;
+                }
+                write!(f, ",color={},fuzz={}", color(c), fuzz)
+            }
+            MaterialSpec::Dielectric { index_of_refraction, absorption } => {
+                write!(f, "glass,ior={},absorption={}", index_of_refraction, color(absorption))
+            }
+            MaterialSpec::Light { color: c, temperature } => {
+                write!(f, "light,color={}", color(c))?;
+                match temperature {
+                    Some(t) => write!(f, ",temperature={}", t),
+                    None => Ok(()),
+                }
+            }
+            MaterialSpec::Measured { brdf } => match brdf {
+                Some(brdf) => write!(f, "measured,file={}", brdf.name()),
+                None => write!(f, "measured"),
+            },
+            MaterialSpec::Velvet { color: c, sheen, roughness } => {
+                write!(f, "velvet,color={},sheen={},roughness={}", color(c), color(sheen), roughness)
+            }
+            MaterialSpec::Image { path, encoding } => {
+                write!(f, "image")?;
+                if let Some(path) = path {
+                    write!(f, ",file={}", path)?;
+                }
+                match encoding {
+                    image_texture::Encoding::Linear => Ok(()),
+                    image_texture::Encoding::Srgb => write!(f, ",encoding=srgb"),
+                }
+            }
+        }
+    }
+}
 
-        Box::new(shapes)
+impl std::str::FromStr for MaterialSpec {
+    type Err = String;
+
+    // The kind (lambertian, metal, glass, light, measured or velvet) followed by its options: color= as r:g:b or one gray value,
+    // fuzz= and conductor= (gold, silver, copper or aluminum, which resets the color to white so that a later color=
+    // tints it) for metal, ior= and absorption= per unit of distance, as r:g:b or one value, for glass, and
+    // temperature= in kelvins for light. Measured needs file=, the path of a MERL .binary BRDF. Velvet takes sheen=,
+    // a color like color=, and roughness= from 0 to 1. Image needs file=, the path of an image or a tiled image,
+    // and takes encoding=linear or srgb.
+    fn from_str(s: &str) -> Result<MaterialSpec, String> {
+        let mut options = s.split(',').map(|o| o.trim());
+        let mut spec = match options.next().unwrap() {
+            "lambertian" => MaterialSpec::Lambertian { color: Color::new(0.73, 0.73, 0.73) },
+            "metal" => MaterialSpec::Metal { color: Color::new(0.8, 0.85, 0.88), fuzz: 0.0, conductor: None },
+            "glass" => MaterialSpec::Dielectric { index_of_refraction: 1.5, absorption: Color::ZERO },
+            "light" => MaterialSpec::Light { color: Color::new(4.0, 4.0, 4.0), temperature: None },
+            "measured" => MaterialSpec::Measured { brdf: None },
+            "velvet" => MaterialSpec::Velvet {
+                color: Color::new(0.45, 0.05, 0.1),
+                sheen: Color::new(1.0, 0.8, 0.85),
+                roughness: 0.5,
+            },
+            "image" => MaterialSpec::Image { path: None, encoding: image_texture::Encoding::Linear },
+            kind => return Err(format!("unknown material '{}'", kind)),
+        };
+        for option in options {
+            let (key, value) = option.split_once('=').ok_or(format!("material option '{}' has no value", option))?;
+            let number = |value: &str| value.parse::<f64>().map_err(|e| format!("bad value of {}: {}", key, e));
+            let color = || {
+                let channels = value.split(':').map(number).collect::<Result<Vec<f64>, String>>()?;
+                match channels[..] {
+                    [gray] => Ok(Color::new(gray, gray, gray)),
+                    [r, g, b] => Ok(Color::new(r, g, b)),
+                    _ => Err(format!("{} {} is neither r:g:b nor one value", key, value)),
+                }
+            };
+            match (key, &mut spec) {
+                (
+                    "color",
+                    MaterialSpec::Lambertian { color: c }
+                    | MaterialSpec::Metal { color: c, .. }
+                    | MaterialSpec::Light { color: c, .. }
+                    | MaterialSpec::Velvet { color: c, .. },
+                ) => *c = color()?,
+                ("sheen", MaterialSpec::Velvet { sheen, .. }) => *sheen = color()?,
+                ("roughness", MaterialSpec::Velvet { roughness, .. }) => *roughness = number(value)?,
+                ("fuzz", MaterialSpec::Metal { fuzz, .. }) => *fuzz = number(value)?,
+                ("conductor", MaterialSpec::Metal { color, conductor, .. }) => {
+                    *conductor = Some(Conductor::named(value).ok_or(format!("unknown conductor '{}'", value))?);
+                    *color = Color::ONE;
+                }
+                ("ior", MaterialSpec::Dielectric { index_of_refraction, .. }) => *index_of_refraction = number(value)?,
+                ("absorption", MaterialSpec::Dielectric { absorption, .. }) => *absorption = color()?,
+                ("temperature", MaterialSpec::Light { temperature, .. }) => *temperature = Some(number(value)?),
+                ("file", MaterialSpec::Measured { brdf }) => *brdf = Some(assets::brdf(value)?),
+                ("file", MaterialSpec::Image { path, .. }) => {
+                    std::fs::metadata(value).map_err(|e| format!("{}: {}", value, e))?;
+                    *path = Some(value.to_string());
+                }
+                ("encoding", MaterialSpec::Image { encoding, .. }) => {
+                    *encoding = match value {
+                        "linear" => image_texture::Encoding::Linear,
+                        "srgb" => image_texture::Encoding::Srgb,
+                        _ => return Err(format!("encoding '{}', expected linear or srgb", value)),
+                    }
+                }
+                _ => return Err(format!("material option '{}' doesn't apply to {:?}", option, spec)),
+            }
+        }
+        match spec {
+            MaterialSpec::Measured { brdf: None } => return Err("measured material needs file=".to_string()),
+            MaterialSpec::Image { path: None, .. } => return Err("image material needs file=".to_string()),
+            _ => {}
+        }
+        Ok(spec)
     }
 }
-struct DebugPerlin {}
 
-impl World for DebugPerlin {
+// The usual setup for looking at a material: a ball of it on a checkered floor next to a gray diffuse
+// reference ball, under one area light and a sky, so materials render under the same light.
+pub struct MaterialPreview {
+    pub material: MaterialSpec,
+}
+
+impl std::str::FromStr for MaterialPreview {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MaterialPreview, String> {
+        Ok(MaterialPreview { material: s.parse()? })
+    }
+}
+
+fn material_preview_light() -> XZRect<DiffuseLight<SolidColor>> {
+    XZRect::new(-1.5, 1.5, -0.5, 2.5, 6.0, DiffuseLight::new(SolidColor::new(6.0, 6.0, 6.0)))
+}
+
+impl World for MaterialPreview {
     fn name(&self) -> &'static str {
-        "debug_perlin"
+        "material_preview"
+    }
+
+    fn description(&self) -> &'static str {
+        "A ball of a material next to a gray one under an area light and the sky, see --material."
+    }
+
+    fn recommended(&self) -> RecommendedSettings {
+        RecommendedSettings { light_sampling: true, ..RecommendedSettings::default() }
     }
+
     fn background(&self) -> Box<dyn Background> {
         Box::new(GradientBackground::default())
     }
 
     fn camera(&self) -> WorldCamera {
-        WorldCamera {
-            lookfrom: Point3::new(278.0, 278.0, -600.0),
-            lookat: Point3::new(278.0, 278.0, 0.0),
-            field_of_view: 40.0,
-        }
+        WorldCamera { lookfrom: Point3::new(0.0, 2.0, 8.0), lookat: Point3::new(0.0, 0.8, 0.0), field_of_view: 30.0 }
     }
 
-    fn build(&self, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+    fn build(&self, _: BvhConfig, _: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
         let mut shapes = HittableList::new();
+        let floor =
+            Lambertian::new(textures::Checker::new(SolidColor::new(0.2, 0.2, 0.2), SolidColor::new(0.8, 0.8, 0.8)));
+        shapes.add(XZRect::new(-50.0, 50.0, -50.0, 50.0, 0.0, floor));
+        shapes.add(Sphere::new(Point3::new(0.4, 1.0, 0.0), 1.0, self.material.build()));
+        shapes.add(Sphere::new(Point3::new(-1.5, 0.5, 0.8), 0.5, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5))));
+        shapes.add(material_preview_light());
+        Box::new(shapes)
+    }
 
-        {
-            // Noise.
-            let pertext = NoiseTexture::new(0.1, rng);
-            shapes.add(Sphere::new(Point3::new(278.0, 278.0, 0.0), 80.0, Lambertian::new(pertext)));
+    fn lights(&self, _: BvhConfig, _: &mut dyn rand::RngCore) -> HittableList<'static> {
+        let mut lights = HittableList::new();
+        lights.add(material_preview_light());
+        lights
+    }
+}
+
+// White furnace: a ball of the material alone in a uniformly white environment. A material that reflects all light
+// disappears into the background, and a gray diffuse one shows its albedo all over, whatever the bounces; anything
+// else points at a material or tracer losing or making up energy.
+pub struct Furnace {
+    pub material: MaterialSpec,
+}
+
+impl std::str::FromStr for Furnace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Furnace, String> {
+        Ok(Furnace { material: s.parse()? })
+    }
+}
+
+impl World for Furnace {
+    fn name(&self) -> &'static str {
+        "furnace"
+    }
+
+    fn description(&self) -> &'static str {
+        "A ball of a material in a uniformly white environment, for checking energy, see --furnace."
+    }
+
+    fn recommended(&self) -> RecommendedSettings {
+        RecommendedSettings { aspect_ratio: (1, 1), ..RecommendedSettings::default() }
+    }
+
+    fn background(&self) -> Box<dyn Background> {
+        Box::new(SolidBackground::new(Color::ONE))
+    }
+
+    fn camera(&self) -> WorldCamera {
+        WorldCamera { lookfrom: Point3::new(0.0, 0.0, 5.0), lookat: Point3::ZERO, field_of_view: 30.0 }
+    }
+
+    fn build(&self, _: BvhConfig, _: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+        Box::new(Sphere::new(Point3::ZERO, 1.0, self.material.build()))
+    }
+}
+
+// A room lit only by the sky through two windows, with no light inside for light sampling to find and no portal
+// telling the tracer where the windows are: the light arrives after bounces off the walls, the hardest case for
+// a path tracer. The sky is a bright gradient; `--background=hdr:<path>` lights the room with an environment map.
+struct Interior {}
+
+// The room spans x from -3 to 3, y from 0 to 3 and z from -4 to 2, with a window in the back wall and one in the
+// left wall.
+const INTERIOR_BACK_WINDOW: (f64, f64, f64, f64) = (-1.2, 1.2, 1.0, 2.4);
+const INTERIOR_LEFT_WINDOW: (f64, f64, f64, f64) = (-2.8, -0.8, 0.9, 2.4);
+
+impl World for Interior {
+    fn name(&self) -> &'static str {
+        "interior"
+    }
+
+    fn description(&self) -> &'static str {
+        "A room lit only by the sky through its windows, with no light to sample."
+    }
+
+    fn recommended(&self) -> RecommendedSettings {
+        RecommendedSettings { samples_per_pixel: 1000, ..RecommendedSettings::default() }
+    }
+
+    fn background(&self) -> Box<dyn Background> {
+        Box::new(GradientBackground::new(Color::new(2.0, 2.8, 4.5), Color::new(1.2, 1.0, 0.8)))
+    }
+
+    fn camera(&self) -> WorldCamera {
+        WorldCamera { lookfrom: Point3::new(2.5, 1.7, 1.8), lookat: Point3::new(-1.0, 1.1, -3.0), field_of_view: 65.0 }
+    }
+
+    fn build(&self, _: BvhConfig, _: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+        let mut shapes = HittableList::new();
+        let wall = Lambertian::new(SolidColor::new(0.8, 0.78, 0.72));
+        let floor = Lambertian::new(SolidColor::new(0.45, 0.3, 0.18));
+        shapes.add(XZRect::new(-3.0, 3.0, -4.0, 2.0, 0.0, floor));
+        shapes.add(XZRect::new(-3.0, 3.0, -4.0, 2.0, 3.0, wall));
+        shapes.add(YZRect::new(0.0, 3.0, -4.0, 2.0, 3.0, wall));
+        shapes.add(XYRect::new(-3.0, 3.0, 0.0, 3.0, 2.0, wall));
+        // The walls with windows, around their openings.
+        let (x0, x1, y0, y1) = INTERIOR_BACK_WINDOW;
+        shapes.add(XYRect::new(-3.0, x0, 0.0, 3.0, -4.0, wall));
+        shapes.add(XYRect::new(x1, 3.0, 0.0, 3.0, -4.0, wall));
+        shapes.add(XYRect::new(x0, x1, 0.0, y0, -4.0, wall));
+        shapes.add(XYRect::new(x0, x1, y1, 3.0, -4.0, wall));
+        let (z0, z1, y0, y1) = INTERIOR_LEFT_WINDOW;
+        shapes.add(YZRect::new(0.0, 3.0, -4.0, z0, -3.0, wall));
+        shapes.add(YZRect::new(0.0, 3.0, z1, 2.0, -3.0, wall));
+        shapes.add(YZRect::new(0.0, y0, z0, z1, -3.0, wall));
+        shapes.add(YZRect::new(y1, 3.0, z0, z1, -3.0, wall));
+
+        // A velvet sofa under the left window, a table with a glass ball and a metal one on the floor.
+        let velvet = Velvet::new(SolidColor::new(0.1, 0.15, 0.4), Color::new(0.7, 0.75, 1.0), 0.5);
+        shapes.add(Block::new(Point3::new(-3.0, 0.0, -2.9), Point3::new(-2.2, 0.45, -0.7), velvet.clone()));
+        shapes.add(Block::new(Point3::new(-3.0, 0.45, -2.9), Point3::new(-2.8, 0.9, -0.7), velvet));
+        let wood = Lambertian::new(SolidColor::new(0.35, 0.2, 0.1));
+        shapes.add(Block::new(Point3::new(-0.6, 0.7, -2.2), Point3::new(0.8, 0.78, -1.2), wood));
+        for (x, z) in [(-0.5, -2.1), (0.7, -2.1), (-0.5, -1.3), (0.7, -1.3)] {
+            shapes.add(Block::new(Point3::new(x - 0.05, 0.0, z - 0.05), Point3::new(x + 0.05, 0.7, z + 0.05), wood));
         }
+        shapes.add(Sphere::new(Point3::new(0.1, 1.0, -1.7), 0.22, Dielectric::new(1.5)));
+        shapes.add(Sphere::new(Point3::new(1.6, 0.35, -2.8), 0.35, Metal::new(Color::new(0.8, 0.85, 0.88), 0.05)));
 
         Box::new(shapes)
     }
 }
 
+fn caustics_light() -> Sphere<DiffuseLight<SolidColor>> {
+    Sphere::new(Point3::new(-3.0, 6.0, -2.0), 0.8, DiffuseLight::new(SolidColor::new(20.0, 19.0, 16.0)))
+}
+
+// Glass focusing a small light into caustics on a white floor, which light sampling can't find: the light seen from
+// the floor through the glass is a refracted image that shadow rays pass straight through, see README.md.
+struct Caustics {}
+
+impl World for Caustics {
+    fn name(&self) -> &'static str {
+        "caustics"
+    }
+
+    fn description(&self) -> &'static str {
+        "Glass balls and a glass block focusing a small light into caustics on a white floor."
+    }
+
+    fn recommended(&self) -> RecommendedSettings {
+        RecommendedSettings { samples_per_pixel: 1000, light_sampling: true, ..RecommendedSettings::default() }
+    }
+
+    fn background(&self) -> Box<dyn Background> {
+        Box::new(BlackBackground::new())
+    }
+
+    fn camera(&self) -> WorldCamera {
+        WorldCamera { lookfrom: Point3::new(1.0, 5.0, 8.0), lookat: Point3::new(0.0, 0.5, 0.0), field_of_view: 35.0 }
+    }
+
+    fn build(&self, _: BvhConfig, _: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+        let mut shapes = HittableList::new();
+        let white = Lambertian::new(SolidColor::new(0.8, 0.8, 0.8));
+        shapes.add(XZRect::new(-10.0, 10.0, -10.0, 10.0, 0.0, white));
+        shapes.add(XYRect::new(-10.0, 10.0, 0.0, 10.0, -5.0, white));
+        shapes.add(Sphere::new(Point3::new(-0.6, 1.0, 0.0), 1.0, Dielectric::new(1.5)));
+        let amber = Dielectric::tinted(1.5, Color::new(0.1, 0.4, 1.2));
+        shapes.add(Block::new(Point3::new(1.0, 0.0, -0.8), Point3::new(1.8, 1.6, 0.0), amber));
+        shapes.add(Sphere::new(Point3::new(0.9, 0.4, 1.4), 0.4, Dielectric::new(1.33)));
+        shapes.add(caustics_light());
+        Box::new(shapes)
+    }
+
+    fn lights(&self, _: BvhConfig, _: &mut dyn rand::RngCore) -> HittableList<'static> {
+        let mut lights = HittableList::new();
+        lights.add(caustics_light());
+        lights
+    }
+}
+
+fn final_scene_light() -> XZRect<DiffuseLight<SolidColor>> {
+    XZRect::new(123.0, 423.0, 147.0, 412.0, 554.0, DiffuseLight::new(SolidColor::new(9.0, 9.0, 9.0)))
+}
+
+// Boxes of random heights, each from the rng of its place.
+fn final_scene_ground(sites: &SiteRngator, bvh: BvhConfig) -> bhv::SceneBuilder<'static> {
+    let ground = Lambertian::new(SolidColor::new(0.48, 0.83, 0.53));
+    const BLOCKS_PER_SIDE: i32 = 20;
+    let mut ground_blocks = bhv::SceneBuilder::with_config(bvh);
+    for i in 0..BLOCKS_PER_SIDE {
+        for j in 0..BLOCKS_PER_SIDE {
+            let w = 100.0;
+            let x0 = -1000.0 + (i as f64) * w;
+            let z0 = -1000.0 + (j as f64) * w;
+            let y0 = 0.0;
+            let x1 = x0 + w;
+            let y1 = sites.site(&[i as u64, j as u64]).gen_range(1.0..70.0);
+            let z1 = z0 + w;
+            ground_blocks.add(Block::new(Point3::new(x0, y0, z0), Point3::new(x1, y1, z1), ground.clone()));
+        }
+    }
+    ground_blocks
+}
+
+// Cube of small white spheres.
+fn final_scene_foam(sites: &SiteRngator, bvh: BvhConfig) -> bhv::SceneBuilder<'static> {
+    let mut foam = bhv::SceneBuilder::with_config(bvh);
+    let white = Lambertian::new(SolidColor::new(0.73, 0.73, 0.73));
+    let cube = scatter::Region::Inside(AABB::new(Point3::ZERO, Point3::new(165.0, 165.0, 165.0)));
+    for placement in scatter::scatter(&cube, &scatter::ScatterParams::new(1000), sites) {
+        foam.add(Sphere::new(placement.position, 10.0, white.clone()));
+    }
+    foam
+}
+
 struct FinalScene {}
 
 impl World for FinalScene {
     fn name(&self) -> &'static str {
         "final_scene"
     }
+
+    fn description(&self) -> &'static str {
+        "The cover of the second book: fog, glass, textures, instances and motion blur."
+    }
+
+    fn recommended(&self) -> RecommendedSettings {
+        RecommendedSettings { samples_per_pixel: 10000, aspect_ratio: (1, 1), light_sampling: true }
+    }
+
+    fn assets(&self) -> Vec<&'static str> {
+        vec!["earthmap.jpg"]
+    }
+
     fn background(&self) -> Box<dyn Background> {
         Box::new(BlackBackground::new())
     }
@@ -383,34 +1214,12 @@ impl World for FinalScene {
         }
     }
 
-    fn build(&self, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+    fn build(&self, bvh: BvhConfig, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
         let mut shapes = HittableList::new();
 
-        {
-            // Light.
-            let light = DiffuseLight::new(SolidColor::new(9.0, 9.0, 9.0));
-            shapes.add(XZRect::new(123.0, 423.0, 147.0, 412.0, 554.0, light));
-        }
+        shapes.add(final_scene_light());
 
-        {
-            // Ground.
-            let ground = Lambertian::new(SolidColor::new(0.48, 0.83, 0.53));
-            const BLOCKS_PER_SIDE: i32 = 20;
-            let mut ground_blocks = bhv::SceneBuilder::new();
-            for i in 0..BLOCKS_PER_SIDE {
-                for j in 0..BLOCKS_PER_SIDE {
-                    let w = 100.0;
-                    let x0 = -1000.0 + (i as f64) * w;
-                    let z0 = -1000.0 + (j as f64) * w;
-                    let y0 = 0.0;
-                    let x1 = x0 + w;
-                    let y1 = rng.gen_range(1.0..70.0);
-                    let z1 = z0 + w;
-                    ground_blocks.add(Block::new(Point3::new(x0, y0, z0), Point3::new(x1, y1, z1), ground.clone()));
-                }
-            }
-            shapes.add(bhv::BHV::new(&mut ground_blocks, rng));
-        }
+        shapes.add(bhv::Group::new("ground", final_scene_ground(&SiteRngator::from_rng(rng), bvh), rng));
 
         // Gold sphere in the top left corner.
         let gold_sphere =
@@ -430,16 +1239,9 @@ impl World for FinalScene {
             shapes.add(volumes::ConstantMedium::from_color(boundary, 0.2, Color::new(0.2, 0.4, 0.9)));
         }
 
-        {
-            // Fill everything with fog.
-            let boundary = Sphere::new(Point3::ZERO, 1000.0, Dielectric::new(1.5));
-            shapes.add(volumes::ConstantMedium::from_color(boundary, 0.0001, Color::ONE));
-        }
-
         {
             // Earth.
-            let img = image::open("earthmap.jpg").unwrap();
-            let earth_texture = image_texture::Image::new(img.to_rgb8());
+            let earth_texture = image_texture::Image::open("earthmap.jpg");
             let earth_surface = Lambertian::new(earth_texture);
             shapes.add(Sphere::new(Point3::new(400.0, 200.0, 400.0), 100.0, earth_surface));
         }
@@ -450,35 +1252,321 @@ impl World for FinalScene {
             shapes.add(Sphere::new(Point3::new(220.0, 280.0, 300.0), 80.0, Lambertian::new(pertext)));
         }
 
-        {
-            // Foam.
-            let mut foam = bhv::SceneBuilder::new();
-            let white = Lambertian::new(SolidColor::new(0.73, 0.73, 0.73));
-            for _ in 0..1000 {
-                foam.add(Sphere::new(Point3::random(0.0, 165.0, rng), 10.0, white.clone()));
-            }
-            let foam = bhv::BHV::new(&mut foam, rng);
-            shapes.add(transforms::Translate::new(
-                Vec3::new(-100.0, 270.0, 395.0),
-                transforms::Rotate::new(Axis::Y, 15.0, foam),
-            ));
+        let foam = bhv::Group::new("foam", final_scene_foam(&SiteRngator::from_rng(rng), bvh), rng);
+        shapes.add(transforms::Translate::new(
+            Vec3::new(-100.0, 270.0, 395.0),
+            transforms::Rotate::new(Axis::Y, 15.0, foam),
+        ));
+
+        // Fill everything with fog.
+        let fog = volumes::FogParams { radius: 1000.0, ..volumes::FogParams::new(0.0001, Color::ONE) };
+        Box::new(volumes::Fog::new(Box::new(shapes), fog))
+    }
+
+    fn lights(&self, _: BvhConfig, _: &mut dyn rand::RngCore) -> HittableList<'static> {
+        let mut lights = HittableList::new();
+        lights.add(final_scene_light());
+        lights
+    }
+}
+
+// Zigzag strip of thin quads, lit only by itself.
+fn neon_sign(bvh: BvhConfig, rng: &mut dyn rand::RngCore) -> Mesh<'static> {
+    const SEGMENTS: usize = 6;
+    let mut vertices = Vec::new();
+    let mut faces = Vec::new();
+    for i in 0..=SEGMENTS {
+        let x = -3.0 + i as f64;
+        let y = if i % 2 == 0 { 1.0 } else { 2.5 };
+        vertices.push(Point3::new(x, y, -0.05));
+        vertices.push(Point3::new(x, y + 0.15, 0.05));
+        if i > 0 {
+            let v = 2 * i;
+            faces.push([v - 2, v, v + 1]);
+            faces.push([v - 2, v + 1, v - 1]);
         }
+    }
+    Mesh::new(&vertices, &faces, DiffuseLight::new(SolidColor::new(4.0, 0.5, 2.0)), bvh, rng)
+}
+
+struct Neon {}
+
+impl World for Neon {
+    fn name(&self) -> &'static str {
+        "neon"
+    }
+
+    fn description(&self) -> &'static str {
+        "A neon sign of emissive triangles, sampled as a mesh light."
+    }
+
+    fn recommended(&self) -> RecommendedSettings {
+        RecommendedSettings { light_sampling: true, ..RecommendedSettings::default() }
+    }
+
+    fn background(&self) -> Box<dyn Background> {
+        Box::new(BlackBackground::new())
+    }
 
+    fn camera(&self) -> WorldCamera {
+        WorldCamera { lookfrom: Point3::new(0.0, 3.0, 12.0), lookat: Point3::new(0.0, 1.5, 0.0), field_of_view: 35.0 }
+    }
+
+    fn build(&self, bvh: BvhConfig, rng: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+        let mut shapes = HittableList::new();
+        let white = Lambertian::new(SolidColor::new(0.73, 0.73, 0.73));
+        shapes.add(XZRect::new(-10.0, 10.0, -10.0, 10.0, 0.0, white));
+        shapes.add(XYRect::new(-10.0, 10.0, 0.0, 10.0, -1.0, white));
+        shapes.add(Sphere::new(Point3::new(2.0, 0.7, 1.5), 0.7, Metal::new(Color::new(0.8, 0.8, 0.9), 0.1)));
+        shapes.add(Sphere::new(Point3::new(-1.5, 0.5, 2.0), 0.5, white));
+        shapes.add(neon_sign(bvh, rng));
         Box::new(shapes)
     }
+
+    fn lights(&self, bvh: BvhConfig, rng: &mut dyn rand::RngCore) -> HittableList<'static> {
+        let mut lights = HittableList::new();
+        lights.add(neon_sign(bvh, rng));
+        lights
+    }
+}
+
+// Worlds added by `register_world`.
+static REGISTERED: Mutex<Vec<Arc<dyn World>>> = Mutex::new(Vec::new());
+
+// Adds `world` to the ones returned by `worlds`, and so to the choices of `--world`. It replaces a built-in
+// or previously registered world with the same name.
+pub fn register_world(world: Box<dyn World>) {
+    REGISTERED.lock().unwrap().push(Arc::from(world));
+}
+
+pub fn worlds() -> Vec<Arc<dyn World>> {
+    let mut worlds: Vec<Arc<dyn World>> = vec![
+        Arc::new(Simple {}),
+        Arc::new(RandomSpheres::new("random")),
+        Arc::new(RandomSpheres { checker: true, ..RandomSpheres::new("random_chk") }),
+        Arc::new(RandomSpheres { bounce: true, ..RandomSpheres::new("random_bouncing") }),
+        Arc::new(TwoSpheres {}),
+        Arc::new(SimpleLight {}),
+        Arc::new(Cornell::new("cornell_box", CornellContents::Blocks)),
+        Arc::new(Cornell::new("cornell_smoke", CornellContents::Smoke)),
+        Arc::new(Cornell { glass: true, ..Cornell::new("cornell_glass", CornellContents::Blocks) }),
+        Arc::new(Cornell { mirror: true, ..Cornell::new("cornell_mirror", CornellContents::Blocks) }),
+        Arc::new(Earth {}),
+        Arc::new(DebugPerlin::new()),
+        Arc::new(MaterialPreview { material: MaterialSpec::Lambertian { color: Color::new(0.73, 0.73, 0.73) } }),
+        Arc::new(Furnace { material: MaterialSpec::Lambertian { color: Color::new(0.5, 0.5, 0.5) } }),
+        Arc::new(Interior {}),
+        Arc::new(Caustics {}),
+        Arc::new(FinalScene {}),
+        Arc::new(Neon {}),
+        Arc::new(Grass {}),
+        Arc::new(SdfShapes {}),
+        Arc::new(Rocks {}),
+    ];
+    for world in REGISTERED.lock().unwrap().iter() {
+        match worlds.iter().position(|w| w.name() == world.name()) {
+            Some(i) => worlds[i] = world.clone(),
+            None => worlds.push(world.clone()),
+        }
+    }
+    worlds
 }
 
-pub fn worlds() -> Vec<Box<dyn World>> {
-    vec![
-        Box::new(Simple {}),
-        Box::new(Random {}),
-        Box::new(RandomChk {}),
-        Box::new(TwoSpheres {}),
-        Box::new(SimpleLight {}),
-        Box::new(CornellBox {}),
-        Box::new(CornellSmoke {}),
-        Box::new(Earth {}),
-        Box::new(DebugPerlin {}),
-        Box::new(FinalScene {}),
-    ]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Named {
+        name: &'static str,
+        field_of_view: f64,
+    }
+
+    impl World for Named {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+        fn description(&self) -> &'static str {
+            "Nothing at all."
+        }
+        fn background(&self) -> Box<dyn Background> {
+            Box::new(BlackBackground::new())
+        }
+        fn camera(&self) -> WorldCamera {
+            WorldCamera {
+                lookfrom: Point3::ZERO,
+                lookat: Point3::new(0.0, 0.0, -1.0),
+                field_of_view: self.field_of_view,
+            }
+        }
+        fn build(&self, _: BvhConfig, _: &mut dyn rand::RngCore) -> Box<dyn Hittable> {
+            Box::new(HittableList::new())
+        }
+    }
+
+    #[test]
+    fn test_register_world() {
+        let builtin = worlds().len();
+        register_world(Box::new(Named { name: "registered", field_of_view: 10.0 }));
+        register_world(Box::new(Named { name: "registered", field_of_view: 20.0 }));
+
+        let worlds = worlds();
+        assert_eq!(builtin + 1, worlds.len());
+        let registered: Vec<_> = worlds.iter().filter(|w| w.name() == "registered").collect();
+        assert_eq!(1, registered.len());
+        assert_eq!(20.0, registered[0].camera().field_of_view);
+    }
+
+    #[test]
+    fn test_small_spheres() {
+        let sites = SiteRngator::new(7);
+        let centers = |mix| {
+            let spheres = RandomSpheres::new("random").with_material_mix(mix).unwrap().small_spheres(&sites);
+            spheres.iter().map(|s| s.center).collect::<Vec<_>>()
+        };
+        let diffuse = centers([1.0, 0.0, 0.0]);
+        assert!(diffuse.len() > 400);
+        assert_eq!(diffuse, centers([1.0, 0.0, 0.0]));
+        // Drawing other materials takes other numbers from the rngs, but of their own cells only.
+        assert_eq!(diffuse, centers([0.0, 0.0, 1.0]));
+        assert_eq!(diffuse, centers([0.2, 0.3, 0.5]));
+        assert_ne!(diffuse[0], RandomSpheres::new("random").small_spheres(&SiteRngator::new(8))[0].center);
+        for mix in [[0.0, 0.0, 0.0], [1.0, -0.5, 0.0], [f64::NAN, 1.0, 1.0], [f64::INFINITY, 1.0, 1.0]] {
+            assert!(RandomSpheres::new("random").with_material_mix(mix).is_err(), "{:?}", mix);
+        }
+    }
+
+    #[test]
+    fn test_parse_cornell() {
+        let cornell = "smoke, mirror".parse::<Cornell>().unwrap();
+        assert_eq!(CornellContents::Smoke, cornell.contents);
+        assert!(cornell.mirror && !cornell.glass);
+        assert_eq!(CornellContents::Blocks, "glass".parse::<Cornell>().unwrap().contents);
+        assert!("spheres,gold".parse::<Cornell>().is_err());
+        assert!("spheres,holdout".parse::<Cornell>().unwrap().holdout);
+        assert!("smoke,holdout".parse::<Cornell>().is_err());
+        assert!("closed".parse::<Cornell>().unwrap().closed);
+    }
+
+    #[test]
+    fn test_parse_noise() {
+        let world = "turbulence, depth=3, table=256".parse::<DebugPerlin>().unwrap();
+        assert_eq!(NoiseMode::Turbulence, world.noise.mode);
+        assert_eq!(3, world.noise.depth);
+        assert_eq!(256, world.noise.table_size);
+        assert_eq!(0.1, world.noise.scale);
+        assert_eq!(None, world.noise.seed);
+        assert_eq!(Some(7), "seed=7".parse::<DebugPerlin>().unwrap().noise.seed);
+        assert!("table=100".parse::<DebugPerlin>().is_err());
+        assert!("scale=big".parse::<DebugPerlin>().is_err());
+        assert!("wood".parse::<DebugPerlin>().is_err());
+    }
+
+    #[test]
+    fn test_furnace() {
+        use crate::camera::CameraParams;
+        use crate::postprocess::PostProcess;
+        use crate::raytrace::{Accumulator, Bounces, RecursiveRayTracer, Renderer, RenderingParams};
+        use crate::rngator::{Rngator, SeedableRngator};
+        // The mean of the pixels inside the ball, which covers the middle of a 9x9 image.
+        let render = |material: &str| {
+            let world = material.parse::<Furnace>().unwrap();
+            let rngator = SeedableRngator::new(1);
+            let (shapes, background, view) =
+                (world.build(BvhConfig::default(), &mut rngator.rng(0)), world.background(), world.camera());
+            let camera = CameraParams {
+                lookfrom: view.lookfrom,
+                lookat: view.lookat,
+                up: Vec3::new(0.0, 1.0, 0.0),
+                field_of_view: view.field_of_view,
+                aspect_ratio: 1.0,
+                aperture: 0.0,
+                focus_dist: 5.0,
+                shift: 0.0,
+                near: 0.0,
+                far: f64::INFINITY,
+            }
+            .camera();
+            let params = RenderingParams {
+                samples_per_pixel: 64,
+                image_width: 9,
+                image_height: 9,
+                post: PostProcess::new(),
+                shutter_open: 0.0,
+                shutter_close: 0.0,
+            };
+            let tracer = RecursiveRayTracer {
+                max_depth: 50,
+                max_bounces: Bounces::UNLIMITED,
+                regularization: None,
+                check_nan: false,
+            };
+            let renderer =
+                Renderer::new_with_rng(&camera, shapes.as_ref(), background.as_ref(), params, tracer, rngator);
+            let mut accumulator = Accumulator::new(9, 9);
+            renderer.accumulate(&mut accumulator, |_, _| ());
+            let image = accumulator.to_linear();
+            assert_eq!(Color::ONE, image[0][0]);
+            let inside: Vec<Color> =
+                (3..6).flat_map(|j| (3..6).map(move |i| (i, j))).map(|(i, j)| image[j][i]).collect();
+            inside.iter().fold(Color::ZERO, |sum, c| sum + *c) / inside.len() as f64
+        };
+        let near = |a: Color, b: Color| (a - b).length() < 0.01;
+        let gray = render("lambertian,color=0.5");
+        assert!(near(Color::new(0.5, 0.5, 0.5), gray), "{:?}", gray);
+        // Neither clear glass nor a perfect mirror absorb anything.
+        let glass = render("glass");
+        assert!(near(Color::ONE, glass), "{:?}", glass);
+        let mirror = render("metal,color=1");
+        assert!(near(Color::ONE, mirror), "{:?}", mirror);
+        let gold = render("metal,conductor=gold");
+        assert!(gold.r() <= 1.0 && gold.b() < gold.r(), "{:?}", gold);
+        // White cloth neither, though its weights vary more, so the mean is noisier.
+        let velvet = render("velvet,color=1,sheen=1");
+        assert!((velvet - Color::ONE).length() < 0.03, "{:?}", velvet);
+    }
+
+    #[test]
+    fn test_summary() {
+        for world in worlds() {
+            assert!(!world.description().is_empty(), "{} has no description", world.name());
+        }
+        let earth = worlds().into_iter().find(|w| w.name() == "earth").unwrap();
+        let earth = summary(earth.as_ref());
+        assert!(earth.starts_with("earth\n    A globe"), "{}", earth);
+        assert!(earth.contains("--samples_per_pixel=200 --aspect_ratio=16:9\n"), "{}", earth);
+        assert!(earth.ends_with("needs earthmap.jpg\n"), "{}", earth);
+        let cornell = summary(&Cornell::new("cornell", CornellContents::Smoke));
+        assert!(cornell.contains("--aspect_ratio=1:1 --light_sampling\n"), "{}", cornell);
+    }
+
+    #[test]
+    fn test_parse_material() {
+        let metal = "metal, color=0.9:0.6:0.2, fuzz=0.3".parse::<MaterialSpec>().unwrap();
+        assert_eq!(MaterialSpec::Metal { color: Color::new(0.9, 0.6, 0.2), fuzz: 0.3, conductor: None }, metal);
+        let gold = MaterialSpec::Metal { color: Color::ONE, fuzz: 0.0, conductor: Some(Conductor::GOLD) };
+        assert_eq!(gold, "metal,conductor=gold".parse().unwrap());
+        assert!("metal,conductor=brass".parse::<MaterialSpec>().is_err());
+        assert!("measured".parse::<MaterialSpec>().is_err());
+        assert!("measured,file=/nonexistent/brdf.binary".parse::<MaterialSpec>().is_err());
+        assert!("image".parse::<MaterialSpec>().is_err());
+        assert!("image,file=/nonexistent/earth.png".parse::<MaterialSpec>().is_err());
+        assert!("image,encoding=gamma".parse::<MaterialSpec>().is_err());
+        let gray = "lambertian,color=0.5".parse::<MaterialSpec>().unwrap();
+        assert_eq!(MaterialSpec::Lambertian { color: Color::new(0.5, 0.5, 0.5) }, gray);
+        let water = MaterialSpec::Dielectric { index_of_refraction: 1.33, absorption: Color::ZERO };
+        assert_eq!(water, "glass,ior=1.33".parse().unwrap());
+        let tinted = MaterialSpec::Dielectric { index_of_refraction: 1.5, absorption: Color::new(0.1, 0.2, 0.3) };
+        assert_eq!(tinted, "glass,absorption=0.1:0.2:0.3".parse().unwrap());
+        let candle = MaterialSpec::Light { color: Color::new(2.0, 2.0, 2.0), temperature: Some(1800.0) };
+        assert_eq!(candle, "light,temperature=1800,color=2".parse().unwrap());
+        for spec in ["metal,conductor=copper,color=0.9,fuzz=0.1", "light,temperature=2700", "velvet,roughness=0.3"] {
+            let spec = spec.parse::<MaterialSpec>().unwrap();
+            assert_eq!(spec, spec.to_string().parse().unwrap());
+        }
+        let felt = MaterialSpec::Velvet { color: Color::new(0.2, 0.3, 0.1), sheen: Color::ONE, roughness: 0.8 };
+        assert_eq!(felt, "velvet,color=0.2:0.3:0.1,sheen=1,roughness=0.8".parse().unwrap());
+        assert!("glass,fuzz=0.1".parse::<MaterialSpec>().is_err());
+        assert!("metal,color=1:2".parse::<MaterialSpec>().is_err());
+        assert!("leather".parse::<MaterialSpec>().is_err());
+    }
 }
@@ -0,0 +1,90 @@
+// Renders every built-in world tiny, with a fixed seed, and compares it with its reference image in
+// tests/golden, so that changes to the tracer or the BVH don't silently change the output. After a change
+// that is meant to change it, look at the new images and rewrite the references with
+//     GOLDEN_UPDATE=1 cargo test --test golden
+use image::RgbImage;
+use raytracer::bhv::BvhConfig;
+use raytracer::camera::CameraParams;
+use raytracer::postprocess::PostProcess;
+use raytracer::raytrace::{Accumulator, Bounces, RecursiveRayTracer, Renderer, RenderingParams};
+use raytracer::rngator::{Rngator, SeedableRngator};
+use raytracer::vec::Vec3;
+use raytracer::worlds::{self, World};
+use raytracer::{compare, output};
+use std::path::PathBuf;
+
+const WIDTH: usize = 48;
+const HEIGHT: usize = 27;
+const SAMPLES: usize = 4;
+const SEED: u64 = 1;
+// Root mean square difference allowed, in 8-bit levels: enough for rounding, far less than a change of noise.
+const TOLERANCE: f64 = 1.0;
+
+fn render(world: &dyn World) -> RgbImage {
+    let rngator = SeedableRngator::new(SEED);
+    let shapes = world.build(BvhConfig::default(), &mut rngator.rng(0));
+    let background = world.background();
+    let view = world.camera();
+    let camera = CameraParams {
+        lookfrom: view.lookfrom,
+        lookat: view.lookat,
+        up: Vec3::new(0.0, 1.0, 0.0),
+        field_of_view: view.field_of_view,
+        aspect_ratio: WIDTH as f64 / HEIGHT as f64,
+        aperture: 0.0,
+        focus_dist: (view.lookat - view.lookfrom).length(),
+        shift: 0.0,
+        near: 0.0,
+        far: f64::INFINITY,
+    }
+    .camera();
+    let params = RenderingParams {
+        samples_per_pixel: SAMPLES,
+        image_width: WIDTH,
+        image_height: HEIGHT,
+        post: PostProcess { lens: world.lens(), ..PostProcess::new() },
+        shutter_open: 0.0,
+        shutter_close: 0.0,
+    };
+    let tracer =
+        RecursiveRayTracer { max_depth: 10, max_bounces: Bounces::UNLIMITED, regularization: None, check_nan: false };
+    let renderer = Renderer::new_with_rng(&camera, shapes.as_ref(), background.as_ref(), params, tracer, rngator);
+    let mut accumulator = Accumulator::new(WIDTH, HEIGHT);
+    renderer.accumulate(&mut accumulator, |_, _| ());
+    output::to_image(&accumulator.to_rgb(&params.post))
+}
+
+#[test]
+fn test_golden_images() {
+    let update = std::env::var_os("GOLDEN_UPDATE").is_some();
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden");
+    let mut failures = Vec::new();
+    for world in worlds::worlds() {
+        let image = render(world.as_ref());
+        let path = dir.join(format!("{}.png", world.name()));
+        if update {
+            image.save(&path).unwrap();
+            continue;
+        }
+        let reference = match image::open(&path) {
+            Ok(reference) => reference.to_rgb8(),
+            Err(e) => {
+                failures.push(format!("{}: no reference image ({})", world.name(), e));
+                continue;
+            }
+        };
+        let difference = match compare::compare(&image, &reference) {
+            Ok(difference) => difference.rmse,
+            Err(e) => {
+                failures.push(format!("{}: {}", world.name(), e));
+                continue;
+            }
+        };
+        if difference > TOLERANCE {
+            let actual = dir.join(format!("{}.actual.png", world.name()));
+            image.save(&actual).unwrap();
+            failures.push(format!("{}: rmse {:.2}, see {}", world.name(), difference, actual.display()));
+        }
+    }
+    assert!(failures.is_empty(), "images differ from tests/golden:\n{}", failures.join("\n"));
+}